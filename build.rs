@@ -0,0 +1,826 @@
+//! Generates the `device` module from ST's SVD description of the selected
+//! part, so only the fields, registers and instances it has exist.
+//!
+//! The SVDs in `svd/vendor/` are ST's files, unchanged from where
+//! `svd/README.md` says they come from. `svd/patches/` holds one patch per
+//! part, naming the vendor file it starts from and the edits that fix or
+//! trim it, in order; the result is written to
+//! `OUT_DIR/<part>.svd`. A patch is a list of operations, each addressing
+//! peripherals, registers or fields by a `path` such as `RCC.CFGR.SW[01]`,
+//! where every segment is a comma separated list of patterns with `*`, `?`
+//! and `[...]`:
+//!
+//! * `<include>file</include>` applies another patch file from
+//!   `svd/patches/`;
+//! * `<delete path=".."/>` removes what the path matches and, with children,
+//!   only the children of that tag, those with text by `<name>`;
+//! * `<modify path="..">` replaces the children of the same tag, or adds
+//!   them;
+//! * `<merge path=".." name=".."/>` joins the fields matched in a register
+//!   into one;
+//! * `<copy path=".." from=".."/>` gives a peripheral the registers of
+//!   another, and `<derive path=".." from=".."/>` makes it `derivedFrom` it;
+//! * any other element, such as `<field path="..">`, is added to what the
+//!   path matches. An `<enumeratedValues>` added to several fields is
+//!   described on the first and `derivedFrom` it on the others.
+//!
+//! Every peripheral of the groups the drivers use that is not `derivedFrom`
+//! another one becomes a module named after its group, holding the
+//! `Registers` block, the base addresses of all peripherals sharing that
+//! layout, one `registers!` declaration per register and an enum per set of
+//! enumerated values. Register arrays and field arrays (`dim`) are expanded,
+//! and alternate views of a register are folded into the register they alias.
+//!
+//! The `<interrupt>`s of all peripherals become `NvicIdx`. They also give
+//! the interrupt half of the vector table for `rt`: its length, the handler
+//! declarations and the table itself in `interrupts.rs`, and the `PROVIDE`s
+//! that point unbound handlers at `DefaultHandler` in `interrupts.x`, next to
+//! `link.x`.
+//!
+//! It also makes sure exactly one part feature is selected, as the drivers
+//! gate peripherals on them.
+
+extern crate roxmltree;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use roxmltree::{Document, Node};
+
+const LINK: &str = "link.x";
+const VENDOR: &str = "svd/vendor";
+const PATCHES: &str = "svd/patches";
+
+/// The groups the drivers use, the only ones that become modules.
+const GROUPS: &[&str] = &["FLASH", "PWR", "RCC", "GPIO", "TIM", "SPI", "USART"];
+
+/// The containers of the levels a patch path goes through.
+const LEVELS: &[(&str, &str)] = &[("peripherals", "peripheral"), ("registers", "register"), ("fields", "field")];
+
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// An SVD element, owned so the patches can edit it.
+#[derive(Clone)]
+struct Element {
+    tag: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<Element>,
+}
+
+struct Peripheral {
+    module: String,
+    description: String,
+    instances: Vec<(String, u64)>,
+    registers: Vec<Register>,
+    enums: Vec<Enum>,
+}
+
+struct Register {
+    name: String,
+    description: String,
+    offset: u64,
+    reset: u64,
+    fields: Vec<Field>,
+}
+
+#[derive(Clone)]
+struct Field {
+    name: String,
+    description: String,
+    offset: u64,
+    width: u64,
+    ty: String,
+}
+
+struct Interrupt {
+    name: String,
+    description: String,
+    value: u64,
+}
+
+struct Enum {
+    name: String,
+    description: String,
+    values: Vec<(String, String, u64)>,
+}
+
+fn main() {
+    let part = check_part();
+
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=svd");
+    println!("cargo:rerun-if-changed={}", LINK);
+
+    let device = device(&part);
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let mut svd = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    device.write(&mut svd, "");
+    fs::write(Path::new(&out_dir).join(format!("{}.svd", part)), svd).unwrap();
+
+    let mut peripherals: Vec<Peripheral> = Vec::new();
+    let mut modules: BTreeMap<String, usize> = BTreeMap::new();
+
+    let nodes: Vec<&Element> = children(child(&device, "peripherals"), "peripheral").collect();
+    let interrupts = interrupts(&nodes);
+
+    runtime(&interrupts);
+
+    /* Peripherals without registers are only there for their interrupts. */
+    for node in nodes.iter().filter(|n| n.attribute("derivedFrom").is_none() && has_registers(n)) {
+        let name = text_of(node, "name");
+        let group = find_text(node, "groupName").unwrap_or_else(|| name.clone());
+
+        if !GROUPS.contains(&group.as_str()) {
+            continue;
+        }
+
+        let module = group.to_lowercase();
+
+        if modules.insert(module.clone(), peripherals.len()).is_some() {
+            panic!("{}: more than one layout for group {}", name, module);
+        }
+
+        peripherals.push(peripheral(node, &device, module));
+    }
+
+    for node in nodes.iter() {
+        let name = text_of(node, "name");
+        let base = number(&text_of(node, "baseAddress"));
+        let layout = match node.attribute("derivedFrom") {
+            Some(parent) => nodes.iter()
+                .find(|n| text_of(n, "name") == parent)
+                .unwrap_or_else(|| panic!("{}: unknown parent {}", name, parent)),
+            None => node,
+        };
+
+        if !has_registers(layout) {
+            continue;
+        }
+
+        let module = find_text(layout, "groupName")
+            .unwrap_or_else(|| text_of(layout, "name"))
+            .to_lowercase();
+
+        if let Some(&index) = modules.get(&module) {
+            peripherals[index].instances.push((name, base));
+        }
+    }
+
+    let mut out = String::new();
+
+    emit_interrupts(&mut out, &interrupts);
+
+    for p in &peripherals {
+        emit(&mut out, p);
+    }
+
+    let dest = Path::new(&out_dir).join("device.rs");
+    fs::write(dest, out).unwrap();
+}
+
+/// The selected part, such as `stm32f407`.
+fn check_part() -> String {
+    let mut parts: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_STM32F").map(|p| format!("stm32f{}", p.to_lowercase())))
+        .collect();
+
+    match parts.len() {
+        1 => parts.remove(0),
+        0 => panic!("no part selected, enable one of the stm32f4xx features"),
+        _ => panic!("more than one part selected ({}), disable the default features", parts.join(", ")),
+    }
+}
+
+/// The vendor SVD of the part, with its patch applied.
+fn device(part: &str) -> Element {
+    let file = format!("{}.xml", part);
+    let patch = Element::parse(&format!("{}/{}", PATCHES, file));
+    let svd = patch.attribute("svd").unwrap_or_else(|| panic!("{}: no svd to patch", file));
+    let mut device = Element::parse(&format!("{}/{}", VENDOR, svd));
+
+    apply(&mut device, &patch, &file);
+
+    device
+}
+
+fn apply(device: &mut Element, patch: &Element, file: &str) {
+    for op in &patch.children {
+        let path = op.attribute("path").unwrap_or("");
+        let segments: Vec<&str> = if path.is_empty() { Vec::new() } else { path.split('.').collect() };
+
+        let matched = match op.tag.as_str() {
+            "include" => {
+                apply(device, &Element::parse(&format!("{}/{}", PATCHES, op.text)), &op.text);
+                continue;
+            }
+            "delete" => delete(device, &segments, op),
+            "modify" => visit(device, &segments, 0, &mut |e| {
+                for c in &op.children {
+                    match e.children.iter_mut().find(|o| o.tag == c.tag) {
+                        Some(old) => *old = c.clone(),
+                        None => e.children.push(c.clone()),
+                    }
+                }
+            }),
+            "merge" => merge(device, &segments, op.attribute("name").unwrap_or_else(|| panic!("{}: merge {} has no name", file, path))),
+            "copy" | "derive" => {
+                let from = op.attribute("from").unwrap_or_else(|| panic!("{}: {} {} has no from", file, op.tag, path));
+                let source = children(child(device, "peripherals"), "peripheral")
+                    .find(|p| text_of(p, "name") == from)
+                    .unwrap_or_else(|| panic!("{}: no peripheral {}", file, from))
+                    .clone();
+
+                visit(device, &segments, 0, &mut |p| {
+                    if text_of(p, "name") == from {
+                        return;
+                    }
+
+                    p.children.retain(|c| c.tag != "registers");
+                    if op.tag == "derive" {
+                        p.set_attribute("derivedFrom", from);
+                    } else {
+                        p.attributes.retain(|a| a.0 != "derivedFrom");
+                        p.children.extend(source.children.iter().filter(|c| c.tag == "registers").cloned());
+                    }
+                })
+            }
+            _ => add(device, &segments, op),
+        };
+
+        if matched == 0 {
+            panic!("{}: {} {} matches nothing", file, op.tag, path);
+        }
+    }
+}
+
+/// Calls `f` with everything `segments` matches below `element`, which is
+/// at level `depth`, and returns how many there were.
+fn visit(element: &mut Element, segments: &[&str], depth: usize, f: &mut dyn FnMut(&mut Element)) -> usize {
+    let (pattern, rest) = match segments.split_first() {
+        Some(split) => split,
+        None => {
+            f(element);
+            return 1;
+        }
+    };
+    let (container, tag) = LEVELS[depth];
+    let mut count = 0;
+
+    for c in element.children.iter_mut().filter(|c| c.tag == container) {
+        for item in c.children.iter_mut().filter(|i| i.tag == tag) {
+            if matches(pattern, &text_of(item, "name")) {
+                count += visit(item, rest, depth + 1, f);
+            }
+        }
+    }
+
+    count
+}
+
+fn delete(device: &mut Element, segments: &[&str], op: &Element) -> usize {
+    if !op.children.is_empty() {
+        let mut count = 0;
+
+        visit(device, segments, 0, &mut |e| {
+            let before = e.children.len();
+            e.children.retain(|c| !op.children.iter().any(|d| {
+                d.tag == c.tag && (d.text.is_empty() || find_text(c, "name").map(|n| matches(&d.text, &n)).unwrap_or(false))
+            }));
+            count += before - e.children.len();
+        });
+
+        return count;
+    }
+
+    let (pattern, parent) = segments.split_last().expect("delete without a path");
+    let (container, tag) = LEVELS[parent.len()];
+    let mut count = 0;
+
+    visit(device, parent, 0, &mut |e| {
+        for c in e.children.iter_mut().filter(|c| c.tag == container) {
+            let before = c.children.len();
+            c.children.retain(|i| i.tag != tag || !matches(pattern, &text_of(i, "name")));
+            count += before - c.children.len();
+        }
+    });
+
+    count
+}
+
+fn merge(device: &mut Element, segments: &[&str], name: &str) -> usize {
+    let (pattern, register) = segments.split_last().expect("merge without a path");
+    let mut count = 0;
+
+    visit(device, register, 0, &mut |r| {
+        let fields = r.children.iter_mut().find(|c| c.tag == "fields").expect("merge in a register without fields");
+        let parts: Vec<usize> = (0..fields.children.len())
+            .filter(|&i| matches(pattern, &text_of(&fields.children[i], "name")))
+            .collect();
+
+        if parts.is_empty() {
+            return;
+        }
+
+        let ranges: Vec<(u64, u64)> = parts.iter().map(|&i| bit_range(&fields.children[i])).collect();
+        let lsb = ranges.iter().map(|r| r.0).min().unwrap();
+        let msb = ranges.iter().map(|r| r.0 + r.1).max().unwrap();
+
+        let mut field = fields.children[parts[0]].clone();
+        field.children.retain(|c| !["name", "bitOffset", "bitWidth", "lsb", "msb", "bitRange"].contains(&c.tag.as_str()));
+        field.children.insert(0, Element::leaf("name", name));
+        field.children.push(Element::leaf("bitOffset", &lsb.to_string()));
+        field.children.push(Element::leaf("bitWidth", &(msb - lsb).to_string()));
+
+        fields.children[parts[0]] = field;
+        for &i in parts[1..].iter().rev() {
+            fields.children.remove(i);
+        }
+        count += 1;
+    });
+
+    count
+}
+
+fn add(device: &mut Element, segments: &[&str], op: &Element) -> usize {
+    let mut item = op.clone();
+    item.attributes.retain(|a| a.0 != "path");
+
+    let container = LEVELS.iter().find(|l| l.1 == item.tag).map(|l| l.0);
+    let mut count = 0;
+
+    visit(device, segments, 0, &mut |e| {
+        let item = if item.tag == "enumeratedValues" && count > 0 {
+            let mut derived = Element::leaf("enumeratedValues", "");
+            derived.set_attribute("derivedFrom", &text_of(&item, "name"));
+            derived
+        } else {
+            item.clone()
+        };
+
+        let parent = match container {
+            Some(tag) => {
+                if !e.children.iter().any(|c| c.tag == tag) {
+                    e.children.push(Element::leaf(tag, ""));
+                }
+                e.children.iter_mut().find(|c| c.tag == tag).unwrap()
+            }
+            None => e,
+        };
+
+        if container.is_some() && children(parent, &item.tag).any(|c| text_of(c, "name") == text_of(&item, "name")) {
+            panic!("{} {} is already there", item.tag, text_of(&item, "name"));
+        }
+
+        parent.children.push(item);
+        count += 1;
+    });
+
+    count
+}
+
+/// Whether `name` matches one of the comma separated `patterns`.
+fn matches(patterns: &str, name: &str) -> bool {
+    fn glob(pattern: &[u8], name: &[u8]) -> bool {
+        match pattern.split_first() {
+            None => name.is_empty(),
+            Some((b'*', rest)) => (0..name.len() + 1).any(|i| glob(rest, &name[i..])),
+            Some((b'?', rest)) => !name.is_empty() && glob(rest, &name[1..]),
+            Some((b'[', rest)) => {
+                let end = rest.iter().position(|&c| c == b']').expect("unclosed [ in pattern");
+                let (set, rest) = (&rest[..end], &rest[end + 1..]);
+                let hit = |c: u8| (0..set.len()).any(|i| match set.get(i + 1) {
+                    Some(b'-') if i + 2 < set.len() => set[i] <= c && c <= set[i + 2],
+                    _ => set[i] == c,
+                });
+
+                !name.is_empty() && hit(name[0]) && glob(rest, &name[1..])
+            }
+            Some((&c, rest)) => name.first() == Some(&c) && glob(rest, &name[1..]),
+        }
+    }
+
+    patterns.split(',').any(|p| glob(p.trim().as_bytes(), name.as_bytes()))
+}
+
+/// The interrupts of all peripherals, by position. Peripherals sharing a
+/// vector, such as TIM1 and TIM9, may both list it.
+fn interrupts(nodes: &[&Element]) -> Vec<Interrupt> {
+    let mut interrupts: BTreeMap<u64, Interrupt> = BTreeMap::new();
+
+    for node in nodes {
+        for i in children(node, "interrupt") {
+            let interrupt = Interrupt {
+                name: text_of(i, "name").to_uppercase(),
+                description: description(i),
+                value: number(&text_of(i, "value")),
+            };
+
+            if let Some(other) = interrupts.get(&interrupt.value) {
+                if other.name != interrupt.name {
+                    panic!("interrupt {} is both {} and {}", interrupt.value, other.name, interrupt.name);
+                }
+                continue;
+            }
+
+            interrupts.insert(interrupt.value, interrupt);
+        }
+    }
+
+    interrupts.into_values().collect()
+}
+
+fn runtime(interrupts: &[Interrupt]) {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let count = interrupts.last().map(|i| i.value + 1).unwrap_or(0);
+
+    let mut rs = String::new();
+    let mut x = String::new();
+
+    writeln!(rs, "/// Number of device interrupt vectors.").unwrap();
+    writeln!(rs, "pub const INTERRUPTS: usize = {};\n", count).unwrap();
+    writeln!(rs, "#[cfg(target_os = \"none\")]").unwrap();
+    writeln!(rs, "extern \"C\" {{").unwrap();
+    for i in interrupts {
+        writeln!(rs, "    fn {}();", i.name).unwrap();
+    }
+    writeln!(rs, "}}\n").unwrap();
+
+    writeln!(rs, "#[cfg(target_os = \"none\")]").unwrap();
+    writeln!(rs, "#[link_section = \".vector_table.interrupts\"]").unwrap();
+    writeln!(rs, "#[no_mangle]").unwrap();
+    writeln!(rs, "#[used]").unwrap();
+    writeln!(rs, "pub static __INTERRUPTS: [Vector; INTERRUPTS] = {{").unwrap();
+    writeln!(rs, "    let mut table = [Vector {{ reserved: 0 }}; INTERRUPTS];").unwrap();
+    for i in interrupts {
+        writeln!(rs, "    table[{}] = Vector {{ handler: {} }};", i.value, i.name).unwrap();
+        writeln!(x, "PROVIDE({} = DefaultHandler);", i.name).unwrap();
+    }
+    writeln!(rs, "    table").unwrap();
+    writeln!(rs, "}};").unwrap();
+
+    fs::write(Path::new(&out_dir).join("interrupts.rs"), rs).unwrap();
+    fs::write(Path::new(&out_dir).join("interrupts.x"), x).unwrap();
+    fs::copy(LINK, Path::new(&out_dir).join(LINK)).unwrap_or_else(|e| panic!("{}: {}", LINK, e));
+
+    if env::var("CARGO_CFG_TARGET_OS").map(|os| os == "none").unwrap_or(false) {
+        println!("cargo:rustc-link-search={}", out_dir);
+    }
+}
+
+fn peripheral(node: &Element, device: &Element, module: String) -> Peripheral {
+    let default_reset = find_text(node, "resetValue")
+        .or_else(|| find_text(device, "resetValue"))
+        .map(|v| number(&v))
+        .unwrap_or(0);
+
+    let mut enums = Vec::new();
+    let mut registers: Vec<Register> = Vec::new();
+    let mut alternates = Vec::new();
+
+    for reg in children(child(node, "registers"), "register") {
+        let fields = fields(reg, &mut enums);
+
+        for (name, offset) in expand(reg, &text_of(reg, "name"), number(&text_of(reg, "addressOffset"))) {
+            let register = Register {
+                name: name.to_lowercase(),
+                description: description(reg),
+                offset,
+                reset: find_text(reg, "resetValue").map(|v| number(&v)).unwrap_or(default_reset),
+                fields: fields.clone(),
+            };
+
+            match find_text(reg, "alternateRegister") {
+                Some(target) => alternates.push((target.to_lowercase(), register)),
+                None => registers.push(register),
+            }
+        }
+    }
+
+    for (target, alternate) in alternates {
+        let register = registers.iter_mut()
+            .find(|r| r.name == target)
+            .unwrap_or_else(|| panic!("{}: unknown alternate register {}", alternate.name, target));
+
+        for field in alternate.fields {
+            if register.fields.iter().all(|f| f.name != field.name) {
+                register.fields.push(field);
+            }
+        }
+    }
+
+    registers.sort_by_key(|r| r.offset);
+
+    for pair in registers.windows(2) {
+        if pair[0].offset + 4 > pair[1].offset {
+            panic!("{}: {} overlaps {}", module, pair[0].name, pair[1].name);
+        }
+    }
+
+    Peripheral {
+        module,
+        description: description(node),
+        instances: Vec::new(),
+        registers,
+        enums,
+    }
+}
+
+fn fields(reg: &Element, enums: &mut Vec<Enum>) -> Vec<Field> {
+    let mut fields = Vec::new();
+
+    let node = match reg.children.iter().find(|n| n.tag == "fields") {
+        Some(node) => node,
+        None => return fields,
+    };
+
+    for field in children(node, "field") {
+        let (offset, width) = bit_range(field);
+        let desc = description(field);
+
+        let ty = match field.children.iter().find(|n| n.tag == "enumeratedValues") {
+            Some(values) => match values.attribute("derivedFrom") {
+                Some(parent) => parent.to_string(),
+                None => {
+                    let name = text_of(values, "name");
+                    let values = children(values, "enumeratedValue")
+                        .map(|v| (text_of(v, "name"), description(v), number(&text_of(v, "value"))))
+                        .collect();
+
+                    enums.push(Enum { name: name.clone(), description: desc.clone(), values });
+                    name
+                }
+            },
+            None if width == 1 => "bool".to_string(),
+            None => "u32".to_string(),
+        };
+
+        for (name, offset) in expand(field, &text_of(field, "name"), offset) {
+            fields.push(Field { name, description: desc.clone(), offset, width, ty: ty.clone() });
+        }
+    }
+
+    fields
+}
+
+/// The names and offsets of the elements of a `dim` array, or of the single
+/// element otherwise.
+fn expand(node: &Element, name: &str, offset: u64) -> Vec<(String, u64)> {
+    let dim = match find_text(node, "dim") {
+        Some(dim) => number(&dim),
+        None => return vec![(name.to_string(), offset)],
+    };
+    let increment = number(&text_of(node, "dimIncrement"));
+
+    let indices: Vec<String> = match find_text(node, "dimIndex") {
+        Some(index) => match index.find('-') {
+            Some(dash) => {
+                let (first, last) = (number(&index[..dash]), number(&index[dash + 1..]));
+                (first..last + 1).map(|i| i.to_string()).collect()
+            }
+            None => index.split(',').map(|s| s.trim().to_string()).collect(),
+        },
+        None => (0..dim).map(|i| i.to_string()).collect(),
+    };
+
+    assert_eq!(indices.len() as u64, dim, "{}: dimIndex does not match dim", name);
+
+    indices.iter().enumerate()
+        .map(|(i, index)| (name.replace("%s", index), offset + i as u64 * increment))
+        .collect()
+}
+
+fn bit_range(field: &Element) -> (u64, u64) {
+    if let Some(offset) = find_text(field, "bitOffset") {
+        return (number(&offset), number(&text_of(field, "bitWidth")));
+    }
+
+    if let Some(lsb) = find_text(field, "lsb") {
+        let lsb = number(&lsb);
+        return (lsb, number(&text_of(field, "msb")) - lsb + 1);
+    }
+
+    let range = text_of(field, "bitRange");
+    let range = range.trim_start_matches('[').trim_end_matches(']');
+    let colon = range.find(':').unwrap_or_else(|| panic!("bad bitRange {}", range));
+    let (msb, lsb) = (number(&range[..colon]), number(&range[colon + 1..]));
+
+    (lsb, msb - lsb + 1)
+}
+
+fn emit_interrupts(out: &mut String, interrupts: &[Interrupt]) {
+    writeln!(out, "/// Interrupt positions in the vector table of the selected part.").unwrap();
+    writeln!(out, "#[repr(C)]").unwrap();
+    writeln!(out, "#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]").unwrap();
+    writeln!(out, "#[allow(non_camel_case_types)]").unwrap();
+    writeln!(out, "pub enum NvicIdx {{").unwrap();
+
+    for (n, i) in interrupts.iter().enumerate() {
+        doc(out, "    ", &i.description);
+        if n == 0 {
+            writeln!(out, "    #[default]").unwrap();
+        }
+        writeln!(out, "    {} = {},", i.name, i.value).unwrap();
+    }
+
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+}
+
+fn emit(out: &mut String, p: &Peripheral) {
+    doc(out, "", &p.description);
+    writeln!(out, "pub mod {} {{", p.module).unwrap();
+    writeln!(out, "    use common::field::Reg;").unwrap();
+    writeln!(out).unwrap();
+
+    for &(ref name, base) in &p.instances {
+        writeln!(out, "    pub const {}: usize = {};", name, hex(base)).unwrap();
+    }
+
+    writeln!(out).unwrap();
+    writeln!(out, "    #[repr(C)]").unwrap();
+    writeln!(out, "    pub struct Registers {{").unwrap();
+
+    let mut next = 0;
+    let mut reserved = 0;
+
+    for r in &p.registers {
+        if r.offset > next {
+            writeln!(out, "        _reserved{}: [u32; {}],", reserved, (r.offset - next) / 4).unwrap();
+            reserved += 1;
+        }
+
+        doc(out, "        ", &r.description);
+        writeln!(out, "        pub {}: Reg<{}::Spec>,", ident(&r.name), ident(&r.name)).unwrap();
+        next = r.offset + 4;
+    }
+
+    writeln!(out, "    }}").unwrap();
+
+    for e in &p.enums {
+        writeln!(out).unwrap();
+        doc(out, "    ", &e.description);
+        writeln!(out, "    #[derive(Copy, Clone, Debug, PartialEq, Eq)]").unwrap();
+        writeln!(out, "    #[allow(non_camel_case_types)]").unwrap();
+        writeln!(out, "    pub enum {} {{", e.name).unwrap();
+
+        for &(ref variant, ref description, value) in &e.values {
+            doc(out, "        ", description);
+            writeln!(out, "        {} = {},", variant, value).unwrap();
+        }
+
+        writeln!(out, "    }}").unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "    field_values! {{").unwrap();
+        let variants: Vec<&str> = e.values.iter().map(|v| v.0.as_str()).collect();
+        writeln!(out, "        {} {{ {} }}", e.name, variants.join(", ")).unwrap();
+        writeln!(out, "    }}").unwrap();
+    }
+
+    writeln!(out).unwrap();
+    writeln!(out, "    registers! {{").unwrap();
+
+    for r in &p.registers {
+        doc(out, "        ", &r.description);
+        writeln!(out, "        pub mod {} = {} => {{", ident(&r.name), hex(r.reset)).unwrap();
+
+        for f in &r.fields {
+            doc(out, "            ", &f.description);
+            writeln!(out, "            {} {}: {}, {} => {},",
+                     f.name, ident(&f.name.to_lowercase()), f.offset, f.width, f.ty).unwrap();
+        }
+
+        writeln!(out, "        }}").unwrap();
+    }
+
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+}
+
+fn doc(out: &mut String, indent: &str, text: &str) {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if !text.is_empty() {
+        writeln!(out, "{}/// {}", indent, text).unwrap();
+    }
+}
+
+fn ident(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn hex(value: u64) -> String {
+    format!("0x{:04x}_{:04x}", value >> 16, value & 0xffff)
+}
+
+fn number(text: &str) -> u64 {
+    let text = text.trim();
+
+    let parsed = if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        u64::from_str_radix(hex, 16)
+    } else if let Some(binary) = text.strip_prefix('#') {
+        u64::from_str_radix(binary, 2)
+    } else {
+        text.parse()
+    };
+
+    parsed.unwrap_or_else(|_| panic!("bad number {}", text))
+}
+
+fn description(node: &Element) -> String {
+    find_text(node, "description").unwrap_or_default()
+}
+
+fn has_registers(node: &Element) -> bool {
+    node.children.iter().any(|n| n.tag == "registers")
+}
+
+fn child<'a>(node: &'a Element, tag: &str) -> &'a Element {
+    node.children.iter()
+        .find(|n| n.tag == tag)
+        .unwrap_or_else(|| panic!("<{}> has no <{}>", node.tag, tag))
+}
+
+fn children<'a>(node: &'a Element, tag: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+    node.children.iter().filter(move |n| n.tag == tag)
+}
+
+fn find_text(node: &Element, tag: &str) -> Option<String> {
+    node.children.iter()
+        .find(|n| n.tag == tag)
+        .map(|n| n.text.clone())
+}
+
+fn text_of(node: &Element, tag: &str) -> String {
+    find_text(node, tag)
+        .unwrap_or_else(|| panic!("<{}> has no <{}>", node.tag, tag))
+}
+
+impl Element {
+    fn parse(path: &str) -> Element {
+        let text = fs::read_to_string(path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        let doc = Document::parse(&text).unwrap_or_else(|e| panic!("{}: {}", path, e));
+
+        Element::from(doc.root_element())
+    }
+
+    fn from(node: Node) -> Element {
+        Element {
+            tag: node.tag_name().name().to_string(),
+            attributes: node.attributes().map(|a| (a.name().to_string(), a.value().to_string())).collect(),
+            text: node.children().filter_map(|n| if n.is_text() { n.text() } else { None }).collect::<String>().trim().to_string(),
+            children: node.children().filter(|n| n.is_element()).map(Element::from).collect(),
+        }
+    }
+
+    fn leaf(tag: &str, text: &str) -> Element {
+        Element { tag: tag.to_string(), attributes: Vec::new(), text: text.to_string(), children: Vec::new() }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|a| a.0 == name).map(|a| a.1.as_str())
+    }
+
+    fn set_attribute(&mut self, name: &str, value: &str) {
+        self.attributes.retain(|a| a.0 != name);
+        self.attributes.push((name.to_string(), value.to_string()));
+    }
+
+    fn write(&self, out: &mut String, indent: &str) {
+        write!(out, "{}<{}", indent, self.tag).unwrap();
+        for (name, value) in &self.attributes {
+            write!(out, " {}=\"{}\"", name, escape(value).replace('"', "&quot;")).unwrap();
+        }
+
+        if self.children.is_empty() {
+            writeln!(out, ">{}</{}>", escape(&self.text), self.tag).unwrap();
+            return;
+        }
+
+        writeln!(out, ">{}", escape(&self.text)).unwrap();
+        let inner = format!("{}  ", indent);
+        for c in &self.children {
+            c.write(out, &inner);
+        }
+        writeln!(out, "{}</{}>", indent, self.tag).unwrap();
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
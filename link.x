@@ -0,0 +1,101 @@
+/* Linker script for the opencm runtime.
+
+   The application provides `memory.x`, describing the FLASH and RAM regions
+   of its part, somewhere on the linker search path (usually the root of the
+   binary crate), and links with `-C link-arg=-Tlink.x`. For a STM32F407:
+
+       MEMORY
+       {
+         FLASH : ORIGIN = 0x08000000, LENGTH = 1024K
+         RAM   : ORIGIN = 0x20000000, LENGTH = 128K
+       }
+
+   The stack starts at the end of RAM unless `memory.x` sets `_stack_start`,
+   and the room left for a stack guard below it is 256 bytes unless it sets
+   `_stack_guard_size` to another power of two of at least 32. */
+
+INCLUDE memory.x
+
+ENTRY(Reset);
+
+EXTERN(__RESET_VECTOR);
+EXTERN(__EXCEPTIONS);
+EXTERN(__INTERRUPTS);
+EXTERN(DefaultHandler_);
+EXTERN(FaultHandler_);
+
+/* Handlers the application doesn't bind with `exception!` or `interrupt!`. */
+PROVIDE(DefaultHandler = DefaultHandler_);
+PROVIDE(NMI = DefaultHandler);
+PROVIDE(HardFault = FaultHandler_);
+PROVIDE(MemManage = FaultHandler_);
+PROVIDE(BusFault = FaultHandler_);
+PROVIDE(UsageFault = FaultHandler_);
+PROVIDE(SVCall = DefaultHandler);
+PROVIDE(DebugMonitor = DefaultHandler);
+PROVIDE(PendSV = DefaultHandler);
+PROVIDE(SysTick = DefaultHandler);
+
+INCLUDE interrupts.x
+
+PROVIDE(_stack_start = ORIGIN(RAM) + LENGTH(RAM));
+PROVIDE(_stack_guard_size = 256);
+
+SECTIONS
+{
+  .vector_table ORIGIN(FLASH) :
+  {
+    LONG(_stack_start);
+    KEEP(*(.vector_table.reset_vector));
+    KEEP(*(.vector_table.exceptions));
+    KEEP(*(.vector_table.interrupts));
+  } > FLASH
+
+  .text :
+  {
+    *(.text .text.*);
+  } > FLASH
+
+  .rodata : ALIGN(4)
+  {
+    *(.rodata .rodata.*);
+    . = ALIGN(4);
+  } > FLASH
+
+  .data : ALIGN(4)
+  {
+    __sdata = .;
+    *(.data .data.*);
+    . = ALIGN(4);
+    __edata = .;
+  } > RAM AT > FLASH
+
+  __sidata = LOADADDR(.data);
+
+  .bss (NOLOAD) : ALIGN(4)
+  {
+    __sbss = .;
+    *(.bss .bss.*);
+    *(COMMON);
+    . = ALIGN(4);
+    __ebss = .;
+  } > RAM
+
+  /* Room for an MPU region between .bss and the stack growing down
+     towards it, see `mpu::guard_main_stack`. */
+  .stack_guard (NOLOAD) : ALIGN(_stack_guard_size)
+  {
+    __stack_guard = .;
+    . += _stack_guard_size;
+  } > RAM
+
+  /DISCARD/ :
+  {
+    *(.ARM.exidx .ARM.exidx.*);
+  }
+}
+
+ASSERT(ORIGIN(FLASH) % 4 == 0, "FLASH must start on a word boundary");
+ASSERT(_stack_start % 8 == 0, "the stack must start on a double word boundary");
+ASSERT(_stack_guard_size >= 32 && (_stack_guard_size & (_stack_guard_size - 1)) == 0,
+       "_stack_guard_size must be a power of two of at least 32");
@@ -1,3 +1,307 @@
+//! Cortex-M instructions and special registers.
+//!
+//! On the host the sleep, event and breakpoint instructions do nothing, the
+//! barriers become fences, and the special registers are plain per-thread
+//! values. Code that masks interrupts or switches stacks therefore still runs
+//! in tests, it just has nothing to mask or switch.
+
+#[cfg(target_os = "none")]
+use core::arch::asm;
+
+#[cfg(target_os = "none")]
+mod imp {
+    use core::arch::asm;
+
+    #[inline(always)]
+    pub fn nop() { unsafe { asm!("nop", options(nomem, nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn wfi() { unsafe { asm!("wfi", options(nomem, nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn wfe() { unsafe { asm!("wfe", options(nomem, nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn sev() { unsafe { asm!("sev", options(nomem, nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn bkpt() { unsafe { asm!("bkpt #0", options(nomem, nostack, preserves_flags)) } }
+
+    /* The barriers and the interrupt mask must not be reordered with memory
+       accesses, so these leave out `nomem`. */
+    #[inline(always)]
+    pub fn dsb() { unsafe { asm!("dsb sy", options(nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn dmb() { unsafe { asm!("dmb sy", options(nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn isb() { unsafe { asm!("isb sy", options(nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn cpsid() { unsafe { asm!("cpsid i", options(nostack, preserves_flags)) } }
+    #[inline(always)]
+    pub fn cpsie() { unsafe { asm!("cpsie i", options(nostack, preserves_flags)) } }
+}
+
+#[cfg(not(target_os = "none"))]
+mod imp {
+    use core::sync::atomic::{fence, Ordering};
+
+    pub fn nop() {}
+    pub fn wfi() {}
+    pub fn wfe() {}
+    pub fn sev() {}
+    pub fn bkpt() {}
+
+    pub fn dsb() { fence(Ordering::SeqCst) }
+    pub fn dmb() { fence(Ordering::SeqCst) }
+    pub fn isb() { fence(Ordering::SeqCst) }
+    pub fn cpsid() { super::host::set(&super::host::PRIMASK, 1) }
+    pub fn cpsie() { super::host::set(&super::host::PRIMASK, 0) }
+}
+
+/// Special registers of the calling thread, standing in for the core's.
+#[cfg(not(target_os = "none"))]
+mod host {
+    use std::cell::Cell;
+    use std::thread::LocalKey;
+
+    thread_local! {
+        pub static PRIMASK: Cell<u32> = const { Cell::new(0) };
+        pub static BASEPRI: Cell<u32> = const { Cell::new(0) };
+        pub static CONTROL: Cell<u32> = const { Cell::new(0) };
+        pub static PSP: Cell<u32> = const { Cell::new(0) };
+        pub static MSP: Cell<u32> = const { Cell::new(0) };
+        pub static FPSCR: Cell<u32> = const { Cell::new(0) };
+    }
+
+    pub fn get(reg: &'static LocalKey<Cell<u32>>) -> u32 {
+        reg.with(|r| r.get())
+    }
+
+    pub fn set(reg: &'static LocalKey<Cell<u32>>, value: u32) {
+        reg.with(|r| r.set(value))
+    }
+}
+
+/// Reads a special register with `mrs`.
+macro_rules! mrs {
+    ($reg:ident) => {{
+        #[cfg(target_os = "none")]
+        let value = {
+            let value: u32;
+            unsafe { asm!(concat!("mrs {}, ", stringify!($reg)), out(reg) value, options(nomem, nostack, preserves_flags)) };
+            value
+        };
+        #[cfg(not(target_os = "none"))]
+        let value = host::get(&host::$reg);
+
+        value
+    }};
+}
+
+/// Writes a special register with `msr`.
+macro_rules! msr {
+    ($reg:ident, $host:ident, $value:expr) => {{
+        #[cfg(target_os = "none")]
+        asm!(concat!("msr ", stringify!($reg), ", {}"), in(reg) $value, options(nostack, preserves_flags));
+        #[cfg(not(target_os = "none"))]
+        host::set(&host::$host, $value);
+    }};
+}
+
+/// Does nothing for one cycle.
+#[inline(always)]
 pub fn nop() {
-    unsafe {asm!("nop")}
-}
\ No newline at end of file
+    imp::nop()
+}
+
+/// Sleeps until an interrupt is pending.
+#[inline(always)]
+pub fn wfi() {
+    imp::wfi()
+}
+
+/// Sleeps until an event or an interrupt, returns at once if the event
+/// register is already set.
+#[inline(always)]
+pub fn wfe() {
+    imp::wfe()
+}
+
+/// Signals an event to every core, waking up `wfe`.
+#[inline(always)]
+pub fn sev() {
+    imp::sev()
+}
+
+/// Halts in the debugger, or raises a HardFault when none is attached.
+#[inline(always)]
+pub fn bkpt() {
+    imp::bkpt()
+}
+
+/// Data synchronization barrier: completes every outstanding memory access,
+/// register writes included, before the next instruction.
+#[inline(always)]
+pub fn dsb() {
+    imp::dsb()
+}
+
+/// Data memory barrier: orders memory accesses before and after it.
+#[inline(always)]
+pub fn dmb() {
+    imp::dmb()
+}
+
+/// Instruction synchronization barrier: refetches the pipeline, so changes to
+/// CONTROL, the vector table or the MPU apply to the next instruction.
+#[inline(always)]
+pub fn isb() {
+    imp::isb()
+}
+
+/// Masks every interrupt with a configurable priority.
+#[inline(always)]
+pub fn cpsid() {
+    imp::cpsid()
+}
+
+/// Unmasks interrupts again.
+///
+/// # Safety
+///
+/// Must not be called inside a critical section that relies on interrupts
+/// staying masked.
+#[inline(always)]
+pub unsafe fn cpsie() {
+    imp::cpsie()
+}
+
+/// PRIMASK, bit 0 set while interrupts are masked by `cpsid`.
+#[inline(always)]
+pub fn primask() -> u32 {
+    mrs!(PRIMASK)
+}
+
+/// BASEPRI, the priority at and below which interrupts are masked, 0 when
+/// none are.
+#[inline(always)]
+pub fn basepri() -> u32 {
+    mrs!(BASEPRI)
+}
+
+/// # Safety
+///
+/// Lowering BASEPRI can unmask interrupts a critical section relies on.
+#[inline(always)]
+pub unsafe fn set_basepri(value: u32) {
+    msr!(BASEPRI, BASEPRI, value)
+}
+
+/// Raises BASEPRI to `value`, leaving it alone if it already masks more.
+///
+/// # Safety
+///
+/// See `set_basepri`.
+#[inline(always)]
+pub unsafe fn set_basepri_max(value: u32) {
+    #[cfg(target_os = "none")]
+    msr!(BASEPRI_MAX, BASEPRI, value);
+    #[cfg(not(target_os = "none"))]
+    {
+        let current = host::get(&host::BASEPRI);
+
+        if value != 0 && (current == 0 || value < current) {
+            host::set(&host::BASEPRI, value);
+        }
+    }
+}
+
+/// CONTROL: bit 0 nPRIV, bit 1 SPSEL, bit 2 FPCA.
+#[inline(always)]
+pub fn control() -> u32 {
+    mrs!(CONTROL)
+}
+
+/// Writes CONTROL, followed by the `isb` the architecture requires.
+///
+/// # Safety
+///
+/// Switching stacks or dropping privilege changes how all following code
+/// runs.
+#[inline(always)]
+pub unsafe fn set_control(value: u32) {
+    msr!(CONTROL, CONTROL, value);
+    isb();
+}
+
+/// Process stack pointer.
+#[inline(always)]
+pub fn psp() -> u32 {
+    mrs!(PSP)
+}
+
+/// # Safety
+///
+/// Must point to the top of a valid stack before thread mode switches to it.
+#[inline(always)]
+pub unsafe fn set_psp(value: u32) {
+    msr!(PSP, PSP, value)
+}
+
+/// Main stack pointer.
+#[inline(always)]
+pub fn msp() -> u32 {
+    mrs!(MSP)
+}
+
+/// # Safety
+///
+/// Moves the stack from under the running code.
+#[inline(always)]
+pub unsafe fn set_msp(value: u32) {
+    msr!(MSP, MSP, value)
+}
+
+/// FPSCR, the floating-point status and control register. The FPU must be
+/// enabled.
+#[inline(always)]
+pub fn fpscr() -> u32 {
+    #[cfg(target_os = "none")]
+    {
+        /* vmrs r0, fpscr, encoded so it assembles without FPU target
+           features. */
+        let value: u32;
+        unsafe { asm!(".inst.w 0xeef10a10", out("r0") value, options(nomem, nostack, preserves_flags)) };
+        value
+    }
+    #[cfg(not(target_os = "none"))]
+    host::get(&host::FPSCR)
+}
+
+/// # Safety
+///
+/// Changes rounding and flush-to-zero behaviour of all following float code.
+#[inline(always)]
+pub unsafe fn set_fpscr(value: u32) {
+    /* vmsr fpscr, r0 */
+    #[cfg(target_os = "none")]
+    asm!(".inst.w 0xeee10a10", in("r0") value, options(nomem, nostack));
+    #[cfg(not(target_os = "none"))]
+    host::set(&host::FPSCR, value);
+}
+
+/// Busy-waits for at least `cycles` core clock cycles.
+#[inline(always)]
+pub fn delay_cycles(cycles: u32) {
+    #[cfg(target_os = "none")]
+    {
+        /* SUBS and a taken BNE take at least two cycles per round. */
+        let rounds = cycles / 2 + 1;
+
+        unsafe {
+            asm!("1:",
+                 "subs {0}, #1",
+                 "bne 1b",
+                 inout(reg) rounds => _,
+                 options(nomem, nostack));
+        }
+    }
+    #[cfg(not(target_os = "none"))]
+    let _ = cycles;
+}
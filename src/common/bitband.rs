@@ -0,0 +1,79 @@
+//! Atomic single-bit access through the Cortex-M bit-band alias regions.
+//!
+//! Every bit of the first megabyte of SRAM and of the peripheral region has
+//! its own word in an alias region. Writing 0 or 1 to that word clears or
+//! sets the bit in one bus transaction, so an interrupt can not slip in
+//! between the read and the write of a read-modify-write and lose its update.
+//!
+//! On the host there are no alias regions. The register files are private to
+//! their thread and nothing interrupts the driver, so a plain read-modify-write
+//! of the register is used instead.
+
+use common::VolatileCell;
+#[cfg(target_os = "none")]
+use core::ptr;
+use common::interrupt;
+
+const SRAM_BASE: usize = 0x2000_0000;
+const SRAM_ALIAS: usize = 0x2200_0000;
+const PERIPH_BASE: usize = 0x4000_0000;
+const PERIPH_ALIAS: usize = 0x4200_0000;
+
+/// Size of each of the two bit-band regions.
+const REGION_SIZE: usize = 0x10_0000;
+
+/// Returns the alias word of `bit` of the word at `addr`, or `None` when
+/// `addr` is outside the bit-band regions.
+pub const fn alias(addr: usize, bit: u32) -> Option<usize> {
+    let (base, alias) = if addr >= PERIPH_BASE && addr < PERIPH_BASE + REGION_SIZE {
+        (PERIPH_BASE, PERIPH_ALIAS)
+    } else if addr >= SRAM_BASE && addr < SRAM_BASE + REGION_SIZE {
+        (SRAM_BASE, SRAM_ALIAS)
+    } else {
+        return None;
+    };
+
+    Some(alias + (addr - base) * 32 + bit as usize * 4)
+}
+
+/// Sets or clears `bit` of `reg` in one write.
+#[cfg(target_os = "none")]
+pub fn write(reg: &VolatileCell<u32>, bit: u32, value: bool) {
+    match alias(reg as *const _ as usize, bit) {
+        Some(word) => unsafe { ptr::write_volatile(word as *mut u32, value as u32) },
+        None => interrupt::free(|| reg.update(|r| (r & !(1 << bit)) | ((value as u32) << bit))),
+    }
+}
+
+/// Sets or clears `bit` of `reg` in one write.
+#[cfg(not(target_os = "none"))]
+pub fn write(reg: &VolatileCell<u32>, bit: u32, value: bool) {
+    interrupt::free(|| reg.update(|r| (r & !(1 << bit)) | ((value as u32) << bit)));
+}
+
+pub fn set(reg: &VolatileCell<u32>, bit: u32) {
+    write(reg, bit, true);
+}
+
+pub fn clear(reg: &VolatileCell<u32>, bit: u32) {
+    write(reg, bit, false);
+}
+
+/// Sets the bits of `mask` in `reg`. A single bit goes through its alias,
+/// several bits are read-modify-written with interrupts masked.
+pub fn check(reg: &VolatileCell<u32>, mask: u32) {
+    if mask.is_power_of_two() {
+        set(reg, mask.trailing_zeros());
+    } else {
+        interrupt::free(|| reg.check(mask));
+    }
+}
+
+/// Clears the bits of `mask` in `reg`, the counterpart of `check`.
+pub fn uncheck(reg: &VolatileCell<u32>, mask: u32) {
+    if mask.is_power_of_two() {
+        clear(reg, mask.trailing_zeros());
+    } else {
+        interrupt::free(|| reg.uncheck(mask));
+    }
+}
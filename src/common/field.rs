@@ -0,0 +1,305 @@
+//! Typed register fields.
+//!
+//! A register is declared once with `registers!`, which gives it a marker type,
+//! its reset value and one `Field` descriptor per bit field. Drivers then go
+//! through `Reg`, which only accepts descriptors and values of the right type:
+//!
+//! ```ignore
+//! timer.cr1.modify(|_, w| w.set(cr1::CMS, Alignment::Center1).set(cr1::CEN, true));
+//! let running = timer.cr1.read().cen();
+//! ```
+//!
+//! A field of one register can not be written to another, and a field with
+//! enumerated values only takes its own enum, so a PPRE value can not end up
+//! in HPRE. Fields that don't fit their register fail to compile.
+
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use common::{bitband, interrupt};
+use common::VolatileCell;
+
+/// A register declared with `registers!`.
+pub trait Register {
+    const RESET: u32;
+}
+
+/// A value that can be stored in a bit field.
+pub trait FieldValue: Copy {
+    /// What reading the field gives. Enumerated fields read `None` for
+    /// reserved encodings.
+    type Read;
+
+    fn into_bits(self) -> u32;
+    fn from_bits(bits: u32) -> Self::Read;
+}
+
+impl FieldValue for bool {
+    type Read = bool;
+
+    fn into_bits(self) -> u32 { self as u32 }
+    fn from_bits(bits: u32) -> bool { bits != 0 }
+}
+
+impl FieldValue for u8 {
+    type Read = u8;
+
+    fn into_bits(self) -> u32 { self as u32 }
+    fn from_bits(bits: u32) -> u8 { bits as u8 }
+}
+
+impl FieldValue for u16 {
+    type Read = u16;
+
+    fn into_bits(self) -> u32 { self as u32 }
+    fn from_bits(bits: u32) -> u16 { bits as u16 }
+}
+
+impl FieldValue for u32 {
+    type Read = u32;
+
+    fn into_bits(self) -> u32 { self }
+    fn from_bits(bits: u32) -> u32 { bits }
+}
+
+/// `width` bits at `offset` of register `REG`, holding a `V`.
+pub struct Field<REG, V> {
+    offset: u32,
+    width: u32,
+    _marker: PhantomData<fn() -> (REG, V)>,
+}
+
+impl<REG, V> Clone for Field<REG, V> {
+    fn clone(&self) -> Self { *self }
+}
+
+impl<REG, V> Copy for Field<REG, V> {}
+
+impl<REG, V> Field<REG, V> {
+    pub const fn new(offset: u32, width: u32) -> Field<REG, V> {
+        assert!(width > 0 && offset + width <= 32, "field does not fit in a 32-bit register");
+
+        Field { offset, width, _marker: PhantomData }
+    }
+
+    /// The `n`th of a row of identical fields `stride` bits apart, like the
+    /// per pin fields of GPIO or the per channel fields of the timers, with
+    /// `self` being the first of them.
+    pub const fn nth(self, n: u32, stride: u32) -> Field<REG, V> {
+        Field::new(self.offset + n * stride, self.width)
+    }
+
+    pub const fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub const fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// The bits of the field in place.
+    pub const fn mask(&self) -> u32 {
+        (u32::MAX >> (32 - self.width)) << self.offset
+    }
+}
+
+/// A value read from register `REG`.
+pub struct R<REG> {
+    bits: u32,
+    _reg: PhantomData<REG>,
+}
+
+impl<REG> R<REG> {
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn get<V: FieldValue>(&self, field: Field<REG, V>) -> V::Read {
+        V::from_bits((self.bits & field.mask()) >> field.offset)
+    }
+
+    pub fn is_set(&self, field: Field<REG, bool>) -> bool {
+        self.bits & field.mask() != 0
+    }
+}
+
+/// A value about to be written to register `REG`.
+pub struct W<REG> {
+    bits: u32,
+    _reg: PhantomData<REG>,
+}
+
+impl<REG> W<REG> {
+    /// Replaces the whole register value.
+    pub fn bits(&mut self, bits: u32) -> &mut Self {
+        self.bits = bits;
+        self
+    }
+
+    /// Stores `value` in `field`. Integers wider than the field are truncated.
+    pub fn set<V: FieldValue>(&mut self, field: Field<REG, V>, value: V) -> &mut Self {
+        self.bits = (self.bits & !field.mask()) | ((value.into_bits() << field.offset) & field.mask());
+        self
+    }
+}
+
+/// A register of type `REG`.
+///
+/// Still derefs to the plain `VolatileCell` for whole register access.
+#[repr(transparent)]
+pub struct Reg<REG: Register> {
+    cell: VolatileCell<u32>,
+    _reg: PhantomData<REG>,
+}
+
+impl<REG: Register> Reg<REG> {
+    pub fn read(&self) -> R<REG> {
+        R { bits: self.cell.get(), _reg: PhantomData }
+    }
+
+    /// Writes the reset value as changed by `f`.
+    pub fn write<F>(&self, f: F)
+        where F: FnOnce(&mut W<REG>) -> &mut W<REG>
+    {
+        let mut w = W { bits: REG::RESET, _reg: PhantomData };
+        f(&mut w);
+        self.cell.set(w.bits);
+    }
+
+    /// Reads the register and writes it back as changed by `f`.
+    pub fn modify<F>(&self, f: F)
+        where F: for<'w> FnOnce(&R<REG>, &'w mut W<REG>) -> &'w mut W<REG>
+    {
+        let r = self.read();
+        let mut w = W { bits: r.bits, _reg: PhantomData };
+        f(&r, &mut w);
+        self.cell.set(w.bits);
+    }
+
+    pub fn reset(&self) {
+        self.cell.set(REG::RESET);
+    }
+
+    pub fn is_set(&self, field: Field<REG, bool>) -> bool {
+        self.read().is_set(field)
+    }
+
+    pub fn set_bit(&self, field: Field<REG, bool>) {
+        self.modify(|_, w| w.set(field, true));
+    }
+
+    pub fn clear_bit(&self, field: Field<REG, bool>) {
+        self.modify(|_, w| w.set(field, false));
+    }
+
+    /// `modify` with interrupts masked, for registers an interrupt handler
+    /// may change as well.
+    pub fn modify_atomic<F>(&self, f: F)
+        where F: for<'w> FnOnce(&R<REG>, &'w mut W<REG>) -> &'w mut W<REG>
+    {
+        interrupt::free(|| self.modify(f));
+    }
+
+    /// Stores `value` in `field` without an interruptible read-modify-write.
+    /// Single-bit fields are written through their bit-band alias.
+    pub fn set_atomic<V: FieldValue>(&self, field: Field<REG, V>, value: V) {
+        if field.width == 1 {
+            bitband::write(&self.cell, field.offset, value.into_bits() != 0);
+        } else {
+            self.modify_atomic(|_, w| w.set(field, value));
+        }
+    }
+}
+
+impl<REG: Register> Deref for Reg<REG> {
+    type Target = VolatileCell<u32>;
+
+    fn deref(&self) -> &VolatileCell<u32> {
+        &self.cell
+    }
+}
+
+/// Declares registers as modules holding a `Spec` marker type, the reset
+/// value and the field descriptors.
+///
+/// Each field is `NAME [accessor]: offset, width => Type`. With an accessor
+/// name, `R<Spec>` and `W<Spec>` also get a method of that name, so
+/// `r.cen()` reads what `r.get(CEN)` does and `w.cen(true)` writes it.
+///
+/// The register modules see everything their parent module does.
+macro_rules! registers {
+    ($(
+        $(#[$meta:meta])*
+        pub mod $reg:ident = $reset:expr => {
+            $(
+                $(#[$fmeta:meta])*
+                $field:ident $($accessor:ident)*: $offset:expr, $width:expr => $ty:ty
+            ),* $(,)*
+        }
+    )*) => {$(
+        $(#[$meta])*
+        #[allow(unused_imports)]
+        pub mod $reg {
+            use super::*;
+            use $crate::common::field::{Field, FieldValue, Register, R, W};
+
+            pub enum Spec {}
+
+            impl Register for Spec {
+                const RESET: u32 = $reset;
+            }
+
+            $(
+                registers!(@field [$(#[$fmeta])*] $field [$($accessor)*] $offset, $width => $ty);
+            )*
+        }
+    )*};
+
+    (@field [$(#[$fmeta:meta])*] $field:ident [] $offset:expr, $width:expr => $ty:ty) => {
+        $(#[$fmeta])*
+        pub const $field: Field<Spec, $ty> = Field::new($offset, $width);
+    };
+
+    (@field [$(#[$fmeta:meta])*] $field:ident [$accessor:ident] $offset:expr, $width:expr => $ty:ty) => {
+        $(#[$fmeta])*
+        pub const $field: Field<Spec, $ty> = Field::new($offset, $width);
+
+        impl R<Spec> {
+            $(#[$fmeta])*
+            pub fn $accessor(&self) -> <$ty as FieldValue>::Read {
+                self.get($field)
+            }
+        }
+
+        impl W<Spec> {
+            $(#[$fmeta])*
+            pub fn $accessor(&mut self, value: $ty) -> &mut Self {
+                self.set($field, value)
+            }
+        }
+    };
+}
+
+/// Makes C-like enums usable as enumerated field values. Every variant is
+/// encoded by its discriminant.
+macro_rules! field_values {
+    ($($ty:ident { $($variant:ident),* $(,)* })*) => {$(
+        impl $crate::common::field::FieldValue for $ty {
+            type Read = Option<$ty>;
+
+            fn into_bits(self) -> u32 {
+                self as u32
+            }
+
+            fn from_bits(bits: u32) -> Option<$ty> {
+                $(
+                    if bits == $ty::$variant as u32 {
+                        return Some($ty::$variant);
+                    }
+                )*
+
+                None
+            }
+        }
+    )*};
+}
@@ -0,0 +1,101 @@
+//! Interrupt masking, globally through PRIMASK or up to a priority through
+//! BASEPRI.
+
+use core::marker::PhantomData;
+
+use common::asm;
+use hal::nvic::PRIORITY_BITS;
+
+/// Runs `f` with interrupts masked through PRIMASK. The previous mask state
+/// is restored afterwards, so nested calls don't unmask early.
+#[inline]
+pub fn free<F: FnOnce() -> R, R>(f: F) -> R {
+    let primask = asm::primask();
+
+    asm::cpsid();
+
+    let r = f();
+
+    if primask & 1 == 0 {
+        unsafe { asm::cpsie() };
+    }
+
+    r
+}
+
+/// Proof that interrupts at `priority` and less urgent ones are masked,
+/// handed to the closure of `with_ceiling`.
+pub struct CriticalSection {
+    priority: u8,
+    _local: PhantomData<*const ()>,
+}
+
+impl CriticalSection {
+    /// The most urgent NVIC priority masked, 0 when all of them are.
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+}
+
+/// Runs `f` with BASEPRI raised to the NVIC `priority`, so interrupts at
+/// that priority and below can't preempt it while more urgent ones still
+/// can. BASEPRI can't mask priority 0, for it this masks everything through
+/// PRIMASK like `free`. A section never lowers the mask of one it is nested
+/// in, and puts back the previous BASEPRI when done.
+///
+/// Panics if `priority` doesn't fit the implemented priority bits.
+pub fn with_ceiling<F: FnOnce(&CriticalSection) -> R, R>(priority: u8, f: F) -> R {
+    assert!(priority < 1 << PRIORITY_BITS, "priority {} out of range", priority);
+
+    let cs = CriticalSection { priority, _local: PhantomData };
+
+    if priority == 0 {
+        return free(|| f(&cs));
+    }
+
+    let basepri = asm::basepri();
+
+    unsafe { asm::set_basepri_max((priority as u32) << (8 - PRIORITY_BITS)) };
+
+    let r = f(&cs);
+
+    unsafe { asm::set_basepri(basepri) };
+
+    r
+}
+
+/// Data shared between interrupt handlers of different priorities.
+///
+/// `ceiling` is the most urgent priority of any code using the data. Access
+/// is only granted inside a `with_ceiling` section masking that priority, so
+/// none of the users can preempt another while it holds a reference. The
+/// data is shared, mutate it through a `Cell` or `RefCell`.
+pub struct Mutex<T> {
+    ceiling: u8,
+    data: T,
+}
+
+unsafe impl<T: Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(ceiling: u8, data: T) -> Mutex<T> {
+        Mutex { ceiling, data }
+    }
+
+    pub fn ceiling(&self) -> u8 {
+        self.ceiling
+    }
+
+    /// Panics if `cs` doesn't mask the ceiling of the mutex.
+    pub fn borrow<'cs>(&'cs self, cs: &'cs CriticalSection) -> &'cs T {
+        assert!(cs.priority <= self.ceiling,
+            "section at priority {} doesn't mask the ceiling {}", cs.priority, self.ceiling);
+
+        &self.data
+    }
+
+    /// Runs `f` on the data inside a section at the ceiling.
+    pub fn lock<F: FnOnce(&T) -> R, R>(&self, f: F) -> R {
+        with_ceiling(self.ceiling, |cs| f(self.borrow(cs)))
+    }
+}
@@ -0,0 +1,291 @@
+//! Register block access.
+//!
+//! Drivers never dereference a peripheral base address directly, they ask
+//! `block` for it. On the target (`target_os = "none"`) this is the fixed bus
+//! address. Everywhere else the block is backed by an in-memory register file,
+//! so the drivers can be exercised by `cargo test` on the host.
+
+/// Returns the register block of type `T` located at `base`.
+///
+/// # Safety
+///
+/// `base` must be the address of a peripheral whose register layout is `T`.
+#[cfg(target_os = "none")]
+#[inline(always)]
+pub unsafe fn block<T>(base: usize) -> &'static T {
+    &*(base as *const T)
+}
+
+/// Returns the register block of type `T` located at `base`.
+///
+/// # Safety
+///
+/// `base` must be the address of a peripheral whose register layout is `T`.
+#[cfg(not(target_os = "none"))]
+pub unsafe fn block<T>(base: usize) -> &'static T {
+    &*(host::block(base, ::core::mem::size_of::<T>()) as *const T)
+}
+
+/// In-memory register files used instead of the peripheral bus on the host.
+///
+/// Every thread owns its own set of register files, so tests running in
+/// parallel never see each other's register state. A block is allocated and
+/// zeroed on first access.
+///
+/// Plain memory is enough for configuration code, but not for code that spins
+/// on a status bit. A `Model` attached to a block sees every driver access to
+/// it and can update the register file the way the hardware would.
+///
+/// With `trace_start` every driver access is also recorded, which lets tests
+/// compare the register sequence of a driver function against a golden one.
+#[cfg(not(target_os = "none"))]
+pub mod host {
+    use std::any::Any;
+    use std::boxed::Box;
+    use std::cell::RefCell;
+    use std::vec::Vec;
+    use std::ptr;
+
+    /// Peripheral blocks on the F4 are 1KB apart.
+    const BLOCK_SIZE: usize = 0x400;
+
+    /// Behaviour of one peripheral block.
+    ///
+    /// `offset` is the byte offset of the accessed register inside the block,
+    /// `old` and `new` hold the register contents zero-extended to 32 bits.
+    pub trait Model: Any {
+        /// Bytes of registers in the block, for blocks larger than 1KB.
+        fn size(&self) -> usize { 0 }
+
+        /// Called once when the model is attached, to load reset values.
+        fn reset(&mut self, _regs: &mut Regs) {}
+
+        /// Called before the driver reads the register at `offset`.
+        fn read(&mut self, _regs: &mut Regs, _offset: usize) {}
+
+        /// Called after the driver changed the register at `offset`.
+        fn write(&mut self, _regs: &mut Regs, _offset: usize, _old: u32, _new: u32) {}
+
+        /// Called after the driver wrote `size` bytes at `offset`, for blocks
+        /// where the width of the access matters. Forwards to `write`.
+        fn write_sized(&mut self, regs: &mut Regs, offset: usize, _size: usize, old: u32, new: u32) {
+            self.write(regs, offset, old, new)
+        }
+    }
+
+    /// The register file of one block, as seen by a `Model`.
+    pub struct Regs {
+        mem: *mut u32,
+        words: usize,
+    }
+
+    impl Regs {
+        fn word(&self, offset: usize) -> *mut u32 {
+            assert!(offset.is_multiple_of(4) && offset / 4 < self.words,
+                "invalid register offset {:#x}", offset);
+
+            unsafe { self.mem.add(offset / 4) }
+        }
+
+        pub fn get(&self, offset: usize) -> u32 {
+            unsafe { ptr::read_volatile(self.word(offset)) }
+        }
+
+        pub fn set(&mut self, offset: usize, value: u32) {
+            unsafe { ptr::write_volatile(self.word(offset), value) }
+        }
+
+        pub fn test(&self, offset: usize, bits: u32) -> bool {
+            self.get(offset) & bits != 0
+        }
+
+        pub fn check(&mut self, offset: usize, bits: u32) {
+            let reg = self.get(offset);
+            self.set(offset, reg | bits);
+        }
+
+        pub fn uncheck(&mut self, offset: usize, bits: u32) {
+            let reg = self.get(offset);
+            self.set(offset, reg & !bits);
+        }
+    }
+
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub enum Op {
+        Read,
+        Write,
+    }
+
+    /// One recorded register access. For reads `old` and `new` are both the
+    /// value the driver got.
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    pub struct Access {
+        pub op: Op,
+        pub base: usize,
+        pub offset: usize,
+        pub old: u32,
+        pub new: u32,
+    }
+
+    struct Block {
+        base: usize,
+        regs: Regs,
+        model: Option<Box<dyn Model>>,
+    }
+
+    thread_local! {
+        static BLOCKS: RefCell<Vec<Block>> = const { RefCell::new(Vec::new()) };
+        static TRACE: RefCell<Option<Vec<Access>>> = const { RefCell::new(None) };
+    }
+
+    fn alloc(blocks: &mut Vec<Block>, base: usize, size: usize) -> usize {
+        if let Some(index) = blocks.iter().position(|b| b.base == base) {
+            assert!(size <= blocks[index].regs.words * 4,
+                "register block at {:#010x} accessed with a larger layout", base);
+
+            return index;
+        }
+
+        let words = size.max(BLOCK_SIZE).div_ceil(4);
+        let mem = vec![0u32; words].leak().as_mut_ptr();
+
+        blocks.push(Block { base, regs: Regs { mem, words }, model: None });
+
+        blocks.len() - 1
+    }
+
+    pub(super) fn block(base: usize, size: usize) -> *mut u32 {
+        BLOCKS.with(|blocks| {
+            let mut blocks = blocks.borrow_mut();
+            let index = alloc(&mut blocks, base, size);
+
+            blocks[index].regs.mem
+        })
+    }
+
+    /// Runs `f` on the model of the block that contains `addr`, if any, and
+    /// returns the base and offset of `addr` when it belongs to a block.
+    fn with_block_model<F>(addr: usize, f: F) -> Option<(usize, usize)>
+        where F: FnOnce(&mut dyn Model, &mut Regs, usize)
+    {
+        BLOCKS.with(|blocks| {
+            let mut blocks = blocks.borrow_mut();
+            let block = blocks.iter_mut().find(|b| {
+                let start = b.regs.mem as usize;
+                addr >= start && addr < start + b.regs.words * 4
+            })?;
+            let offset = addr - block.regs.mem as usize;
+
+            if let Some(ref mut model) = block.model {
+                f(&mut **model, &mut block.regs, offset);
+            }
+
+            Some((block.base, offset))
+        })
+    }
+
+    fn record(op: Op, base: usize, offset: usize, old: u32, new: u32) {
+        TRACE.with(|trace| {
+            if let Some(ref mut trace) = *trace.borrow_mut() {
+                trace.push(Access { op, base, offset, old, new });
+            }
+        })
+    }
+
+    /// Reads `size` bytes at `addr` without notifying any model.
+    pub(crate) fn load(addr: usize, size: usize) -> u32 {
+        unsafe {
+            match size {
+                1 => ptr::read_volatile(addr as *const u8) as u32,
+                2 => ptr::read_volatile(addr as *const u16) as u32,
+                _ => ptr::read_volatile(addr as *const u32),
+            }
+        }
+    }
+
+    /// Called by `VolatileCell` before a register is read.
+    pub(crate) fn before_read(addr: usize, size: usize) {
+        if let Some((base, offset)) = with_block_model(addr, |model, regs, offset| model.read(regs, offset)) {
+            let value = load(addr, size);
+
+            record(Op::Read, base, offset, value, value);
+        }
+    }
+
+    /// Called by `VolatileCell` after a register was written.
+    pub(crate) fn after_write(addr: usize, size: usize, old: u32) {
+        let new = load(addr, size);
+
+        if let Some((base, offset)) = with_block_model(addr, |model, regs, offset| model.write_sized(regs, offset, size, old, new)) {
+            record(Op::Write, base, offset, old, new);
+        }
+    }
+
+    /// Starts recording the register accesses of the calling thread,
+    /// discarding anything recorded before.
+    pub fn trace_start() {
+        TRACE.with(|trace| *trace.borrow_mut() = Some(Vec::new()))
+    }
+
+    /// Stops recording and returns the accesses in the order they happened.
+    pub fn trace_stop() -> Vec<Access> {
+        TRACE.with(|trace| trace.borrow_mut().take().unwrap_or_default())
+    }
+
+    /// Attaches `model` to the block at `base`, replacing any previous one,
+    /// and lets it load its reset values.
+    pub fn attach<M: Model>(base: usize, mut model: M) {
+        BLOCKS.with(|blocks| {
+            let mut blocks = blocks.borrow_mut();
+            let index = alloc(&mut blocks, base, model.size());
+            let block = &mut blocks[index];
+
+            model.reset(&mut block.regs);
+            block.model = Some(Box::new(model));
+        })
+    }
+
+    /// Runs `f` on the model of type `M` attached to the block at `base`.
+    ///
+    /// Panics if no such model is attached.
+    pub fn with_model<M: Model, R, F: FnOnce(&mut M, &mut Regs) -> R>(base: usize, f: F) -> R {
+        BLOCKS.with(|blocks| {
+            let mut blocks = blocks.borrow_mut();
+            let block = blocks.iter_mut().find(|b| b.base == base)
+                .expect("no register block at this address");
+            let model = block.model.as_mut()
+                .and_then(|m| (&mut **m as &mut dyn Any).downcast_mut::<M>())
+                .expect("no model of this type attached");
+
+            f(model, &mut block.regs)
+        })
+    }
+
+    fn word(base: usize, offset: usize) -> *mut u32 {
+        assert!(offset.is_multiple_of(4), "unaligned register offset {:#x}", offset);
+
+        unsafe { block(base, offset + 4).add(offset / 4) }
+    }
+
+    /// Reads the 32-bit register at `offset` of the block at `base`.
+    pub fn peek(base: usize, offset: usize) -> u32 {
+        unsafe { ptr::read_volatile(word(base, offset)) }
+    }
+
+    /// Writes the 32-bit register at `offset` of the block at `base`,
+    /// bypassing the drivers and the attached model.
+    pub fn poke(base: usize, offset: usize, value: u32) {
+        unsafe { ptr::write_volatile(word(base, offset), value) }
+    }
+
+    /// Zeroes every register file of the calling thread and detaches all
+    /// models.
+    pub fn reset() {
+        BLOCKS.with(|blocks| {
+            for block in blocks.borrow_mut().iter_mut() {
+                unsafe { ptr::write_bytes(block.regs.mem, 0, block.regs.words) };
+                block.model = None;
+            }
+        })
+    }
+}
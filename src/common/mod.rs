@@ -1,4 +1,12 @@
 mod volatile_cell;
+#[macro_use]
+pub mod field;
 pub mod asm;
+pub mod bitband;
+pub mod interrupt;
+pub mod mmio;
+pub mod spin;
+#[cfg(not(target_os = "none"))]
+pub mod sim;
 
 pub use self::volatile_cell::VolatileCell;
\ No newline at end of file
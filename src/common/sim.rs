@@ -0,0 +1,665 @@
+//! Behavioural models of the F4 peripherals for the host register files.
+//!
+//! Attach a model to a block with `mmio::host::attach` and the drivers can run
+//! code that waits on hardware: oscillators and over-drive report ready, SPI
+//! and UART data is looped back or taken from a script, timers, SysTick and
+//! the cycle counter count, interrupts are enabled and pended, and stimulus
+//! port writes come out as ITM packets.
+
+use std::collections::VecDeque;
+use std::vec::Vec;
+
+use common::mmio::host::{Model, Regs};
+
+/// Reset and clock control.
+///
+/// Every oscillator reports ready as soon as it is switched on and the clock
+/// switch status follows the requested system clock immediately. The reset
+/// flags read as after power-on until RMVF clears them.
+pub struct Rcc;
+
+mod rcc {
+    pub const CR: usize = 0x00;
+    pub const PLLCFGR: usize = 0x04;
+    pub const CFGR: usize = 0x08;
+    pub const BDCR: usize = 0x70;
+    pub const CSR: usize = 0x74;
+    pub const CSR_RMVF: u32 = 1 << 24;
+    pub const CSR_RSTF: u32 = 0x7F << 25;
+
+    /* (ON, RDY) pairs of CR */
+    pub const CR_OSC: [(u32, u32); 5] = [
+        (1 << 0,  1 << 1),  /* HSI */
+        (1 << 16, 1 << 17), /* HSE */
+        (1 << 24, 1 << 25), /* PLL */
+        (1 << 26, 1 << 27), /* PLLI2S */
+        (1 << 28, 1 << 29), /* PLLSAI */
+    ];
+}
+
+impl Model for Rcc {
+    fn reset(&mut self, regs: &mut Regs) {
+        regs.set(rcc::CR, 0x0000_0083);
+        regs.set(rcc::PLLCFGR, 0x2400_3010);
+        regs.set(rcc::CSR, 0x0E00_0000);
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, _old: u32, new: u32) {
+        match offset {
+            rcc::CR => for &(on, rdy) in rcc::CR_OSC.iter() {
+                if new & on != 0 { regs.check(rcc::CR, rdy) } else { regs.uncheck(rcc::CR, rdy) }
+            },
+            rcc::CFGR => regs.set(rcc::CFGR, (new & !(0b11 << 2)) | ((new & 0b11) << 2)),
+            rcc::BDCR => if new & 1 != 0 { regs.check(rcc::BDCR, 1 << 1) } else { regs.uncheck(rcc::BDCR, 1 << 1) },
+            rcc::CSR => {
+                if new & rcc::CSR_RMVF != 0 {
+                    regs.set(rcc::CSR, new & !(rcc::CSR_RSTF | rcc::CSR_RMVF));
+                }
+                if new & 1 != 0 { regs.check(rcc::CSR, 1 << 1) } else { regs.uncheck(rcc::CSR, 1 << 1) }
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Power controller.
+///
+/// Over-drive and its switch report ready as soon as they are enabled.
+pub struct Pwr;
+
+mod pwr {
+    pub const CR: usize = 0x00;
+    pub const CSR: usize = 0x04;
+
+    /* VOS out of reset differs between parts, the SVD of the part has it. */
+    pub const CR_RESET: u32 = <::device::pwr::cr::Spec as ::common::field::Register>::RESET;
+
+    pub const CR_ODEN: u32 = 1 << 16;
+    pub const CR_ODSWEN: u32 = 1 << 17;
+}
+
+impl Model for Pwr {
+    fn reset(&mut self, regs: &mut Regs) {
+        regs.set(pwr::CR, pwr::CR_RESET);
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, _old: u32, new: u32) {
+        if offset == pwr::CR {
+            /* ODRDY and ODSWRDY sit at the positions of their enables. */
+            for &bit in [pwr::CR_ODEN, pwr::CR_ODSWEN].iter() {
+                if new & bit != 0 { regs.check(pwr::CSR, bit) } else { regs.uncheck(pwr::CSR, bit) }
+            }
+        }
+    }
+}
+
+/// General purpose I/O port.
+///
+/// BSRR writes update ODR. IDR reads back ODR for pins in output mode and the
+/// level driven with `drive` for all other pins.
+#[derive(Default)]
+pub struct Gpio {
+    input: u16,
+}
+
+mod gpio {
+    pub const MODER: usize = 0x00;
+    pub const IDR: usize = 0x10;
+    pub const ODR: usize = 0x14;
+    pub const BSRR: usize = 0x18;
+    pub const BRR: usize = 0x1A;
+}
+
+impl Gpio {
+    pub fn new() -> Gpio {
+        Gpio::default()
+    }
+
+    /// Drives the input `pins` high or low from outside the chip.
+    pub fn drive(&mut self, pins: u16, high: bool) {
+        if high {
+            self.input |= pins;
+        } else {
+            self.input &= !pins;
+        }
+    }
+}
+
+impl Model for Gpio {
+    fn read(&mut self, regs: &mut Regs, offset: usize) {
+        if offset == gpio::IDR {
+            let moder = regs.get(gpio::MODER);
+            let outputs = (0..16).filter(|pin| (moder >> (pin * 2)) & 0b11 == 0b01)
+                .fold(0u32, |mask, pin| mask | (1 << pin));
+
+            regs.set(gpio::IDR, (regs.get(gpio::ODR) & outputs) | (self.input as u32 & !outputs));
+        }
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, _old: u32, new: u32) {
+        let (set, reset) = match offset {
+            gpio::BSRR => (new & 0xffff, new >> 16),
+            gpio::BRR => (0, new),
+            _ => return,
+        };
+
+        let odr = regs.get(gpio::ODR);
+        regs.set(gpio::ODR, (odr & !reset) | set);
+
+        /* BSRR is write only and always reads back as zero. */
+        regs.set(gpio::BSRR, 0);
+    }
+}
+
+/// Serial port.
+///
+/// The transmitter is always ready. Transmitted words are either looped back
+/// to the receiver or collected for the test to inspect, received words are
+/// taken from a queue filled with `receive`.
+#[derive(Default)]
+pub struct Uart {
+    loopback: bool,
+    rx: VecDeque<u16>,
+    tx: Vec<u16>,
+}
+
+mod uart {
+    pub const SR: usize = 0x00;
+    pub const DR: usize = 0x04;
+
+    pub const SR_TXE: u32 = 1 << 7;
+    pub const SR_TC: u32 = 1 << 6;
+    pub const SR_RXNE: u32 = 1 << 5;
+}
+
+impl Uart {
+    pub fn new() -> Uart {
+        Uart::default()
+    }
+
+    pub fn loopback() -> Uart {
+        Uart { loopback: true, ..Uart::default() }
+    }
+
+    /// Queues `words` on the receive line.
+    pub fn receive(&mut self, words: &[u16]) {
+        self.rx.extend(words.iter().cloned());
+    }
+
+    /// Words transmitted so far, unless looped back.
+    pub fn transmitted(&self) -> &[u16] {
+        &self.tx
+    }
+
+    fn sync(&self, regs: &mut Regs) {
+        if self.rx.is_empty() {
+            regs.uncheck(uart::SR, uart::SR_RXNE);
+        } else {
+            regs.check(uart::SR, uart::SR_RXNE);
+        }
+    }
+}
+
+impl Model for Uart {
+    fn reset(&mut self, regs: &mut Regs) {
+        regs.set(uart::SR, uart::SR_TXE | uart::SR_TC);
+    }
+
+    fn read(&mut self, regs: &mut Regs, offset: usize) {
+        match offset {
+            uart::SR => self.sync(regs),
+            uart::DR => {
+                if let Some(word) = self.rx.pop_front() {
+                    regs.set(uart::DR, word as u32);
+                }
+
+                self.sync(regs);
+            },
+            _ => {}
+        }
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, _old: u32, new: u32) {
+        if offset == uart::DR {
+            if self.loopback {
+                self.rx.push_back(new as u16);
+            } else {
+                self.tx.push(new as u16);
+            }
+
+            regs.check(uart::SR, uart::SR_TXE | uart::SR_TC);
+            self.sync(regs);
+        }
+    }
+}
+
+/// SPI master.
+///
+/// Every word written to DR completes a transfer at once. The word clocked in
+/// is either the one sent (loopback) or the next scripted response. OVR and
+/// MODF, once set, only clear with their sequence: a read of DR then SR, and
+/// a read of SR then a write to CR1.
+#[derive(Default)]
+pub struct Spi {
+    loopback: bool,
+    responses: VecDeque<u16>,
+    rx: VecDeque<u16>,
+    sent: Vec<u16>,
+    /* DR was read with OVR set. */
+    ovr_dr_read: bool,
+    /* SR was read with MODF set. */
+    modf_sr_read: bool,
+}
+
+mod spi {
+    pub const CR1: usize = 0x00;
+    pub const SR: usize = 0x08;
+    pub const DR: usize = 0x0C;
+
+    pub const SR_OVR: u32 = 1 << 6;
+    pub const SR_MODF: u32 = 1 << 5;
+    pub const SR_TXE: u32 = 1 << 1;
+    pub const SR_RXNE: u32 = 1 << 0;
+}
+
+impl Spi {
+    /// A slave that answers every word with the next one of `responses`, and
+    /// with zero once they run out.
+    pub fn scripted(responses: &[u16]) -> Spi {
+        Spi { responses: responses.iter().cloned().collect(), ..Spi::default() }
+    }
+
+    pub fn loopback() -> Spi {
+        Spi { loopback: true, ..Spi::default() }
+    }
+
+    /// Words written to DR so far.
+    pub fn sent(&self) -> &[u16] {
+        &self.sent
+    }
+
+    fn sync(&self, regs: &mut Regs) {
+        if self.rx.is_empty() {
+            regs.uncheck(spi::SR, spi::SR_RXNE);
+        } else {
+            regs.check(spi::SR, spi::SR_RXNE);
+        }
+    }
+}
+
+impl Model for Spi {
+    fn reset(&mut self, regs: &mut Regs) {
+        regs.set(spi::SR, spi::SR_TXE);
+    }
+
+    fn read(&mut self, regs: &mut Regs, offset: usize) {
+        if offset == spi::DR {
+            if let Some(word) = self.rx.pop_front() {
+                regs.set(spi::DR, word as u32);
+            }
+
+            self.ovr_dr_read = regs.get(spi::SR) & spi::SR_OVR != 0;
+            self.sync(regs);
+        } else if offset == spi::SR {
+            if self.ovr_dr_read {
+                self.ovr_dr_read = false;
+                regs.uncheck(spi::SR, spi::SR_OVR);
+            }
+
+            self.modf_sr_read = regs.get(spi::SR) & spi::SR_MODF != 0;
+        }
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, _old: u32, new: u32) {
+        if offset == spi::CR1 && self.modf_sr_read {
+            self.modf_sr_read = false;
+            regs.uncheck(spi::SR, spi::SR_MODF);
+        } else if offset == spi::DR {
+            let word = new as u16;
+            let response = if self.loopback { word } else { self.responses.pop_front().unwrap_or(0) };
+
+            self.sent.push(word);
+            self.rx.push_back(response);
+            self.sync(regs);
+        }
+    }
+}
+
+/// Basic, general purpose or advanced timer.
+///
+/// While CEN is set the counter moves one step for every read of CNT or SR,
+/// so polling loops make progress. `advance` moves it by more steps at once.
+/// Overflow and underflow raise UIF, compare matches raise CCxIF.
+#[derive(Default)]
+pub struct Timer;
+
+mod timer {
+    pub const CR1: usize = 0x00;
+    pub const SR: usize = 0x10;
+    pub const EGR: usize = 0x14;
+    pub const CNT: usize = 0x24;
+    pub const ARR: usize = 0x2C;
+    pub const CCR: [usize; 4] = [0x34, 0x38, 0x3C, 0x40];
+
+    pub const CR1_CEN: u32 = 1 << 0;
+    pub const CR1_OPM: u32 = 1 << 3;
+    pub const CR1_DIR: u32 = 1 << 4;
+    pub const SR_UIF: u32 = 1 << 0;
+    pub const EGR_UG: u32 = 1 << 0;
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer
+    }
+
+    /// Lets the running counter count `steps` times.
+    pub fn advance(&mut self, regs: &mut Regs, steps: u32) {
+        for _ in 0..steps {
+            if !regs.test(timer::CR1, timer::CR1_CEN) {
+                break;
+            }
+
+            self.step(regs);
+        }
+    }
+
+    fn step(&mut self, regs: &mut Regs) {
+        let cr1 = regs.get(timer::CR1);
+        let arr = regs.get(timer::ARR);
+        let cnt = regs.get(timer::CNT);
+
+        let (cnt, overflow) = if cr1 & timer::CR1_DIR != 0 {
+            if cnt == 0 { (arr, true) } else { (cnt - 1, false) }
+        } else if cnt >= arr {
+            (0, true)
+        } else {
+            (cnt + 1, false)
+        };
+
+        regs.set(timer::CNT, cnt);
+
+        if overflow {
+            regs.check(timer::SR, timer::SR_UIF);
+
+            if cr1 & timer::CR1_OPM != 0 {
+                regs.uncheck(timer::CR1, timer::CR1_CEN);
+            }
+        }
+
+        for (channel, &ccr) in timer::CCR.iter().enumerate() {
+            if regs.get(ccr) == cnt {
+                regs.check(timer::SR, 1 << (channel + 1));
+            }
+        }
+    }
+}
+
+impl Model for Timer {
+    fn reset(&mut self, regs: &mut Regs) {
+        regs.set(timer::ARR, 0xffff);
+    }
+
+    fn read(&mut self, regs: &mut Regs, offset: usize) {
+        if offset == timer::CNT || offset == timer::SR {
+            self.advance(regs, 1);
+        }
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, old: u32, new: u32) {
+        match offset {
+            /* Status flags are cleared by writing zero, ones are ignored. */
+            timer::SR => regs.set(timer::SR, old & new),
+            timer::EGR => {
+                if new & timer::EGR_UG != 0 {
+                    let cnt = if regs.test(timer::CR1, timer::CR1_DIR) { regs.get(timer::ARR) } else { 0 };
+
+                    regs.set(timer::CNT, cnt);
+                    regs.check(timer::SR, timer::SR_UIF);
+                }
+
+                regs.set(timer::EGR, 0);
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Nested vectored interrupt controller.
+///
+/// The set and clear registers of the enable and pending bits work as on the
+/// core, both reading back the current bits, and STIR pends its interrupt.
+/// Nothing is ever taken, interrupts stay pending until cleared, and
+/// `activate` stands in for the core entering or leaving a handler.
+#[derive(Default)]
+pub struct Nvic;
+
+mod nvic {
+    pub const ISER: usize = 0x000;
+    pub const ICER: usize = 0x080;
+    pub const ISPR: usize = 0x100;
+    pub const ICPR: usize = 0x180;
+    pub const IABR: usize = 0x200;
+    pub const STIR: usize = 0xE00;
+
+    /// Set and clear registers are 8 words each.
+    pub const BANK: usize = 0x20;
+}
+
+impl Nvic {
+    pub fn new() -> Nvic {
+        Nvic
+    }
+
+    /// Marks `interrupt` as being handled, or not any more.
+    pub fn activate(&mut self, regs: &mut Regs, interrupt: usize, active: bool) {
+        let (offset, bit) = (nvic::IABR + interrupt / 32 * 4, 1 << (interrupt % 32));
+
+        if active { regs.check(offset, bit) } else { regs.uncheck(offset, bit) }
+    }
+
+    /// Sets or clears `bits` in the word at `index` of a set/clear pair.
+    fn update(regs: &mut Regs, set: usize, clear: usize, index: usize, bits: u32, on: bool) {
+        let word = index * 4;
+        let value = if on { regs.get(set + word) | bits } else { regs.get(set + word) & !bits };
+
+        regs.set(set + word, value);
+        regs.set(clear + word, value);
+    }
+}
+
+impl Model for Nvic {
+    fn size(&self) -> usize {
+        nvic::STIR + 4
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, old: u32, new: u32) {
+        /* The write already replaced the register, put `old` back first. */
+        let bank = |base: usize| offset >= base && offset < base + nvic::BANK;
+        let index = (offset % nvic::BANK) / 4;
+
+        if bank(nvic::ISER) || bank(nvic::ICER) || bank(nvic::ISPR) || bank(nvic::ICPR) {
+            regs.set(offset, old);
+        }
+
+        if bank(nvic::ISER) {
+            Nvic::update(regs, nvic::ISER, nvic::ICER, index, new, true);
+        } else if bank(nvic::ICER) {
+            Nvic::update(regs, nvic::ISER, nvic::ICER, index, new, false);
+        } else if bank(nvic::ISPR) {
+            Nvic::update(regs, nvic::ISPR, nvic::ICPR, index, new, true);
+        } else if bank(nvic::ICPR) {
+            Nvic::update(regs, nvic::ISPR, nvic::ICPR, index, new, false);
+        } else if offset == nvic::STIR {
+            let interrupt = (new & 0x1FF) as usize;
+
+            Nvic::update(regs, nvic::ISPR, nvic::ICPR, interrupt / 32, 1 << (interrupt % 32), true);
+            regs.set(nvic::STIR, 0);
+        }
+    }
+}
+
+/// SysTick counter.
+///
+/// While enabled the counter moves one step down for every read of VAL,
+/// reloading from LOAD after reaching zero. `advance` moves it by more steps
+/// at once and `elapsed` tells how many it moved in total.
+#[derive(Default)]
+pub struct SysTick {
+    elapsed: u64,
+}
+
+mod systick {
+    pub const CTRL: usize = 0x0;
+    pub const LOAD: usize = 0x4;
+    pub const VAL: usize = 0x8;
+
+    pub const CTRL_ENABLE: u32 = 1 << 0;
+    pub const CTRL_COUNTFLAG: u32 = 1 << 16;
+}
+
+impl SysTick {
+    pub fn new() -> SysTick {
+        SysTick::default()
+    }
+
+    /// Lets the running counter count `steps` times.
+    pub fn advance(&mut self, regs: &mut Regs, steps: u32) {
+        for _ in 0..steps {
+            if !regs.test(systick::CTRL, systick::CTRL_ENABLE) {
+                break;
+            }
+
+            let val = regs.get(systick::VAL);
+
+            if val == 0 {
+                regs.set(systick::VAL, regs.get(systick::LOAD));
+                regs.check(systick::CTRL, systick::CTRL_COUNTFLAG);
+            } else {
+                regs.set(systick::VAL, val - 1);
+            }
+
+            self.elapsed += 1;
+        }
+    }
+
+    pub fn elapsed(&self) -> u64 {
+        self.elapsed
+    }
+}
+
+impl Model for SysTick {
+    fn read(&mut self, regs: &mut Regs, offset: usize) {
+        if offset == systick::VAL {
+            self.advance(regs, 1);
+        }
+    }
+
+    fn write(&mut self, regs: &mut Regs, offset: usize, _old: u32, _new: u32) {
+        /* Any write clears the counter. */
+        if offset == systick::VAL {
+            regs.set(systick::VAL, 0);
+            regs.uncheck(systick::CTRL, systick::CTRL_COUNTFLAG);
+        }
+    }
+}
+
+/// DWT cycle counter.
+///
+/// While CYCCNTENA is set CYCCNT moves one cycle for every read of it, and
+/// `advance` moves it by more at once.
+#[derive(Default)]
+pub struct Dwt;
+
+mod dwt {
+    pub const CTRL: usize = 0x0;
+    pub const CYCCNT: usize = 0x4;
+
+    pub const CTRL_CYCCNTENA: u32 = 1 << 0;
+}
+
+impl Dwt {
+    pub fn new() -> Dwt {
+        Dwt
+    }
+
+    /// Lets `cycles` core clock cycles pass.
+    pub fn advance(&mut self, regs: &mut Regs, cycles: u32) {
+        if regs.test(dwt::CTRL, dwt::CTRL_CYCCNTENA) {
+            let cyccnt = regs.get(dwt::CYCCNT);
+
+            regs.set(dwt::CYCCNT, cyccnt.wrapping_add(cycles));
+        }
+    }
+}
+
+impl Model for Dwt {
+    fn read(&mut self, regs: &mut Regs, offset: usize) {
+        if offset == dwt::CYCCNT {
+            self.advance(regs, 1);
+        }
+    }
+}
+
+/// Instrumentation trace macrocell.
+///
+/// Stimulus ports read FIFOREADY unless stalled. While ITMENA and the port's
+/// TER bit are set, every write is encoded as an instrumentation packet of
+/// its width and appended to the SWO byte stream, which `take` returns.
+#[derive(Default)]
+pub struct Itm {
+    swo: Vec<u8>,
+    stalled: bool,
+}
+
+mod itm {
+    pub const STIM_END: usize = 0x400;
+    pub const TER: usize = 0xE00;
+    pub const TCR: usize = 0xE80;
+    pub const SIZE: usize = 0xFB8;
+
+    pub const TCR_ITMENA: u32 = 1 << 0;
+}
+
+impl Itm {
+    pub fn new() -> Itm {
+        Itm::default()
+    }
+
+    /// Keeps every FIFO full, or drains them again.
+    pub fn stall(&mut self, stalled: bool) {
+        self.stalled = stalled;
+    }
+
+    /// Takes the bytes sent on SWO so far.
+    pub fn take(&mut self) -> Vec<u8> {
+        ::core::mem::take(&mut self.swo)
+    }
+}
+
+impl Model for Itm {
+    fn size(&self) -> usize {
+        itm::SIZE
+    }
+
+    fn read(&mut self, regs: &mut Regs, offset: usize) {
+        if offset < itm::STIM_END {
+            regs.set(offset, !self.stalled as u32);
+        }
+    }
+
+    fn write_sized(&mut self, regs: &mut Regs, offset: usize, size: usize, _old: u32, new: u32) {
+        if offset >= itm::STIM_END {
+            return;
+        }
+
+        let port = offset / 4;
+
+        if regs.test(itm::TCR, itm::TCR_ITMENA) && regs.test(itm::TER, 1 << port) {
+            let code = match size { 1 => 0b01, 2 => 0b10, _ => 0b11 };
+
+            self.swo.push((port << 3) as u8 | code);
+            self.swo.extend_from_slice(&new.to_le_bytes()[..size]);
+        }
+    }
+}
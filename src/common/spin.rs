@@ -0,0 +1,23 @@
+//! Bounded busy-waiting on status flags.
+
+use common::asm;
+
+/// Polls before a wait gives up. Covers HSE start-up, the slowest flag the
+/// drivers wait on, at the highest core clock with room to spare.
+pub const LIMIT: u32 = 1_000_000;
+
+/// Polls `ready` until it returns `Ok(true)` or an error, at most `LIMIT`
+/// times. Returns `timeout` if the flag never came up.
+pub fn until<E, F>(timeout: E, mut ready: F) -> Result<(), E>
+    where F: FnMut() -> Result<bool, E>
+{
+    for _ in 0..LIMIT {
+        if ready()? {
+            return Ok(());
+        }
+
+        asm::nop();
+    }
+
+    Err(timeout)
+}
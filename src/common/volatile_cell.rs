@@ -1,3 +1,8 @@
+use core::cell::UnsafeCell;
+#[cfg(not(target_os = "none"))]
+use core::mem;
+#[cfg(not(target_os = "none"))]
+use common::mmio;
 use core::ops::{BitOrAssign, BitAndAssign, BitOr, BitAnd, Shl, Shr, Not};
 
 pub trait Zero {
@@ -21,26 +26,34 @@ impl Zero for u32 { fn zero() -> Self { 0 } }
 impl Zero for u64 { fn zero() -> Self { 0 } }
 impl Zero for usize { fn zero() -> Self { 0 } }
 
-#[derive(Copy, Clone)]
 #[repr(C)]
 pub struct VolatileCell<T: Radix> {
-    value: T,
+    value: UnsafeCell<T>,
 }
 
 #[allow(dead_code)]
 impl<T: Radix> VolatileCell<T> {
     pub const fn new(value: T) -> Self {
-        VolatileCell { value }
+        VolatileCell { value: UnsafeCell::new(value) }
     }
 
     #[inline(always)]
     pub fn get(&self) -> T {
-        unsafe { ::core::ptr::read_volatile(&self.value) }
+        #[cfg(not(target_os = "none"))]
+        mmio::host::before_read(self.value.get() as usize, mem::size_of::<T>());
+
+        unsafe { ::core::ptr::read_volatile(self.value.get()) }
     }
 
     #[inline(always)]
     pub fn set(&self, value: T) {
-        unsafe { ::core::ptr::write_volatile(&self.value as *const T as *mut T, value) };
+        #[cfg(not(target_os = "none"))]
+        let old = mmio::host::load(self.value.get() as usize, mem::size_of::<T>());
+
+        unsafe { ::core::ptr::write_volatile(self.value.get(), value) };
+
+        #[cfg(not(target_os = "none"))]
+        mmio::host::after_write(self.value.get() as usize, mem::size_of::<T>(), old);
     }
 
     pub fn update<F: FnOnce(T) -> T>(&self, f: F) {
@@ -54,7 +67,7 @@ impl<T: Radix> VolatileCell<T> {
     }
 
     pub fn mask_get(&self, mask: T, shift: T) -> T {
-        (self.get() & !(mask << shift)) >> shift
+        (self.get() >> shift) & mask
     }
 
     pub fn test(&self, val: T) -> bool {
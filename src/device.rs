@@ -0,0 +1,9 @@
+//! Register blocks generated by the build script from the selected part's vendor SVD in
+//! `svd/vendor/`, with its patch from `svd/patches/` applied.
+//!
+//! One module per peripheral layout, named after its SVD group (`gpio`, `tim`,
+//! `usart`, ...). Each holds the base addresses of the peripherals sharing the
+//! layout, the `Registers` block, a `registers!` declaration per register and
+//! the enumerated field values. Drivers import the module as their `flags`.
+
+include!(concat!(env!("OUT_DIR"), "/device.rs"));
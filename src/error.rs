@@ -0,0 +1,181 @@
+//! Errors returned by the drivers.
+//!
+//! Every driver reports failures through `Error`, with one variant per
+//! peripheral holding what went wrong there. Waits on a status flag are
+//! bounded and give a timeout instead of hanging, hardware error flags seen
+//! during a transfer are reported, and invalid arguments are rejected before
+//! any register is touched.
+
+use core::result;
+
+use embedded_hal::spi;
+use embedded_hal_nb::serial;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    Rcc(RccError),
+    Gpio(GpioError),
+    Spi(SpiError),
+    Uart(UartError),
+    Timer(TimerError),
+    Nvic(NvicError),
+    Mpu(MpuError),
+    Fpu(FpuError),
+    Itm(ItmError),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RccError {
+    /// An oscillator or PLL did not report ready.
+    OscillatorTimeout,
+    /// SWS did not follow the requested system clock source.
+    ClockSwitchTimeout,
+    /// The regulator did not report over-drive ready.
+    OverDriveTimeout,
+    /// An input or requested clock outside what the part supports.
+    FrequencyOutOfRange(u32),
+    /// No PLL setting within the datasheet limits gives the requested
+    /// clocks exactly.
+    NoClockConfig,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GpioError {
+    /// No port with this index on the selected part.
+    InvalidPort(u16),
+    /// Ports have 16 pins.
+    InvalidPin(u16),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SpiError {
+    Timeout,
+    /// OVR, received data was lost.
+    Overrun,
+    /// MODF, NSS was pulled low while in master mode.
+    ModeFault,
+    /// CRCERR, the received CRC did not match.
+    Crc,
+    /// Standard SPI modes go from 0 to 3.
+    InvalidMode(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UartError {
+    Timeout,
+    /// PE
+    Parity,
+    /// FE
+    Framing,
+    /// NF
+    Noise,
+    /// ORE
+    Overrun,
+    /// The baud rate is zero or too high for the bus clock.
+    BaudRate(u32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TimerError {
+    /// The handle is none of the timers of the selected part.
+    UnknownTimer,
+    /// The feature only exists on the advanced timers, TIM1 and TIM8.
+    NotAdvanced,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NvicError {
+    /// More than the preemption bits of the current priority grouping
+    /// hold, or than the 4 implemented priority bits.
+    InvalidPriority(u8),
+    /// More than the sub-priority bits of the current grouping hold.
+    InvalidSubPriority(u8),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MpuError {
+    /// The MPU has 8 regions.
+    InvalidRegion(u8),
+    /// Sizes are a power of two of at least 32 bytes.
+    InvalidSize(u32),
+    /// The base address is not aligned to the size.
+    Misaligned(u32),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FpuError {
+    /// Exception entry doesn't preserve the FPU registers, so the flags of
+    /// the interrupted code can't be cleared.
+    NoPreservation,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ItmError {
+    /// Zero, faster than the core clock, or too slow for the 13-bit SWO
+    /// prescaler.
+    InvalidBaudRate(u32),
+    /// The ITM has 32 stimulus ports.
+    InvalidPort(u8),
+}
+
+impl From<RccError> for Error {
+    fn from(e: RccError) -> Error { Error::Rcc(e) }
+}
+
+impl From<GpioError> for Error {
+    fn from(e: GpioError) -> Error { Error::Gpio(e) }
+}
+
+impl From<SpiError> for Error {
+    fn from(e: SpiError) -> Error { Error::Spi(e) }
+}
+
+impl From<UartError> for Error {
+    fn from(e: UartError) -> Error { Error::Uart(e) }
+}
+
+impl From<TimerError> for Error {
+    fn from(e: TimerError) -> Error { Error::Timer(e) }
+}
+
+impl From<NvicError> for Error {
+    fn from(e: NvicError) -> Error { Error::Nvic(e) }
+}
+
+impl From<MpuError> for Error {
+    fn from(e: MpuError) -> Error { Error::Mpu(e) }
+}
+
+impl From<FpuError> for Error {
+    fn from(e: FpuError) -> Error { Error::Fpu(e) }
+}
+
+impl From<ItmError> for Error {
+    fn from(e: ItmError) -> Error { Error::Itm(e) }
+}
+
+/* Lets device crates written against embedded-hal see what went wrong. */
+
+impl spi::Error for Error {
+    fn kind(&self) -> spi::ErrorKind {
+        match *self {
+            Error::Spi(SpiError::Overrun) => spi::ErrorKind::Overrun,
+            Error::Spi(SpiError::ModeFault) => spi::ErrorKind::ModeFault,
+            _ => spi::ErrorKind::Other,
+        }
+    }
+}
+
+impl serial::Error for Error {
+    fn kind(&self) -> serial::ErrorKind {
+        match *self {
+            Error::Uart(UartError::Overrun) => serial::ErrorKind::Overrun,
+            Error::Uart(UartError::Framing) => serial::ErrorKind::FrameFormat,
+            Error::Uart(UartError::Parity) => serial::ErrorKind::Parity,
+            Error::Uart(UartError::Noise) => serial::ErrorKind::Noise,
+            _ => serial::ErrorKind::Other,
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! DWT cycle counter, for delays and timing measured in core clock cycles.
+//!
+//! CYCCNT is 32 bits wide and wraps after 2^32 cycles, 25 seconds at
+//! 168 MHz. An `Instant` can't measure spans longer than that.
+
+use core::ops::{Add, Sub};
+
+use common::{asm, mmio};
+use common::VolatileCell;
+use rcc;
+
+const DWT_BASE: usize = 0xE000_1000;
+const COREDEBUG_BASE: usize = 0xE000_EDF0;
+
+#[repr(C)]
+struct Dwt {
+    pub ctrl:   VolatileCell<u32>,
+    pub cyccnt: VolatileCell<u32>,
+}
+
+#[repr(C)]
+struct CoreDebug {
+    pub dhcsr: VolatileCell<u32>,
+    pub dcrsr: VolatileCell<u32>,
+    pub dcrdr: VolatileCell<u32>,
+    pub demcr: VolatileCell<u32>,
+}
+
+const CTRL_CYCCNTENA: u32 = 1 << 0;
+/* Powers the DWT and ITM. */
+const DEMCR_TRCENA: u32 = 1 << 24;
+
+fn dwt() -> &'static Dwt {
+    unsafe { mmio::block(DWT_BASE) }
+}
+
+/// Turns on the trace blocks, the DWT and the ITM, in DEMCR.
+pub(crate) fn enable_trace() {
+    let debug: &CoreDebug = unsafe { mmio::block(COREDEBUG_BASE) };
+
+    debug.demcr.set(debug.demcr.get() | DEMCR_TRCENA);
+}
+
+/// Starts the cycle counter from zero.
+pub fn enable() {
+    let dwt = dwt();
+
+    enable_trace();
+    dwt.cyccnt.set(0);
+    dwt.ctrl.set(dwt.ctrl.get() | CTRL_CYCCNTENA);
+}
+
+pub fn disable() {
+    let dwt = dwt();
+
+    dwt.ctrl.set(dwt.ctrl.get() & !CTRL_CYCCNTENA);
+}
+
+pub fn is_enabled() -> bool {
+    dwt().ctrl.get() & CTRL_CYCCNTENA != 0
+}
+
+/// Core clock cycles counted since `enable`, modulo 2^32.
+pub fn cycles() -> u32 {
+    dwt().cyccnt.get()
+}
+
+/// Busy-waits for at least `cycles` core clock cycles, counted by CYCCNT.
+/// Without the counter running this falls back to counting instructions.
+pub fn delay_cycles(cycles: u32) {
+    if !is_enabled() {
+        asm::delay_cycles(cycles);
+        return;
+    }
+
+    let start = Instant::now();
+
+    while start.elapsed().as_cycles() < cycles {}
+}
+
+/// Busy-waits for at least `ns` nanoseconds of the current core clock.
+pub fn delay_ns(ns: u32) {
+    let mut cycles = (ns as u64 * rcc::ahb_frequency() as u64).div_ceil(1_000_000_000);
+
+    /* Stay well clear of the wrap of CYCCNT. */
+    while cycles > 0 {
+        let chunk = cycles.min(1 << 31);
+
+        delay_cycles(chunk as u32);
+        cycles -= chunk;
+    }
+}
+
+/// A point in time, as read from CYCCNT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Instant {
+    cycles: u32,
+}
+
+impl Instant {
+    pub fn now() -> Instant {
+        Instant { cycles: cycles() }
+    }
+
+    /// Time from `earlier` to this instant, assuming CYCCNT wrapped at most
+    /// once in between.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_cycles(self.cycles.wrapping_sub(earlier.cycles))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        Instant { cycles: self.cycles.wrapping_add(duration.cycles) }
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// A span of core clock cycles. The conversions to time use the core clock
+/// at the time of the call.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Duration {
+    cycles: u32,
+}
+
+impl Duration {
+    pub const fn from_cycles(cycles: u32) -> Duration {
+        Duration { cycles }
+    }
+
+    pub fn as_cycles(&self) -> u32 {
+        self.cycles
+    }
+
+    pub fn as_nanos(&self) -> u64 {
+        self.cycles as u64 * 1_000_000_000 / rcc::ahb_frequency() as u64
+    }
+
+    pub fn as_micros(&self) -> u64 {
+        self.cycles as u64 * 1_000_000 / rcc::ahb_frequency() as u64
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        self.cycles as u64 * 1_000 / rcc::ahb_frequency() as u64
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, other: Duration) -> Duration {
+        Duration { cycles: self.cycles.wrapping_add(other.cycles) }
+    }
+}
+
+/// Cycle statistics of a code region run many times, e.g. an interrupt
+/// handler.
+///
+/// ```ignore
+/// let mut profile = Profile::new();
+///
+/// loop {
+///     let _scope = profile.scope();
+///     ...
+/// }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Profile {
+    count: u32,
+    min: u32,
+    max: u32,
+    total: u64,
+}
+
+impl Profile {
+    pub const fn new() -> Profile {
+        Profile { count: 0, min: u32::MAX, max: 0, total: 0 }
+    }
+
+    /// Measures the region from here to the end of the returned scope.
+    pub fn scope(&mut self) -> Scope<'_> {
+        Scope { profile: self, start: Instant::now() }
+    }
+
+    /// Measures `f`.
+    pub fn measure<F: FnOnce() -> R, R>(&mut self, f: F) -> R {
+        let _scope = self.scope();
+
+        f()
+    }
+
+    /// Adds one run of `cycles` cycles.
+    pub fn record(&mut self, cycles: u32) {
+        self.count += 1;
+        self.min = self.min.min(cycles);
+        self.max = self.max.max(cycles);
+        self.total += cycles as u64;
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Fewest cycles of a run, 0 before the first one.
+    pub fn min(&self) -> u32 {
+        if self.count == 0 { 0 } else { self.min }
+    }
+
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// Mean cycles per run, 0 before the first one.
+    pub fn average(&self) -> u32 {
+        if self.count == 0 { 0 } else { (self.total / self.count as u64) as u32 }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Profile::new();
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        Profile::new()
+    }
+}
+
+/// A region being measured by a `Profile`, recorded when dropped.
+pub struct Scope<'a> {
+    profile: &'a mut Profile,
+    start: Instant,
+}
+
+impl<'a> Drop for Scope<'a> {
+    fn drop(&mut self) {
+        let cycles = self.start.elapsed().as_cycles();
+
+        self.profile.record(cycles);
+    }
+}
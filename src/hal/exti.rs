@@ -1,5 +1,7 @@
 use core::ops::Deref;
 use common::VolatileCell;
+use common::mmio;
+use common::bitband;
 
 const EXTI: Exti = Exti { addr: 0x4001_3C00 as *const Registers };
 
@@ -20,7 +22,7 @@ impl Deref for Exti {
     type Target = Registers;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.addr }
+        unsafe { mmio::block(self.addr as usize) }
     }
 }
 
@@ -47,14 +49,17 @@ pub struct Registers {
     pub pr:    VolatileCell<u32>,
 }
 
+/* IMR and EMR are shared by every line and get reconfigured from interrupt
+   handlers, so the masks are updated atomically. */
+
 pub fn enable(mask: u32) {
-    EXTI.imr.check(mask);
-    EXTI.emr.check(mask);
+    bitband::check(&EXTI.imr, mask);
+    bitband::check(&EXTI.emr, mask);
 }
 
 pub fn disable(mask: u32) {
-    EXTI.imr.uncheck(mask);
-    EXTI.emr.uncheck(mask);
+    bitband::uncheck(&EXTI.imr, mask);
+    bitband::uncheck(&EXTI.emr, mask);
 }
 
 pub fn trigger_off(mask: u32) {
@@ -0,0 +1,295 @@
+//! Fault decoding.
+//!
+//! Out of reset every fault escalates to HardFault; `enable` gives
+//! MemManage, BusFault and UsageFault their own handlers. Unless the
+//! application binds them itself, `link.x` points all four at a handler that
+//! takes the exception frame stacked by the faulting code, decodes the fault
+//! status registers into a `FaultReport` and passes both to the hook set with
+//! `set_hook`. A fault can't be returned from, the handler parks the core
+//! once the hook returns.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use hal::scb;
+#[cfg(target_os = "none")]
+use hal::scb::ICSR_VECTACTIVE;
+
+/// Registers the core pushes on exception entry.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ExceptionFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HardFaultReason {
+    /// A vector table read failed during exception processing.
+    VectorTable,
+    /// A breakpoint or watchpoint with no debugger to halt.
+    Debug,
+    Unknown,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemManageReason {
+    /// Instruction fetch from a region without execute permission.
+    InstructionAccess,
+    /// Load or store the MPU doesn't allow.
+    DataAccess,
+    /// Unstacking on exception return.
+    Unstacking,
+    /// Stacking on exception entry, e.g. a stack overflow into a guard region.
+    Stacking,
+    /// Lazy stacking of the FPU registers.
+    LazyFpStacking,
+    Unknown,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BusFaultReason {
+    InstructionBus,
+    /// The faulting instruction is the stacked PC.
+    PreciseData,
+    /// The stacked PC is somewhere after the faulting instruction.
+    ImpreciseData,
+    Unstacking,
+    Stacking,
+    LazyFpStacking,
+    Unknown,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum UsageFaultReason {
+    UndefinedInstruction,
+    /// Execution in ARM state, or any other invalid EPSR.
+    InvalidState,
+    /// Invalid EXC_RETURN on exception return.
+    InvalidPc,
+    /// Coprocessor instruction with the coprocessor off, e.g. the FPU.
+    NoCoprocessor,
+    Unaligned,
+    DivideByZero,
+    Unknown,
+}
+
+/// What went wrong and where. A HardFault escalated from a configurable
+/// fault is reported as that fault.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultReport {
+    HardFault { pc: u32, reason: HardFaultReason },
+    /// `address` is the faulting data address, when MMFAR holds it.
+    MemManage { pc: u32, reason: MemManageReason, address: Option<u32> },
+    /// `address` is the faulting data address, when BFAR holds it.
+    BusFault { pc: u32, reason: BusFaultReason, address: Option<u32> },
+    UsageFault { pc: u32, reason: UsageFaultReason },
+}
+
+impl FaultReport {
+    /// Address of the faulting instruction, or the one after it for
+    /// imprecise bus faults.
+    pub fn pc(&self) -> u32 {
+        match *self {
+            FaultReport::HardFault { pc, .. } |
+            FaultReport::MemManage { pc, .. } |
+            FaultReport::BusFault { pc, .. } |
+            FaultReport::UsageFault { pc, .. } => pc,
+        }
+    }
+}
+
+/// Called with the report and the stacked registers of every fault.
+pub type Hook = fn(&FaultReport, &ExceptionFrame);
+
+const SHCSR_MEMFAULTENA: u32 = 1 << 16;
+const SHCSR_BUSFAULTENA: u32 = 1 << 17;
+const SHCSR_USGFAULTENA: u32 = 1 << 18;
+
+const CFSR_MMFSR: u32 = 0x0000_00FF;
+const CFSR_BFSR: u32 = 0x0000_FF00;
+const CFSR_UFSR: u32 = 0xFFFF_0000;
+const CFSR_MMARVALID: u32 = 1 << 7;
+const CFSR_BFARVALID: u32 = 1 << 15;
+
+const HFSR_VECTTBL: u32 = 1 << 1;
+const HFSR_FORCED: u32 = 1 << 30;
+const HFSR_DEBUGEVT: u32 = 1 << 31;
+
+const MEM_MANAGE: [(u32, MemManageReason); 5] = [
+    (1 << 0, MemManageReason::InstructionAccess),
+    (1 << 1, MemManageReason::DataAccess),
+    (1 << 3, MemManageReason::Unstacking),
+    (1 << 4, MemManageReason::Stacking),
+    (1 << 5, MemManageReason::LazyFpStacking),
+];
+
+const BUS_FAULT: [(u32, BusFaultReason); 6] = [
+    (1 << 8,  BusFaultReason::InstructionBus),
+    (1 << 9,  BusFaultReason::PreciseData),
+    (1 << 10, BusFaultReason::ImpreciseData),
+    (1 << 11, BusFaultReason::Unstacking),
+    (1 << 12, BusFaultReason::Stacking),
+    (1 << 13, BusFaultReason::LazyFpStacking),
+];
+
+const USAGE_FAULT: [(u32, UsageFaultReason); 6] = [
+    (1 << 16, UsageFaultReason::UndefinedInstruction),
+    (1 << 17, UsageFaultReason::InvalidState),
+    (1 << 18, UsageFaultReason::InvalidPc),
+    (1 << 19, UsageFaultReason::NoCoprocessor),
+    (1 << 24, UsageFaultReason::Unaligned),
+    (1 << 25, UsageFaultReason::DivideByZero),
+];
+
+/// Vector numbers of the fault exceptions.
+const VECTOR_HARD_FAULT: u32 = 3;
+const VECTOR_MEM_MANAGE: u32 = 4;
+const VECTOR_BUS_FAULT: u32 = 5;
+const VECTOR_USAGE_FAULT: u32 = 6;
+
+static HOOK: AtomicUsize = AtomicUsize::new(0);
+
+/// Gives MemManage, BusFault and UsageFault their own handlers instead of
+/// escalating them to HardFault.
+pub fn enable() {
+    let shcsr = &scb::scb().shcsr;
+
+    shcsr.set(shcsr.get() | SHCSR_MEMFAULTENA | SHCSR_BUSFAULTENA | SHCSR_USGFAULTENA);
+}
+
+/// Sets the hook the fault handler reports to.
+pub fn set_hook(hook: Hook) {
+    HOOK.store(hook as usize, Ordering::SeqCst);
+}
+
+fn hook() -> Option<Hook> {
+    match HOOK.load(Ordering::SeqCst) {
+        0 => None,
+        hook => Some(unsafe { ::core::mem::transmute::<usize, Hook>(hook) }),
+    }
+}
+
+fn reason<R: Copy>(cfsr: u32, reasons: &[(u32, R)], unknown: R) -> R {
+    reasons.iter().find(|r| cfsr & r.0 != 0).map(|r| r.1).unwrap_or(unknown)
+}
+
+fn configurable(vector: u32, pc: u32, cfsr: u32) -> Option<FaultReport> {
+    let scb = scb::scb();
+
+    let report = match vector {
+        VECTOR_MEM_MANAGE => FaultReport::MemManage {
+            pc,
+            reason: reason(cfsr, &MEM_MANAGE, MemManageReason::Unknown),
+            address: if cfsr & CFSR_MMARVALID != 0 { Some(scb.mmfar.get()) } else { None },
+        },
+        VECTOR_BUS_FAULT => FaultReport::BusFault {
+            pc,
+            reason: reason(cfsr, &BUS_FAULT, BusFaultReason::Unknown),
+            address: if cfsr & CFSR_BFARVALID != 0 { Some(scb.bfar.get()) } else { None },
+        },
+        VECTOR_USAGE_FAULT => FaultReport::UsageFault {
+            pc,
+            reason: reason(cfsr, &USAGE_FAULT, UsageFaultReason::Unknown),
+        },
+        _ => return None,
+    };
+
+    Some(report)
+}
+
+/// Decodes the fault status registers for the fault handled by the
+/// exception `vector`, 3 to 6, and the frame it stacked.
+pub fn decode(vector: u32, frame: &ExceptionFrame) -> FaultReport {
+    let scb = scb::scb();
+    let cfsr = scb.cfsr.get();
+    let hfsr = scb.hfsr.get();
+    let pc = frame.pc;
+
+    if let Some(report) = configurable(vector, pc, cfsr) {
+        return report;
+    }
+
+    let reason = if hfsr & HFSR_VECTTBL != 0 {
+        HardFaultReason::VectorTable
+    } else if hfsr & HFSR_FORCED != 0 {
+        let escalated = if cfsr & CFSR_MMFSR != 0 {
+            VECTOR_MEM_MANAGE
+        } else if cfsr & CFSR_BFSR != 0 {
+            VECTOR_BUS_FAULT
+        } else if cfsr & CFSR_UFSR != 0 {
+            VECTOR_USAGE_FAULT
+        } else {
+            VECTOR_HARD_FAULT
+        };
+
+        match configurable(escalated, pc, cfsr) {
+            Some(report) => return report,
+            None => HardFaultReason::Unknown,
+        }
+    } else if hfsr & HFSR_DEBUGEVT != 0 {
+        HardFaultReason::Debug
+    } else {
+        HardFaultReason::Unknown
+    };
+
+    FaultReport::HardFault { pc, reason }
+}
+
+/// Clears the sticky fault status bits, so the next fault is decoded on
+/// its own.
+pub fn clear() {
+    let scb = scb::scb();
+
+    scb.cfsr.set(scb.cfsr.get());
+    scb.hfsr.set(scb.hfsr.get());
+}
+
+/// What the default fault handler does for the fault of exception `vector`:
+/// decodes it, clears the status bits and reports to the hook. For fault
+/// handlers bound by the application.
+pub fn handle(vector: u32, frame: &ExceptionFrame) -> FaultReport {
+    let report = decode(vector, frame);
+
+    clear();
+
+    if let Some(hook) = hook() {
+        hook(&report, frame);
+    }
+
+    report
+}
+
+#[cfg(target_os = "none")]
+extern "C" fn entry(frame: &ExceptionFrame) -> ! {
+    handle(scb::scb().icsr.get() & ICSR_VECTACTIVE, frame);
+
+    loop {
+        ::common::asm::bkpt();
+    }
+}
+
+/// Handler of the fault exceptions the application doesn't bind. Passes the
+/// frame from the stack the faulting code ran on to `entry`.
+///
+/// # Safety
+///
+/// Only the core may call this, as a fault handler.
+#[cfg(target_os = "none")]
+#[unsafe(naked)]
+#[no_mangle]
+pub unsafe extern "C" fn FaultHandler_() {
+    ::core::arch::naked_asm!(
+        "tst lr, #4",
+        "ite eq",
+        "mrseq r0, msp",
+        "mrsne r0, psp",
+        "b {entry}",
+        entry = sym entry,
+    )
+}
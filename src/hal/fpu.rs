@@ -0,0 +1,262 @@
+//! Floating-point unit: access enable, context preservation and the
+//! cumulative exception flags.
+//!
+//! The Reset handler of `rt` enables the FPU before `main`, so code built for
+//! eabihf can use it from the start.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{asm, mmio};
+use common::VolatileCell;
+use error::{FpuError, Result};
+use hal::nvic::{self, NvicIdx};
+use hal::scb;
+
+const FPU_BASE: usize = 0xE000_EF34;
+
+#[repr(C)]
+struct Fpu {
+    pub fpccr:  VolatileCell<u32>,
+    pub fpcar:  VolatileCell<u32>,
+    pub fpdscr: VolatileCell<u32>,
+}
+
+/* Full access for CP10 and CP11. */
+const CPACR_FPU: u32 = 0b1111 << 20;
+
+const FPCCR_LSPEN: u32 = 1 << 30;
+const FPCCR_ASPEN: u32 = 1 << 31;
+
+const FPSCR_IOC: u32 = 1 << 0;
+const FPSCR_DZC: u32 = 1 << 1;
+const FPSCR_OFC: u32 = 1 << 2;
+const FPSCR_UFC: u32 = 1 << 3;
+const FPSCR_IXC: u32 = 1 << 4;
+const FPSCR_IDC: u32 = 1 << 7;
+const FPSCR_FLAGS: u32 = FPSCR_IOC | FPSCR_DZC | FPSCR_OFC | FPSCR_UFC | FPSCR_IXC | FPSCR_IDC;
+/* The flags `report_exceptions` passes on, not inexact and underflow. */
+const FPSCR_ERRORS: u32 = FPSCR_IOC | FPSCR_DZC | FPSCR_OFC | FPSCR_IDC;
+
+/// FPSCR sits after S0 to S15 in the extended exception frame.
+#[cfg(target_os = "none")]
+const FRAME_FPSCR: u32 = 0x40;
+
+/// Clear in EXC_RETURN when the interrupted code's FPU registers were
+/// stacked, or space was reserved for them.
+#[cfg(target_os = "none")]
+const EXC_RETURN_STANDARD_FRAME: u32 = 1 << 4;
+
+fn fpu() -> &'static Fpu {
+    unsafe { mmio::block(FPU_BASE) }
+}
+
+/// Gives privileged and unprivileged code access to the FPU.
+pub fn enable() {
+    let cpacr = &scb::scb().cpacr;
+
+    cpacr.set(cpacr.get() | CPACR_FPU);
+    asm::dsb();
+    asm::isb();
+}
+
+/// Takes the FPU away, float instructions raise a NOCP UsageFault.
+pub fn disable() {
+    let cpacr = &scb::scb().cpacr;
+
+    cpacr.set(cpacr.get() & !CPACR_FPU);
+    asm::dsb();
+    asm::isb();
+}
+
+pub fn is_enabled() -> bool {
+    scb::scb().cpacr.get() & CPACR_FPU == CPACR_FPU
+}
+
+/// How the FPU registers of interrupted code are saved on exception entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Preservation {
+    /// Not at all, handlers must not use the FPU.
+    None,
+    /// Always stacked along with the other registers.
+    Automatic,
+    /// Space is reserved, the registers are only stacked if the handler uses
+    /// the FPU. The reset default.
+    Lazy,
+}
+
+pub fn set_preservation(preservation: Preservation) {
+    let fpccr = &fpu().fpccr;
+    let bits = match preservation {
+        Preservation::None => 0,
+        Preservation::Automatic => FPCCR_ASPEN,
+        Preservation::Lazy => FPCCR_ASPEN | FPCCR_LSPEN,
+    };
+
+    fpccr.set((fpccr.get() & !(FPCCR_ASPEN | FPCCR_LSPEN)) | bits);
+}
+
+pub fn preservation() -> Preservation {
+    let fpccr = fpu().fpccr.get();
+
+    match (fpccr & FPCCR_ASPEN != 0, fpccr & FPCCR_LSPEN != 0) {
+        (false, _) => Preservation::None,
+        (true, false) => Preservation::Automatic,
+        (true, true) => Preservation::Lazy,
+    }
+}
+
+/// Cumulative exception flags of FPSCR, set by any operation since they
+/// were last cleared.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct Flags {
+    pub invalid_operation: bool,
+    pub divide_by_zero: bool,
+    pub overflow: bool,
+    pub underflow: bool,
+    pub inexact: bool,
+    pub input_denormal: bool,
+}
+
+impl Flags {
+    fn from_fpscr(fpscr: u32) -> Flags {
+        Flags {
+            invalid_operation: fpscr & FPSCR_IOC != 0,
+            divide_by_zero: fpscr & FPSCR_DZC != 0,
+            overflow: fpscr & FPSCR_OFC != 0,
+            underflow: fpscr & FPSCR_UFC != 0,
+            inexact: fpscr & FPSCR_IXC != 0,
+            input_denormal: fpscr & FPSCR_IDC != 0,
+        }
+    }
+
+    pub fn any(&self) -> bool {
+        *self != Flags::default()
+    }
+}
+
+pub fn flags() -> Flags {
+    Flags::from_fpscr(asm::fpscr())
+}
+
+pub fn clear_flags() {
+    unsafe { asm::set_fpscr(asm::fpscr() & !FPSCR_FLAGS) };
+}
+
+/// Called with the flags that raised the FPU interrupt, never with only
+/// `underflow` or `inexact`.
+pub type Hook = fn(Flags);
+
+static HOOK: AtomicUsize = AtomicUsize::new(0);
+
+fn hook() -> Option<Hook> {
+    match HOOK.load(Ordering::SeqCst) {
+        0 => None,
+        hook => Some(unsafe { ::core::mem::transmute::<usize, Hook>(hook) }),
+    }
+}
+
+/// Reports floating-point errors through the FPU interrupt: points its vector
+/// at a handler that passes the flags to `hook`, and enables the interrupt.
+///
+/// The F4 raises the interrupt for any cumulative flag and none of them can
+/// be masked, so it fires on nearly every inexact result, that is most float
+/// arithmetic. The handler clears inexact and underflow without calling
+/// `hook`, which only hears of invalid operations, divisions by zero,
+/// overflows and denormal inputs. The cost of an interrupt per inexact
+/// operation stays, so this is a debugging aid, not for production code.
+///
+/// The handler clears the flags in the FPSCR the interrupted code returns
+/// to, which the core only stacks with automatic or lazy preservation.
+pub fn report_exceptions(hook: Hook) -> Result<()> {
+    if preservation() == Preservation::None {
+        return Err(FpuError::NoPreservation.into());
+    }
+
+    HOOK.store(hook as usize, Ordering::SeqCst);
+
+    register();
+    nvic::enable(NvicIdx::FPU);
+
+    Ok(())
+}
+
+#[cfg(target_os = "none")]
+fn register() {
+    nvic::register_vector(NvicIdx::FPU, entry);
+}
+
+/* The host has no exception entry, `nvic::host::raise` calls the handler. */
+#[cfg(not(target_os = "none"))]
+fn register() {
+    nvic::register(NvicIdx::FPU, &|| exception(false));
+}
+
+/// Vector of the FPU interrupt, passes EXC_RETURN on to `handle`.
+///
+/// # Safety
+///
+/// Only the core may call this, as an exception handler.
+#[cfg(target_os = "none")]
+#[unsafe(naked)]
+unsafe extern "C" fn entry() {
+    ::core::arch::naked_asm!(
+        "mov r0, lr",
+        "b {handle}",
+        handle = sym handle,
+    )
+}
+
+#[cfg(target_os = "none")]
+extern "C" fn handle(exc_return: u32) {
+    exception(exc_return & EXC_RETURN_STANDARD_FRAME == 0);
+}
+
+/// Reads and clears the flags in the FPSCR of the interrupted code: the one
+/// the core stacked on entry with an `extended` frame, the live one
+/// otherwise.
+#[cfg(target_os = "none")]
+fn take_stacked_flags(extended: bool) -> u32 {
+    /* FPCAR still points at an older frame, maybe one in use. */
+    if !extended {
+        return take_live_flags();
+    }
+
+    /* Reading FPSCR makes the core finish lazy stacking. */
+    let _ = asm::fpscr();
+    asm::dsb();
+
+    let stacked = (fpu().fpcar.get() + FRAME_FPSCR) as *mut u32;
+
+    unsafe {
+        let fpscr = ::core::ptr::read_volatile(stacked);
+        ::core::ptr::write_volatile(stacked, fpscr & !FPSCR_FLAGS);
+        fpscr
+    }
+}
+
+/* The host has no exception frame, the handler sees the thread's FPSCR. */
+#[cfg(not(target_os = "none"))]
+fn take_stacked_flags(_extended: bool) -> u32 {
+    take_live_flags()
+}
+
+fn take_live_flags() -> u32 {
+    let fpscr = asm::fpscr();
+
+    unsafe { asm::set_fpscr(fpscr & !FPSCR_FLAGS) };
+    fpscr
+}
+
+/* The flags stay set, keeping the interrupt raised, until cleared where the
+   interrupted code returns to. */
+fn exception(extended: bool) {
+    let fpscr = take_stacked_flags(extended) & FPSCR_ERRORS;
+
+    if fpscr == 0 {
+        return;
+    }
+
+    if let Some(hook) = hook() {
+        hook(Flags::from_fpscr(fpscr));
+    }
+}
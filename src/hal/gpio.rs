@@ -1,42 +1,72 @@
+use core::convert::Infallible;
+use core::result;
 use core::ops::Deref;
-use common::VolatileCell;
-
-const GPIO_BASE: usize = 0x4002_0000;
-const SIZE: usize = 0x00000400;
-
-pub const GPIOA: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 0) as *const Registers };
-pub const GPIOB: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 1) as *const Registers };
-pub const GPIOC: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 2) as *const Registers };
-pub const GPIOD: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 3) as *const Registers };
-pub const GPIOE: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 4) as *const Registers };
-pub const GPIOF: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 5) as *const Registers };
-pub const GPIOG: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 6) as *const Registers };
-pub const GPIOH: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 7) as *const Registers };
-pub const GPIOI: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 8) as *const Registers };
-pub const GPIOJ: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 9) as *const Registers };
-pub const GPIOK: Gpio = Gpio { regs: (GPIO_BASE + SIZE * 10) as *const Registers };
-
-pub fn get_port_by_index(index: u16) -> Gpio {
-    match index {
+use common::mmio;
+
+use error::{GpioError, Result};
+use embedded_hal::digital;
+
+use device::gpio as flags;
+use self::flags::Registers;
+
+pub use device::gpio::{PinMode, OutputType, OutputSpeed, PuPdMode, PeripheralFunction};
+
+pub(crate) const GPIOA: Gpio = Gpio::new(flags::GPIOA);
+pub(crate) const GPIOB: Gpio = Gpio::new(flags::GPIOB);
+pub(crate) const GPIOC: Gpio = Gpio::new(flags::GPIOC);
+pub(crate) const GPIOD: Gpio = Gpio::new(flags::GPIOD);
+pub(crate) const GPIOE: Gpio = Gpio::new(flags::GPIOE);
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const GPIOF: Gpio = Gpio::new(flags::GPIOF);
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const GPIOG: Gpio = Gpio::new(flags::GPIOG);
+pub(crate) const GPIOH: Gpio = Gpio::new(flags::GPIOH);
+#[cfg(any(feature = "stm32f405", feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
+pub(crate) const GPIOI: Gpio = Gpio::new(flags::GPIOI);
+#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+pub(crate) const GPIOJ: Gpio = Gpio::new(flags::GPIOJ);
+#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+pub(crate) const GPIOK: Gpio = Gpio::new(flags::GPIOK);
+
+/// Returns the port with the given index, GPIOA being 0.
+///
+/// # Safety
+///
+/// The returned handle aliases the one held by `Peripherals`, the caller must
+/// make sure the two are not used to reconfigure the port at the same time.
+pub unsafe fn get_port_by_index(index: u16) -> Result<Gpio> {
+    Ok(match index {
         0 => GPIOA,
         1 => GPIOB,
         2 => GPIOC,
         3 => GPIOD,
         4 => GPIOE,
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         5 => GPIOF,
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         6 => GPIOG,
         7 => GPIOH,
+        #[cfg(any(feature = "stm32f405", feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
         8 => GPIOI,
+        #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
         9 => GPIOJ,
+        #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
         10 => GPIOK,
-        _ => panic!("Port not found!")
-    }
+        _ => return Err(GpioError::InvalidPort(index).into())
+    })
 }
 
-mod flags {
-    pub const LCKK: u32 = 1 << 16;
+fn check_pin(pin: u16) -> Result<()> {
+    if pin > 15 {
+        return Err(GpioError::InvalidPin(pin).into());
+    }
+
+    Ok(())
 }
 
+/// Alternate function numbers of the peripherals the selected part has. A few
+/// pins route a peripheral through another function than the one given here,
+/// check the alternate function table of the datasheet.
 pub mod function {
     use super::PeripheralFunction as PF;
 
@@ -50,6 +80,7 @@ pub mod function {
     pub const TIM3: PF  = PF::AF2;
     pub const TIM4: PF  = PF::AF2;
     pub const TIM5: PF  = PF::AF2;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const TIM8: PF  = PF::AF3;
     pub const TIM9: PF  = PF::AF3;
     pub const TIM10: PF = PF::AF3;
@@ -60,45 +91,60 @@ pub mod function {
     pub const SPI1: PF  = PF::AF5;
     pub const SPI2: PF  = PF::AF5;
     pub const SPI3: PF  = PF::AF6;
+    #[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
+    pub const SPI4: PF  = PF::AF5;
+    #[cfg(feature = "stm32f411")]
+    pub const SPI5: PF  = PF::AF6;
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub const SPI5: PF  = PF::AF5;
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub const SPI6: PF  = PF::AF5;
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+    pub const SAI1: PF  = PF::AF6;
     pub const USART1: PF = PF::AF7;
     pub const USART2: PF = PF::AF7;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const USART3: PF = PF::AF7;
     pub const I2S3_EXT: PF = PF::AF7;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const UART4: PF = PF::AF8;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const UART5: PF = PF::AF8;
     pub const USART6: PF = PF::AF8;
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub const UART7: PF = PF::AF8;
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub const UART8: PF = PF::AF8;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const CAN1: PF  = PF::AF9;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const CAN2: PF  = PF::AF9;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const TIM12: PF = PF::AF9;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const TIM13: PF = PF::AF9;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const TIM14: PF = PF::AF9;
     pub const OTG_FS: PF = PF::AF10;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const OTG_HS: PF = PF::AF10;
+    #[cfg(any(feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
     pub const ETH: PF    = PF::AF11;
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
     pub const FMC: PF    = PF::AF12;
+    #[cfg(any(feature = "stm32f405", feature = "stm32f407"))]
     pub const FSMC: PF   = PF::AF12;
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
     pub const OTG_HS_FS: PF = PF::AF12;
     pub const SDIO: PF   = PF::AF12;
+    #[cfg(any(feature = "stm32f407", feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
     pub const DCMI: PF   = PF::AF13;
+    #[cfg(feature = "stm32f429")]
+    pub const LTDC: PF   = PF::AF14;
     pub const EVENTOUT: PF   = PF::AF15;
 }
 
-#[repr(C)]
-pub struct Registers {
-    pub moder:   VolatileCell<u32>,
-    pub otyper:  VolatileCell<u32>,
-    pub ospeedr: VolatileCell<u32>,
-    pub pupdr:   VolatileCell<u32>,
-    pub idr:     VolatileCell<u32>,
-    pub odr:     VolatileCell<u32>,
-    pub bsr:     VolatileCell<u16>,
-    pub brr:     VolatileCell<u16>,
-    pub lckr:    VolatileCell<u32>,
-    pub afrl:    VolatileCell<u32>,
-    pub afrh:    VolatileCell<u32>
-}
-
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(PartialEq, Eq)]
 pub struct Gpio {
     regs: *const Registers
 }
@@ -115,24 +161,23 @@ impl Deref for Gpio {
     type Target = Registers;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { &*self.regs }
+        unsafe { mmio::block(self.regs as usize) }
     }
 }
 
 impl Gpio {
     pub fn set_high(&self, pins: u16) {
-        self.bsr.check(pins);
+        self.bsrr.set(pins as u32);
     }
 
     pub fn set_low(&self, pins: u16) {
-        self.brr.check(pins);
+        self.bsrr.set((pins as u32) << 16);
     }
 
     pub fn toggle(&self, pins: u16) {
         let port = self.odr.get() as u16;
 
-        self.bsr.check(!port & pins);
-        self.brr.check(port & pins);
+        self.bsrr.set(((port & pins) as u32) << 16 | (!port & pins) as u32);
     }
 
     pub fn write(&self, value: u16) {
@@ -147,103 +192,118 @@ impl Gpio {
         (self.idr.get() as u16) & pins
     }
 
-    pub fn read_pin(&self, pin: u16) -> bool {
-        (self.idr.get() as u16) & (1u16 << pin) != 0
+    pub fn read_pin(&self, pin: u16) -> Result<bool> {
+        check_pin(pin)?;
+
+        Ok((self.idr.get() as u16) & (1u16 << pin) != 0)
     }
 
     pub fn lock(&self, pins: u16) {
-        let mut reg32;
-
         /* Special "Lock Key Writing Sequence", see datasheet. */
-        self.lckr.set(flags::LCKK | pins as u32);	/* Set LCKK. */
-        self.lckr.set(!flags::LCKK & pins as u32);	/* Clear LCKK. */
-        self.lckr.set(flags::LCKK | pins as u32);	/* Set LCKK. */
+        self.lckr.write(|w| w.bits(pins as u32).lckk(true));	/* Set LCKK. */
+        self.lckr.write(|w| w.bits(pins as u32).lckk(false));	/* Clear LCKK. */
+        self.lckr.write(|w| w.bits(pins as u32).lckk(true));	/* Set LCKK. */
 
-        reg32 = self.lckr.get();			/* Read LCKK. */
-        reg32 = self.lckr.get();			/* Read LCKK again. */
+        self.lckr.get();			/* Read LCKK. */
+        self.lckr.get();			/* Read LCKK again. */
+    }
 
-        /* Tell the compiler the variable is actually used. It will get
-        * optimized out anyways.
-        */
+    pub fn set_pin_output_speed(&self, pin: u16, speed: OutputSpeed) -> Result<()> {
+        check_pin(pin)?;
 
-        reg32 = reg32;
+        self.ospeedr.set_atomic(flags::ospeedr::OSPEEDR0.nth(pin as u32, 2), speed);
+
+        Ok(())
     }
 
-    pub fn set_pin_output_speed(&self, pin: u16, speed: OutputSpeed) {
-        self.ospeedr.mask_set(0b11, (pin as u32) << 1, speed as u32);
+    pub fn set_pin_output_type(&self, pin: u16, mode: OutputType) -> Result<()> {
+        check_pin(pin)?;
+
+        self.otyper.set_atomic(flags::otyper::OT0.nth(pin as u32, 1), mode);
+
+        Ok(())
     }
 
-    pub fn set_pin_output_type(&self, pin: u16, mode: OutputType) {
-        match mode {
-            OutputType::PushPull => self.otyper.uncheck(pin as u32),
-            OutputType::OpenDrain => self.otyper.check(pin as u32)
-        };
+    pub fn set_pin_mode(&self, pin: u16, mode: PinMode) -> Result<()> {
+        check_pin(pin)?;
+
+        self.moder.set_atomic(flags::moder::MODER0.nth(pin as u32, 2), mode);
+
+        Ok(())
     }
 
-    pub fn set_pin_mode(&self, pin: u16, mode: PinMode) {
-        self.moder.mask_set(0b11, (pin as u32) << 1, mode as u32);
+    pub fn set_pin_pupd(&self, pin: u16, pupd: PuPdMode) -> Result<()> {
+        check_pin(pin)?;
+
+        self.pupdr.set_atomic(flags::pupdr::PUPDR0.nth(pin as u32, 2), pupd);
+
+        Ok(())
     }
 
-    pub fn set_pin_pupd(&self, pin: u16, pupd: PuPdMode) {
-        self.pupdr.mask_set(0b11, (pin as u32) << 1, pupd as u32);
+    /// A single pin of the port, for drivers written against the
+    /// embedded-hal digital traits.
+    pub fn pin(&self, pin: u16) -> Result<Pin<'_>> {
+        check_pin(pin)?;
+
+        Ok(Pin { port: self, mask: 1 << pin })
     }
 
-    pub fn set_pin_af(&self, pin: u16, af: PeripheralFunction) {
-        let reg = if pin > 7 {
-            &self.afrh
+    pub fn set_pin_af(&self, pin: u16, af: PeripheralFunction) -> Result<()> {
+        check_pin(pin)?;
+
+        if pin > 7 {
+            self.afrh.set_atomic(flags::afrh::AFRH8.nth(pin as u32 - 8, 4), af);
         } else {
-            &self.afrl
-        };
+            self.afrl.set_atomic(flags::afrl::AFRL0.nth(pin as u32, 4), af);
+        }
 
-        reg.mask_set(0b1111, pin as u32 % 8, af as u32);
+        Ok(())
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum PuPdMode {
-    None = 0x0,
-    Up   = 0x1,
-    Down = 0x2,
+/// One pin of a port. Configuring the pin is still done on the port.
+pub struct Pin<'a> {
+    port: &'a Gpio,
+    mask: u16,
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum PinMode {
-    Input  = 0x0,
-    Output = 0x1,
-    AF     = 0x2,
-    Analog = 0x3
+impl<'a> digital::ErrorType for Pin<'a> {
+    type Error = Infallible;
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum OutputSpeed {
-    Low,
-    Medium,
-    Fast,
-    High
+impl<'a> digital::OutputPin for Pin<'a> {
+    fn set_low(&mut self) -> result::Result<(), Infallible> {
+        self.port.set_low(self.mask);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> result::Result<(), Infallible> {
+        self.port.set_high(self.mask);
+        Ok(())
+    }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum OutputType {
-    PushPull,
-    OpenDrain
+impl<'a> digital::StatefulOutputPin for Pin<'a> {
+    fn is_set_high(&mut self) -> result::Result<bool, Infallible> {
+        Ok(self.port.odr.get() as u16 & self.mask != 0)
+    }
+
+    fn is_set_low(&mut self) -> result::Result<bool, Infallible> {
+        Ok(self.port.odr.get() as u16 & self.mask == 0)
+    }
+
+    fn toggle(&mut self) -> result::Result<(), Infallible> {
+        self.port.toggle(self.mask);
+        Ok(())
+    }
 }
 
-#[derive(Copy,Clone)]
-pub enum PeripheralFunction {
-    AF0  = 0x0, // RTC_50Hz; MCO; TAMPER; SWJ; TRACE
-    AF1  = 0x1, // TIM1; TIM2
-    AF2  = 0x2, // TIM3; TIM4; TIM5
-    AF3  = 0x3, // TIM8; TIM9; TIM10; TIM11
-    AF4  = 0x4, // I2C1; I2C2; I2C3
-    AF5  = 0x5, // SPI1; SPI2
-    AF6  = 0x6, // SPI3
-    AF7  = 0x7, // USART1; USART2; USART3; I2S3ext
-    AF8  = 0x8, // UART4; UART5; USART6
-    AF9  = 0x9, // CAN1; CAN2; TIM12; TIM13; TIM14
-    AF10 = 0xa, // OTG_FS; OTG_HS
-    AF11 = 0xb, // ETH
-    AF12 = 0xc, // FSMC; OTG_HS_FS; SDIO
-    AF13 = 0xd, // DCMI
-    AF14 = 0xe, //
-    AF15 = 0xf  // EVENTOUT
-}
\ No newline at end of file
+impl<'a> digital::InputPin for Pin<'a> {
+    fn is_high(&mut self) -> result::Result<bool, Infallible> {
+        Ok(self.port.read_pins(self.mask) != 0)
+    }
+
+    fn is_low(&mut self) -> result::Result<bool, Infallible> {
+        Ok(self.port.read_pins(self.mask) == 0)
+    }
+}
@@ -0,0 +1,355 @@
+//! ITM stimulus ports, traced out of the SWO pin.
+//!
+//! `enable` sets up the TPIU for asynchronous NRZ output at the given baud
+//! rate of the current core clock, so call it again after `rcc::set_clock`.
+//! PB3 is TRACESWO out of reset. Writes to a stimulus port become packets on
+//! SWO, which a debug probe decodes; a write while the port's FIFO is full is
+//! lost, so the writes here wait for it or report `WouldBlock`. They are
+//! dropped while the ITM or the port is off, which makes logging through a
+//! `Port` safe without a probe attached.
+//!
+//! ```ignore
+//! itm::enable(2_000_000)?;
+//! itm::enable_port(0)?;
+//! writeln!(itm::port(0)?, "tick {}", systick::uptime_ms()).ok();
+//! ```
+
+use core::convert::Infallible;
+use core::fmt;
+
+use embedded_hal_nb::nb;
+
+use common::{interrupt, mmio, spin};
+use common::VolatileCell;
+use error::{ItmError, Result};
+use hal::dwt;
+use rcc;
+
+const ITM_BASE: usize = 0xE000_0000;
+const TPIU_BASE: usize = 0xE004_0000;
+const DBGMCU_BASE: usize = 0xE004_2000;
+
+#[repr(C)]
+struct Itm {
+    pub stim:       [VolatileCell<u32>; 256],
+    _reserved0:     [u32; 640],
+    pub ter:        [VolatileCell<u32>; 8],
+    _reserved1:     [u32; 8],
+    pub tpr:        VolatileCell<u32>,
+    _reserved2:     [u32; 15],
+    pub tcr:        VolatileCell<u32>,
+    _reserved3:     [u32; 75],
+    pub lar:        VolatileCell<u32>,
+    pub lsr:        VolatileCell<u32>,
+}
+
+#[repr(C)]
+struct Tpiu {
+    pub sspsr:      VolatileCell<u32>,
+    pub cspsr:      VolatileCell<u32>,
+    _reserved0:     [u32; 2],
+    pub acpr:       VolatileCell<u32>,
+    _reserved1:     [u32; 55],
+    pub sppr:       VolatileCell<u32>,
+    _reserved2:     [u32; 131],
+    pub ffsr:       VolatileCell<u32>,
+    pub ffcr:       VolatileCell<u32>,
+}
+
+#[repr(C)]
+struct DbgMcu {
+    pub idcode: VolatileCell<u32>,
+    pub cr:     VolatileCell<u32>,
+}
+
+/// Stimulus ports of the ITM.
+pub const PORTS: u8 = 32;
+
+/* Unlocks writes to the other ITM registers. */
+const LAR_KEY: u32 = 0xC5AC_CE55;
+
+const TCR_ITMENA: u32 = 1 << 0;
+const TCR_SYNCENA: u32 = 1 << 2;
+const TCR_TRACEBUSID_SHIFT: u32 = 16;
+
+const STIM_FIFOREADY: u32 = 1 << 0;
+
+/* One bit trace port, NRZ (UART) encoding and no formatter. */
+const CSPSR_PORT_1BIT: u32 = 1;
+const SPPR_NRZ: u32 = 2;
+const FFCR_TRIGIN: u32 = 1 << 8;
+const ACPR_MAX: u32 = 0x1FFF;
+
+const DBGMCU_CR_TRACE_IOEN: u32 = 1 << 5;
+const DBGMCU_CR_TRACE_MODE: u32 = 0b11 << 6;
+
+fn itm() -> &'static Itm {
+    unsafe { mmio::block(ITM_BASE) }
+}
+
+fn check_port(number: u8) -> Result<()> {
+    if number >= PORTS {
+        return Err(ItmError::InvalidPort(number).into());
+    }
+
+    Ok(())
+}
+
+/// Starts SWO output at `baud` bits per second and the ITM, with every
+/// stimulus port off. The baud rate is the core clock divided by a whole
+/// number, rounded down to it.
+pub fn enable(baud: u32) -> Result<()> {
+    let ahb = rcc::ahb_frequency();
+
+    if baud == 0 || baud > ahb || ahb / baud - 1 > ACPR_MAX {
+        return Err(ItmError::InvalidBaudRate(baud).into());
+    }
+
+    let tpiu: &Tpiu = unsafe { mmio::block(TPIU_BASE) };
+    let dbgmcu: &DbgMcu = unsafe { mmio::block(DBGMCU_BASE) };
+    let itm = itm();
+
+    dwt::enable_trace();
+    dbgmcu.cr.set((dbgmcu.cr.get() & !DBGMCU_CR_TRACE_MODE) | DBGMCU_CR_TRACE_IOEN);
+
+    tpiu.cspsr.set(CSPSR_PORT_1BIT);
+    tpiu.acpr.set(ahb / baud - 1);
+    tpiu.sppr.set(SPPR_NRZ);
+    tpiu.ffcr.set(FFCR_TRIGIN);
+
+    itm.lar.set(LAR_KEY);
+    itm.tcr.set((1 << TCR_TRACEBUSID_SHIFT) | TCR_SYNCENA | TCR_ITMENA);
+    itm.ter[0].set(0);
+
+    Ok(())
+}
+
+pub fn disable() {
+    let itm = itm();
+
+    itm.tcr.set(itm.tcr.get() & !TCR_ITMENA);
+}
+
+pub fn is_enabled() -> bool {
+    itm().tcr.get() & TCR_ITMENA != 0
+}
+
+pub fn enable_port(number: u8) -> Result<()> {
+    check_port(number)?;
+
+    let ter = &itm().ter[0];
+    ter.set(ter.get() | (1 << number));
+
+    Ok(())
+}
+
+pub fn disable_port(number: u8) -> Result<()> {
+    check_port(number)?;
+
+    let ter = &itm().ter[0];
+    ter.set(ter.get() & !(1 << number));
+
+    Ok(())
+}
+
+/// A stimulus port, and a `fmt::Write` sink for it. Every interrupt handler
+/// can log through its own.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Port {
+    number: u8,
+}
+
+pub fn port(number: u8) -> Result<Port> {
+    check_port(number)?;
+
+    Ok(Port { number })
+}
+
+impl Port {
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    /// Whether writes go out: the ITM and this port are on.
+    pub fn is_enabled(&self) -> bool {
+        is_enabled() && itm().ter[0].get() & (1 << self.number) != 0
+    }
+
+    fn stim(&self) -> &'static VolatileCell<u32> {
+        &itm().stim[self.number as usize]
+    }
+
+    /* Checking FIFOREADY and writing can't be split by a handler writing to
+       the same port. */
+    fn try_write<F: FnOnce(&'static VolatileCell<u32>)>(&self, write: F) -> nb::Result<(), Infallible> {
+        interrupt::free(|| {
+            let stim = self.stim();
+
+            if stim.get() & STIM_FIFOREADY == 0 {
+                return Err(nb::Error::WouldBlock);
+            }
+
+            write(stim);
+
+            Ok(())
+        })
+    }
+
+    /// Sends a one byte packet, unless the port's FIFO is full.
+    pub fn try_write_u8(&self, value: u8) -> nb::Result<(), Infallible> {
+        self.try_write(|stim| {
+            let stim = unsafe { &*(stim as *const VolatileCell<u32> as *const VolatileCell<u8>) };
+            stim.set(value);
+        })
+    }
+
+    /// Sends a two byte packet, unless the port's FIFO is full.
+    pub fn try_write_u16(&self, value: u16) -> nb::Result<(), Infallible> {
+        self.try_write(|stim| {
+            let stim = unsafe { &*(stim as *const VolatileCell<u32> as *const VolatileCell<u16>) };
+            stim.set(value);
+        })
+    }
+
+    /// Sends a four byte packet, unless the port's FIFO is full.
+    pub fn try_write_u32(&self, value: u32) -> nb::Result<(), Infallible> {
+        self.try_write(|stim| stim.set(value))
+    }
+
+    /// Sends `bytes` in word packets, waiting for the FIFO. Gives up with
+    /// the bytes not sent once the FIFO stays full for too long.
+    pub fn write_all(&self, bytes: &[u8]) -> core::result::Result<(), usize> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let mut words = bytes.chunks_exact(4);
+
+        for (i, word) in words.by_ref().enumerate() {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+
+            wait(|| self.try_write_u32(word)).map_err(|_| bytes.len() - 4 * i)?;
+        }
+
+        let rest = words.remainder();
+
+        for (i, &byte) in rest.iter().enumerate() {
+            wait(|| self.try_write_u8(byte)).map_err(|_| rest.len() - i)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn wait<F: FnMut() -> nb::Result<(), Infallible>>(mut write: F) -> core::result::Result<(), ()> {
+    spin::until((), || match write() {
+        Ok(()) => Ok(true),
+        Err(nb::Error::WouldBlock) => Ok(false),
+        Err(nb::Error::Other(e)) => match e {},
+    })
+}
+
+impl fmt::Write for Port {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+/// Decoding of the packets a debug probe receives on SWO, for checking
+/// trace output on the host.
+#[cfg(not(target_os = "none"))]
+pub mod host {
+    use std::vec::Vec;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum Packet {
+        /// Marks a packet boundary, sent periodically with `SYNCENA`.
+        Sync,
+        /// Stimulus or hardware packets were lost.
+        Overflow,
+        /// A write of 1, 2 or 4 bytes to a stimulus port.
+        Instrumentation { port: u8, payload: Vec<u8> },
+        /// A packet of the DWT, by its discriminator.
+        Hardware { id: u8, payload: Vec<u8> },
+        /// Cycles since the previous timestamp, local timestamps only.
+        Timestamp { delta: u32 },
+        /// Any other packet, by its header, with its payload skipped.
+        Unknown(u8),
+    }
+
+    /* Bit 7 of every byte but the last of a variable length packet. */
+    const CONTINUATION: u8 = 1 << 7;
+
+    /// Decodes `bytes` into packets. An incomplete packet at the end is left
+    /// out.
+    pub fn decode(bytes: &[u8]) -> Vec<Packet> {
+        let mut packets = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let header = bytes[i];
+            i += 1;
+
+            let packet = match header {
+                0x00 => {
+                    /* At least 47 zero bits, then a one. */
+                    let zeros = bytes[i..].iter().take_while(|&&b| b == 0).count();
+                    i += zeros;
+
+                    if i == bytes.len() {
+                        break;
+                    }
+
+                    i += 1;
+                    if zeros >= 4 && bytes[i - 1] == 0x80 { Packet::Sync } else { Packet::Unknown(0) }
+                }
+                0x70 => Packet::Overflow,
+                _ if header & 0b11 != 0 => {
+                    let size = [0, 1, 2, 4][(header & 0b11) as usize];
+
+                    if i + size > bytes.len() {
+                        break;
+                    }
+
+                    let payload = bytes[i..i + size].to_vec();
+                    i += size;
+
+                    if header & 0b100 == 0 {
+                        Packet::Instrumentation { port: header >> 3, payload }
+                    } else {
+                        Packet::Hardware { id: header >> 3, payload }
+                    }
+                }
+                _ if header & 0x0F == 0 && header & CONTINUATION == 0 => {
+                    Packet::Timestamp { delta: ((header >> 4) & 0b111) as u32 }
+                }
+                _ => {
+                    let mut value = 0u32;
+                    let mut shift = 0;
+                    let mut more = header & CONTINUATION != 0;
+
+                    while more {
+                        if i == bytes.len() {
+                            return packets;
+                        }
+
+                        let byte = bytes[i];
+                        i += 1;
+
+                        if shift < 32 {
+                            value |= ((byte & !CONTINUATION) as u32) << shift;
+                        }
+                        shift += 7;
+                        more = byte & CONTINUATION != 0;
+                    }
+
+                    if header & 0x0F == 0 { Packet::Timestamp { delta: value } } else { Packet::Unknown(header) }
+                }
+            };
+
+            packets.push(packet);
+        }
+
+        packets
+    }
+}
@@ -7,4 +7,13 @@ pub mod uart;
 pub mod syscfg;
 pub mod pwr;
 pub mod timer;
-pub mod spi;
\ No newline at end of file
+pub mod spi;
+pub mod scb;
+pub mod systick;
+pub mod dwt;
+pub mod fault;
+pub mod mpu;
+pub mod fpu;
+pub mod itm;
+pub mod peripherals;
+pub use self::peripherals::Peripherals;
@@ -0,0 +1,223 @@
+//! Memory protection unit: 8 regions with their own access permissions and
+//! memory attributes.
+//!
+//! Regions are a power of two from 32 bytes to 4 GB in size and aligned to
+//! their size. Where regions overlap the higher numbered one applies. With
+//! `enable(true)` memory outside every region keeps the default map for
+//! privileged code.
+
+use common::{asm, mmio};
+use common::VolatileCell;
+use error::{MpuError, Result};
+
+const MPU_BASE: usize = 0xE000_ED90;
+
+#[repr(C)]
+struct Mpu {
+    pub type_: VolatileCell<u32>,
+    pub ctrl:  VolatileCell<u32>,
+    pub rnr:   VolatileCell<u32>,
+    pub rbar:  VolatileCell<u32>,
+    pub rasr:  VolatileCell<u32>,
+}
+
+pub const REGIONS: u8 = 8;
+
+/// Smallest region.
+pub const MIN_SIZE: u32 = 32;
+
+/// Size of the stack guard `link.x` reserves, unless `memory.x` sets
+/// `_stack_guard_size`. A function whose frame is larger than the guard can
+/// skip over it and write into `.bss` unnoticed.
+pub const GUARD_SIZE: u32 = 256;
+
+const CTRL_ENABLE: u32 = 1 << 0;
+const CTRL_PRIVDEFENA: u32 = 1 << 2;
+
+const RASR_ENABLE: u32 = 1 << 0;
+const RASR_SIZE_SHIFT: u32 = 1;
+const RASR_B: u32 = 1 << 16;
+const RASR_C: u32 = 1 << 17;
+const RASR_S: u32 = 1 << 18;
+const RASR_TEX_SHIFT: u32 = 19;
+const RASR_AP_SHIFT: u32 = 24;
+const RASR_XN: u32 = 1 << 28;
+
+fn mpu() -> &'static Mpu {
+    unsafe { mmio::block(MPU_BASE) }
+}
+
+/// Access permissions, for privileged and unprivileged code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    NoAccess = 0b000,
+    PrivilegedReadWrite = 0b001,
+    PrivilegedReadWriteUserReadOnly = 0b010,
+    ReadWrite = 0b011,
+    PrivilegedReadOnly = 0b101,
+    ReadOnly = 0b110,
+}
+
+/// Memory type and cache policy, as TEX, C and B.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Cache {
+    /// Accesses complete in order, one at a time. Always shareable.
+    StronglyOrdered,
+    /// Peripherals.
+    Device,
+    NonCacheable,
+    WriteThrough,
+    WriteBack,
+    WriteBackWriteAllocate,
+}
+
+impl Cache {
+    fn bits(self) -> u32 {
+        let (tex, c, b) = match self {
+            Cache::StronglyOrdered        => (0b000, 0, 0),
+            Cache::Device                 => (0b000, 0, RASR_B),
+            Cache::WriteThrough           => (0b000, RASR_C, 0),
+            Cache::WriteBack              => (0b000, RASR_C, RASR_B),
+            Cache::NonCacheable           => (0b001, 0, 0),
+            Cache::WriteBackWriteAllocate => (0b001, RASR_C, RASR_B),
+        };
+
+        (tex << RASR_TEX_SHIFT) | c | b
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub base: u32,
+    /// Bytes, a power of two of at least `MIN_SIZE`. 0 stands for 4 GB.
+    pub size: u32,
+    pub access: Access,
+    pub execute_never: bool,
+    pub cache: Cache,
+    pub shareable: bool,
+}
+
+fn check_region(number: u8) -> Result<()> {
+    if number >= REGIONS {
+        return Err(MpuError::InvalidRegion(number).into());
+    }
+
+    Ok(())
+}
+
+/// SIZE field of RASR for `size` bytes.
+fn size_bits(size: u32) -> Result<u32> {
+    if size == 0 {
+        return Ok(31);
+    }
+
+    if size < MIN_SIZE || !size.is_power_of_two() {
+        return Err(MpuError::InvalidSize(size).into());
+    }
+
+    Ok(size.trailing_zeros() - 1)
+}
+
+/// Turns the MPU on. With `privileged_default` privileged code may still
+/// access memory no region covers, as if the MPU were off.
+pub fn enable(privileged_default: bool) {
+    let ctrl = if privileged_default { CTRL_ENABLE | CTRL_PRIVDEFENA } else { CTRL_ENABLE };
+
+    asm::dsb();
+    mpu().ctrl.set(ctrl);
+    asm::dsb();
+    asm::isb();
+}
+
+pub fn disable() {
+    asm::dmb();
+    mpu().ctrl.set(0);
+}
+
+pub fn is_enabled() -> bool {
+    mpu().ctrl.get() & CTRL_ENABLE != 0
+}
+
+/// Programs region `number`. It takes effect at once if the MPU is on.
+pub fn set_region(number: u8, region: &Region) -> Result<()> {
+    check_region(number)?;
+
+    let size = size_bits(region.size)?;
+
+    if region.size != 0 && region.base & (region.size - 1) != 0 {
+        return Err(MpuError::Misaligned(region.base).into());
+    }
+
+    let mut rasr = RASR_ENABLE | (size << RASR_SIZE_SHIFT) | region.cache.bits()
+        | ((region.access as u32) << RASR_AP_SHIFT);
+
+    if region.execute_never {
+        rasr |= RASR_XN;
+    }
+
+    if region.shareable {
+        rasr |= RASR_S;
+    }
+
+    let mpu = mpu();
+
+    mpu.rnr.set(number as u32);
+    mpu.rasr.set(0);
+    mpu.rbar.set(region.base);
+    mpu.rasr.set(rasr);
+    asm::dsb();
+    asm::isb();
+
+    Ok(())
+}
+
+pub fn disable_region(number: u8) -> Result<()> {
+    check_region(number)?;
+
+    let mpu = mpu();
+
+    mpu.rnr.set(number as u32);
+    mpu.rasr.set(0);
+    asm::dsb();
+    asm::isb();
+
+    Ok(())
+}
+
+/// Makes the `size` bytes at `base` inaccessible with region `number` and
+/// turns the MPU on, keeping the default map for the rest of memory. An
+/// access there, such as a stack overflowing into it, raises MemManage, or
+/// HardFault unless `fault::enable` was called.
+pub fn set_guard(number: u8, base: u32, size: u32) -> Result<()> {
+    set_region(number, &Region {
+        base,
+        size,
+        access: Access::NoAccess,
+        execute_never: true,
+        cache: Cache::StronglyOrdered,
+        shareable: true,
+    })?;
+
+    enable(true);
+
+    Ok(())
+}
+
+/// Puts a guard with region `number` below the main stack, between it and
+/// `.bss`, where `link.x` leaves `_stack_guard_size` bytes for one. The guard
+/// only catches overflows that touch it, so it must be larger than the
+/// biggest stack frame; raise `_stack_guard_size` in `memory.x` from its
+/// default of `GUARD_SIZE` if one is larger.
+#[cfg(target_os = "none")]
+pub fn guard_main_stack(number: u8) -> Result<()> {
+    extern "C" {
+        static __stack_guard: u32;
+        static _stack_guard_size: u32;
+    }
+
+    /* The size is the address of the symbol. */
+    let base = ::core::ptr::addr_of!(__stack_guard) as u32;
+    let size = ::core::ptr::addr_of!(_stack_guard_size) as u32;
+
+    set_guard(number, base, size)
+}
@@ -1,143 +1,322 @@
-use core::mem;
-
+use common::{asm, interrupt, mmio};
 use common::VolatileCell;
+use error::{NvicError, Result};
+use hal::scb::{self, AIRCR_PRIGROUP_MASK, AIRCR_PRIGROUP_SHIFT, ICSR_VECTACTIVE};
+use rt;
+
+pub use device::NvicIdx;
+
 const NVIC_BASE: usize   = 0xE000_E100;
 
-#[repr(C, packed)]
+#[repr(C)]
 struct Nvic {
     pub iser: [VolatileCell<u32>; 8], _reserved0: [u32; 24],
     pub icer: [VolatileCell<u32>; 8], _reserved1: [u32; 24],
     pub ispr: [VolatileCell<u32>; 8], _reserved2: [u32; 24],
     pub icpr: [VolatileCell<u32>; 8], _reserved3: [u32; 24],
     pub iabr: [VolatileCell<u32>; 8], _reserved4: [u32; 56],
-    pub ipr:  [VolatileCell<u8>; 240]
+    pub ipr:  [VolatileCell<u8>; 240], _reserved5: [u32; 644],
+    pub stir: VolatileCell<u32>
 }
 
-#[repr(C)]
-#[derive(Copy,Clone)]
-#[allow(non_camel_case_types)]
-pub enum NvicIdx {
-    WWDG,
-    PVD,
-    TAMP_STAMP,
-    RTC_WKUP,
-    FLASH,
-    RCC,
-    EXTI0,
-    EXTI1,
-    EXTI2,
-    EXTI3,
-    EXTI4,
-    DMA1_STREAM0,
-    DMA1_STREAM1,
-    DMA1_STREAM2,
-    DMA1_STREAM3,
-    DMA1_STREAM4,
-    DMA1_STREAM5,
-    DMA1_STREAM6,
-    ADC,
-    CAN1_TX,
-    CAN1_RX0,
-    CAN1_RX1,
-    CAN1_SCE,
-    EXTI9_5,
-    TIM1_BRK_TIM9,
-    TIM1_UP_TIM10,
-    TIM1_TRG_COM_TIM11,
-    TIM1_CC,
-    TIM2,
-    TIM3,
-    TIM4,
-    I2C1_EV,
-    I2C1_ER,
-    I2C2_EV,
-    I2C2_ER,
-    SPI1,
-    SPI2,
-    USART1,
-    USART2,
-    USART3,
-    EXTI15_10,
-    RTC_ALARM,
-    OTG_FS_WKUP,
-    TIM8_BRK_TIM12,
-    TIM8_UP_TIM13,
-    TIM8_TRG_COM_TIM14,
-    TIM8_CC,
-    DMA1_STREAM7,
-    FSMC,
-    SDIO,
-    TIM5,
-    SPI3,
-    UART4,
-    UART5,
-    TIM6_DAC,
-    TIM7,
-    DMA2_STREAM0,
-    DMA2_STREAM1,
-    DMA2_STREAM2,
-    DMA2_STREAM3,
-    DMA2_STREAM4,
-    ETH,
-    ETH_WKUP,
-    CAN2_TX,
-    CAN2_RX0,
-    CAN2_RX1,
-    CAN2_SCE,
-    OTG_FS,
-    DMA2_STREAM5,
-    DMA2_STREAM6,
-    DMA2_STREAM7,
-    USART6,
-    I2C3_EV,
-    I2C3_ER,
-    OTG_HS_EP1_OUT,
-    OTG_HS_EP1_IN,
-    OTG_HS_WKUP,
-    OTG_HS,
-    DCMI,
-    CRYP,
-    HASH_RNG,
-    FPU,
-        _reserved0,
-        _reserved1,
-        _reserved2,
-        _reserved3,
-        _reserved4,
-        _reserved5,
-    LCD_TFT,
-    LCD_TFT_1,
-}
-
-impl ::core::default::Default for NvicIdx {
-    fn default() -> NvicIdx {
-        NvicIdx::WWDG
+/// Priority bits the F4 implements, the top 4 of each IPR byte. Lower
+/// values are more urgent.
+pub const PRIORITY_BITS: u8 = 4;
+
+/// How the priority bits split into the preemption priority, which decides
+/// whether an interrupt can interrupt another, and the sub-priority, which
+/// only orders pending interrupts of the same preemption priority.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PriorityGroup {
+    Preempt4Sub0 = 3,
+    Preempt3Sub1 = 4,
+    Preempt2Sub2 = 5,
+    Preempt1Sub3 = 6,
+    Preempt0Sub4 = 7,
+}
+
+impl PriorityGroup {
+    pub fn preempt_bits(self) -> u8 {
+        7 - self as u8
+    }
+
+    pub fn sub_bits(self) -> u8 {
+        PRIORITY_BITS - self.preempt_bits()
+    }
+}
+
+/// Sets the priority grouping in AIRCR. Applies to every interrupt and
+/// exception at once, so it is meant to be set before enabling any.
+pub fn set_priority_grouping(group: PriorityGroup) {
+    scb::write_aircr(AIRCR_PRIGROUP_MASK, (group as u32) << AIRCR_PRIGROUP_SHIFT);
+}
+
+pub fn priority_grouping() -> PriorityGroup {
+    match (scb::scb().aircr.get() & AIRCR_PRIGROUP_MASK) >> AIRCR_PRIGROUP_SHIFT {
+        4 => PriorityGroup::Preempt3Sub1,
+        5 => PriorityGroup::Preempt2Sub2,
+        6 => PriorityGroup::Preempt1Sub3,
+        7 => PriorityGroup::Preempt0Sub4,
+        /* With 4 implemented bits groups 0 to 3 all preempt on every bit. */
+        _ => PriorityGroup::Preempt4Sub0,
+    }
+}
+
+/// Combines a preemption priority and a sub-priority into the priority
+/// taken by `set_priority`, split as the current grouping says.
+pub fn encode_priority(preempt: u8, sub: u8) -> Result<u8> {
+    let group = priority_grouping();
+
+    if preempt >= 1 << group.preempt_bits() {
+        return Err(NvicError::InvalidPriority(preempt).into());
+    }
+
+    if sub >= 1 << group.sub_bits() {
+        return Err(NvicError::InvalidSubPriority(sub).into());
+    }
+
+    Ok((preempt << group.sub_bits()) | sub)
+}
+
+/// Splits a priority into its preemption priority and sub-priority under
+/// the current grouping.
+pub fn decode_priority(priority: u8) -> (u8, u8) {
+    let sub_bits = priority_grouping().sub_bits();
+
+    (priority >> sub_bits, priority & ((1 << sub_bits) - 1))
+}
+
+/// Sets the priority of `signal`, from 0, the most urgent, to 15.
+pub fn set_priority(signal: NvicIdx, priority: u8) -> Result<()> {
+    if priority >= 1 << PRIORITY_BITS {
+        return Err(NvicError::InvalidPriority(priority).into());
     }
+
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
+    nvic.ipr[signal as usize].set(priority << (8 - PRIORITY_BITS));
+
+    Ok(())
 }
 
+pub fn get_priority(signal: NvicIdx) -> u8 {
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
+
+    nvic.ipr[signal as usize].get() >> (8 - PRIORITY_BITS)
+}
+
+/// Unmasks `signal`, at the priority set with `set_priority`; 0 out of reset.
 pub fn enable(signal: NvicIdx) {
-    let nvic: &mut Nvic = unsafe {mem::transmute(NVIC_BASE)};
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
     let interrupt = signal as usize;
 
-    unsafe {
-        nvic.ipr[interrupt].set(0u8);
-        nvic.iser[interrupt / 32].set((1 << (interrupt & 0b11111)) as u32);
-    }
+    nvic.iser[interrupt / 32].set((1 << (interrupt & 0b11111)) as u32);
 }
 
 pub fn disable(signal: NvicIdx) {
-    let nvic: &mut Nvic = unsafe {mem::transmute(NVIC_BASE)};
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
     let interrupt = signal as usize;
 
-    unsafe {
-        nvic.icer[interrupt / 32].set((1 << (interrupt & 0b11111)) as u32);
-    };
+    nvic.icer[interrupt / 32].set((1 << (interrupt & 0b11111)) as u32);
 }
 
 pub fn clear_pending(signal: NvicIdx) {
-    let nvic: &mut Nvic = unsafe {mem::transmute(NVIC_BASE)};
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
+    let interrupt = signal as usize;
+    nvic.icpr[interrupt / 32].set((1 << (interrupt & 0b11111)) as u32);
+}
+
+/// Pends `signal` as if its peripheral had raised it. It is taken once
+/// enabled and no more urgent exception runs.
+pub fn set_pending(signal: NvicIdx) {
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
     let interrupt = signal as usize;
-    unsafe {
-        nvic.icpr[interrupt / 32].set((1 << (interrupt & 0b11111)) as u32);
+    nvic.ispr[interrupt / 32].set((1 << (interrupt & 0b11111)) as u32);
+}
+
+/// Pends `signal` through STIR, a single write that needs no read of the
+/// pending bits. Handlers use it to defer work to a less urgent interrupt.
+pub fn trigger(signal: NvicIdx) {
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
+    nvic.stir.set(signal as u32);
+}
+
+pub fn is_pending(signal: NvicIdx) -> bool {
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
+    let interrupt = signal as usize;
+    nvic.ispr[interrupt / 32].get() & (1 << (interrupt & 0b11111)) != 0
+}
+
+/// Whether the handler of `signal` is running, or was preempted and not
+/// finished yet.
+pub fn is_active(signal: NvicIdx) -> bool {
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
+    let interrupt = signal as usize;
+    nvic.iabr[interrupt / 32].get() & (1 << (interrupt & 0b11111)) != 0
+}
+
+pub fn is_enabled(signal: NvicIdx) -> bool {
+    let nvic: &Nvic = unsafe { mmio::block(NVIC_BASE) };
+    let interrupt = signal as usize;
+    nvic.iser[interrupt / 32].get() & (1 << (interrupt & 0b11111)) != 0
+}
+
+/// An interrupt handler registered at runtime.
+pub type Handler = &'static (dyn Fn() + Sync);
+
+/// Vectors in the table: the stack pointer, 15 exceptions and the interrupts.
+const VECTORS: usize = 16 + rt::INTERRUPTS;
+
+/// VTOR needs the table aligned to its size rounded up to a power of two.
+#[repr(C, align(512))]
+struct Vectors([usize; VECTORS]);
+
+const _: () = assert!(VECTORS * 4 <= 512, "vector table outgrew its alignment");
+
+/// The vector table in RAM, the handlers dispatched through it and the base
+/// of the table it was copied from.
+struct Table {
+    vectors: Vectors,
+    handlers: [Option<Handler>; rt::INTERRUPTS],
+    original: usize,
+}
+
+impl Table {
+    const fn new() -> Table {
+        Table { vectors: Vectors([0; VECTORS]), handlers: [None; rt::INTERRUPTS], original: 0 }
+    }
+
+    /// Copies the active vector table to RAM and points VTOR at the copy,
+    /// unless that was done already.
+    fn relocate(&mut self) {
+        let vtor = &scb::scb().vtor;
+        let ram = self.vectors.0.as_ptr() as usize;
+
+        /* The host's table sits above 4GB, VTOR only keeps the low bits. */
+        if vtor.get() == ram as u32 {
+            return;
+        }
+
+        self.original = vtor.get() as usize;
+
+        for (n, vector) in self.vectors.0.iter_mut().enumerate() {
+            *vector = unsafe { read_vector(self.original, n) };
+        }
+
+        asm::dsb();
+        vtor.set(ram as u32);
+        asm::dsb();
+        asm::isb();
+    }
+}
+
+#[cfg(target_os = "none")]
+fn with_table<R, F: FnOnce(&mut Table) -> R>(f: F) -> R {
+    static mut TABLE: Table = Table::new();
+
+    interrupt::free(|| f(unsafe { &mut *::core::ptr::addr_of_mut!(TABLE) }))
+}
+
+/* On the host every thread gets its own table, like its own register files. */
+#[cfg(not(target_os = "none"))]
+fn with_table<R, F: FnOnce(&mut Table) -> R>(f: F) -> R {
+    thread_local! {
+        static TABLE: ::core::cell::RefCell<Table> = const { ::core::cell::RefCell::new(Table::new()) };
+    }
+
+    interrupt::free(|| TABLE.with(|table| f(&mut table.borrow_mut())))
+}
+
+/// Reads vector `n` of the table at `base`.
+#[cfg(target_os = "none")]
+unsafe fn read_vector(base: usize, n: usize) -> usize {
+    ::core::ptr::read_volatile((base as *const usize).add(n))
+}
+
+/* The host has no table at the reset VTOR of 0. */
+#[cfg(not(target_os = "none"))]
+unsafe fn read_vector(base: usize, n: usize) -> usize {
+    if base == 0 { 0 } else { ::core::ptr::read_volatile((base as *const usize).add(n)) }
+}
+
+/// Vector of every registered interrupt, calls the handler of the interrupt
+/// being serviced.
+unsafe extern "C" fn dispatch() {
+    let vector = (scb::scb().icsr.get() & ICSR_VECTACTIVE) as usize;
+    let handler = match vector.checked_sub(16) {
+        Some(interrupt) => with_table(|table| table.handlers.get(interrupt).cloned().flatten()),
+        None => None,
     };
+
+    if let Some(handler) = handler {
+        handler();
+    }
+}
+
+/// Makes `handler` the handler of `signal`, returning the one it replaces.
+///
+/// The first call moves the vector table to RAM, copying the table VTOR
+/// points at so the other vectors keep their handlers. Registering doesn't
+/// unmask the interrupt, that is still up to `enable`.
+pub fn register(signal: NvicIdx, handler: Handler) -> Option<Handler> {
+    let interrupt = signal as usize;
+
+    with_table(|table| {
+        table.relocate();
+        table.vectors.0[16 + interrupt] = dispatch as *const () as usize;
+        table.handlers[interrupt].replace(handler)
+    })
+}
+
+/// Points the vector of `signal` at `vector` itself rather than at the
+/// dispatcher, for handlers that need the state the core enters them with,
+/// such as EXC_RETURN in LR. Returns the handler `register` gave it, if any.
+pub fn register_vector(signal: NvicIdx, vector: unsafe extern "C" fn()) -> Option<Handler> {
+    let interrupt = signal as usize;
+
+    with_table(|table| {
+        table.relocate();
+        table.vectors.0[16 + interrupt] = vector as *const () as usize;
+        table.handlers[interrupt].take()
+    })
+}
+
+/// Removes the handler or vector registered for `signal` and puts back its
+/// vector from the table VTOR pointed at before the move to RAM.
+pub fn unregister(signal: NvicIdx) -> Option<Handler> {
+    let interrupt = signal as usize;
+
+    with_table(|table| {
+        table.vectors.0[16 + interrupt] = unsafe { read_vector(table.original, 16 + interrupt) };
+        table.handlers[interrupt].take()
+    })
+}
+
+/// Exception entry for host tests, which have no core to take vectors.
+#[cfg(not(target_os = "none"))]
+pub mod host {
+    use super::{with_table, NvicIdx};
+    use hal::scb;
+
+    /// Takes the vector of `signal` from the RAM table with ICSR showing it
+    /// active, as the core would. Returns false, without calling anything,
+    /// when the vector is empty.
+    pub fn raise(signal: NvicIdx) -> bool {
+        let vector = 16 + signal as usize;
+        let address = with_table(|table| table.vectors.0[vector]);
+
+        if address == 0 {
+            return false;
+        }
+
+        let icsr = &scb::scb().icsr;
+        icsr.set(vector as u32);
+
+        unsafe {
+            let handler: unsafe extern "C" fn() = ::core::mem::transmute(address);
+            handler();
+        }
+
+        icsr.set(0);
+        true
+    }
 }
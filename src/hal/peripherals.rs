@@ -0,0 +1,190 @@
+//! Ownership of the peripheral handles.
+//!
+//! The handles of the GPIO ports, the USARTs, the SPIs and the timers can only
+//! be obtained from `Peripherals::take`, which succeeds once. Whoever holds a
+//! handle is the only one configuring that peripheral; drivers that set a
+//! peripheral up, like `uart::enable` or `Spi::init_master`, take the handle by
+//! value and hand it back.
+//!
+//! On the host the taken flag is kept per thread, like the register files in
+//! `common::mmio::host`, so every test can take its own set.
+
+use gpio::{self, Gpio};
+use spi::{self, Spi};
+use timer::{self, Timer};
+use uart::{self, Uart};
+
+#[allow(non_snake_case)]
+pub struct Peripherals {
+    pub GPIOA: Gpio,
+    pub GPIOB: Gpio,
+    pub GPIOC: Gpio,
+    pub GPIOD: Gpio,
+    pub GPIOE: Gpio,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub GPIOF: Gpio,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub GPIOG: Gpio,
+    pub GPIOH: Gpio,
+    #[cfg(any(feature = "stm32f405", feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
+    pub GPIOI: Gpio,
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub GPIOJ: Gpio,
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub GPIOK: Gpio,
+
+    pub USART1: Uart,
+    pub USART2: Uart,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub USART3: Uart,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub UART4: Uart,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub UART5: Uart,
+    pub USART6: Uart,
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub UART7: Uart,
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub UART8: Uart,
+
+    pub SPI1: Spi,
+    pub SPI2: Spi,
+    pub SPI3: Spi,
+    #[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
+    pub SPI4: Spi,
+    #[cfg(any(feature = "stm32f411", feature = "stm32f427", feature = "stm32f429"))]
+    pub SPI5: Spi,
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    pub SPI6: Spi,
+
+    pub TIM1: Timer,
+    pub TIM2: Timer,
+    pub TIM3: Timer,
+    pub TIM4: Timer,
+    pub TIM5: Timer,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub TIM6: Timer,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub TIM7: Timer,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub TIM8: Timer,
+    pub TIM9: Timer,
+    pub TIM10: Timer,
+    pub TIM11: Timer,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub TIM12: Timer,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub TIM13: Timer,
+    #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+    pub TIM14: Timer,
+}
+
+impl Peripherals {
+    /// Returns the peripheral handles the first time it is called and `None`
+    /// afterwards.
+    pub fn take() -> Option<Peripherals> {
+        if taken::swap(true) {
+            None
+        } else {
+            Some(unsafe { Peripherals::steal() })
+        }
+    }
+
+    /// Returns the peripheral handles whether or not they were taken before.
+    ///
+    /// # Safety
+    ///
+    /// The handles alias any that are already out, the caller must make sure
+    /// no peripheral ends up being configured from two places.
+    pub unsafe fn steal() -> Peripherals {
+        taken::swap(true);
+
+        Peripherals {
+            GPIOA: gpio::GPIOA,
+            GPIOB: gpio::GPIOB,
+            GPIOC: gpio::GPIOC,
+            GPIOD: gpio::GPIOD,
+            GPIOE: gpio::GPIOE,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            GPIOF: gpio::GPIOF,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            GPIOG: gpio::GPIOG,
+            GPIOH: gpio::GPIOH,
+            #[cfg(any(feature = "stm32f405", feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
+            GPIOI: gpio::GPIOI,
+            #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+            GPIOJ: gpio::GPIOJ,
+            #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+            GPIOK: gpio::GPIOK,
+
+            USART1: uart::USART1,
+            USART2: uart::USART2,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            USART3: uart::USART3,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            UART4: uart::UART4,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            UART5: uart::UART5,
+            USART6: uart::USART6,
+            #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+            UART7: uart::UART7,
+            #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+            UART8: uart::UART8,
+
+            SPI1: spi::SPI1,
+            SPI2: spi::SPI2,
+            SPI3: spi::SPI3,
+            #[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
+            SPI4: spi::SPI4,
+            #[cfg(any(feature = "stm32f411", feature = "stm32f427", feature = "stm32f429"))]
+            SPI5: spi::SPI5,
+            #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+            SPI6: spi::SPI6,
+
+            TIM1: timer::TIM1,
+            TIM2: timer::TIM2,
+            TIM3: timer::TIM3,
+            TIM4: timer::TIM4,
+            TIM5: timer::TIM5,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            TIM6: timer::TIM6,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            TIM7: timer::TIM7,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            TIM8: timer::TIM8,
+            TIM9: timer::TIM9,
+            TIM10: timer::TIM10,
+            TIM11: timer::TIM11,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            TIM12: timer::TIM12,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            TIM13: timer::TIM13,
+            #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+            TIM14: timer::TIM14,
+        }
+    }
+}
+
+#[cfg(target_os = "none")]
+mod taken {
+    use core::sync::atomic::{AtomicBool, Ordering};
+
+    static TAKEN: AtomicBool = AtomicBool::new(false);
+
+    pub fn swap(value: bool) -> bool {
+        TAKEN.swap(value, Ordering::AcqRel)
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+mod taken {
+    use std::cell::Cell;
+
+    thread_local! {
+        static TAKEN: Cell<bool> = const { Cell::new(false) };
+    }
+
+    pub fn swap(value: bool) -> bool {
+        TAKEN.with(|taken| taken.replace(value))
+    }
+}
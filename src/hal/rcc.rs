@@ -1,15 +1,30 @@
-use common::VolatileCell;
+use core::ptr;
+
+use common::mmio;
 use common::asm;
+use common::bitband;
+use common::spin;
+use common::VolatileCell;
+use common::field::Field;
 
 use hal::{
 	pwr,
-	flash
+	flash,
+	systick
 };
+use hal::scb::{self, AIRCR_SYSRESETREQ};
+
+use error::{Error, RccError, Result};
+
+use device::rcc as flags;
+use self::flags::{Registers, RCC};
+
+pub use device::rcc::{Hpre, Ppre, SysClk, Mco1, Mco2, McoPre, RtcSel};
 
-const RCC_BASE: usize = 0x4002_3800;
 static mut FREQUENCIES: Frequencies = Frequencies::new(8_000_000, 8_000_000, 8_000_000);
 
 #[allow(dead_code)]
+#[derive(Copy, Clone, PartialEq, Eq)]
 pub enum Peripheral {
 	/* AHB1 peripherals*/
 	GPIOA	    = 0x000,
@@ -17,37 +32,52 @@ pub enum Peripheral {
 	GPIOC	    = 0x002,
 	GPIOD	    = 0x003,
 	GPIOE	    = 0x004,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	GPIOF	    = 0x005,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	GPIOG	    = 0x006,
 	GPIOH	    = 0x007,
+	#[cfg(any(feature = "stm32f405", feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
 	GPIOI	    = 0x008,
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
 	GPIOJ	    = 0x009,
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
 	GPIOK	    = 0x00A,
 	CRC		    = 0x00C,
 	FLTIF	    = 0x00F,
 	SRAM1	    = 0x010,
 	SRAM2	    = 0x011,
 	BKPSRAM	    = 0x012,
-	SRAM3	    = 0x013,/* F2xx, F3xx */
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+	SRAM3	    = 0x013,
 	DMA1	    = 0x015,
 	DMA2	    = 0x016,
-	DMA2D	    = 0x017, /* F4x9 */
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+	DMA2D	    = 0x017,
+	#[cfg(any(feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
 	ETHMAC	    = 0x019,
+	#[cfg(any(feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
 	ETHMACTX	= 0x01A,
+	#[cfg(any(feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
 	ETHMACRX	= 0x01B,
+	#[cfg(any(feature = "stm32f407", feature = "stm32f427", feature = "stm32f429"))]
 	ETHMACPTP	= 0x01C,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	OTGHS	    = 0x01D,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	OTGHSULPI	= 0x01E,
 
 	/* AHB2 peripherals */
+	#[cfg(any(feature = "stm32f407", feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
 	DCMI	    = 0x100,
-	CRYP	    = 0x104,
-	HASH	    = 0x105,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411", feature = "stm32f446")))]
 	RNG		    = 0x106,
 	OTGFS	    = 0x107,
 
 	/* AHB3 peripherals */
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	FSMC	    = 0x200,
+	#[cfg(feature = "stm32f446")]
 	QSPIC	    = 0x201,
 
 	/* APB1 peripherals*/
@@ -55,354 +85,524 @@ pub enum Peripheral {
 	TIM3	    = 0x301,
 	TIM4	    = 0x302,
 	TIM5	    = 0x303,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	TIM6	    = 0x304,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	TIM7	    = 0x305,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	TIM12	    = 0x306,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	TIM13	    = 0x307,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	TIM14	    = 0x308,
 	WWDG	    = 0x30B,
 	SPI2	    = 0x30E,
 	SPI3	    = 0x30F,
 	USART2	    = 0x311,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	USART3	    = 0x312,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	UART4	    = 0x313,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	UART5	    = 0x314,
 	I2C1	    = 0x315,
 	I2C2	    = 0x316,
 	I2C3	    = 0x317,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	CAN1	    = 0x319,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	CAN2	    = 0x31A,
     PWR		    = 0x31C,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	DAC		    = 0x31D,
-	UART7	    = 0x31E,/* F2xx, F3xx */
-	UART8	    = 0x31F,/* F2xx, F3xx */
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+	UART7	    = 0x31E,
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+	UART8	    = 0x31F,
 
 	/* APB2 peripherals */
 	TIM1	    = 0x400,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	TIM8	    = 0x401,
 	USART1	    = 0x404,
 	USART6	    = 0x405,
 	ADC1	    = 0x408,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	ADC2	    = 0x409,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	ADC3	    = 0x40A,
 	SDIO	    = 0x40B,
 	SPI1	    = 0x40C,
-	SPI4	    = 0x40D,/* F2xx, F3xx */
+	#[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
+	SPI4	    = 0x40D,
 	SYSCFG	    = 0x40E,
 	TIM9	    = 0x410,
 	TIM10	    = 0x411,
 	TIM11	    = 0x412,
-	SPI5	    = 0x414,/* F2xx, F3xx */
-	SPI6	    = 0x415,/* F2xx, F3xx */
-	SAI1	    = 0x416,/* F4x9 */
-	LTDC	    = 0x41A,/* F4x9 */
-	DSI		    = 0x41B,/* F4x9 */
+	#[cfg(any(feature = "stm32f411", feature = "stm32f427", feature = "stm32f429"))]
+	SPI5	    = 0x414,
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+	SPI6	    = 0x415,
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+	SAI1	    = 0x416,
+	#[cfg(feature = "stm32f429")]
+	LTDC	    = 0x41A,
 	/* BDCR */
 	RTC		    = 0x50F
 }
 
-#[repr(C)]
-struct Registers {
-    pub cr: 	  	VolatileCell<u32>,
-    pub pllcfgr:  	VolatileCell<u32>,
-    pub cfgr: 	  	VolatileCell<u32>,
-    pub cir:	  	VolatileCell<u32>,
-    pub ahb1rstr: 	VolatileCell<u32>,
-    pub ahb2rstr: 	VolatileCell<u32>,
-    pub ahb3rstr: 	VolatileCell<u32>, _reserved0: [u8; 4usize],
-    pub apb1rstr: 	VolatileCell<u32>,
-    pub apb2rstr: 	VolatileCell<u32>, _reserved1: [u8; 8usize],
-    pub ahb1enr:  	VolatileCell<u32>,
-    pub ahb2enr:  	VolatileCell<u32>,
-    pub ahb3enr:  	VolatileCell<u32>, _reserved2: [u8; 4usize],
-    pub apb1enr:  	VolatileCell<u32>,
-    pub apb2enr:  	VolatileCell<u32>, _reserved3: [u8; 8usize],
-    pub ahb1lpenr: 	VolatileCell<u32>,
-    pub ahb2lpenr: 	VolatileCell<u32>,
-    pub ahb3lpenr: 	VolatileCell<u32>, _reserved4: [u8; 4usize],
-    pub apb1lpenr: 	VolatileCell<u32>,
-    pub apb2lpenr: 	VolatileCell<u32>, _reserved5: [u8; 8usize],
-    pub bdcr:     	VolatileCell<u32>,
-    pub csr:      	VolatileCell<u32>, _reserved6: [u8; 8usize],
-    pub sscgr: 		VolatileCell<u32>,
-    pub plli2scfgr: VolatileCell<u32>,
+/// Splits a `Peripheral` into its bus and its bit in that bus' registers.
+fn bus_bit(perph: Peripheral) -> (u32, u32) {
+    let p = perph as u32;
+
+    ((p >> 8) & 0xFF, p & 0xFF)
+}
+
+fn enr(rcc: &Registers, bus: u32) -> Option<&VolatileCell<u32>> {
+	let reg: &VolatileCell<u32> = match bus {
+		0 => &rcc.ahb1enr,
+		1 => &rcc.ahb2enr,
+		#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+		2 => &rcc.ahb3enr,
+		3 => &rcc.apb1enr,
+		4 => &rcc.apb2enr,
+		5 => &rcc.bdcr,
+		_ => return None
+	};
+
+	Some(reg)
+}
+
+fn rstr(rcc: &Registers, bus: u32) -> Option<&VolatileCell<u32>> {
+	let reg: &VolatileCell<u32> = match bus {
+		0 => &rcc.ahb1rstr,
+		1 => &rcc.ahb2rstr,
+		#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+		2 => &rcc.ahb3rstr,
+		3 => &rcc.apb1rstr,
+		4 => &rcc.apb2rstr,
+		_ => return None
+	};
+
+	Some(reg)
 }
 
+/* The enable and reset bits are shared with every other driver, and with
+   interrupt handlers, so they are only ever touched through bit-banding. */
+
 pub fn enable(perph: Peripheral) {
-    let reg: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+    let rcc: &Registers = unsafe { mmio::block(RCC) };
+	let (bus, bit) = bus_bit(perph);
 
-    let p = perph as u32;
-	let bit = 1u32 << (p & 0xFF) as u32;
-	let bus = (p >> 8) & 0xFF;
+	if let Some(reg) = enr(rcc, bus) {
+		bitband::set(reg, bit);
 
-	match bus {
-		0 => reg.ahb1enr.check(bit),
-		1 => reg.ahb2enr.check(bit),
-		2 => reg.ahb3enr.check(bit),
-		3 => reg.apb1enr.check(bit),
-		4 => reg.apb2enr.check(bit),
-		5 => reg.bdcr.check(bit),
-		_ => {}
+		/* The clock reaches the peripheral a few bus cycles after the write
+		   (errata 2.1.13), keep the next access from overtaking it. */
+		asm::dsb();
 	}
 }
 
 pub fn disable(perph: Peripheral) {
-    let reg: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+    let rcc: &Registers = unsafe { mmio::block(RCC) };
+	let (bus, bit) = bus_bit(perph);
 
-    let p = perph as u32;
-	let bit = (p & 0xFF) as u32;
-	let bus = (p >> 8) & 0xFF;
-
-	match bus {
-		0 => reg.ahb1enr.uncheck(bit),
-		1 => reg.ahb2enr.uncheck(bit),
-		2 => reg.ahb3enr.uncheck(bit),
-		3 => reg.apb1enr.uncheck(bit),
-		4 => reg.apb2enr.uncheck(bit),
-		5 => reg.bdcr.uncheck(bit),
-		_ => {}
+	if let Some(reg) = enr(rcc, bus) {
+		bitband::clear(reg, bit);
 	}
 }
 
 pub fn reset_pulse(perph: Peripheral) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+	reset_hold(perph);
+	reset_release(perph);
+}
 
-    let p = perph as u32;
-	let bit = (p & 0xFF) as u32;
-	let bus = (p >> 8) & 0xFF;
+pub fn reset_hold(perph: Peripheral) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
+	let (bus, bit) = bus_bit(perph);
 
-	match bus {
-		0 => { rcc.ahb1rstr.check(bit); rcc.ahb1rstr.uncheck(bit); },
-		1 => { rcc.ahb2rstr.check(bit); rcc.ahb2rstr.uncheck(bit); },
-		2 => { rcc.ahb3rstr.check(bit); rcc.ahb3rstr.uncheck(bit); },
-		3 => { rcc.apb1rstr.check(bit); rcc.apb1rstr.uncheck(bit); },
-		4 => { rcc.apb2rstr.check(bit); rcc.apb2rstr.uncheck(bit); },
-		// 5 => rcc.bdcr.uncheck(bit)
-		_ => {}
+	if let Some(reg) = rstr(rcc, bus) {
+		bitband::set(reg, bit);
 	}
 }
 
-pub fn reset_hold(perph: Peripheral) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+pub fn reset_release(perph: Peripheral) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
+	let (bus, bit) = bus_bit(perph);
 
-    let p = perph as u32;
-	let bit = (p & 0xFF) as u32;
-	let bus = (p >> 8) & 0xFF;
+	if let Some(reg) = rstr(rcc, bus) {
+		bitband::clear(reg, bit);
+		asm::dsb();
+	}
+}
+
+/// What reset the chip, as recorded in RCC CSR.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ResetCause {
+	/// Entering Standby or Stop with the option bytes forbidding it.
+	LowPower,
+	WindowWatchdog,
+	IndependentWatchdog,
+	/// `system_reset`, or any other SYSRESETREQ.
+	Software,
+	PowerOn,
+	/// NRST pulled low, also set along with every other cause.
+	Pin,
+	/// Brownout, also set on power-on.
+	BrownOut,
+}
 
-	match bus {
-		0 => rcc.ahb1rstr.check(bit),
-		1 => rcc.ahb2rstr.check(bit),
-		2 => rcc.ahb3rstr.check(bit),
-		3 => rcc.apb1rstr.check(bit),
-		4 => rcc.apb2rstr.check(bit),
-		// 5 => rcc.bdcr.uncheck(bit)
-		_ => {}
+/* Most specific first. */
+const RESET_CAUSES: [ResetCause; 7] = [
+	ResetCause::LowPower,
+	ResetCause::WindowWatchdog,
+	ResetCause::IndependentWatchdog,
+	ResetCause::Software,
+	ResetCause::PowerOn,
+	ResetCause::BrownOut,
+	ResetCause::Pin,
+];
+
+impl ResetCause {
+	fn flag(self) -> Field<flags::csr::Spec, bool> {
+		match self {
+			ResetCause::LowPower => flags::csr::LPWRRSTF,
+			ResetCause::WindowWatchdog => flags::csr::WWDGRSTF,
+			ResetCause::IndependentWatchdog => flags::csr::IWDGRSTF,
+			ResetCause::Software => flags::csr::SFTRSTF,
+			ResetCause::PowerOn => flags::csr::PORRSTF,
+			ResetCause::Pin => flags::csr::PINRSTF,
+			ResetCause::BrownOut => flags::csr::BORRSTF,
+		}
 	}
 }
 
-pub fn reset_release(perph: Peripheral) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+/// The causes flagged since the flags were last cleared. A reset usually
+/// sets several, e.g. a power-on sets `PowerOn`, `BrownOut` and `Pin`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct ResetCauses {
+	bits: u8,
+}
 
-    let p = perph as u32;
-	let bit = (p & 0xFF) as u32;
-	let bus = (p >> 8) & 0xFF;
+impl ResetCauses {
+	pub fn contains(&self, cause: ResetCause) -> bool {
+		self.bits & (1 << cause as u8) != 0
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.bits == 0
+	}
+
+	/// The cause that best explains the reset: the most specific one, with
+	/// `Pin` last as every reset drives NRST.
+	pub fn primary(&self) -> Option<ResetCause> {
+		self.iter().next()
+	}
+
+	/// The flagged causes, most specific first.
+	pub fn iter(&self) -> impl Iterator<Item = ResetCause> {
+		let causes = *self;
+
+		RESET_CAUSES.iter().cloned().filter(move |&cause| causes.contains(cause))
+	}
+}
+
+/// Reads the reset flags and clears them with RMVF, so the next call reports
+/// the next reset. Call it once, early in `main`.
+pub fn reset_cause() -> ResetCauses {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
+	let csr = rcc.csr.read();
+	let mut causes = ResetCauses::default();
+
+	for &cause in RESET_CAUSES.iter() {
+		if csr.is_set(cause.flag()) {
+			causes.bits |= 1 << cause as u8;
+		}
+	}
+
+	rcc.csr.set_bit(flags::csr::RMVF);
+
+	causes
+}
+
+/// Resets the whole chip through SYSRESETREQ; `reset_cause` reports
+/// `Software` afterwards. On the host, where nothing resets, this panics
+/// once the request is written.
+pub fn system_reset() -> ! {
+	asm::dsb();
+	scb::write_aircr(AIRCR_SYSRESETREQ, AIRCR_SYSRESETREQ);
+	asm::dsb();
+
+	#[cfg(not(target_os = "none"))]
+	panic!("system reset");
 
-	match bus {
-		0 => rcc.ahb1rstr.uncheck(bit),
-		1 => rcc.ahb2rstr.uncheck(bit),
-		2 => rcc.ahb3rstr.uncheck(bit),
-		3 => rcc.apb1rstr.uncheck(bit),
-		4 => rcc.apb2rstr.uncheck(bit),
-		// 5 => rcc.bdcr.uncheck(bit)
-		_ => {}
+	#[cfg(target_os = "none")]
+	loop {
+		asm::nop();
 	}
 }
 
-pub fn set_clock(base_clock: CrystalClock, target_clock: Clock) {
-	let scale = CLOCK_SCALE[base_clock as usize][target_clock as usize];
+/// Runs the PLL off an HSE crystal at `target_clock`, with a 48 MHz USB
+/// clock.
+pub fn set_clock(base_clock: CrystalClock, target_clock: Clock) -> Result<()> {
+	configure(&ClockConfig::solve(Source::HseCrystal(base_clock.frequency()), target_clock.frequency(), true)?)
+}
 
+/// Switches the clock tree to `config`.
+pub fn configure(config: &ClockConfig) -> Result<()> {
 	/* Enable internal high-speed oscillator. */
-	osc_on(flags::Osc::HSI);
-	wait_for_osc_ready(flags::Osc::HSI);
+	osc_on(Osc::HSI);
+	wait_for_osc_ready(Osc::HSI)?;
 
 	/* Select HSI as SYSCLK source. */
-	set_sysclk_source(flags::cfgr::SW_HSI);
+	set_sysclk_source(SysClk::HSI);
+
+	/* PLLCFGR is read-only while the PLL runs. */
+	if is_osc_on(Osc::PLL) {
+		osc_off(Osc::PLL);
+	}
 
-	// /* Enable external high-speed oscillator 8MHz. */
-	osc_on(flags::Osc::HSE);
-	wait_for_osc_ready(flags::Osc::HSE);
+	match config.source {
+		Source::Hsi => osc_off(Osc::HSE),
+		Source::HseCrystal(_) | Source::HseBypass(_) => {
+			let bypass = matches!(config.source, Source::HseBypass(_));
 
-	// /* Enable/disable high performance mode */
-	if !scale.power_save {
-		pwr::set_vos_scale(pwr::flags::VOSScale::Scale1);
-	} else {
-		pwr::set_vos_scale(pwr::flags::VOSScale::Scale2);
+			/* HSEBYP can only change with HSE off. */
+			if is_hse_bypassed() != bypass {
+				osc_off(Osc::HSE);
+				set_hse_bypass(bypass);
+			}
+
+			osc_on(Osc::HSE);
+			wait_for_osc_ready(Osc::HSE)?;
+		}
 	}
 
+	// /* Enable/disable high performance mode */
+	/* PWR ignores writes with its clock off. */
+	enable(Peripheral::PWR);
+	pwr::set_vos_scale(config.vos);
+
 	// /*
 	//  * Set prescalers for AHB, ADC, ABP1, ABP2.
 	//  * Do this before touching the PLL (TODO: why?).
 	//  */
-	set_hpre(scale.hpre);
-	set_ppre1(scale.ppre1);
-	set_ppre2(scale.ppre2);
+	set_hpre(config.hpre);
+	set_ppre1(config.ppre1);
+	set_ppre2(config.ppre2);
+
+	if config.pll {
+		match config.source {
+			Source::Hsi => set_main_pll_hsi(config.pllm, config.plln, config.pllp, config.pllq, config.pllr),
+			_ => set_main_pll_hse(config.pllm, config.plln, config.pllp, config.pllq, config.pllr),
+		}
 
-	set_main_pll_hse(scale.pllm, scale.plln, scale.pllp, scale.pllq, scale.pllr);
+		// /* Enable PLL oscillator and wait for it to stabilize. */
+		osc_on(Osc::PLL);
+		wait_for_osc_ready(Osc::PLL)?;
+	}
 
-	// /* Enable PLL oscillator and wait for it to stabilize. */
-	osc_on(flags::Osc::PLL);
-	wait_for_osc_ready(flags::Osc::PLL);
+	/* Over-drive switches with the PLL on but not yet the system clock. */
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+	if config.over_drive {
+		pwr::enable_over_drive()?;
+	} else if pwr::is_over_drive() {
+		pwr::disable_over_drive();
+	}
 
 	// /* Configure flash settings. */
-	flash::set_ws(flash::flags::acr::DCEN | flash::flags::acr::ICEN | scale.flash_config);
+	flash::enable_caches();
+	flash::set_ws(config.flash_latency);
+
+	let sysclk = match config.source {
+		_ if config.pll => SysClk::PLL,
+		Source::Hsi => SysClk::HSI,
+		_ => SysClk::HSE,
+	};
 
 	// /* Select PLL as SYSCLK source. */
-	set_sysclk_source(flags::cfgr::SW_PLL);
+	set_sysclk_source(sysclk);
 
 	// /* Wait for PLL clock to be selected. */
-	wait_for_sysclk_status(flags::Osc::PLL);
+	wait_for_sysclk_status(sysclk)?;
 
 	// /* Set the peripheral clock frequencies used. */
-
-	let master_clock: u32 = 1000_000 * (scale.plln >> 1);
-
 	unsafe {
-		FREQUENCIES.update(
-			master_clock,
-			match scale.ppre1 {
-				flags::cfgr::PPRE_DIV_NONE => master_clock,
-				flags::cfgr::PPRE_DIV_2    => master_clock >> 1,
-				flags::cfgr::PPRE_DIV_4    => master_clock >> 2,
-				flags::cfgr::PPRE_DIV_8    => master_clock >> 3,
-				flags::cfgr::PPRE_DIV_16   => master_clock >> 4,
-				_ => unreachable!()
-			},
-			match scale.ppre2 {
-				flags::cfgr::PPRE_DIV_NONE => master_clock,
-				flags::cfgr::PPRE_DIV_2    => master_clock >> 1,
-				flags::cfgr::PPRE_DIV_4    => master_clock >> 2,
-				flags::cfgr::PPRE_DIV_8    => master_clock >> 3,
-				flags::cfgr::PPRE_DIV_16   => master_clock >> 4,
-				_ => unreachable!()
-			}
-		);
+		(*ptr::addr_of_mut!(FREQUENCIES)).update(config.ahb(), config.apb1(), config.apb2());
 	}
-	// rcc_ahb_frequency  = clock->ahb_frequency;
-	// rcc_apb1_frequency = clock->apb1_frequency;
-	// rcc_apb2_frequency = clock->apb2_frequency;
+
+	/* Keep the millisecond tick a millisecond long. */
+	systick::reconfigure();
 
 	/* Disable internal high-speed oscillator. */
-	osc_off(flags::Osc::HSI);
+	if config.source != Source::Hsi {
+		osc_off(Osc::HSI);
+	}
+
+	Ok(())
+}
+
+const fn ahb_frequency_of(sysclk: u32, hpre: Hpre) -> u32 {
+	match hpre {
+		Hpre::Div1   => sysclk,
+		Hpre::Div2   => sysclk >> 1,
+		Hpre::Div4   => sysclk >> 2,
+		Hpre::Div8   => sysclk >> 3,
+		Hpre::Div16  => sysclk >> 4,
+		Hpre::Div64  => sysclk >> 6,
+		Hpre::Div128 => sysclk >> 7,
+		Hpre::Div256 => sysclk >> 8,
+		Hpre::Div512 => sysclk >> 9,
+	}
+}
+
+const fn apb_frequency(ahb: u32, ppre: Ppre) -> u32 {
+	match ppre {
+		Ppre::Div1  => ahb,
+		Ppre::Div2  => ahb >> 1,
+		Ppre::Div4  => ahb >> 2,
+		Ppre::Div8  => ahb >> 3,
+		Ppre::Div16 => ahb >> 4,
+	}
 }
 
-fn osc_on(osc: flags::Osc)
+fn osc_on(osc: Osc)
 {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
 	match osc {
-		flags::Osc::PLL => rcc.cr.check(flags::cr::PLLON),
-		flags::Osc::HSE => rcc.cr.check(flags::cr::HSEON),
-		flags::Osc::PLLSAI => rcc.cr.check(flags::cr::PLLSAION),
-		flags::Osc::PLLI2S => rcc.cr.check(flags::cr::PLLI2SON),
-		flags::Osc::HSI => rcc.cr.check(flags::cr::HSION),
-		flags::Osc::LSE => rcc.bdcr.check(flags::bdcr::LSEON),
-		flags::Osc::LSI => rcc.csr.check(flags::csr::LSION),
+		Osc::PLL => rcc.cr.set_bit(flags::cr::PLLON),
+		Osc::HSE => rcc.cr.set_bit(flags::cr::HSEON),
+		#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+		Osc::PLLSAI => rcc.cr.set_bit(flags::cr::PLLSAION),
+		Osc::PLLI2S => rcc.cr.set_bit(flags::cr::PLLI2SON),
+		Osc::HSI => rcc.cr.set_bit(flags::cr::HSION),
+		Osc::LSE => rcc.bdcr.set_bit(flags::bdcr::LSEON),
+		Osc::LSI => rcc.csr.set_bit(flags::csr::LSION),
 	};
 }
 
-fn osc_off(osc: flags::Osc) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+fn osc_off(osc: Osc) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
 	match osc {
-		flags::Osc::PLL => rcc.cr.uncheck(flags::cr::PLLON),
-		flags::Osc::HSE => rcc.cr.uncheck(flags::cr::HSEON),
-		flags::Osc::PLLSAI => rcc.cr.uncheck(flags::cr::PLLSAION),
-		flags::Osc::PLLI2S => rcc.cr.uncheck(flags::cr::PLLI2SON),
-		flags::Osc::HSI => rcc.cr.uncheck(flags::cr::HSION),
-		flags::Osc::LSE => rcc.bdcr.uncheck(flags::bdcr::LSEON),
-		flags::Osc::LSI => rcc.csr.uncheck(flags::csr::LSION),
+		Osc::PLL => rcc.cr.clear_bit(flags::cr::PLLON),
+		Osc::HSE => rcc.cr.clear_bit(flags::cr::HSEON),
+		#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+		Osc::PLLSAI => rcc.cr.clear_bit(flags::cr::PLLSAION),
+		Osc::PLLI2S => rcc.cr.clear_bit(flags::cr::PLLI2SON),
+		Osc::HSI => rcc.cr.clear_bit(flags::cr::HSION),
+		Osc::LSE => rcc.bdcr.clear_bit(flags::bdcr::LSEON),
+		Osc::LSI => rcc.csr.clear_bit(flags::csr::LSION),
 	};
 }
 
-fn is_osc_ready(osc: flags::Osc) -> bool
-{
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
+fn is_osc_on(osc: Osc) -> bool {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
 	match osc {
-		flags::Osc::PLL => rcc.cr.test(flags::cr::PLLRDY),
-		flags::Osc::HSE => rcc.cr.test(flags::cr::HSERDY),
-		flags::Osc::PLLSAI => rcc.cr.test(flags::cr::PLLSAIRDY),
-		flags::Osc::PLLI2S => rcc.cr.test(flags::cr::PLLI2SRDY),
-		flags::Osc::HSI => rcc.cr.test(flags::cr::HSIRDY),
-		flags::Osc::LSE => rcc.bdcr.test(flags::bdcr::LSERDY),
-		flags::Osc::LSI => rcc.csr.test(flags::csr::LSIRDY)
+		Osc::PLL => rcc.cr.is_set(flags::cr::PLLON),
+		Osc::HSE => rcc.cr.is_set(flags::cr::HSEON),
+		#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+		Osc::PLLSAI => rcc.cr.is_set(flags::cr::PLLSAION),
+		Osc::PLLI2S => rcc.cr.is_set(flags::cr::PLLI2SON),
+		Osc::HSI => rcc.cr.is_set(flags::cr::HSION),
+		Osc::LSE => rcc.bdcr.is_set(flags::bdcr::LSEON),
+		Osc::LSI => rcc.csr.is_set(flags::csr::LSION),
 	}
 }
 
-fn wait_for_osc_ready(osc: flags::Osc) {
-	while !is_osc_ready(osc) {
-		asm::nop();
-	};
+fn is_hse_bypassed() -> bool {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
+
+	rcc.cr.is_set(flags::cr::HSEBYP)
 }
 
-fn set_sysclk_source(clk: u32) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
-	let mut reg32 = rcc.cfgr.get();
+fn set_hse_bypass(bypass: bool) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
-	reg32 &= !0b11;
-	rcc.cfgr.set(reg32 | clk);
+	rcc.cr.modify(|_, w| w.hsebyp(bypass));
 }
 
-fn wait_for_sysclk_status(osc: flags::Osc) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
-	
+fn is_osc_ready(osc: Osc) -> bool
+{
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
+
 	match osc {
-		flags::Osc::PLL => while ((rcc.cfgr.get() >> flags::cfgr::SWS_SHIFT) & flags::cfgr::SWS_MASK) != flags::cfgr::SWS_PLL {asm::nop();},
-		flags::Osc::HSE => while ((rcc.cfgr.get() >> flags::cfgr::SWS_SHIFT) & flags::cfgr::SWS_MASK) != flags::cfgr::SWS_HSE {asm::nop();},
-		flags::Osc::HSI => while ((rcc.cfgr.get() >> flags::cfgr::SWS_SHIFT) & flags::cfgr::SWS_MASK) != flags::cfgr::SWS_HSI {asm::nop();},
-		_ => {}
-	};
+		Osc::PLL => rcc.cr.is_set(flags::cr::PLLRDY),
+		Osc::HSE => rcc.cr.is_set(flags::cr::HSERDY),
+		#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+		Osc::PLLSAI => rcc.cr.is_set(flags::cr::PLLSAIRDY),
+		Osc::PLLI2S => rcc.cr.is_set(flags::cr::PLLI2SRDY),
+		Osc::HSI => rcc.cr.is_set(flags::cr::HSIRDY),
+		Osc::LSE => rcc.bdcr.is_set(flags::bdcr::LSERDY),
+		Osc::LSI => rcc.csr.is_set(flags::csr::LSIRDY)
+	}
 }
 
-fn set_ppre2(ppre2: u32) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
-	let mut reg32 = rcc.cfgr.get();
+fn wait_for_osc_ready(osc: Osc) -> Result<()> {
+	spin::until(RccError::OscillatorTimeout.into(), || Ok(is_osc_ready(osc)))
+}
+
+fn set_sysclk_source(clk: SysClk) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
-	reg32 &= !0b111 << 13;
-	rcc.cfgr.set(reg32 | (ppre2 << 13));
+	rcc.cfgr.modify(|_, w| w.sw(clk));
 }
 
-fn set_ppre1(ppre1: u32) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
-	let mut reg32 = rcc.cfgr.get();
+fn wait_for_sysclk_status(clk: SysClk) -> Result<()> {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
-	reg32 &= !0b111 << 10;
-	rcc.cfgr.set(reg32 | (ppre1 << 10));
+	spin::until(RccError::ClockSwitchTimeout.into(), || Ok(rcc.cfgr.read().sws() == Some(clk)))
 }
 
-fn set_hpre(hpre: u32) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
-	let mut reg32 = rcc.cfgr.get();
+fn set_ppre2(ppre2: Ppre) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
-	reg32 &= 0b1111 << 4;
-	rcc.cfgr.set(reg32 | (hpre << 4));
+	rcc.cfgr.modify(|_, w| w.ppre2(ppre2));
 }
 
+fn set_ppre1(ppre1: Ppre) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
-pub fn set_main_pll_hse(pllm: u32, plln: u32, pllp: u32, pllq: u32, mut pllr: u32) {
-	let rcc: &Registers = unsafe { &*(RCC_BASE as *const Registers) };
-	
-	/* Use reset value if not legal, for parts without pllr */
-	if pllr < 2 {
-		pllr = 2;
-	}
+	rcc.cfgr.modify(|_, w| w.ppre1(ppre1));
+}
+
+fn set_hpre(hpre: Hpre) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
 
-	rcc.pllcfgr.set(flags::pllcfgr::PLLSRC | /* HSE */
-		((pllm & flags::pllcfgr::PLLM_MASK) << flags::pllcfgr::PLLM_SHIFT) |
-		((plln & flags::pllcfgr::PLLN_MASK) << flags::pllcfgr::PLLN_SHIFT) |
-		((((pllp >> 1) - 1) & flags::pllcfgr::PLLP_MASK) << flags::pllcfgr::PLLP_SHIFT) |
-		((pllq & flags::pllcfgr::PLLQ_MASK) << flags::pllcfgr::PLLQ_SHIFT) |
-		((pllr & flags::pllcfgr::PLLR_MASK) << flags::pllcfgr::PLLR_SHIFT));
+	rcc.cfgr.modify(|_, w| w.hpre(hpre));
+}
+
+
+pub fn set_main_pll_hse(pllm: u32, plln: u32, pllp: u32, pllq: u32, pllr: u32) {
+	set_main_pll(true, pllm, plln, pllp, pllq, pllr);
+}
+
+/// `set_main_pll_hse` with the PLL fed by the 16 MHz HSI.
+pub fn set_main_pll_hsi(pllm: u32, plln: u32, pllp: u32, pllq: u32, pllr: u32) {
+	set_main_pll(false, pllm, plln, pllp, pllq, pllr);
+}
+
+fn set_main_pll(hse: bool, pllm: u32, plln: u32, pllp: u32, pllq: u32, pllr: u32) {
+	let rcc: &Registers = unsafe { mmio::block(RCC) };
+
+	rcc.pllcfgr.write(|w| {
+		w.pllsrc(hse)
+			.pllm(pllm)
+			.plln(plln)
+			.pllp((pllp >> 1) - 1)
+			.pllq(pllq);
+
+		/* Only the F446 has PLLR, keep the reset value if not legal. */
+		#[cfg(feature = "stm32f446")]
+		w.pllr(if pllr < 2 { 2 } else { pllr });
+
+		w
+	});
+
+	#[cfg(not(feature = "stm32f446"))]
+	let _ = pllr;
 }
 
 pub fn ahb_frequency() -> u32 {
@@ -441,170 +641,17 @@ pub fn apb2_us_psc() -> u32 {
 	unsafe {FREQUENCIES.apb2_uspsc}
 }
 
-#[allow(dead_code)]
-mod flags {
-	pub const HSI: u32 = 0;
-	pub const HSE: u32 = 0;
-	pub const PLL: u32 = 0;
-
-	pub mod cfgr {
-		/* MCO2: Microcontroller clock output 2 */
-		pub const MCO2_SHIFT: u32 = 30;
-		pub const MC02_MASK: u32 = 0x3;
-		pub const MCO2_SYSCLK: u32 = 0x0;
-		pub const MCO2_PLLI2S: u32 = 0x1;
-		pub const MCO2_HSE: u32 = 0x2;
-		pub const MCO2_PLL: u32 = 0x3;
-
-		/* MCO1/2PRE: MCO Prescalers */
-		pub const MCO2PRE_SHIFT: u32 = 27;
-		pub const MCO2PRE_MASK: u32 = 0x7;
-		pub const MCO1PRE_SHIFT: u32 = 24;
-		pub const MCO1PRE_MASK: u32 = 0x7;
-		pub const MCOPRE_DIV_NONE: u32 = 0x0;
-		pub const MCOPRE_DIV_2: u32 = 0x4;
-		pub const MCOPRE_DIV_3: u32 = 0x5;
-		pub const MCOPRE_DIV_4: u32 = 0x6;
-		pub const MCOPRE_DIV_5: u32 = 0x7;
-
-		/* I2SSRC: I2S clock selection */
-		pub const I2SSRC: u32 = 1 << 23;
-
-		/* MCO1: Microcontroller clock output 1 */
-		pub const MCO1_SHIFT: u32 = 21;
-		pub const MCO1_MASK: u32 = 0x3;
-		pub const MCO1_HSI: u32 = 0x0;
-		pub const MCO1_LSE: u32 = 0x1;
-		pub const MCO1_HSE: u32 = 0x2;
-		pub const MCO1_PLL: u32 = 0x3;
-		pub const MCO_SHIFT: u32 = MCO1_SHIFT;
-		pub const MCO_MASK: u32 = MCO1_MASK;
-
-		/* RTCPRE: HSE division factor for RTC clock */
-		pub const RTCPRE_SHIFT: u32 = 16;
-		pub const RTCPRE_MASK: u32 = 0x1f;
-
-		/* PPRE1/2: APB high-speed prescalers */
-		pub const PPRE2_SHIFT: u32 = 13;
-		pub const PPRE2_MASK: u32 = 0x7;
-		pub const PPRE1_SHIFT: u32 = 10;
-		pub const PPRE1_MASK: u32 = 0x7;
-		pub const PPRE_DIV_NONE: u32 = 0x0;
-		pub const PPRE_DIV_2: u32 = 0x4;
-		pub const PPRE_DIV_4: u32 = 0x5;
-		pub const PPRE_DIV_8: u32 = 0x6;
-		pub const PPRE_DIV_16: u32 = 0x7;
-
-		/* HPRE: AHB high-speed prescaler */
-		pub const HPRE_SHIFT: u32 = 4;
-		pub const HPRE_MASK: u32 = 0xf;
-		pub const HPRE_DIV_NONE: u32 = 0x0;
-		pub const HPRE_DIV_2: u32 = 0x8 + 0;
-		pub const HPRE_DIV_4: u32 = 0x8 + 1;
-		pub const HPRE_DIV_8: u32 = 0x8 + 2;
-		pub const HPRE_DIV_16: u32 = 0x8 + 3;
-		pub const HPRE_DIV_64: u32 = 0x8 + 4;
-		pub const HPRE_DIV_128: u32 = 0x8 + 5;
-		pub const HPRE_DIV_256: u32 = 0x8 + 6;
-		pub const HPRE_DIV_512: u32 = 0x8 + 7;
-
-		/* SWS: System clock switch status */
-		pub const SWS_SHIFT: u32 = 2;
-		pub const SWS_MASK: u32 = 0x3;
-		pub const SWS_HSI: u32 = 0x0;
-		pub const SWS_HSE: u32 = 0x1;
-		pub const SWS_PLL: u32 = 0x2;
-
-		/* SW: System clock switch */
-		pub const SW_SHIFT: u32 = 0;
-		pub const SW_HSI: u32 = 0x0;
-		pub const SW_HSE: u32 = 0x1;
-		pub const SW_PLL: u32 = 0x2;
-	}
-
-	pub mod pllcfgr {
-		pub const PLLR_SHIFT: u32 = 28;
-		pub const PLLR_MASK: u32 = 0x7;
-		
-		/* PLLQ: [27:24] */
-		pub const PLLQ_SHIFT: u32 = 24;
-		pub const PLLQ_MASK: u32 = 0xf;
-		pub const PLLSRC: u32 = 1 << 22;
-		
-		/* PLLP: [17:16] */
-		pub const PLLP_SHIFT: u32 = 16;
-		pub const PLLP_MASK: u32 = 0x3;
-		
-		/* PLLN: [14:6] */
-		pub const PLLN_SHIFT: u32 = 6;
-		pub const PLLN_MASK: u32 = 0x1ff;
-		
-		/* PLLM: [5:0] */
-		pub const PLLM_SHIFT: u32 = 0;
-		pub const PLLM_MASK: u32 = 0x3f;
-	}
-
-	pub mod cr {
-		pub const PLLSAIRDY: u32 = 1 << 29;
-		pub const PLLSAION: u32 = 1 << 28;
-		pub const PLLI2SRDY: u32 = 1 << 27;
-		pub const PLLI2SON: u32 = 1 << 26;
-		pub const PLLRDY: u32 = 1 << 25;
-		pub const PLLON: u32 = 1 << 24;
-		pub const CSSON: u32 = 1 << 19;
-		pub const HSEBYP: u32 = 1 << 18;
-		pub const HSERDY: u32 = 1 << 17;
-		pub const HSEON: u32 = 1 << 16;
-
-		/* HSICAL: [15:8] */
-		/* HSITRIM: [7:3] */
-		pub const HSITRIM_SHIFT: u32 = 3;
-		pub const HSITRIM_MASK: u32 = 0x1f;
-		pub const HSIRDY: u32 = 1 << 1;
-		pub const HSION: u32 = 1 << 0;
-	}
-
-	pub mod csr {
-		pub const LPWRRSTF: u32 = 1 << 31;
-		pub const WWDGRSTF: u32 = 1 << 30;
-		pub const IWDGRSTF: u32 = 1 << 29;
-		pub const SFTRSTF: u32 = 1 << 28;
-		pub const PORRSTF: u32 = 1 << 27;
-		pub const PINRSTF: u32 = 1 << 26;
-		pub const BORRSTF: u32 = 1 << 25;
-		pub const RMVF: u32 = 1 << 24;
-		pub const RESET_FLAGS: u32 = LPWRRSTF | WWDGRSTF | IWDGRSTF | SFTRSTF | PORRSTF | PINRSTF | BORRSTF;
-		pub const LSIRDY: u32 = 1 << 1;
-		pub const LSION: u32 = 1 << 0;
-	}
-
-	pub mod bdcr {
-		pub const BDRST: u32 = 1 << 16;
-		pub const RTCEN: u32 = 1 << 15;
-
-		/* RCC_BDCR[9:8]: RTCSEL */
-		pub const RTCSEL_SHIFT: u32 = 8;
-		pub const RTCSEL_MASK: u32 = 0x3;
-		pub const RTCSEL_NONE: u32 = 0;
-		pub const RTCSEL_LSE: u32 = 1;
-		pub const RTCSEL_LSI: u32 = 2;
-		pub const RTCSEL_HSE: u32 = 3;
-		pub const LSEMOD: u32 = 1 << 3;
-		pub const LSEBYP: u32 = 1 << 2;
-		pub const LSERDY: u32 = 1 << 1;
-		pub const LSEON: u32 = 1 << 0;
-	}
-
-	#[derive(Copy, Clone)]
-	pub enum Osc {
-		PLL,
-		PLLSAI,
-		PLLI2S,
-		HSE,
-		HSI,
-		LSE,
-		LSI
-	}
+#[derive(Copy, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+enum Osc {
+	PLL,
+	#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+	PLLSAI,
+	PLLI2S,
+	HSE,
+	HSI,
+	LSE,
+	LSI
 }
 
 pub enum CrystalClock {
@@ -614,71 +661,385 @@ pub enum CrystalClock {
 	Clock25MHz,
 }
 
+impl CrystalClock {
+	pub const fn frequency(&self) -> u32 {
+		match *self {
+			CrystalClock::Clock8MHz  => 8_000_000,
+			CrystalClock::Clock12MHz => 12_000_000,
+			CrystalClock::Clock16MHz => 16_000_000,
+			CrystalClock::Clock25MHz => 25_000_000,
+		}
+	}
+}
+
+/// System clock targets of `set_clock`. Only those the selected part can run
+/// at exist.
 pub enum Clock {
 	Clock48MHz,
 	Clock84MHz,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	Clock120MHz,
+	#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
 	Clock168MHz,
 }
 
-#[derive(Copy, Clone)]
-struct ClockScale {
-	pllm: u32,
-	plln: u32,
-	pllp: u32,
-	pllq: u32,
-	pllr: u32,
-	hpre: u32,
-	ppre1: u32,
-	ppre2: u32,
-	power_save: bool,
-	flash_config: u32,
-}
-
-impl ClockScale {
-	const fn new(pllm: u32, plln: u32, pllp: u32, pllq: u32, pllr: u32, hpre: u32, 
-				 ppre1: u32, ppre2: u32, power_save: bool, flash_config: u32) -> ClockScale 
-	{
-		ClockScale {
+impl Clock {
+	pub const fn frequency(&self) -> u32 {
+		match *self {
+			Clock::Clock48MHz  => 48_000_000,
+			Clock::Clock84MHz  => 84_000_000,
+			#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+			Clock::Clock120MHz => 120_000_000,
+			#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+			Clock::Clock168MHz => 168_000_000,
+		}
+	}
+}
+
+/// Highest clock frequencies of the selected part, and the limits of the
+/// PLL, regulator and flash that go with them.
+#[cfg(feature = "stm32f401")]
+pub mod limits {
+	use pwr::VOSScale;
+
+	pub const SYSCLK: u32 = 84_000_000;
+	pub const APB1: u32   = 42_000_000;
+	pub const APB2: u32   = 84_000_000;
+
+	pub const VCO_MIN: u32  = 192_000_000;
+	pub const PLLN_MIN: u32 = 192;
+	/// Highest SYSCLK of each regulator scale, lowest scale first.
+	pub const SCALES: &[(VOSScale, u32)] = &[(VOSScale::Scale3, 60_000_000), (VOSScale::Scale2, 84_000_000)];
+	/// SYSCLK above which over-drive is needed.
+	pub const OVER_DRIVE: u32 = SYSCLK;
+	/// Highest HCLK of each number of flash wait states, at 2.7 V to 3.6 V.
+	pub const FLASH_WS: &[u32] = &[30_000_000, 60_000_000, 84_000_000];
+}
+
+/// Highest clock frequencies of the selected part, and the limits of the
+/// PLL, regulator and flash that go with them.
+#[cfg(feature = "stm32f411")]
+pub mod limits {
+	use pwr::VOSScale;
+
+	pub const SYSCLK: u32 = 100_000_000;
+	pub const APB1: u32   = 50_000_000;
+	pub const APB2: u32   = 100_000_000;
+
+	pub const VCO_MIN: u32  = 100_000_000;
+	pub const PLLN_MIN: u32 = 50;
+	/// Highest SYSCLK of each regulator scale, lowest scale first.
+	pub const SCALES: &[(VOSScale, u32)] = &[
+		(VOSScale::Scale3, 64_000_000), (VOSScale::Scale2, 84_000_000), (VOSScale::Scale1, 100_000_000),
+	];
+	/// SYSCLK above which over-drive is needed.
+	pub const OVER_DRIVE: u32 = SYSCLK;
+	/// Highest HCLK of each number of flash wait states, at 2.7 V to 3.6 V.
+	pub const FLASH_WS: &[u32] = &[30_000_000, 64_000_000, 90_000_000, 100_000_000];
+}
+
+/// Highest clock frequencies of the selected part, and the limits of the
+/// PLL, regulator and flash that go with them.
+#[cfg(any(feature = "stm32f405", feature = "stm32f407"))]
+pub mod limits {
+	use pwr::VOSScale;
+
+	pub const SYSCLK: u32 = 168_000_000;
+	pub const APB1: u32   = 42_000_000;
+	pub const APB2: u32   = 84_000_000;
+
+	pub const VCO_MIN: u32  = 100_000_000;
+	pub const PLLN_MIN: u32 = 50;
+	/// Highest SYSCLK of each regulator scale, lowest scale first.
+	pub const SCALES: &[(VOSScale, u32)] = &[(VOSScale::Scale2, 144_000_000), (VOSScale::Scale1, 168_000_000)];
+	/// SYSCLK above which over-drive is needed.
+	pub const OVER_DRIVE: u32 = SYSCLK;
+	/// Highest HCLK of each number of flash wait states, at 2.7 V to 3.6 V.
+	pub const FLASH_WS: &[u32] = &[
+		30_000_000, 60_000_000, 90_000_000, 120_000_000, 150_000_000, 168_000_000,
+	];
+}
+
+/// Highest clock frequencies of the selected part, and the limits of the
+/// PLL, regulator and flash that go with them.
+#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))]
+pub mod limits {
+	use pwr::VOSScale;
+
+	pub const SYSCLK: u32 = 180_000_000;
+	pub const APB1: u32   = 45_000_000;
+	pub const APB2: u32   = 90_000_000;
+
+	pub const VCO_MIN: u32  = 100_000_000;
+	pub const PLLN_MIN: u32 = 50;
+	/// Highest SYSCLK of each regulator scale, lowest scale first.
+	pub const SCALES: &[(VOSScale, u32)] = &[
+		(VOSScale::Scale3, 120_000_000), (VOSScale::Scale2, 144_000_000), (VOSScale::Scale1, 180_000_000),
+	];
+	/// SYSCLK above which over-drive is needed.
+	pub const OVER_DRIVE: u32 = 168_000_000;
+	/// Highest HCLK of each number of flash wait states, at 2.7 V to 3.6 V.
+	pub const FLASH_WS: &[u32] = &[
+		30_000_000, 60_000_000, 90_000_000, 120_000_000, 150_000_000, 180_000_000,
+	];
+}
+
+/* Limits every F4 shares. */
+const HSI: u32 = 16_000_000;
+const HSE_MIN: u32 = 4_000_000;
+const HSE_MAX: u32 = 26_000_000;
+const HSE_BYPASS_MIN: u32 = 1_000_000;
+const HSE_BYPASS_MAX: u32 = 50_000_000;
+const VCO_IN_MIN: u32 = 1_000_000;
+const VCO_IN_MAX: u32 = 2_000_000;
+const VCO_MAX: u32 = 432_000_000;
+const PLLM_MIN: u32 = 2;
+const PLLM_MAX: u32 = 63;
+const PLLN_MAX: u32 = 432;
+const PLLQ_MIN: u32 = 2;
+const PLLQ_MAX: u32 = 15;
+const PLL48: u32 = 48_000_000;
+
+/// The oscillator feeding the clock tree.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Source {
+	/// The internal 16 MHz RC oscillator, for boards without a crystal.
+	Hsi,
+	/// A crystal of this many Hz on OSC_IN and OSC_OUT, 4 to 26 MHz.
+	HseCrystal(u32),
+	/// An external clock of this many Hz on OSC_IN, 1 to 50 MHz.
+	HseBypass(u32),
+}
+
+impl Source {
+	pub const fn frequency(&self) -> u32 {
+		match *self {
+			Source::Hsi => HSI,
+			Source::HseCrystal(hz) | Source::HseBypass(hz) => hz,
+		}
+	}
+
+	const fn in_range(&self) -> bool {
+		match *self {
+			Source::Hsi => true,
+			Source::HseCrystal(hz) => hz >= HSE_MIN && hz <= HSE_MAX,
+			Source::HseBypass(hz) => hz >= HSE_BYPASS_MIN && hz <= HSE_BYPASS_MAX,
+		}
+	}
+}
+
+/// A clock tree: its source, directly or through the main PLL, the PLL
+/// factors, the bus prescalers, and the regulator scale and flash wait
+/// states the clocks need. Found with `ClockConfig::solve`, applied with
+/// `configure`.
+///
+/// ```ignore
+/// const CLOCKS: ClockConfig = match ClockConfig::solve(Source::HseCrystal(24_000_000), 180_000_000, false) {
+///     Ok(config) => config,
+///     Err(_) => panic!("no clock configuration"),
+/// };
+///
+/// rcc::configure(&CLOCKS)?;
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ClockConfig {
+	pub source: Source,
+	/// Whether SYSCLK comes from the PLL rather than straight from `source`.
+	/// The PLL factors are ignored without it.
+	pub pll: bool,
+	pub pllm: u32,
+	pub plln: u32,
+	pub pllp: u32,
+	pub pllq: u32,
+	pub pllr: u32,
+	pub hpre: Hpre,
+	pub ppre1: Ppre,
+	pub ppre2: Ppre,
+	pub vos: pwr::VOSScale,
+	pub over_drive: bool,
+	pub flash_latency: flash::Latency,
+}
+
+impl ClockConfig {
+	/// Finds the PLL factors and AHB prescaler that turn `source` into
+	/// exactly `hclk` Hz on the core and AHB, and with `usb` exactly 48 MHz
+	/// on the PLL48 output for USB, SDIO and the RNG. Prefers the smallest
+	/// prescaler, so the slowest SYSCLK, which reaches targets below what
+	/// the PLL puts out. A SYSCLK equal to the source frequency skips the PLL
+	/// unless `usb` needs it. Prefers a 2 MHz VCO input, for the least
+	/// jitter, and the slowest VCO. The APB buses run as fast as the part
+	/// allows, with the lowest regulator scale and fewest flash wait states
+	/// for `hclk`.
+	pub const fn solve(source: Source, hclk: u32, usb: bool) -> Result<ClockConfig> {
+		let input = source.frequency();
+
+		if !source.in_range() {
+			return Err(Error::Rcc(RccError::FrequencyOutOfRange(input)));
+		}
+
+		if hclk == 0 || hclk > limits::SYSCLK {
+			return Err(Error::Rcc(RccError::FrequencyOutOfRange(hclk)));
+		}
+
+		let mut i = 0;
+
+		while i < HPRE.len() {
+			let (div, hpre) = HPRE[i];
+			let sysclk = hclk as u64 * div as u64;
+
+			if sysclk > limits::SYSCLK as u64 {
+				break;
+			}
+
+			let sysclk = sysclk as u32;
+
+			if sysclk == input && !usb {
+				return Ok(ClockConfig::new(source, None, hpre, hclk));
+			}
+
+			if let Some(factors) = pll_factors(input, sysclk, usb) {
+				return Ok(ClockConfig::new(source, Some(factors), hpre, hclk));
+			}
+
+			i += 1;
+		}
+
+		Err(Error::Rcc(RccError::NoClockConfig))
+	}
+
+	/* `pll` holds PLLM, PLLN, PLLP and PLLQ, if SYSCLK comes from the PLL. */
+	const fn new(source: Source, pll: Option<(u32, u32, u32, u32)>, hpre: Hpre, hclk: u32) -> ClockConfig {
+		/* PLLCFGR reset values without the PLL. */
+		let (pllm, plln, pllp, pllq) = match pll {
+			Some(factors) => factors,
+			None => (16, 192, 2, 4),
+		};
+
+		let mut vos = 0;
+		while hclk > limits::SCALES[vos].1 {
+			vos += 1;
+		}
+
+		let mut ws = 0;
+		while hclk > limits::FLASH_WS[ws] {
+			ws += 1;
+		}
+
+		ClockConfig {
+			source,
+			pll: pll.is_some(),
 			pllm,
 			plln,
 			pllp,
-			pllq,
-			pllr,
+			pllq: if pllq < PLLQ_MIN { PLLQ_MIN } else { pllq },
+			/* Reset value, for parts with PLLR. */
+			pllr: 2,
 			hpre,
-			ppre1,
-			ppre2,
-			power_save,
-			flash_config,
+			ppre1: apb_prescaler(hclk, limits::APB1),
+			ppre2: apb_prescaler(hclk, limits::APB2),
+			vos: limits::SCALES[vos].0,
+			over_drive: hclk > limits::OVER_DRIVE,
+			flash_latency: latency(ws),
+		}
+	}
+
+	pub const fn sysclk(&self) -> u32 {
+		if !self.pll {
+			return self.source.frequency();
+		}
+
+		(self.source.frequency() as u64 * self.plln as u64 / (self.pllm * self.pllp) as u64) as u32
+	}
+
+	/// The PLL48 output, for USB, SDIO and the RNG. 0 without the PLL.
+	pub const fn pll48(&self) -> u32 {
+		if !self.pll {
+			return 0;
 		}
+
+		(self.source.frequency() as u64 * self.plln as u64 / (self.pllm * self.pllq) as u64) as u32
+	}
+
+	pub const fn ahb(&self) -> u32 {
+		ahb_frequency_of(self.sysclk(), self.hpre)
+	}
+
+	pub const fn apb1(&self) -> u32 {
+		apb_frequency(self.ahb(), self.ppre1)
+	}
+
+	pub const fn apb2(&self) -> u32 {
+		apb_frequency(self.ahb(), self.ppre2)
 	}
 }
 
-const CLOCK_SCALE: [[ClockScale; 4]; 4] = [
-	[ 
-		//              M   N    P  Q  R  HPRE                        PPRE1                    PPRE2                       PWRSV  FLASH_CONFIG
-		ClockScale::new(8,  96,  2, 2, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(8,  336, 4, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_2, flags::cfgr::PPRE_DIV_NONE, false, flash::flags::acr::LATENCY_2WS),
-		ClockScale::new(8,  240, 2, 5, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(8,  336, 2, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    false, flash::flags::acr::LATENCY_5WS),
-	], [
-		ClockScale::new(12, 96,  2, 2, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(12, 336, 4, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_2, flags::cfgr::PPRE_DIV_NONE, false, flash::flags::acr::LATENCY_2WS),
-		ClockScale::new(12, 240, 2, 5, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(12, 336, 2, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    false, flash::flags::acr::LATENCY_5WS),
-	], [
-		ClockScale::new(16, 96,  2, 2, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(16, 336, 4, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_2, flags::cfgr::PPRE_DIV_NONE, false, flash::flags::acr::LATENCY_2WS),
-		ClockScale::new(16, 240, 2, 5, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(16, 336, 2, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    false, flash::flags::acr::LATENCY_5WS),
-	], [
-		ClockScale::new(25, 96,  2, 2, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(25, 336, 4, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_2, flags::cfgr::PPRE_DIV_NONE, false, flash::flags::acr::LATENCY_2WS),
-		ClockScale::new(25, 240, 2, 5, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    true,  flash::flags::acr::LATENCY_3WS),
-		ClockScale::new(25, 336, 2, 7, 0, flags::cfgr::HPRE_DIV_NONE, flags::cfgr::PPRE_DIV_4, flags::cfgr::PPRE_DIV_2,    false, flash::flags::acr::LATENCY_5WS),
-	],
+/// AHB prescalers `solve` tries, smallest first, with their divisor.
+const HPRE: [(u32, Hpre); 9] = [
+	(1, Hpre::Div1), (2, Hpre::Div2), (4, Hpre::Div4), (8, Hpre::Div8), (16, Hpre::Div16),
+	(64, Hpre::Div64), (128, Hpre::Div128), (256, Hpre::Div256), (512, Hpre::Div512),
 ];
 
+/// PLLM, PLLN, PLLP and PLLQ giving exactly `sysclk` from `input`, and with
+/// `usb` exactly 48 MHz on PLL48.
+const fn pll_factors(input: u32, sysclk: u32, usb: bool) -> Option<(u32, u32, u32, u32)> {
+	let mut pllm = PLLM_MIN;
+
+	while pllm <= PLLM_MAX {
+		if input >= VCO_IN_MIN * pllm && input <= VCO_IN_MAX * pllm {
+			let mut pllp = 2;
+
+			while pllp <= 8 {
+				let vco = sysclk as u64 * pllp as u64;
+				let plln = vco * pllm as u64 / input as u64;
+
+				if vco * pllm as u64 == plln * input as u64
+					&& plln >= limits::PLLN_MIN as u64 && plln <= PLLN_MAX as u64
+					&& vco >= limits::VCO_MIN as u64 && vco <= VCO_MAX as u64
+				{
+					/* Without USB, just keep PLL48 under 48 MHz. */
+					let pllq = (vco as u32).div_ceil(PLL48);
+
+					if !usb || ((vco as u32).is_multiple_of(PLL48) && pllq >= PLLQ_MIN && pllq <= PLLQ_MAX) {
+						return Some((pllm, plln as u32, pllp, pllq));
+					}
+				}
+
+				pllp += 2;
+			}
+		}
+
+		pllm += 1;
+	}
+
+	None
+}
+
+/// Smallest APB prescaler keeping the bus at or below `max`.
+const fn apb_prescaler(ahb: u32, max: u32) -> Ppre {
+	if ahb <= max {
+		Ppre::Div1
+	} else if ahb <= 2 * max {
+		Ppre::Div2
+	} else if ahb <= 4 * max {
+		Ppre::Div4
+	} else if ahb <= 8 * max {
+		Ppre::Div8
+	} else {
+		Ppre::Div16
+	}
+}
+
+const fn latency(ws: usize) -> flash::Latency {
+	match ws {
+		0 => flash::Latency::Ws0,
+		1 => flash::Latency::Ws1,
+		2 => flash::Latency::Ws2,
+		3 => flash::Latency::Ws3,
+		4 => flash::Latency::Ws4,
+		_ => flash::Latency::Ws5,
+	}
+}
+
 struct Frequencies {
 	pub ahb: u32,
 	pub apb1: u32,
@@ -704,9 +1065,9 @@ impl Frequencies {
 			apb1_mspsc: apb1 / 1000,
 			apb2_mspsc: apb2 / 1000,
 
-			ahb_uspsc:  ahb  / 1000_000,
-			apb1_uspsc: apb1 / 1000_000,
-			apb2_uspsc: apb2 / 1000_000,
+			ahb_uspsc:  ahb  / 1_000_000,
+			apb1_uspsc: apb1 / 1_000_000,
+			apb2_uspsc: apb2 / 1_000_000,
 		}
 	}
 
@@ -719,8 +1080,8 @@ impl Frequencies {
 		self.apb1_mspsc = apb1 / 1000;
 		self.apb2_mspsc = apb2 / 1000;
 
-		self.ahb_uspsc  = ahb  / 1000_000;
-		self.apb1_uspsc = apb1 / 1000_000;
-		self.apb2_uspsc = apb2 / 1000_000;
+		self.ahb_uspsc  = ahb  / 1_000_000;
+		self.apb1_uspsc = apb1 / 1_000_000;
+		self.apb2_uspsc = apb2 / 1_000_000;
 	}
 }
\ No newline at end of file
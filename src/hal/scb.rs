@@ -0,0 +1,52 @@
+//! System control block, the core's registers for exception handling,
+//! the vector table and system reset.
+
+use common::VolatileCell;
+use common::mmio;
+
+pub(crate) const SCB_BASE: usize = 0xE000_ED00;
+
+#[repr(C)]
+pub(crate) struct Scb {
+    pub cpuid: VolatileCell<u32>,
+    pub icsr:  VolatileCell<u32>,
+    pub vtor:  VolatileCell<u32>,
+    pub aircr: VolatileCell<u32>,
+    pub scr:   VolatileCell<u32>,
+    pub ccr:   VolatileCell<u32>,
+    pub shpr:  [VolatileCell<u8>; 12],
+    pub shcsr: VolatileCell<u32>,
+    pub cfsr:  VolatileCell<u32>,
+    pub hfsr:  VolatileCell<u32>,
+    pub dfsr:  VolatileCell<u32>,
+    pub mmfar: VolatileCell<u32>,
+    pub bfar:  VolatileCell<u32>,
+    pub afsr:  VolatileCell<u32>,
+        _reserved0: [u32; 18],
+    pub cpacr: VolatileCell<u32>,
+}
+
+pub(crate) fn scb() -> &'static Scb {
+    unsafe { mmio::block(SCB_BASE) }
+}
+
+/// ICSR: number of the exception being handled, 0 in thread mode.
+pub(crate) const ICSR_VECTACTIVE: u32 = 0x1FF;
+/// ICSR: the SysTick exception is pending.
+pub(crate) const ICSR_PENDSTSET: u32 = 1 << 26;
+
+/// AIRCR: writes are ignored unless they carry this key.
+pub(crate) const AIRCR_VECTKEY: u32 = 0x05FA << 16;
+pub(crate) const AIRCR_VECTKEY_MASK: u32 = 0xFFFF << 16;
+pub(crate) const AIRCR_PRIGROUP_SHIFT: u32 = 8;
+pub(crate) const AIRCR_PRIGROUP_MASK: u32 = 0b111 << AIRCR_PRIGROUP_SHIFT;
+/// AIRCR: asks the reset controller for a system reset.
+pub(crate) const AIRCR_SYSRESETREQ: u32 = 1 << 2;
+
+/// Writes the AIRCR bits in `mask` to `value`, adding the key.
+pub(crate) fn write_aircr(mask: u32, value: u32) {
+    let aircr = &scb().aircr;
+    let keep = aircr.get() & !(AIRCR_VECTKEY_MASK | mask);
+
+    aircr.set(AIRCR_VECTKEY | keep | (value & mask));
+}
@@ -1,287 +1,26 @@
 use core::ops::Deref;
 
 use rcc;
-use common::asm;
-use common::VolatileCell;
-
-pub const SPI1: Spi = Spi { addr: 0x40013000 };
-pub const SPI2: Spi = Spi { addr: 0x40003800 };
-pub const SPI3: Spi = Spi { addr: 0x40003c00 };
-pub const SPI4: Spi = Spi { addr: 0x40013400 };
-pub const SPI5: Spi = Spi { addr: 0x40015000 };
-pub const SPI6: Spi = Spi { addr: 0x40015400 };
-
-mod flags {
-    pub mod cr1 {
-        /* BIDIMODE: Bidirectional data mode enable */
-        pub const BIDIMODE_2LINE_UNIDIR: u32 = 0 << 15;
-        pub const BIDIMODE_1LINE_BIDIR: u32 = 1 << 15;
-        pub const BIDIMODE: u32 = 1 << 15;
-
-        /* BIDIOE: Output enable in bidirectional mode */
-        pub const BIDIOE: u32 = 1 << 14;
-
-        /* CRCEN: Hardware CRC calculation enable */
-        pub const CRCEN: u32 = 1 << 13;
-
-        /* CRCNEXT: Transmit CRC next */
-        pub const CRCNEXT: u32 = 1 << 12;
-
-        pub const CRCL_8BIT: u32 = 0 << 11;
-        pub const CRCL_16BIT: u32 = 1 << 11;
-        /**@}*/
-        pub const CRCL: u32 = 1 << 11;
-
-        pub const DFF_8BIT: u32 = 0 << 11;
-        pub const DFF_16BIT: u32 = 1 << 11;
-        /**@}*/
-        pub const DFF: u32 = 1 << 11;
-
-        /* RXONLY: Receive only */
-        pub const RXONLY: u32 = 1 << 10;
-
-        /* SSM: Software slave management */
-        pub const SSM: u32 = 1 << 9;
-
-        /* SSI: Internal slave select */
-        pub const SSI: u32 = 1 << 8;
-
-        /* LSBFIRST: Frame format */
-        /****************************************************************************/
-        /** @defgroup spi_lsbfirst SPI lsb/msb first
-        @ingroup spi_defines
-
-        @{*/
-        pub const MSBFIRST: u32 = 0 << 7;
-        pub const LSBFIRST: u32 = 1 << 7;
-        /**@}*/
-
-        /* SPE: SPI enable */
-        pub const SPE: u32 = 1 << 6;
-
-        /* BR[2:0]: Baud rate control */
-        /****************************************************************************/
-        /** @defgroup spi_baudrate SPI peripheral baud rates
-        @ingroup spi_defines
-
-        @{*/
-        pub const BAUDRATE_FPCLK_DIV_2: u32 = 0x00 << 3;
-        pub const BAUDRATE_FPCLK_DIV_4: u32 = 0x01 << 3;
-        pub const BAUDRATE_FPCLK_DIV_8: u32 = 0x02 << 3;
-        pub const BAUDRATE_FPCLK_DIV_16: u32 = 0x03 << 3;
-        pub const BAUDRATE_FPCLK_DIV_32: u32 = 0x04 << 3;
-        pub const BAUDRATE_FPCLK_DIV_64: u32 = 0x05 << 3;
-        pub const BAUDRATE_FPCLK_DIV_128: u32 = 0x06 << 3;
-        pub const BAUDRATE_FPCLK_DIV_256: u32 = 0x07 << 3;
-        /**@}*/
-        /****************************************************************************/
-        /** @defgroup spi_br_pre SPI peripheral baud rate prescale values
-        @ingroup spi_defines
-
-        @{*/
-        pub const BR_FPCLK_DIV_2: u32 = 0x0;
-        pub const BR_FPCLK_DIV_4: u32 = 0x1;
-        pub const BR_FPCLK_DIV_8: u32 = 0x2;
-        pub const BR_FPCLK_DIV_16: u32 = 0x3;
-        pub const BR_FPCLK_DIV_32: u32 = 0x4;
-        pub const BR_FPCLK_DIV_64: u32 = 0x5;
-        pub const BR_FPCLK_DIV_128: u32 = 0x6;
-        pub const BR_FPCLK_DIV_256: u32 = 0x7;
-        /**@}*/
-
-        /* MSTR: Master selection */
-        pub const MSTR: u32 = 1 << 2;
-
-        /* CPOL: Clock polarity */
-        /****************************************************************************/
-        /** @defgroup spi_cpol SPI clock polarity
-        @ingroup spi_defines
-
-        @{*/
-        pub const CPOL_CLK_TO_0_WHEN_IDLE: u32 = 0 << 1;
-        pub const CPOL_CLK_TO_1_WHEN_IDLE: u32 = 1 << 1;
-        /**@}*/
-        pub const CPOL: u32 = 1 << 1;
-
-        /* CPHA: Clock phase */
-        /****************************************************************************/
-        /** @defgroup spi_cpha SPI clock phase
-        @ingroup spi_defines
-
-        @{*/
-        pub const CPHA_CLK_TRANSITION_1: u32 = 0 << 0;
-        pub const CPHA_CLK_TRANSITION_2: u32 = 1 << 0;
-        /**@}*/
-        pub const CPHA: u32 = 1 << 0;
-    }
-
-    pub mod cr2 {
-        /* LDMA_TX: Last DMA transfer for transmission */
-        pub const LDMA_TX: u32 = 1 << 14;
-
-        /* LDMA_RX: Last DMA transfer for reception */
-        pub const LDMA_RX: u32 = 1 << 13;
-
-        /* FRXTH: FIFO reception threshold */
-        pub const FRXTH: u32 = 1 << 12;
-
-        /* DS [3:0]: Data size */
-        /* 0x0 - 0x2 NOT USED */
-        pub const DS_4BIT: u32 = 0x3 << 8;
-        pub const DS_5BIT: u32 = 0x4 << 8;
-        pub const DS_6BIT: u32 = 0x5 << 8;
-        pub const DS_7BIT: u32 = 0x6 << 8;
-        pub const DS_8BIT: u32 = 0x7 << 8;
-        pub const DS_9BIT: u32 = 0x8 << 8;
-        pub const DS_10BIT: u32 = 0x9 << 8;
-        pub const DS_11BIT: u32 = 0xA << 8;
-        pub const DS_12BIT: u32 = 0xB << 8;
-        pub const DS_13BIT: u32 = 0xC << 8;
-        pub const DS_14BIT: u32 = 0xD << 8;
-        pub const DS_15BIT: u32 = 0xE << 8;
-        pub const DS_16BIT: u32 = 0xF << 8;
-        pub const DS_MASK: u32 = 0xF << 8;
-
-        /* Bits [15:8]: Reserved. Forced to 0 by hardware. Used on F3. */
-
-        /* TXEIE: Tx buffer empty interrupt enable */
-        pub const TXEIE: u32 = 1 << 7;
-
-        /* RXNEIE: Rx buffer not empty interrupt enable */
-        pub const RXNEIE: u32 = 1 << 6;
-
-        /* ERRIE: Error interrupt enable */
-        pub const ERRIE: u32 = 1 << 5;
-
-        /* FRF: Frame format */
-        /* Note: Not used in I2S mode. */
-        pub const FRF: u32 = 1 << 4;
-        pub const FRF_MOTOROLA_MODE: u32 = 0 << 4;
-        pub const FRF_TI_MODE: u32 = 1 << 4;
-
-        /* NSSP: NSS pulse management */
-        pub const NSSP: u32 = 1 << 3;
-
-        /* SSOE: SS output enable */
-        /* Note: Not used in I2S mode. */
-        pub const SSOE: u32 = 1 << 2;
-
-        /* TXDMAEN: Tx buffer DMA enable */
-        pub const TXDMAEN: u32 = 1 << 1;
-
-        /* RXDMAEN: Rx buffer DMA enable */
-        pub const RXDMAEN: u32 = 1 << 0;
-    }
-
-    pub mod sr {
-
-        /* FTLVL[1:0]: FIFO Transmission Level */
-        pub const FTLVL_FIFO_EMPTY: u32 = 0x0 << 11;
-        pub const FTLVL_QUARTER_FIFO: u32 = 0x1 << 11;
-        pub const FTLVL_HALF_FIFO: u32 = 0x2 << 11;
-        pub const FTLVL_FIFO_FULL: u32 = 0x3 << 11;
-
-        /* FRLVL[1:0]: FIFO Reception Level */
-        pub const FRLVL_FIFO_EMPTY: u32 = 0x0 << 9;
-        pub const FRLVL_QUARTER_FIFO: u32 = 0x1 << 9;
-        pub const FRLVL_HALF_FIFO: u32 = 0x2 << 9;
-        pub const FRLVL_FIFO_FULL: u32 = 0x3 << 9;
-
-        /* TIFRFE: TI frame format error */
-        pub const TIFRFE: u32 = 1 << 8;
-
-        /* BSY: Busy flag */
-        pub const BSY: u32 = 1 << 7;
-
-        /* OVR: Overrun flag */
-        pub const OVR: u32 = 1 << 6;
-
-        /* MODF: Mode fault */
-        /* Note: Not used in I2S mode. */
-        pub const MODF: u32 = 1 << 5;
-
-        /* CRCERR: CRC error flag */
-        /* Note: Not used in I2S mode. */
-        pub const CRCERR: u32 = 1 << 4;
-
-        /* UDR: Underrun flag */
-        /* Note: Not used in SPI mode. */
-        pub const UDR: u32 = 1 << 3;
-
-        /* CHSIDE: Channel side */
-        /* Note: Not used in SPI mode. No meaning in PCM mode. */
-        pub const CHSIDE: u32 = 1 << 2;
-
-        /* TXE: Transmit buffer empty */
-        pub const TXE: u32 = 1 << 1;
-
-        /* RXNE: Receive buffer not empty */
-        pub const RXNE: u32 = 1 << 0;
-    }
-
-    pub mod i2scfgr {
-        /* I2SMOD: I2S mode selection */
-        pub const I2SMOD: u32 = 1 << 11;
-
-        /* I2SE: I2S enable */
-        pub const I2SE: u32 = 1 << 10;
-
-        /* I2SCFG[9:8]: I2S configuration mode */
-        pub const I2SCFG_LSB: u32 = 8;
-        pub const I2SCFG_SLAVE_TRANSMIT: u32 = 0x0;
-        pub const I2SCFG_SLAVE_RECEIVE: u32 = 0x1;
-        pub const I2SCFG_MASTER_TRANSMIT: u32 = 0x2;
-        pub const I2SCFG_MASTER_RECEIVE: u32 = 0x3;
-
-        /* PCMSYNC: PCM frame synchronization */
-        pub const PCMSYNC: u32 = 1 << 7;
-
-        /* Bit 6: Reserved. Forced to 0 by hardware. */
-
-        /* I2SSTD[5:4]: I2S standard selection */
-        pub const I2SSTD_LSB: u32 = 4;
-        pub const I2SSTD_I2S_PHILIPS: u32 = 0x0;
-        pub const I2SSTD_MSB_JUSTIFIED: u32 = 0x1;
-        pub const I2SSTD_LSB_JUSTIFIED: u32 = 0x2;
-        pub const I2SSTD_PCM: u32 = 0x3;
-
-        /* CKPOL: Steady state clock polarity */
-        pub const CKPOL: u32 = 1 << 3;
-
-        /* DATLEN[2:1]: Data length to be transferred */
-        pub const DATLEN_LSB: u32 = 1;
-        pub const DATLEN_16BIT: u32 = 0x0;
-        pub const DATLEN_24BIT: u32 = 0x1;
-        pub const DATLEN_32BIT: u32 = 0x2;
-
-        /* CHLEN: Channel length */
-        pub const CHLEN: u32 = 1 << 0;
-    }
-    pub mod i2spr {
-        /* Note: None of these bits are used in SPI mode. */
-
-        /* Bits [15:10]: Reserved. Forced to 0 by hardware. */
-
-        /* MCKOE: Master clock output enable */
-        pub const MCKOE: u32 = 1 << 9;
-
-        /* ODD: Odd factor for the prescaler */
-        pub const ODD: u32 = 1 << 8;
-    }
-}
-
-#[repr(C)]
-pub struct Registers {
-    pub cr1: VolatileCell<u32>,
-    pub cr2: VolatileCell<u32>,
-    pub sr: VolatileCell<u32>,
-    pub dr: VolatileCell<u32>,
-    pub crcpr: VolatileCell<u32>,
-    pub rxcrcr: VolatileCell<u32>,
-    pub txcrcr: VolatileCell<u32>,
-    pub i2scfgr: VolatileCell<u32>,
-    pub i2spr: VolatileCell<u32>,
-}
+use common::field::Field;
+use common::mmio;
+use common::spin;
+use error::{Error, Result, SpiError};
+use embedded_hal::spi;
+
+use device::spi as flags;
+use self::flags::Registers;
+
+pub use device::spi::{BaudRate, ClockPolarity, ClockPhase, DataFrame, BitOrder};
+
+pub(crate) const SPI1: Spi = Spi { addr: flags::SPI1 };
+pub(crate) const SPI2: Spi = Spi { addr: flags::SPI2 };
+pub(crate) const SPI3: Spi = Spi { addr: flags::SPI3 };
+#[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
+pub(crate) const SPI4: Spi = Spi { addr: flags::SPI4 };
+#[cfg(any(feature = "stm32f411", feature = "stm32f427", feature = "stm32f429"))]
+pub(crate) const SPI5: Spi = Spi { addr: flags::SPI5 };
+#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+pub(crate) const SPI6: Spi = Spi { addr: flags::SPI6 };
 
 #[derive(PartialEq, Eq)]
 pub struct Spi {
@@ -292,11 +31,36 @@ impl Deref for Spi {
     type Target = Registers;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { &*(self.addr as *const Registers) }
+        unsafe { mmio::block(self.addr) }
     }
 }
 
 impl Spi {
+    /* Waits for `flag` in SR, giving up on any error flag seen meanwhile.
+       The flag is cleared before reporting it, so the next transfer can go
+       ahead (RM0090 28.4.8). */
+    fn wait_for(&self, flag: Field<flags::sr::Spec, bool>, set: bool) -> Result<()> {
+        spin::until(SpiError::Timeout.into(), || {
+            let sr = self.sr.read();
+
+            if sr.ovr() {
+                /* Read DR, then SR. The overrun word is lost. */
+                let _ = self.dr.get();
+                let _ = self.sr.get();
+                Err(SpiError::Overrun.into())
+            } else if sr.modf() {
+                /* SR was read above, a write to CR1 ends it. The fault
+                   cleared SPE and MSTR, so set them again. */
+                self.cr1.modify(|_, w| w.mstr(true).spe(true));
+                Err(SpiError::ModeFault.into())
+            } else if sr.crcerr() {
+                self.sr.clear_bit(flags::sr::CRCERR);
+                Err(SpiError::Crc.into())
+            } else {
+                Ok(sr.is_set(flag) == set)
+            }
+        })
+    }
 
     /** @brief SPI Reset.
 
@@ -311,8 +75,11 @@ impl Spi {
             &SPI1 => rcc::reset_pulse(rcc::Peripheral::SPI1),
             &SPI2 => rcc::reset_pulse(rcc::Peripheral::SPI2),
             &SPI3 => rcc::reset_pulse(rcc::Peripheral::SPI3),
+            #[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
             &SPI4 => rcc::reset_pulse(rcc::Peripheral::SPI4),
+            #[cfg(any(feature = "stm32f411", feature = "stm32f427", feature = "stm32f429"))]
             &SPI5 => rcc::reset_pulse(rcc::Peripheral::SPI5),
+            #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
             &SPI6 => rcc::reset_pulse(rcc::Peripheral::SPI6),
             _ => {}
         }
@@ -328,9 +95,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable(&self) {
-        self.cr1.check(flags::cr1::SPE); /* Enable SPI. */
+        self.cr1.set_bit(flags::cr1::SPE); /* Enable SPI. */
     }
 
     /* TODO: Error handling? */
@@ -341,9 +107,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable(&self) {
-        self.cr1.uncheck(flags::cr1::SPE); /* Disable SPI. */
+        self.cr1.clear_bit(flags::cr1::SPE); /* Disable SPI. */
     }
 
     /*---------------------------------------------------------------------------*/
@@ -356,22 +121,21 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @returns data Unsigned int16. 8 or 16 bit data from final read.
     */
-
-    pub fn clean_disable(&self) -> u16 {
+    pub fn clean_disable(&self) -> Result<u16> {
         /* Wait to receive last data */
-        while !self.sr.test(flags::sr::RXNE) { asm::nop(); }
+        self.wait_for(flags::sr::RXNE, true)?;
 
         let data = self.dr.get();
 
         /* Wait to transmit last data */
-        while !self.sr.test(flags::sr::TXE) { asm::nop(); };
+        self.wait_for(flags::sr::TXE, true)?;
 
         /* Wait until not busy */
-        while self.sr.test(flags::sr::BSY) { asm::nop(); };
+        self.wait_for(flags::sr::BSY, false)?;
 
-        self.cr1.uncheck(flags::cr1::SPE);
+        self.cr1.clear_bit(flags::cr1::SPE);
 
-        return data as u16;
+        Ok(data as u16)
     }
 
     /*---------------------------------------------------------------------------*/
@@ -382,7 +146,6 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @param[in] data Unsigned int16. 8 or 16 bit data to be written.
     */
-
     pub fn write(&self, data: u16) {
         /* Write data (8 or 16 bits, depending on DFF) into DR. */
         self.dr.set(data as u32);
@@ -397,13 +160,14 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @param[in] data Unsigned int16. 8 or 16 bit data to be written.
     */
-
-    pub fn send(&self, data: u16) {
+    pub fn send(&self, data: u16) -> Result<()> {
         /* Wait for transfer finished. */
-        while !self.sr.test(flags::sr::TXE) { asm::nop(); };
+        self.wait_for(flags::sr::TXE, true)?;
 
         /* Write data (8 or 16 bits, depending on DFF) into DR. */
         self.dr.set(data as u32);
+
+        Ok(())
     }
 
     /*---------------------------------------------------------------------------*/
@@ -414,13 +178,12 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @returns data Unsigned int16. 8 or 16 bit data.
     */
-
-    pub fn read(&self) -> u16 {
+    pub fn read(&self) -> Result<u16> {
         /* Wait for transfer finished. */
-        while !self.sr.test(flags::sr::RXNE) { asm::nop(); }
+        self.wait_for(flags::sr::RXNE, true)?;
 
         /* Read the data (8 or 16 bits, depending on DFF bit) from DR. */
-        return self.dr.get() as u16;
+        Ok(self.dr.get() as u16)
     }
 
     /*---------------------------------------------------------------------------*/
@@ -433,15 +196,14 @@ impl Spi {
     @param[in] data Unsigned int16. 8 or 16 bit data to be written.
     @returns data Unsigned int16. 8 or 16 bit data.
     */
-
-    pub fn xfer(&self, data: u16) -> u16 {
+    pub fn xfer(&self, data: u16) -> Result<u16> {
         self.write(data);
 
         /* Wait for transfer finished. */
-        while !self.sr.test(flags::sr::RXNE) { asm::nop(); }
+        self.wait_for(flags::sr::RXNE, true)?;
 
         /* Read the data (8 or 16 bits, depending on DFF bit) from DR. */
-        return self.dr.get() as u16;
+        Ok(self.dr.get() as u16)
     }
 
     /*---------------------------------------------------------------------------*/
@@ -452,9 +214,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_bidirectional_mode(&self) {
-        self.cr1.check(flags::cr1::BIDIMODE);
+        self.cr1.set_bit(flags::cr1::BIDIMODE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -466,9 +227,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_unidirectional_mode(&self) {
-        self.cr1.uncheck(flags::cr1::BIDIMODE);
+        self.cr1.clear_bit(flags::cr1::BIDIMODE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -480,10 +240,9 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_bidirectional_receive_only_mode(&self) {
-        self.cr1.check(flags::cr1::BIDIMODE);
-        self.cr1.uncheck(flags::cr1::BIDIOE);
+        self.cr1.set_bit(flags::cr1::BIDIMODE);
+        self.cr1.clear_bit(flags::cr1::BIDIOE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -495,10 +254,9 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_bidirectional_transmit_only_mode(&self) {
-        self.cr1.check(flags::cr1::BIDIMODE);
-        self.cr1.check(flags::cr1::BIDIOE);
+        self.cr1.set_bit(flags::cr1::BIDIMODE);
+        self.cr1.set_bit(flags::cr1::BIDIOE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -508,9 +266,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_crc(&self) {
-        self.cr1.check(flags::cr1::CRCEN);
+        self.cr1.set_bit(flags::cr1::CRCEN);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -518,9 +275,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_crc(&self) {
-        self.cr1.uncheck(flags::cr1::CRCEN);
+        self.cr1.clear_bit(flags::cr1::CRCEN);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -532,9 +288,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_next_tx_from_buffer(&self) {
-        self.cr1.uncheck(flags::cr1::CRCNEXT);
+        self.cr1.clear_bit(flags::cr1::CRCNEXT);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -546,9 +301,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_next_tx_from_crc(&self) {
-        self.cr1.check(flags::cr1::CRCNEXT);
+        self.cr1.set_bit(flags::cr1::CRCNEXT);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -556,9 +310,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_full_duplex_mode(&self) {
-        self.cr1.uncheck(flags::cr1::RXONLY);
+        self.cr1.clear_bit(flags::cr1::RXONLY);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -567,9 +320,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_receive_only_mode(&self) {
-        self.cr1.check(flags::cr1::RXONLY);
+        self.cr1.set_bit(flags::cr1::RXONLY);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -579,9 +331,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_software_slave_management(&self) {
-        self.cr1.uncheck(flags::cr1::SSM);
+        self.cr1.clear_bit(flags::cr1::SSM);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -592,11 +343,10 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_software_slave_management(&self) {
-        self.cr1.check(flags::cr1::SSM);
+        self.cr1.set_bit(flags::cr1::SSM);
         /* allow slave select to be an input */
-        self.cr2.uncheck(flags::cr2::SSOE);
+        self.cr2.clear_bit(flags::cr2::SSOE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -610,9 +360,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_nss_high(&self) {
-        self.cr1.check(flags::cr1::SSI);
+        self.cr1.set_bit(flags::cr1::SSI);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -623,9 +372,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_nss_low(&self) {
-        self.cr1.uncheck(flags::cr1::SSI);
+        self.cr1.clear_bit(flags::cr1::SSI);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -633,9 +381,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn send_lsb_first(&self) {
-        self.cr1.check(flags::cr1::LSBFIRST);
+        self.cr1.modify(|_, w| w.lsbfirst(BitOrder::LsbFirst));
     }
 
     /*---------------------------------------------------------------------------*/
@@ -643,29 +390,18 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn send_msb_first(&self) {
-        self.cr1.uncheck(flags::cr1::LSBFIRST);
+        self.cr1.modify(|_, w| w.lsbfirst(BitOrder::MsbFirst));
     }
 
     /*---------------------------------------------------------------------------*/
     /** @brief SPI Set the Baudrate Prescaler
 
-    @todo Why is this specification different to the spi_init_master baudrate
-    values?
-
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
-    @param[in] baudrate Unsigned int8. Baudrate prescale value @ref spi_br_pre.
+    @param[in] baudrate BaudRate. Baudrate prescale value.
     */
-
-    pub fn set_baudrate_prescaler(&self, baudrate: u8) {
-        if baudrate > 7 {
-            return;
-        }
-
-        let mut reg32 = self.cr1.get() & 0xffc7; /* Clear bits [5:3]. */
-        reg32 |= (baudrate as u32) << 3;
-        self.cr1.set(reg32);
+    pub fn set_baudrate_prescaler(&self, baudrate: BaudRate) {
+        self.cr1.modify(|_, w| w.br(baudrate));
     }
 
     /*---------------------------------------------------------------------------*/
@@ -673,9 +409,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_master_mode(&self) {
-        self.cr1.check(flags::cr1::MSTR);
+        self.cr1.set_bit(flags::cr1::MSTR);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -683,9 +418,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn set_slave_mode(&self) {
-        self.cr1.uncheck(flags::cr1::MSTR);
+        self.cr1.clear_bit(flags::cr1::MSTR);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -694,9 +428,8 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @sa spi_set_clock_polarity_0
     */
-
     pub fn set_clock_polarity_1(&self) {
-        self.cr1.check(flags::cr1::CPOL);
+        self.cr1.modify(|_, w| w.cpol(ClockPolarity::IdleHigh));
     }
 
     /*---------------------------------------------------------------------------*/
@@ -705,9 +438,8 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @sa spi_set_clock_polarity_1
     */
-
     pub fn set_clock_polarity_0(&self) {
-        self.cr1.uncheck(flags::cr1::CPOL);
+        self.cr1.modify(|_, w| w.cpol(ClockPolarity::IdleLow));
     }
 
     /*---------------------------------------------------------------------------*/
@@ -716,9 +448,8 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @sa spi_set_clock_phase_0
     */
-
     pub fn set_clock_phase_1(&self) {
-        self.cr1.check(flags::cr1::CPHA);
+        self.cr1.modify(|_, w| w.cpha(ClockPhase::SecondTransition));
     }
 
     /*---------------------------------------------------------------------------*/
@@ -727,9 +458,8 @@ impl Spi {
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     @sa spi_set_clock_phase_1
     */
-
     pub fn set_clock_phase_0(&self) {
-        self.cr1.uncheck(flags::cr1::CPHA);
+        self.cr1.modify(|_, w| w.cpha(ClockPhase::FirstTransition));
     }
 
     /*---------------------------------------------------------------------------*/
@@ -737,9 +467,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_tx_buffer_empty_interrupt(&self) {
-        self.cr2.check(flags::cr2::TXEIE);
+        self.cr2.set_bit(flags::cr2::TXEIE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -747,9 +476,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_tx_buffer_empty_interrupt(&self) {
-        self.cr2.uncheck(flags::cr2::TXEIE);
+        self.cr2.clear_bit(flags::cr2::TXEIE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -757,9 +485,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_rx_buffer_not_empty_interrupt(&self) {
-        self.cr2.check(flags::cr2::RXNEIE);
+        self.cr2.set_bit(flags::cr2::RXNEIE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -767,9 +494,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_rx_buffer_not_empty_interrupt(&self) {
-        self.cr2.uncheck(flags::cr2::RXNEIE);
+        self.cr2.clear_bit(flags::cr2::RXNEIE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -777,9 +503,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_error_interrupt(&self) {
-        self.cr2.check(flags::cr2::ERRIE);
+        self.cr2.set_bit(flags::cr2::ERRIE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -787,9 +512,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_error_interrupt(&self) {
-        self.cr2.uncheck(flags::cr2::ERRIE);
+        self.cr2.clear_bit(flags::cr2::ERRIE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -800,9 +524,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_ss_output(&self) {
-        self.cr2.check(flags::cr2::SSOE);
+        self.cr2.set_bit(flags::cr2::SSOE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -814,9 +537,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_ss_output(&self) {
-        self.cr2.uncheck(flags::cr2::SSOE);
+        self.cr2.clear_bit(flags::cr2::SSOE);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -828,9 +550,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_tx_dma(&self) {
-        self.cr2.check(flags::cr2::TXDMAEN);
+        self.cr2.set_bit(flags::cr2::TXDMAEN);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -838,9 +559,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_tx_dma(&self) {
-        self.cr2.uncheck(flags::cr2::TXDMAEN);
+        self.cr2.clear_bit(flags::cr2::TXDMAEN);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -852,9 +572,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn enable_rx_dma(&self) {
-        self.cr2.check(flags::cr2::RXDMAEN);
+        self.cr2.set_bit(flags::cr2::RXDMAEN);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -862,9 +581,8 @@ impl Spi {
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
     pub fn disable_rx_dma(&self) {
-        self.cr2.uncheck(flags::cr2::RXDMAEN);
+        self.cr2.clear_bit(flags::cr2::RXDMAEN);
     }
 
     /*---------------------------------------------------------------------------*/
@@ -881,14 +599,17 @@ impl Spi {
     @sa spi_set_clock_phase_0 spi_set_clock_phase_1
     @sa spi_set_clock_polarity_0 spi_set_clock_polarity_1
     */
-
-    pub fn set_standard_mode(&self, mode: u8) {
+    pub fn set_standard_mode(&self, mode: u8) -> Result<()> {
         if mode > 3 {
-            return;
+            return Err(SpiError::InvalidMode(mode).into());
         }
 
-        let reg32 = self.cr1.get() & !(flags::cr1::CPOL | flags::cr1::CPHA);
-        self.cr1.set(reg32 | mode as u32);
+        let cpol = if mode & 0b10 != 0 { ClockPolarity::IdleHigh } else { ClockPolarity::IdleLow };
+        let cpha = if mode & 0b01 != 0 { ClockPhase::SecondTransition } else { ClockPhase::FirstTransition };
+
+        self.cr1.modify(|_, w| w.cpol(cpol).cpha(cpha));
+
+        Ok(())
     }
 
 
@@ -896,114 +617,126 @@ impl Spi {
     /** @brief Configure the SPI as Master.
 
     The SPI peripheral is configured as a master with communication parameters
-    baudrate, crc length 8/16 bits, frame format lsb/msb first, clock polarity
+    baudrate, data frame format 8/16 bits, frame format lsb/msb first, clock polarity
     and phase. The SPI enable, CRC enable and CRC next controls are not affected.
     These must be controlled separately.
 
     @todo NSS pin handling.
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
-    @param[in] br Unsigned int32. Baudrate @ref spi_baudrate.
-    @param[in] cpol Unsigned int32. Clock polarity @ref spi_cpol.
-    @param[in] cpha Unsigned int32. Clock Phase @ref spi_cpha.
-    @param[in] crcl Unsigned int32. CRC length 8/16 bits @ref spi_crcl.
-    @param[in] lsbfirst Unsigned int32. Frame format lsb/msb first @ref
-    spi_lsbfirst.
-    @returns int. Error code.
+    @param[in] br BaudRate. Baudrate.
+    @param[in] cpol ClockPolarity. Clock polarity.
+    @param[in] cpha ClockPhase. Clock Phase.
+    @param[in] dff DataFrame. Data frame format 8/16 bits.
+    @param[in] lsbfirst BitOrder. Frame format lsb/msb first.
+    @returns spi Spi. The configured peripheral.
     */
+    pub fn init_master(self, br: BaudRate, cpol: ClockPolarity, cpha: ClockPhase, dff: DataFrame, lsbfirst: BitOrder) -> Spi {
+        self.cr1.modify(|r, w| {
+            /* Reset all bits omitting SPE, CRCEN and CRCNEXT bits. */
+            w.bits(0)
+                .spe(r.spe())
+                .crcen(r.crcen())
+                .crcnext(r.crcnext())
+                .mstr(true)		/* Configure SPI as master. */
+                .br(br)			/* Set baud rate bits. */
+                .cpol(cpol)		/* Set CPOL value. */
+                .cpha(cpha)		/* Set CPHA value. */
+                .dff(dff)		/* Set data frame format (8 or 16 bits). */
+                .lsbfirst(lsbfirst)	/* Set frame format (LSB- or MSB-first). */
 
-    pub fn init_master(&self, br: u32, cpol: u32, cpha: u32, crcl: u32, lsbfirst: u32) {
-        let mut reg32 = self.cr1.get();
-
-        /* Reset all bits omitting SPE, CRCEN and CRCNEXT bits. */
-        reg32 &= flags::cr1::SPE | flags::cr1::CRCEN | flags::cr1::CRCNEXT;
-        reg32 |= flags::cr1::MSTR;	/* Configure SPI as master. */
-
-        reg32 |= br;		/* Set baud rate bits. */
-        reg32 |= cpol;		/* Set CPOL value. */
-        reg32 |= cpha;		/* Set CPHA value. */
-        reg32 |= crcl;		/* Set crc length (8 or 16 bits). */
-        reg32 |= lsbfirst;	/* Set frame format (LSB- or MSB-first). */
-
-        /* TODO: NSS pin handling. */
+            /* TODO: NSS pin handling. */
+        });
 
-        self.cr1.set(reg32);
+        self
     }
 
-    pub fn send8(&self, data: u8) {
+    pub fn send8(&self, data: u8) -> Result<()> {
         /* Wait for transfer finished. */
-        while !self.sr.test(flags::sr::TXE) { asm::nop(); };
+        self.wait_for(flags::sr::TXE, true)?;
 
         /* Write data (8 or 16 bits, depending on DFF) into DR. */
         self.dr.set(data as u32);
+
+        Ok(())
     }
 
-    fn read8(&self) -> u8 {
+    fn read8(&self) -> Result<u8> {
         /* Wait for transfer finished. */
-        while !self.sr.test(flags::sr::RXNE) { asm::nop(); };
+        self.wait_for(flags::sr::RXNE, true)?;
 
         /* Read the data (8 or 16 bits, depending on DFF bit) from DR. */
-        self.dr.get() as u8
+        Ok(self.dr.get() as u8)
+    }
+
+    pub fn i2s_mode_spi_mode(&self) {
+        self.i2scfgr.clear_bit(flags::i2scfgr::I2SMOD);
     }
 
     /*---------------------------------------------------------------------------*/
-    /** @brief SPI Set CRC length to 8 bits
+    /** @brief SPI Set Data Frame Format to 8 bits
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
-    pub fn set_crcl_8bit(&self) {
-        self.cr1.uncheck(flags::cr1::CRCL);
+    pub fn set_dff_8bit(&self) {
+        self.cr1.modify(|_, w| w.dff(DataFrame::Bits8));
     }
 
     /*---------------------------------------------------------------------------*/
-    /** @brief SPI Set CRC length to 16 bits
+    /** @brief SPI Set Data Frame Format to 16 bits
 
     @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
     */
-
-    pub fn set_crcl_16bit(&self) {
-        self.cr1.check(flags::cr1::CRCL);
+    pub fn set_dff_16bit(&self) {
+        self.cr1.modify(|_, w| w.dff(DataFrame::Bits16));
     }
+}
 
-    pub fn set_data_size(&self, data_size: u32) {
-        let mut reg32 = self.cr2.get();
+/* embedded-hal bus access, one 8-bit frame at a time. Every frame is read
+   back so that RXNE never overruns. */
 
-        reg32 &= !flags::cr2::DS_MASK;
-        reg32 |= data_size & flags::cr2::DS_MASK;
+impl spi::ErrorType for Spi {
+    type Error = Error;
+}
 
-        self.cr2.set(reg32);
-    }
+impl spi::SpiBus<u8> for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<()> {
+        for word in words.iter_mut() {
+            *word = self.xfer(0)? as u8;
+        }
 
-    pub fn fifo_reception_threshold_8bit(&self) {
-        self.cr2.check(flags::cr2::FRXTH);
+        Ok(())
     }
 
-    pub fn fifo_reception_threshold_16bit(&self) {
-        self.cr2.uncheck(flags::cr2::FRXTH);
-    }
+    fn write(&mut self, words: &[u8]) -> Result<()> {
+        for &word in words {
+            self.xfer(word as u16)?;
+        }
 
-    pub fn i2s_mode_spi_mode(&self) {
-        self.i2scfgr.uncheck(flags::i2scfgr::I2SMOD);
+        Ok(())
     }
 
-    /*---------------------------------------------------------------------------*/
-    /** @brief SPI Set Data Frame Format to 8 bits
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<()> {
+        for i in 0..read.len().max(write.len()) {
+            let word = self.xfer(write.get(i).cloned().unwrap_or(0) as u16)? as u8;
 
-    @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
-    */
+            if let Some(slot) = read.get_mut(i) {
+                *slot = word;
+            }
+        }
 
-    pub fn set_dff_8bit(&self) {
-        self.cr1.uncheck(flags::cr1::DFF);
+        Ok(())
     }
 
-    /*---------------------------------------------------------------------------*/
-    /** @brief SPI Set Data Frame Format to 16 bits
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<()> {
+        for word in words.iter_mut() {
+            *word = self.xfer(*word as u16)? as u8;
+        }
 
-    @param[in] spi Unsigned int32. SPI peripheral identifier @ref spi_reg_base.
-    */
+        Ok(())
+    }
 
-    pub fn set_dff_16bit(&self) {
-        self.cr1.check(flags::cr1::DFF);
+    fn flush(&mut self) -> Result<()> {
+        self.wait_for(flags::sr::BSY, false)
     }
-}
\ No newline at end of file
+}
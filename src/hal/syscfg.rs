@@ -1,4 +1,5 @@
 use common::VolatileCell;
+use common::mmio;
 
 const SYSCFG_BASE: usize = 0x4001_3800;
 
@@ -12,22 +13,22 @@ struct Registers {
 }
 
 pub fn select_exti_source(exti: u16, port: u16) {
-    let regs: &Registers = unsafe { &*(SYSCFG_BASE as *const Registers) };
+    let regs: &Registers = unsafe { mmio::block(SYSCFG_BASE) };
     let shift = ((exti as u32) % 4) * 4;
     let reg = &regs.exticr[(exti as usize) / 4];
-    let mut val = reg.get() as u32;
+    let mut val = reg.get();
 
-    val = val & !(0x0Fu32 << shift as u32);
-    val = val | ((port as u32) << (shift as u32));
+    val &= !(0x0Fu32 << shift);
+    val |= (port as u32) << shift;
 
     reg.set(val);
 }
 
 pub fn get_exti_source(exti: u16) -> u16 {
-    let regs: &Registers = unsafe { &*(SYSCFG_BASE as *const Registers) };
+    let regs: &Registers = unsafe { mmio::block(SYSCFG_BASE) };
 
     let reg = (exti / 4) as usize;
-    let shift = (exti % 4) as u32;
+    let shift = ((exti % 4) * 4) as u32;
 
-    regs.exticr[reg].mask_get(0b1111 << shift, shift as u32) as u16
+    regs.exticr[reg].mask_get(0b1111, shift) as u16
 }
\ No newline at end of file
@@ -0,0 +1,146 @@
+//! SysTick, the core's 24-bit down counter, as a millisecond tick.
+//!
+//! `start` reloads the counter every millisecond of the core clock and
+//! `rcc::set_clock` adjusts the reload when it changes the clock. The
+//! milliseconds are counted by `tick`, which the application binds to the
+//! exception with `exception!(SysTick, systick::tick)`. The delays poll the
+//! counter itself, so they also work with the exception masked.
+
+use common::{asm, interrupt, mmio};
+use common::VolatileCell;
+use hal::scb::{self, ICSR_PENDSTSET};
+use rcc;
+
+const SYST_BASE: usize = 0xE000_E010;
+
+#[repr(C)]
+struct SysTick {
+    pub ctrl:  VolatileCell<u32>,
+    pub load:  VolatileCell<u32>,
+    pub val:   VolatileCell<u32>,
+    pub calib: VolatileCell<u32>,
+}
+
+const CTRL_ENABLE: u32 = 1 << 0;
+const CTRL_TICKINT: u32 = 1 << 1;
+/* Count the core clock rather than the core clock / 8. */
+const CTRL_CLKSOURCE: u32 = 1 << 2;
+
+fn syst() -> &'static SysTick {
+    unsafe { mmio::block(SYST_BASE) }
+}
+
+#[cfg(target_os = "none")]
+fn with_millis<R, F: FnOnce(&mut u64) -> R>(f: F) -> R {
+    static mut MILLIS: u64 = 0;
+
+    interrupt::free(|| f(unsafe { &mut *::core::ptr::addr_of_mut!(MILLIS) }))
+}
+
+#[cfg(not(target_os = "none"))]
+fn with_millis<R, F: FnOnce(&mut u64) -> R>(f: F) -> R {
+    thread_local! {
+        static MILLIS: ::core::cell::Cell<u64> = const { ::core::cell::Cell::new(0) };
+    }
+
+    MILLIS.with(|millis| {
+        let mut value = millis.get();
+        let r = f(&mut value);
+        millis.set(value);
+        r
+    })
+}
+
+/// Counter ticks in a millisecond of the current core clock.
+fn ticks_per_ms() -> u32 {
+    rcc::ahb_frequency() / 1000
+}
+
+/// Starts the millisecond tick with its exception enabled. The uptime keeps
+/// counting from where it was.
+pub fn start() {
+    let syst = syst();
+
+    syst.load.set(ticks_per_ms() - 1);
+    syst.val.set(0);
+    syst.ctrl.set(CTRL_CLKSOURCE | CTRL_TICKINT | CTRL_ENABLE);
+}
+
+pub fn stop() {
+    syst().ctrl.set(0);
+}
+
+pub fn is_running() -> bool {
+    syst().ctrl.get() & CTRL_ENABLE != 0
+}
+
+/// Recomputes the reload for the current core clock, if the tick runs. The
+/// millisecond in progress restarts.
+pub fn reconfigure() {
+    if is_running() {
+        let syst = syst();
+
+        syst.load.set(ticks_per_ms() - 1);
+        syst.val.set(0);
+    }
+}
+
+/// Counts a millisecond, to be bound to the SysTick exception.
+pub fn tick() {
+    with_millis(|millis| *millis += 1);
+}
+
+/// Microseconds since the tick was first started.
+pub fn uptime_us() -> u64 {
+    let syst = syst();
+
+    interrupt::free(|| {
+        let ticks = syst.load.get() as u64 + 1;
+        let mut val = syst.val.get();
+        let mut millis = with_millis(|millis| *millis);
+
+        /* The counter wrapped but `tick` hasn't run yet, the millisecond is
+           ours to count; VAL is read again as it may be from before the wrap. */
+        if scb::scb().icsr.get() & ICSR_PENDSTSET != 0 {
+            val = syst.val.get();
+            millis += 1;
+        }
+
+        millis * 1000 + (ticks - 1 - val as u64) * 1000 / ticks
+    })
+}
+
+/// Milliseconds since the tick was first started.
+pub fn uptime_ms() -> u64 {
+    uptime_us() / 1000
+}
+
+/// Busy-waits for at least `us` microseconds. Without the tick running this
+/// falls back to counting instructions.
+pub fn delay_us(us: u32) {
+    let syst = syst();
+
+    if !is_running() {
+        asm::delay_cycles(((rcc::ahb_frequency() / 1_000_000) as u64 * us as u64).min(u32::MAX as u64) as u32);
+        return;
+    }
+
+    let reload = syst.load.get() + 1;
+    let mut remaining = us as u64 * reload as u64 / 1000;
+    let mut last = syst.val.get();
+
+    while remaining > 0 {
+        let now = syst.val.get();
+        let elapsed = if now <= last { last - now } else { last + reload - now };
+
+        remaining = remaining.saturating_sub(elapsed as u64);
+        last = now;
+    }
+}
+
+/// Busy-waits for at least `ms` milliseconds.
+pub fn delay_ms(ms: u32) {
+    for _ in 0..ms {
+        delay_us(1000);
+    }
+}
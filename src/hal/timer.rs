@@ -1,23 +1,45 @@
+#![allow(clippy::tabs_in_doc_comments)]
+
+use core::convert::Infallible;
 use core::ops::Deref;
-use common::VolatileCell;
+use core::result;
+use common::asm;
+use common::mmio;
 use rcc;
-
-pub const TIM1:  Timer = Timer { addr: 0x4001_0000 };
-pub const TIM2:  Timer = Timer { addr: 0x4000_0000 };
-pub const TIM3:  Timer = Timer { addr: 0x4000_0400 };
-pub const TIM4:  Timer = Timer { addr: 0x4000_0800 };
-pub const TIM5:  Timer = Timer { addr: 0x4000_0c00 };
-pub const TIM6:  Timer = Timer { addr: 0x4000_1000 };
-pub const TIM7:  Timer = Timer { addr: 0x4000_1400 };
-pub const TIM8:  Timer = Timer { addr: 0x4001_0400 };
-pub const TIM9:  Timer = Timer { addr: 0x4001_4000 };
-pub const TIM10: Timer = Timer { addr: 0x4001_4400 };
-pub const TIM11: Timer = Timer { addr: 0x4001_4800 };
-pub const TIM12: Timer = Timer { addr: 0x4000_1800 };
-pub const TIM13: Timer = Timer { addr: 0x4000_1c00 };
-pub const TIM14: Timer = Timer { addr: 0x4000_2000 };
-
-#[derive(Copy, Clone, PartialEq, Eq)]
+use error::{Result, TimerError};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::pwm;
+
+use device::tim as flags;
+use self::flags::Registers;
+
+pub use device::tim::{
+    OutputCompareMode, InputCaptureFilter, InputCapturePrescaler, ClockDivision, Alignment, Direction,
+    MasterMode, SlaveMode, Trigger, LockLevel, Itr1Remap, Ti4Remap,
+};
+
+pub(crate) const TIM1:  Timer = Timer { addr: flags::TIM1 };
+pub(crate) const TIM2:  Timer = Timer { addr: flags::TIM2 };
+pub(crate) const TIM3:  Timer = Timer { addr: flags::TIM3 };
+pub(crate) const TIM4:  Timer = Timer { addr: flags::TIM4 };
+pub(crate) const TIM5:  Timer = Timer { addr: flags::TIM5 };
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const TIM6:  Timer = Timer { addr: flags::TIM6 };
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const TIM7:  Timer = Timer { addr: flags::TIM7 };
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const TIM8:  Timer = Timer { addr: flags::TIM8 };
+pub(crate) const TIM9:  Timer = Timer { addr: flags::TIM9 };
+pub(crate) const TIM10: Timer = Timer { addr: flags::TIM10 };
+pub(crate) const TIM11: Timer = Timer { addr: flags::TIM11 };
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const TIM12: Timer = Timer { addr: flags::TIM12 };
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const TIM13: Timer = Timer { addr: flags::TIM13 };
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+pub(crate) const TIM14: Timer = Timer { addr: flags::TIM14 };
+
+#[derive(PartialEq, Eq)]
 pub struct Timer {
     addr: usize
 }
@@ -28,652 +50,52 @@ impl Timer {
     }
 
     fn is_advanced(&self) -> bool {
-        *self == TIM1 || *self == TIM8
-    }
-}
-
-impl Deref for Timer {
-    type Target = Registers;
-
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*(self.addr as *const Registers) }
-    }
-}
-
-#[repr(C)]
-pub struct Registers {
-    pub cr1:  VolatileCell<u32>,
-    pub cr2:  VolatileCell<u32>, 
-    pub smcr: VolatileCell<u32>,
-    pub dier: VolatileCell<u32>,
-    pub sr:   VolatileCell<u32>,
-    pub egr:  VolatileCell<u32>, 
-    pub ccmr1: VolatileCell<u32>,
-    pub ccmr2: VolatileCell<u32>,
-    pub ccer: VolatileCell<u32>,
-    pub cnt:  VolatileCell<u32>,
-    pub psc:  VolatileCell<u32>,
-    pub arr:  VolatileCell<u32>,
-    pub rcr:  VolatileCell<u32>,
-    pub ccr1: VolatileCell<u32>,
-    pub ccr2: VolatileCell<u32>,
-    pub ccr3: VolatileCell<u32>,
-    pub ccr4: VolatileCell<u32>,
-    pub bdtr: VolatileCell<u32>,
-    pub dcr:  VolatileCell<u32>,
-    pub dmar: VolatileCell<u32>,
-    pub or:   VolatileCell<u32>,    
-}
-
-#[allow(dead_code)]
-mod flags {
-    pub mod cr1 {
-        /* CKD[1:0]: Clock division */
-        pub const CKD_CK_INT: u32 = 0x0 << 8; 
-        pub const CKD_CK_INT_MUL_2: u32 = 0x1 << 8; 
-        pub const CKD_CK_INT_MUL_4: u32 = 0x2 << 8; 
-        pub const CKD_CK_INT_MASK: u32 = 0x3 << 8;
-
-        /* ARPE: Auto-reload preload enable */
-        pub const ARPE: u32 = 1 << 7; 
-
-        /* CMS[1:0]: Center-aligned mode selection */
-        pub const CMS_EDGE: u32 = 0x0 << 5; 
-        pub const CMS_CENTER_1: u32 = 0x1 << 5; 
-        pub const CMS_CENTER_2: u32 = 0x2 << 5; 
-        pub const CMS_CENTER_3: u32 = 0x3 << 5; 
-        pub const CMS_MASK: u32 = 0x3 << 5; 
-
-        /* DIR: Direction */
-        pub const DIR_UP: u32 = 0 << 4; 
-        pub const DIR_DOWN: u32 = 1 << 4;
-
-        /* OPM: One pulse mode */
-        pub const OPM: u32 = 1 << 3; 
-
-        /* URS: Update request source */
-        pub const URS: u32 = 1 << 2; 
-
-        /* UDIS: Update disable */
-        pub const UDIS: u32 = 1 << 1; 
-
-        /* CEN: Counter enable */
-        pub const CEN: u32 = 1 << 0; 
-    }
-
-    pub mod cr2 {
-        /* OIS4: Output idle state 4 (OC4 output) */
-        pub const OIS4: u32 = 1 << 14; 
-
-        /* OIS3N: Output idle state 3 (OC3N output) */
-        pub const OIS3N: u32 = 1 << 13; 
-        
-        /* OIS3: Output idle state 3 (OC3 output) */
-        pub const OIS3: u32 = 1 << 12; 
-        
-        /* OIS2N: Output idle state 2 (OC2N output) */
-        pub const OIS2N: u32 = 1 << 11; 
-        
-        /* OIS2: Output idle state 2 (OC2 output) */
-        pub const OIS2: u32 = 1 << 10; 
-        
-        /* OIS1N: Output idle state 1 (OC1N output) */
-        pub const OIS1N: u32 = 1 << 9; 
-        
-        /* OIS1: Output idle state 1 (OC1 output) */
-        pub const OIS1: u32 = 1 << 8; 
-        pub const OIS_MASK: u32 = 0x7f << 8; 
-        
-        /* TI1S: TI1 selection */
-        pub const TI1S: u32 = 1 << 7; 
-
-        /* MMS[2:0]: Master mode selection */
-        pub const MMS_RESET: u32 = 0x0 << 4; 
-        pub const MMS_ENABLE: u32 = 0x1 << 4; 
-        pub const MMS_UPDATE: u32 = 0x2 << 4; 
-        pub const MMS_COMPARE_PULSE: u32 = 0x3 << 4; 
-        pub const MMS_COMPARE_OC1REF: u32 = 0x4 << 4; 
-        pub const MMS_COMPARE_OC2REF: u32 = 0x5 << 4; 
-        pub const MMS_COMPARE_OC3REF: u32 = 0x6 << 4; 
-        pub const MMS_COMPARE_OC4REF: u32 = 0x7 << 4; 
-        pub const MMS_MASK: u32 = 0x7 << 4; 
-
-        /* CCDS: Capture/compare DMA selection */
-        pub const CCDS: u32 = 1 << 3; 
-
-        /* CCUS: Capture/compare control update selection */
-        pub const CCUS: u32 = 1 << 2;
-
-        /* CCPC: Capture/compare preload control */
-        pub const CCPC: u32 = 1 << 0; 
-    }
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+        if *self == TIM8 {
+            return true;
+        }
 
-    pub mod smcr {
-        /* ETP: External trigger polarity */
-        pub const ETP: u32 = 1 << 15; 
-
-        /* ECE: External clock enable */
-        pub const ECE: u32 = 1 << 14;
-
-        /* ETPS[1:0]: External trigger prescaler */
-        pub const ETPS_OFF: u32 = 0x0 << 12; 
-        pub const ETPS_ETRP_DIV_2: u32 = 0x1 << 12; 
-        pub const ETPS_ETRP_DIV_4: u32 = 0x2 << 12; 
-        pub const ETPS_ETRP_DIV_8: u32 = 0x3 << 12; 
-        pub const ETPS_MASK: u32 = 0x3 << 12; 
-
-        /* ETF[3:0]: External trigger filter */
-        pub const ETF_OFF: u32 = 0x0 << 8; 
-        pub const ETF_CK_INT_N_2: u32 = 0x1 << 8; 
-        pub const ETF_CK_INT_N_4: u32 = 0x2 << 8; 
-        pub const ETF_CK_INT_N_8: u32 = 0x3 << 8; 
-        pub const ETF_DTS_DIV_2_N_6: u32 = 0x4 << 8; 
-        pub const ETF_DTS_DIV_2_N_8: u32 = 0x5 << 8; 
-        pub const ETF_DTS_DIV_4_N_6: u32 = 0x6 << 8; 
-        pub const ETF_DTS_DIV_4_N_8: u32 = 0x7 << 8; 
-        pub const ETF_DTS_DIV_8_N_6: u32 = 0x8 << 8; 
-        pub const ETF_DTS_DIV_8_N_8: u32 = 0x9 << 8; 
-        pub const ETF_DTS_DIV_16_N_5: u32 = 0xA << 8; 
-        pub const ETF_DTS_DIV_16_N_6: u32 = 0xB << 8; 
-        pub const ETF_DTS_DIV_16_N_8: u32 = 0xC << 8; 
-        pub const ETF_DTS_DIV_32_N_5: u32 = 0xD << 8; 
-        pub const ETF_DTS_DIV_32_N_6: u32 = 0xE << 8; 
-        pub const ETF_DTS_DIV_32_N_8: u32 = 0xF << 8; 
-        pub const ETF_MASK: u32 = 0xF << 8; 
-
-        /* MSM: Master/slave mode */
-        pub const MSM: u32 = 1 << 7; 
-
-        /* TS[2:0]: Trigger selection */
-
-        /** Internal Trigger 0 (ITR0) */
-        pub const TS_ITR0: u32 = 0x0 << 4; 
-        /** Internal Trigger 1 (ITR1) */
-        pub const TS_ITR1: u32 = 0x1 << 4; 
-        /** Internal Trigger 2 (ITR2) */
-        pub const TS_ITR2: u32 = 0x2 << 4; 
-        /** Internal Trigger 3 (ITR3) */
-        pub const TS_ITR3: u32 = 0x3 << 4; 
-        /** TI1 Edge Detector (TI1F_ED) */
-        pub const TS_TI1F_ED: u32 = 0x4 << 4; 
-        /** Filtered Timer Input 1 (TI1FP1) */
-        pub const TS_TI1FP1: u32 = 0x5 << 4; 
-        /** Filtered Timer Input 2 (TI2FP2) */
-        pub const TS_TI2FP2: u32 = 0x6 << 4; 
-        /** External Trigger input (ETRF) */
-        pub const TS_ETRF: u32 = 0x7 << 4; 
-        pub const TS_MASK: u32 = 0x7 << 4; 
-
-        /* SMS[2:0]: Slave mode selection */
-        /** Slave mode disabled */
-        pub const SMS_OFF: u32 = 0x0 << 0; 
-        /** Encoder mode 1 - Counter counts up/down on TI2FP2 edge depending on TI1FP1 level. */
-
-        pub const SMS_EM1: u32 = 0x1 << 0; 
-        /** Encoder mode 2 - Counter counts up/down on TI1FP1 edge depending on TI2FP2 level. */
-
-        pub const SMS_EM2: u32 = 0x2 << 0; 
-        /** Encoder mode 3 - Counter counts up/down on both TI1FP1 and TI2FP2 edges depending
-         *  on the level of the complementary input.
-         */
-        pub const SMS_EM3: u32 = 0x3 << 0; 
-        /** Reset Mode - Rising edge of the selected trigger input (TRGI) reinitializes the 
-         * counter and generates an update of the registers. 
-         */
-        pub const SMS_RM: u32 = 0x4 << 0;
-
-        /** Gated Mode - The counter clock is enabled when the trigger input (TRGI) is high. */
-        pub const SMS_GM: u32 = 0x5 << 0;
-
-        /**  Trigger Mode - The counter starts at a rising edge of the trigger TRGI. */
-        pub const SMS_TM: u32 = 0x6 << 0; 
-        /** External Clock Mode 1 - Rising edges of the selected trigger (TRGI) clock the counter. */
-
-        pub const SMS_ECM1: u32 = 0x7 << 0; 
-        pub const SMS_MASK: u32 = 0x7 << 0; 
+        *self == TIM1
     }
 
-    pub mod dier {
-        /* TDE: Trigger DMA request enable */
-        pub const TDE: u32 = 1 << 14; 
-
-        /* COMDE: COM DMA request enable */
-        pub const COMDE: u32 = 1 << 13;
-
-        /* CC4DE: Capture/Compare 4 DMA request enable */
-        pub const CC4DE: u32 = 1 << 12; 
-        
-        /* CC3DE: Capture/Compare 3 DMA request enable */
-        pub const CC3DE: u32 = 1 << 11; 
-        
-        /* CC2DE: Capture/Compare 2 DMA request enable */
-        pub const CC2DE: u32 = 1 << 10; 
-        
-        /* CC1DE: Capture/Compare 1 DMA request enable */
-        pub const CC1DE: u32 = 1 << 9; 
-        
-        /* UDE: Update DMA request enable */
-        pub const UDE: u32 = 1 << 8; 
-        
-        /* BIE: Break interrupt enable */
-        pub const BIE: u32 = 1 << 7; 
-        
-        /* TIE: Trigger interrupt enable */
-        pub const TIE: u32 = 1 << 6; 
-        
-        /* COMIE: COM interrupt enable */
-        pub const COMIE: u32 = 1 << 5; 
-        
-        /* CC4IE: Capture/compare 4 interrupt enable */
-        pub const CC4IE: u32 = 1 << 4; 
-        
-        /* CC3IE: Capture/compare 3 interrupt enable */
-        pub const CC3IE: u32 = 1 << 3; 
-        
-        /* CC2IE: Capture/compare 2 interrupt enable */
-        pub const CC2IE: u32 = 1 << 2; 
-        
-        /* CC1IE: Capture/compare 1 interrupt enable */
-        pub const CC1IE: u32 = 1 << 1; 
-        
-        /* UIE: Update interrupt enable */
-        pub const UIE: u32 = 1 << 0; 
-    }
+    fn on_apb2(&self) -> bool {
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
+        if *self == TIM8 {
+            return true;
+        }
 
-    pub mod sr {
-        /* CC4OF: Capture/compare 4 overcapture flag */
-        pub const CC4OF: u32 = 1 << 12; 
-        /* CC3OF: Capture/compare 3 overcapture flag */
-        pub const CC3OF: u32 = 1 << 11; 
-        /* CC2OF: Capture/compare 2 overcapture flag */
-        pub const CC2OF: u32 = 1 << 10; 
-        /* CC1OF: Capture/compare 1 overcapture flag */
-        pub const CC1OF: u32 = 1 << 9; 
-        /* BIF: Break interrupt flag */
-        pub const BIF: u32 = 1 << 7; 
-        /* TIF: Trigger interrupt flag */
-        pub const TIF: u32 = 1 << 6; 
-        /* COMIF: COM interrupt flag */
-        pub const COMIF: u32 = 1 << 5; 
-        /* CC4IF: Capture/compare 4 interrupt flag */
-        pub const CC4IF: u32 = 1 << 4; 
-        /* CC3IF: Capture/compare 3 interrupt flag */
-        pub const CC3IF: u32 = 1 << 3; 
-        /* CC2IF: Capture/compare 2 interrupt flag */
-        pub const CC2IF: u32 = 1 << 2; 
-        /* CC1IF: Capture/compare 1 interrupt flag */
-        pub const CC1IF: u32 = 1 << 1; 
-        /* UIF: Update interrupt flag */
-        pub const UIF: u32 = 1 << 0;
+        *self == TIM1 || *self == TIM9 || *self == TIM10 || *self == TIM11
     }
 
-    pub mod egr {
-        /* BG: Break generation */
-        pub const BG: u32 = 1 << 7;
-
-        /* TG: Trigger generation */
-        pub const TG: u32 = 1 << 6;
-
-        /* COMG: Capture/compare control update generation */
-        pub const COMG: u32 = 1 << 5;
-
-        /* CC4G: Capture/compare 4 generation */
-        pub const CC4G: u32 = 1 << 4;
-
-        /* CC3G: Capture/compare 3 generation */
-        pub const CC3G: u32 = 1 << 3;
-
-        /* CC2G: Capture/compare 2 generation */
-        pub const CC2G: u32 = 1 << 2;
+    /// Counter clock before the prescaler. The timers run at twice their bus
+    /// clock whenever the bus is divided down from AHB.
+    pub fn clock_frequency(&self) -> u32 {
+        let bus = if self.on_apb2() { rcc::apb2_frequency() } else { rcc::apb1_frequency() };
 
-        /* CC1G: Capture/compare 1 generation */
-        pub const CC1G: u32 = 1 << 1;
-
-        /* UG: Update generation */
-        pub const UG: u32 = 1 << 0;
+        if bus == rcc::ahb_frequency() { bus } else { bus * 2 }
     }
 
-    pub mod ccmr1 {
-        /* OC2CE: Output compare 2 clear enable */
-        pub const OC2CE: u32 = 1 << 15; 
-
-        /* OC2M[2:0]: Output compare 2 mode */
-        pub const OC2M_FROZEN: u32 = 0x0 << 12; 
-        pub const OC2M_ACTIVE: u32 = 0x1 << 12; 
-        pub const OC2M_INACTIVE: u32 = 0x2 << 12; 
-        pub const OC2M_TOGGLE: u32 = 0x3 << 12; 
-        pub const OC2M_FORCE_LOW: u32 = 0x4 << 12; 
-        pub const OC2M_FORCE_HIGH: u32 = 0x5 << 12; 
-        pub const OC2M_PWM1: u32 = 0x6 << 12; 
-        pub const OC2M_PWM2: u32 = 0x7 << 12; 
-        pub const OC2M_MASK: u32 = 0x7 << 12; 
-
-        /* OC2PE: Output compare 2 preload enable */
-        pub const OC2PE: u32 = 1 << 11; 
-
-        /* OC2FE: Output compare 2 fast enable */
-        pub const OC2FE: u32 = 1 << 10; 
-
-        /* CC2S[1:0]: Capture/compare 2 selection */
-        /* Note: CC2S bits are writable only when the channel is OFF (CC2E = 0 in TIMx_CCER). */
-        pub const CC2S_OUT: u32 = 0x0 << 8; 
-        pub const CC2S_IN_TI2: u32 = 0x1 << 8; 
-        pub const CC2S_IN_TI1: u32 = 0x2 << 8; 
-        pub const CC2S_IN_TRC: u32 = 0x3 << 8; 
-        pub const CC2S_MASK: u32 = 0x3 << 8; 
-
-        /* OC1CE: Output compare 1 clear enable */
-        pub const OC1CE: u32 = 1 << 7; 
-
-        /* OC1M[2:0]: Output compare 1 mode */
-        pub const OC1M_FROZEN: u32 = 0x0 << 4; 
-        pub const OC1M_ACTIVE: u32 = 0x1 << 4; 
-        pub const OC1M_INACTIVE: u32 = 0x2 << 4; 
-        pub const OC1M_TOGGLE: u32 = 0x3 << 4; 
-        pub const OC1M_FORCE_LOW: u32 = 0x4 << 4; 
-        pub const OC1M_FORCE_HIGH: u32 = 0x5 << 4; 
-        pub const OC1M_PWM1: u32 = 0x6 << 4; 
-        pub const OC1M_PWM2: u32 = 0x7 << 4; 
-        pub const OC1M_MASK: u32 = 0x7 << 4; 
-
-        /* OC1PE: Output compare 1 preload enable */
-        pub const OC1PE: u32 = 1 << 3; 
-
-        /* OC1FE: Output compare 1 fast enable */
-        pub const OC1FE: u32 = 1 << 2; 
-
-        /* CC1S[1:0]: Capture/compare 1 selection */
-        /* Note: CC2S bits are writable only when the channel is OFF (CC2E = 0 in TIMx_CCER). */
-        pub const CC1S_OUT: u32 = 0x0 << 0; 
-        pub const CC1S_IN_TI2: u32 = 0x2 << 0; 
-        pub const CC1S_IN_TI1: u32 = 0x1 << 0; 
-        pub const CC1S_IN_TRC: u32 = 0x3 << 0; 
-        pub const CC1S_MASK: u32 = 0x3 << 0; 
-
-        /* --- Input capture mode --- */
-
-        /* IC2F[3:0]: Input capture 2 filter */
-        pub const IC2F_OFF: u32 = 0x0 << 12; 
-        pub const IC2F_CK_INT_N_2: u32 = 0x1 << 12; 
-        pub const IC2F_CK_INT_N_4: u32 = 0x2 << 12; 
-        pub const IC2F_CK_INT_N_8: u32 = 0x3 << 12; 
-        pub const IC2F_DTF_DIV_2_N_6: u32 = 0x4 << 12; 
-        pub const IC2F_DTF_DIV_2_N_8: u32 = 0x5 << 12; 
-        pub const IC2F_DTF_DIV_4_N_6: u32 = 0x6 << 12; 
-        pub const IC2F_DTF_DIV_4_N_8: u32 = 0x7 << 12; 
-        pub const IC2F_DTF_DIV_8_N_6: u32 = 0x8 << 12; 
-        pub const IC2F_DTF_DIV_8_N_8: u32 = 0x9 << 12; 
-        pub const IC2F_DTF_DIV_16_N_5: u32 = 0xA << 12; 
-        pub const IC2F_DTF_DIV_16_N_6: u32 = 0xB << 12; 
-        pub const IC2F_DTF_DIV_16_N_8: u32 = 0xC << 12; 
-        pub const IC2F_DTF_DIV_32_N_5: u32 = 0xD << 12; 
-        pub const IC2F_DTF_DIV_32_N_6: u32 = 0xE << 12; 
-        pub const IC2F_DTF_DIV_32_N_8: u32 = 0xF << 12; 
-        pub const IC2F_MASK: u32 = 0xF << 12; 
-
-        /* IC2PSC[1:0]: Input capture 2 prescaler */
-        pub const IC2PSC_OFF: u32 = 0x0 << 10; 
-        pub const IC2PSC_2: u32 = 0x1 << 10; 
-        pub const IC2PSC_4: u32 = 0x2 << 10; 
-        pub const IC2PSC_8: u32 = 0x3 << 10; 
-        pub const IC2PSC_MASK: u32 = 0x3 << 10; 
-
-        /* IC1F[3:0]: Input capture 1 filter */
-        pub const IC1F_OFF: u32 = 0x0 << 4; 
-        pub const IC1F_CK_INT_N_2: u32 = 0x1 << 4; 
-        pub const IC1F_CK_INT_N_4: u32 = 0x2 << 4; 
-        pub const IC1F_CK_INT_N_8: u32 = 0x3 << 4; 
-        pub const IC1F_DTF_DIV_2_N_6: u32 = 0x4 << 4; 
-        pub const IC1F_DTF_DIV_2_N_8: u32 = 0x5 << 4; 
-        pub const IC1F_DTF_DIV_4_N_6: u32 = 0x6 << 4; 
-        pub const IC1F_DTF_DIV_4_N_8: u32 = 0x7 << 4; 
-        pub const IC1F_DTF_DIV_8_N_6: u32 = 0x8 << 4; 
-        pub const IC1F_DTF_DIV_8_N_8: u32 = 0x9 << 4; 
-        pub const IC1F_DTF_DIV_16_N_5: u32 = 0xA << 4; 
-        pub const IC1F_DTF_DIV_16_N_6: u32 = 0xB << 4; 
-        pub const IC1F_DTF_DIV_16_N_8: u32 = 0xC << 4; 
-        pub const IC1F_DTF_DIV_32_N_5: u32 = 0xD << 4; 
-        pub const IC1F_DTF_DIV_32_N_6: u32 = 0xE << 4; 
-        pub const IC1F_DTF_DIV_32_N_8: u32 = 0xF << 4; 
-        pub const IC1F_MASK: u32 = 0xF << 4; 
-
-        /* IC1PSC[1:0]: Input capture 1 prescaler */
-        pub const IC1PSC_OFF: u32 = 0x0 << 2; 
-        pub const IC1PSC_2: u32 = 0x1 << 2; 
-        pub const IC1PSC_4: u32 = 0x2 << 2; 
-        pub const IC1PSC_8: u32 = 0x3 << 2; 
-        pub const IC1PSC_MASK: u32 = 0x3 << 2; 
-
+    /// An output compare channel as an embedded-hal PWM output. The channel
+    /// mode and the period are configured as usual, the channel only sets the
+    /// compare value.
+    pub fn pwm_channel(&self, channel: OutputCompareChannel) -> PwmChannel<'_> {
+        PwmChannel { timer: self, channel }
     }
+}
 
-    pub mod ccmr2 {
-        /* OC4CE: Output compare 4 clear enable */
-        pub const OC4CE: u32 = 1 << 15; 
-
-        /* OC4M[2:0]: Output compare 4 mode */
-        pub const OC4M_FROZEN: u32 = 0x0 << 12; 
-        pub const OC4M_ACTIVE: u32 = 0x1 << 12; 
-        pub const OC4M_INACTIVE: u32 = 0x2 << 12; 
-        pub const OC4M_TOGGLE: u32 = 0x3 << 12; 
-        pub const OC4M_FORCE_LOW: u32 = 0x4 << 12; 
-        pub const OC4M_FORCE_HIGH: u32 = 0x5 << 12; 
-        pub const OC4M_PWM1: u32 = 0x6 << 12; 
-        pub const OC4M_PWM2: u32 = 0x7 << 12; 
-        pub const OC4M_MASK: u32 = 0x7 << 12; 
-
-        /* OC4PE: Output compare 4 preload enable */
-        pub const OC4PE: u32 = 1 << 11; 
-
-        /* OC4FE: Output compare 4 fast enable */
-        pub const OC4FE: u32 = 1 << 10; 
-
-        /* CC4S[1:0]: Capture/compare 4 selection */
-        /* Note: CC2S bits are writable only when the channel is OFF (CC2E = 0 in TIMx_CCER). */
-        pub const CC4S_OUT: u32 = 0x0 << 8; 
-        pub const CC4S_IN_TI4: u32 = 0x1 << 8; 
-        pub const CC4S_IN_TI3: u32 = 0x2 << 8; 
-        pub const CC4S_IN_TRC: u32 = 0x3 << 8; 
-        pub const CC4S_MASK: u32 = 0x3 << 8; 
-
-        /* OC3CE: Output compare 3 clear enable */
-        pub const OC3CE: u32 = 1 << 7; 
-
-        /* OC3M[2:0]: Output compare 3 mode */
-        pub const OC3M_FROZEN: u32 = 0x0 << 4; 
-        pub const OC3M_ACTIVE: u32 = 0x1 << 4; 
-        pub const OC3M_INACTIVE: u32 = 0x2 << 4; 
-        pub const OC3M_TOGGLE: u32 = 0x3 << 4; 
-        pub const OC3M_FORCE_LOW: u32 = 0x4 << 4; 
-        pub const OC3M_FORCE_HIGH: u32 = 0x5 << 4; 
-        pub const OC3M_PWM1: u32 = 0x6 << 4; 
-        pub const OC3M_PWM2: u32 = 0x7 << 4; 
-        pub const OC3M_MASK: u32 = 0x7 << 4; 
-
-        /* OC3PE: Output compare 3 preload enable */
-        pub const OC3PE: u32 = 1 << 3; 
-
-        /* OC3FE: Output compare 3 fast enable */
-        pub const OC3FE: u32 = 1 << 2; 
-
-        /* CC3S[1:0]: Capture/compare 3 selection */
-        /* Note: CC2S bits are writable only when the channel is OFF (CC2E = 0 in TIMx_CCER). */
-        pub const CC3S_OUT: u32 = 0x0 << 0; 
-        pub const CC3S_IN_TI3: u32 = 0x1 << 0; 
-        pub const CC3S_IN_TI4: u32 = 0x2 << 0; 
-        pub const CC3S_IN_TRC: u32 = 0x3 << 0; 
-        pub const CC3S_MASK: u32 = 0x3 << 0; 
-
-        /* --- Input capture mode --- */
-
-        /* IC4F[3:0]: Input capture 4 filter */
-        pub const IC4F_OFF: u32 = 0x0 << 12; 
-        pub const IC4F_CK_INT_N_2: u32 = 0x1 << 12; 
-        pub const IC4F_CK_INT_N_4: u32 = 0x2 << 12; 
-        pub const IC4F_CK_INT_N_8: u32 = 0x3 << 12; 
-        pub const IC4F_DTF_DIV_2_N_6: u32 = 0x4 << 12; 
-        pub const IC4F_DTF_DIV_2_N_8: u32 = 0x5 << 12; 
-        pub const IC4F_DTF_DIV_4_N_6: u32 = 0x6 << 12; 
-        pub const IC4F_DTF_DIV_4_N_8: u32 = 0x7 << 12; 
-        pub const IC4F_DTF_DIV_8_N_6: u32 = 0x8 << 12; 
-        pub const IC4F_DTF_DIV_8_N_8: u32 = 0x9 << 12; 
-        pub const IC4F_DTF_DIV_16_N_5: u32 = 0xA << 12; 
-        pub const IC4F_DTF_DIV_16_N_6: u32 = 0xB << 12; 
-        pub const IC4F_DTF_DIV_16_N_8: u32 = 0xC << 12; 
-        pub const IC4F_DTF_DIV_32_N_5: u32 = 0xD << 12; 
-        pub const IC4F_DTF_DIV_32_N_6: u32 = 0xE << 12; 
-        pub const IC4F_DTF_DIV_32_N_8: u32 = 0xF << 12; 
-        pub const IC4F_MASK: u32 = 0xF << 12; 
-
-        /* IC4PSC[1:0]: Input capture 4 prescaler */
-        pub const IC4PSC_OFF: u32 = 0x0 << 10; 
-        pub const IC4PSC_2: u32 = 0x1 << 10; 
-        pub const IC4PSC_4: u32 = 0x2 << 10; 
-        pub const IC4PSC_8: u32 = 0x3 << 10; 
-        pub const IC4PSC_MASK: u32 = 0x3 << 10; 
-
-        /* IC3F[3:0]: Input capture 3 filter */
-        pub const IC3F_OFF: u32 = 0x0 << 4; 
-        pub const IC3F_CK_INT_N_2: u32 = 0x1 << 4; 
-        pub const IC3F_CK_INT_N_4: u32 = 0x2 << 4; 
-        pub const IC3F_CK_INT_N_8: u32 = 0x3 << 4; 
-        pub const IC3F_DTF_DIV_2_N_6: u32 = 0x4 << 4; 
-        pub const IC3F_DTF_DIV_2_N_8: u32 = 0x5 << 4; 
-        pub const IC3F_DTF_DIV_4_N_6: u32 = 0x6 << 4; 
-        pub const IC3F_DTF_DIV_4_N_8: u32 = 0x7 << 4; 
-        pub const IC3F_DTF_DIV_8_N_6: u32 = 0x8 << 4; 
-        pub const IC3F_DTF_DIV_8_N_8: u32 = 0x9 << 4; 
-        pub const IC3F_DTF_DIV_16_N_5: u32 = 0xA << 4; 
-        pub const IC3F_DTF_DIV_16_N_6: u32 = 0xB << 4; 
-        pub const IC3F_DTF_DIV_16_N_8: u32 = 0xC << 4; 
-        pub const IC3F_DTF_DIV_32_N_5: u32 = 0xD << 4; 
-        pub const IC3F_DTF_DIV_32_N_6: u32 = 0xE << 4; 
-        pub const IC3F_DTF_DIV_32_N_8: u32 = 0xF << 4; 
-        pub const IC3F_MASK: u32 = 0xF << 4; 
-
-        /* IC3PSC[1:0]: Input capture 3 prescaler */
-        pub const IC3PSC_OFF: u32 = 0x0 << 2; 
-        pub const IC3PSC_2: u32 = 0x1 << 2; 
-        pub const IC3PSC_4: u32 = 0x2 << 2; 
-        pub const IC3PSC_8: u32 = 0x3 << 2; 
-        pub const IC3PSC_MASK: u32 = 0x3 << 2; 
-
+fn check_advanced(timer: &Timer) -> Result<()> {
+    if !timer.is_advanced() {
+        return Err(TimerError::NotAdvanced.into());
     }
 
-    pub mod ccer {
-        /* CC4NP: Capture/compare 4 complementary output polarity */
-        pub const CC4NP: u32 = 1 << 15; 
-        
-        /* CC4NE: Capture/compare 4 complementary output enable */
-        pub const CC4NE: u32 = 1 << 14;
-
-        /* CC4P: Capture/compare 4 output polarity */
-        pub const CC4P: u32 = 1 << 13; 
-        
-        /* CC4E: Capture/compare 4 output enable */
-        pub const CC4E: u32 = 1 << 12; 
-        
-        /* CC3NP: Capture/compare 3 complementary output polarity */
-        pub const CC3NP: u32 = 1 << 11; 
-        
-        /* CC3NE: Capture/compare 3 complementary output enable */
-        pub const CC3NE: u32 = 1 << 10; 
-        
-        /* CC3P: Capture/compare 3 output polarity */
-        pub const CC3P: u32 = 1 << 9; 
-        
-        /* CC3E: Capture/compare 3 output enable */
-        pub const CC3E: u32 = 1 << 8; 
-        
-        /* CC2NP: Capture/compare 2 complementary output polarity */
-        pub const CC2NP: u32 = 1 << 7; 
-        
-        /* CC2NE: Capture/compare 2 complementary output enable */
-        pub const CC2NE: u32 = 1 << 6; 
-        
-        /* CC2P: Capture/compare 2 output polarity */
-        pub const CC2P: u32 = 1 << 5; 
-        
-        /* CC2E: Capture/compare 2 output enable */
-        pub const CC2E: u32 = 1 << 4; 
-        
-        /* CC1NP: Capture/compare 1 complementary output polarity */
-        pub const CC1NP: u32 = 1 << 3; 
-        
-        /* CC1NE: Capture/compare 1 complementary output enable */
-        pub const CC1NE: u32 = 1 << 2; 
-        
-        /* CC1P: Capture/compare 1 output polarity */
-        pub const CC1P: u32 = 1 << 1; 
-        
-        /* CC1E: Capture/compare 1 output enable */
-        pub const CC1E: u32 = 1 << 0; 
-    }
+    Ok(())
+}
 
-    pub mod bdtr {
-        /* MOE: Main output enable */
-        pub const MOE: u32 = 1 << 15; 
-        
-        /* AOE: Automatic output enable */
-        pub const AOE: u32 = 1 << 14; 
-        
-        /* BKP: Break polarity */
-        pub const BKP: u32 = 1 << 13; 
-        
-        /* BKE: Break enable */
-        pub const BKE: u32 = 1 << 12; 
-        
-        /* OSSR: Off-state selection of run mode */
-        pub const OSSR: u32 = 1 << 11; 
-        
-        /* OSSI: Off-state selection of idle mode */
-        pub const OSSI: u32 = 1 << 10; 
-        
-        /* LOCK[1:0]: Lock configuration */
-        pub const LOCK_OFF: u32     = 0x0 << 8; 
-        pub const LOCK_LEVEL_1: u32 = 0x1 << 8; 
-        pub const LOCK_LEVEL_2: u32 = 0x2 << 8; 
-        pub const LOCK_LEVEL_3: u32 = 0x3 << 8; 
-        pub const LOCK_MASK: u32    = 0x3 << 8; 
-        
-        /* DTG[7:0]: Dead-time generator set-up */
-        pub const DTG_MASK: u32 = 0x00FF;
-
-        /* --- TIMx_DCR values ----------------------------------------------------- */
-
-        /* DBL[4:0]: DMA burst length */
-        pub const DBL_MASK: u32 = 0x1F << 8; 
-
-        /* DBA[4:0]: DMA base address */
-        pub const DBA_MASK: u32 = 0x1F << 0;
-    }
+impl Deref for Timer {
+    type Target = Registers;
 
-    pub mod or {
-        /** Internal Trigger 1 remapped to timer 8 trigger out */
-        pub const ITR1_RMP_TIM8_TRGOU: u32 = 0x0 << 10;
-        
-        /** Internal Trigger 1 remapped to PTP trigger out */
-        pub const ITR1_RMP_PTP: u32 = 0x1 << 10;
-        
-        /** Internal Trigger 1 remapped to USB OTG FS SOF */
-        pub const ITR1_RMP_OTG_FS_SOF: u32 = 0x2 << 10;
-        
-        /** Internal Trigger 1 remapped to USB OTG HS SOF */
-        pub const ITR1_RMP_OTG_HS_SOF: u32 = 0x3 << 10;
-        pub const ITR1_RMP_MASK: u32 = 0x3 << 10;
-
-        /** Internal Trigger 4 remapped to GPIO (see reference manual) */
-        pub const ITR4_RMP_GPIO: u32 = 0x0 << 6;
-        
-        /** Internal Trigger 4 remapped to LSI internal clock */
-        pub const ITR4_RMP_LSI: u32 = 0x1 << 6;
-        
-        /** Internal Trigger 4 remapped to LSE internal clock */
-        pub const ITR4_RMP_LSE: u32 = 0x2 << 6;
-        
-        /** Internal Trigger 4 remapped to RTC output event */
-        pub const ITR4_RMP_RTC: u32 = 0x3 << 6;
-        
-        pub const ITR4_RMP_MASK: u32 = 0x3 << 6;
+    fn deref(&self) -> &Self::Target {
+        unsafe { mmio::block(self.addr) }
     }
 }
 
@@ -697,19 +119,6 @@ pub enum OutputCompareChannel {
 	OChannel4,
 }
 
-/** Output Compare mode designators */
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum OutputCompareMode {
-	Frozen,
-	Active,
-	Inactive,
-	Toggle,
-	ForceLow,
-	ForceHigh,
-	PWM1,
-	PWM2,
-}
-
 /** Input Capture channel designators */
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum InputCaptureChannel {
@@ -719,45 +128,6 @@ pub enum InputCaptureChannel {
 	Channel4,
 }
 
-/** Input Capture input filter. The frequency used to sample the
-input and the number of events needed to validate an output transition.
-
-TIM_IC_CK_INT_N_x No division from the Deadtime and Sampling Clock frequency
-(DTF), filter length x
-TIM_IC_DTF_DIV_y_N_x Division by y from the DTF, filter length x
- */
-#[derive(Debug, Copy, Clone, PartialEq)]
-#[allow(non_camel_case_types)]
-pub enum InputCaptureFilter {
-	OFF,
-	CK_INT_N_2,
-	CK_INT_N_4,
-	CK_INT_N_8,
-	DTF_DIV_2_N_6,
-	DTF_DIV_2_N_8,
-	DTF_DIV_4_N_6,
-	DTF_DIV_4_N_8,
-	DTF_DIV_8_N_6,
-	DTF_DIV_8_N_8,
-	DTF_DIV_16_N_5,
-	DTF_DIV_16_N_6,
-	DTF_DIV_16_N_8,
-	DTF_DIV_32_N_5,
-	DTF_DIV_32_N_6,
-	DTF_DIV_32_N_8,
-}
-
-/** Input Capture input prescaler.
-
-InputCapturePrescaler_x Input capture is done every x events*/
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum InputCapturePrescaler {
-	PrescalerOff,
-	Prescaler2,
-	Prescaler4,
-	Prescaler8,
-}
-
 /** Input Capture input source.
 
 The direction of the channel (input/output) as well as the input used. */
@@ -778,33 +148,57 @@ pub enum ExternalTriggerPolarity {
 	Falling,
 }
 
-pub fn reset(timer: Timer) {
-    match timer {
+/** Timer events, numbered by their bit in DIER, SR and EGR */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+	Update  = 0,
+	CC1     = 1,
+	CC2     = 2,
+	CC3     = 3,
+	CC4     = 4,
+	/** Capture/compare control update, advanced timers only */
+	Com     = 5,
+	Trigger = 6,
+	/** Break, advanced timers only */
+	Break   = 7,
+}
+
+
+pub fn reset(timer: &Timer) -> Result<()> {
+    match *timer {
         TIM1  => rcc::reset_pulse(rcc::Peripheral::TIM1),
         TIM2  => rcc::reset_pulse(rcc::Peripheral::TIM2),
         TIM3  => rcc::reset_pulse(rcc::Peripheral::TIM3),
         TIM4  => rcc::reset_pulse(rcc::Peripheral::TIM4),
         TIM5  => rcc::reset_pulse(rcc::Peripheral::TIM5),
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         TIM6  => rcc::reset_pulse(rcc::Peripheral::TIM6),
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         TIM7  => rcc::reset_pulse(rcc::Peripheral::TIM7),
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         TIM8  => rcc::reset_pulse(rcc::Peripheral::TIM8),
         TIM9  => rcc::reset_pulse(rcc::Peripheral::TIM9),
         TIM10 => rcc::reset_pulse(rcc::Peripheral::TIM10),
         TIM11 => rcc::reset_pulse(rcc::Peripheral::TIM11),
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         TIM12 => rcc::reset_pulse(rcc::Peripheral::TIM12),
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         TIM13 => rcc::reset_pulse(rcc::Peripheral::TIM13),
+        #[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))]
         TIM14 => rcc::reset_pulse(rcc::Peripheral::TIM14),
-        Timer { .. } => { } 
+        Timer { .. } => return Err(TimerError::UnknownTimer.into()),
     }
+
+    Ok(())
 }
 
-pub fn enable_update_irq(timer: Timer) {
-	timer.dier.check(flags::dier::UIE);
+pub fn enable_update_irq(timer: &Timer) {
+	timer.dier.set_bit(flags::dier::UIE);
 }
 
 
-pub fn disable_update_irq(timer: Timer) {
-	timer.dier.uncheck(flags::dier::UIE);
+pub fn disable_update_irq(timer: &Timer) {
+	timer.dier.clear_bit(flags::dier::UIE);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -812,12 +206,10 @@ pub fn disable_update_irq(timer: Timer) {
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] irq Unsigned int32. @ref tim_irq_enable. Logical OR of all interrupt
-enable bits to be set
+@param[in] event Event. Event whose interrupt is to be enabled
 */
-
-pub fn enable_irq(timer: Timer, irq: u32) {
-	timer.dier.check(irq);
+pub fn enable_irq(timer: &Timer, event: Event) {
+	timer.dier.set_bit(flags::dier::UIE.nth(event as u32, 1));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -825,51 +217,47 @@ pub fn enable_irq(timer: Timer, irq: u32) {
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] irq Unsigned int32. @ref tim_irq_enable. Logical OR of all interrupt
-enable bits to be cleared
+@param[in] event Event. Event whose interrupt is to be disabled
 */
-
-pub fn disable_irq(timer: Timer, irq: u32) {
-    timer.dier.uncheck(irq);
+pub fn disable_irq(timer: &Timer, event: Event) {
+    timer.dier.clear_bit(flags::dier::UIE.nth(event as u32, 1));
 }
 
 /*---------------------------------------------------------------------------*/
 /** @brief Return Interrupt Source.
 
-Returns true if the specified interrupt flag (UIF, TIF or CCxIF, with BIF or
-COMIF for advanced timers) was set and the interrupt was enabled. If the
-specified flag is not an interrupt flag, the function returns false.
+Returns true if the flag of the specified event (Update, Trigger or CCx, with
+Break or Com for advanced timers) was set and its interrupt was enabled.
 
 @todo Timers 6-7, 9-14 have fewer interrupts, but invalid flags are not caught
 here.
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] flag Unsigned int32. Status register flag  @ref flags::sr::values.
+@param[in] event Event. Event to check
 @returns boolean: flag set.
 */
+pub fn interrupt_source(timer: &Timer, event: Event) -> bool {
+    let bit = flags::sr::UIF.nth(event as u32, 1).mask();
 
-pub fn interrupt_source(timer: Timer, flag: u32) -> bool {
-    /* flag not set or interrupt disabled or not an interrupt source */
-	if ((timer.sr.get() & timer.dier.get() & flag) == 0) ||
-		(flag > flags::sr::BIF) {
-
+    /* flag not set or interrupt disabled */
+	if (timer.sr.get() & timer.dier.get() & bit) == 0 {
 		return false;
 	}
 
-    if (flag == flags::sr::BIF) || (flag == flags::sr::COMIF) {
+    if (event == Event::Break) || (event == Event::Com) {
 		return timer.is_advanced();
 	}
 
-    return true;
+    true
 }
 
-pub fn is_update_flag_checked(timer: Timer) -> bool {
-    timer.sr.test(flags::sr::UIF)
+pub fn is_update_flag_checked(timer: &Timer) -> bool {
+    timer.sr.is_set(flags::sr::UIF)
 }
 
-pub fn clear_update_flag(timer: Timer) {
-    timer.sr.set(!flags::sr::UIF);
+pub fn clear_update_flag(timer: &Timer) {
+    timer.sr.set(!flags::sr::UIF.mask());
 }
 
 /*---------------------------------------------------------------------------*/
@@ -877,12 +265,11 @@ pub fn clear_update_flag(timer: Timer) {
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] flag Unsigned int32. Status register flag  @ref flags::sr::values.
+@param[in] event Event. Event whose status flag is read
 @returns boolean: flag set.
 */
-
-pub fn get_flag(timer: Timer, flag: u32) -> bool {
-	timer.sr.test(flag)
+pub fn get_flag(timer: &Timer, event: Event) -> bool {
+	timer.sr.is_set(flags::sr::UIF.nth(event as u32, 1))
 }
 
 /*---------------------------------------------------------------------------*/
@@ -890,11 +277,10 @@ pub fn get_flag(timer: Timer, flag: u32) -> bool {
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] flag Unsigned int32. @ref flags::sr::values. Status register flag.
+@param[in] event Event. Event whose status flag is cleared
 */
-
-pub fn clear_flag(timer: Timer, flag: u32) {
-	timer.sr.set(!flag);
+pub fn clear_flag(timer: &Timer, event: Event) {
+	timer.sr.set(!flags::sr::UIF.nth(event as u32, 1).mask());
 }
 
 /*---------------------------------------------------------------------------*/
@@ -916,16 +302,12 @@ hardware and cannot be written. The count direction setting has no effect
 in this case.
 
 @param[in] Timer struct. Timer register address base @ref
-tim_reg_fn (TIM1, TIM2 ... TIM5, TIM8) -> base @param[in] clock_div Unsigned int32. Clock Divider Ratio in bits 8,9: @ref
-tim_x_cr1_cdr
-@param[in] alignment Unsigned int32. Alignment bits in 5,6: @ref tim_x_cr1_cms
-@param[in] direction Unsigned int32. Count direction in bit 4,: @ref
-tim_x_cr1_dir
+tim_reg_fn (TIM1, TIM2 ... TIM5, TIM8) -> base @param[in] clock_div ClockDivision. Clock Divider Ratio
+@param[in] alignment Alignment. Edge or center alignment
+@param[in] direction Direction. Count direction
 */
-
-pub fn set_mode(timer: Timer, clock_div: u32, alignment: u32, direction: u32) {
-	timer.cr1.uncheck(flags::cr1::CKD_CK_INT_MASK | flags::cr1::CMS_MASK | flags::cr1::DIR_DOWN);
-	timer.cr1.check(clock_div | alignment | direction);
+pub fn set_mode(timer: &Timer, clock_div: ClockDivision, alignment: Alignment, direction: Direction) {
+	timer.cr1.modify(|_, w| w.ckd(clock_div).cms(alignment).dir(direction));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -936,13 +318,10 @@ in the advanced timers 1 and 8, by division from the timer clock.
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] clock_div Unsigned int32. Clock Divider Ratio in bits 8,9: @ref
-tim_x_cr1_cdr
+@param[in] clock_div ClockDivision. Clock Divider Ratio
 */
-
-pub fn set_clock_division(timer: Timer, clock_div: u32) {
-    timer.cr1.uncheck(flags::cr1::CKD_CK_INT_MASK);
-    timer.cr1.check(clock_div & flags::cr1::CKD_CK_INT_MASK);
+pub fn set_clock_division(timer: &Timer, clock_div: ClockDivision) {
+    timer.cr1.modify(|_, w| w.ckd(clock_div));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -954,9 +333,8 @@ auto-reload register only at the next update event.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn enable_preload(timer: Timer) {
-	timer.cr1.check(flags::cr1::ARPE);
+pub fn enable_preload(timer: &Timer) {
+	timer.cr1.set_bit(flags::cr1::ARPE);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -969,9 +347,8 @@ current count cycle rather than for the cycle following an update event.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn disable_preload(timer: Timer) {
-	timer.cr1.uncheck(flags::cr1::ARPE);
+pub fn disable_preload(timer: &Timer) {
+	timer.cr1.clear_bit(flags::cr1::ARPE);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -981,12 +358,10 @@ The mode can be edge aligned or centered.
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] alignment Unsigned int32. Alignment bits in 5,6: @ref tim_x_cr1_cms
+@param[in] alignment Alignment. Edge or center alignment
 */
-
-pub fn set_alignment(timer: Timer, alignment: u32) {
-    timer.cr1.uncheck(flags::cr1::CMS_MASK);
-    timer.cr1.check(alignment & flags::cr1::CMS_MASK);
+pub fn set_alignment(timer: &Timer, alignment: Alignment) {
+    timer.cr1.modify(|_, w| w.cms(alignment));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -997,9 +372,8 @@ This has no effect if the timer is set to center aligned.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn direction_up(timer: Timer) {
-    timer.cr1.uncheck(flags::cr1::DIR_DOWN);
+pub fn direction_up(timer: &Timer) {
+    timer.cr1.modify(|_, w| w.dir(Direction::Up));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1010,9 +384,8 @@ This has no effect if the timer is set to center aligned.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn direction_down(timer: Timer) {
-    timer.cr1.check(flags::cr1::DIR_DOWN);
+pub fn direction_down(timer: &Timer) {
+    timer.cr1.modify(|_, w| w.dir(Direction::Down));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1021,9 +394,8 @@ pub fn direction_down(timer: Timer) {
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn one_shot_mode(timer: Timer) {
-    timer.cr1.check(flags::cr1::OPM);
+pub fn one_shot_mode(timer: &Timer) {
+    timer.cr1.set_bit(flags::cr1::OPM);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1032,9 +404,8 @@ pub fn one_shot_mode(timer: Timer) {
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn continuous_mode(timer: Timer) {
-    timer.cr1.uncheck(flags::cr1::OPM);
+pub fn continuous_mode(timer: &Timer) {
+    timer.cr1.clear_bit(flags::cr1::OPM);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1048,9 +419,8 @@ The events which will generate an interrupt or DMA request can be
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn update_on_any(timer: Timer) {
-    timer.cr1.uncheck(flags::cr1::URS);
+pub fn update_on_any(timer: &Timer) {
+    timer.cr1.clear_bit(flags::cr1::URS);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1060,9 +430,8 @@ Events.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn update_on_overflow(timer: Timer) {
-    timer.cr1.check(flags::cr1::URS);
+pub fn update_on_overflow(timer: &Timer) {
+    timer.cr1.set_bit(flags::cr1::URS);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1071,9 +440,8 @@ pub fn update_on_overflow(timer: Timer) {
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn enable_update_event(timer: Timer) {
-    timer.cr1.uncheck(flags::cr1::UDIS);
+pub fn enable_update_event(timer: &Timer) {
+    timer.cr1.clear_bit(flags::cr1::UDIS);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1084,9 +452,8 @@ Update events are not generated and the shadow registers keep their values.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn disable_update_event(timer: Timer) {
-	timer.cr1.check(flags::cr1::UDIS);
+pub fn disable_update_event(timer: &Timer) {
+	timer.cr1.set_bit(flags::cr1::UDIS);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1097,9 +464,8 @@ This should be called after the timer initial configuration has been completed.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn enable_counter(timer: Timer) {
-    timer.cr1.check(flags::cr1::CEN);
+pub fn enable_counter(timer: &Timer) {
+    timer.cr1.set_bit(flags::cr1::CEN);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1108,9 +474,8 @@ pub fn enable_counter(timer: Timer) {
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn disable_counter(timer: Timer) {
-	timer.cr1.uncheck(flags::cr1::CEN);
+pub fn disable_counter(timer: &Timer) {
+	timer.cr1.clear_bit(flags::cr1::CEN);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1124,15 +489,17 @@ This determines the value of the timer output compare when it enters idle state.
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] outputs Unsigned int32. Timer Output Idle State Controls @ref
-tim_x_cr2_ois. If several settings are to be made, use the logical OR of the
-output control values.
+@param[in] outputs Unsigned int32. Timer Output Idle State Controls, one bit per
+output in CR2 order starting from bit 0: OC1, OC1N, OC2, OC2N, OC3, OC3N, OC4.
 */
+pub fn set_output_idle_state(timer: &Timer, outputs: u32) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_output_idle_state(timer: Timer, outputs: u32) {
-    if timer.is_advanced() {
-        timer.cr2.check(outputs & flags::cr2::OIS_MASK);
-    }
+    let ois = (outputs & 0x7f) << flags::cr2::OIS1.offset();
+
+    timer.cr2.modify(|r, w| w.bits(r.bits() | ois));
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1146,14 +513,17 @@ This determines the value of the timer output compare when it enters idle state.
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] outputs Unsigned int32. Timer Output Idle State Controls @ref
-tim_x_cr2_ois
+@param[in] outputs Unsigned int32. Timer Output Idle State Controls, in the same
+bit order as for @ref set_output_idle_state
 */
+pub fn reset_output_idle_state(timer: &Timer, outputs: u32) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn reset_output_idle_state(timer: Timer, outputs: u32) {
-    if timer.is_advanced() {
-        timer.cr2.uncheck(outputs & flags::cr2::OIS_MASK);
-    }
+    let ois = (outputs & 0x7f) << flags::cr2::OIS1.offset();
+
+    timer.cr2.modify(|r, w| w.bits(r.bits() & !ois));
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1165,9 +535,8 @@ input channels 1, 2, 3.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn set_ti1_ch123_xor(timer: Timer) {
-    timer.cr2.check(flags::cr2::TI1S);
+pub fn set_ti1_ch123_xor(timer: &Timer) {
+    timer.cr2.set_bit(flags::cr2::TI1S);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1178,9 +547,8 @@ The first timer capture input is taken from the timer input channel 1 only.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn set_ti1_ch1(timer: Timer) {
-	timer.cr2.uncheck(flags::cr2::TI1S);
+pub fn set_ti1_ch1(timer: &Timer) {
+	timer.cr2.clear_bit(flags::cr2::TI1S);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1191,12 +559,10 @@ passing as an internal trigger to the ADC or DAC.
 
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
-@param[in] mode Unsigned int32. Master Mode @ref tim_mastermode
+@param[in] mode MasterMode. Master Mode
 */
-
-pub fn set_master_mode(timer: Timer, mode: u32) {
-    timer.cr2.uncheck(flags::cr2::MMS_MASK);
-    timer.cr2.check(mode);
+pub fn set_master_mode(timer: &Timer, mode: MasterMode) {
+    timer.cr2.modify(|_, w| w.mms(mode));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1207,9 +573,8 @@ Capture/compare events will cause DMA requests to be generated.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn set_dma_on_compare_event(timer: Timer) {
-    timer.cr2.uncheck(flags::cr2::CCDS);
+pub fn set_dma_on_compare_event(timer: &Timer) {
+    timer.cr2.clear_bit(flags::cr2::CCDS);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1220,9 +585,8 @@ Update events will cause DMA requests to be generated.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
-
-pub fn set_dma_on_update_event(timer: Timer) {
-    timer.cr2.check(flags::cr2::CCDS);
+pub fn set_dma_on_update_event(timer: &Timer) {
+    timer.cr2.set_bit(flags::cr2::CCDS);
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1238,11 +602,12 @@ complementary outputs.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
+pub fn enable_compare_control_update_on_trigger(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn enable_compare_control_update_on_trigger(timer: Timer) {
-    if timer.is_advanced() {
-        timer.cr2.check(flags::cr2::CCUS);
-    }
+    timer.cr2.set_bit(flags::cr2::CCUS);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1258,11 +623,12 @@ complementary outputs.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
+pub fn disable_compare_control_update_on_trigger(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn disable_compare_control_update_on_trigger(timer: Timer) {
-    if timer.is_advanced() {
-        timer.cr2.uncheck(flags::cr2::CCUS);
-    }
+    timer.cr2.clear_bit(flags::cr2::CCUS);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1277,11 +643,12 @@ complementary outputs.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
+pub fn enable_preload_complementry_enable_bits(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn enable_preload_complementry_enable_bits(timer: Timer) {
-    if timer.is_advanced() {
-        timer.cr2.check(flags::cr2::CCPC);
-    }
+    timer.cr2.set_bit(flags::cr2::CCPC);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1295,11 +662,12 @@ complementary outputs.
 @param[in] Timer struct. Timer register address base @ref
 tim_reg_base
 */
+pub fn disable_preload_complementry_enable_bits(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn disable_preload_complementry_enable_bits(timer: Timer) {
-    if timer.is_advanced() {
-        timer.cr2.uncheck(flags::cr2::CCPC);
-    }
+    timer.cr2.clear_bit(flags::cr2::CCPC);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1311,8 +679,7 @@ The timer clock is prescaled by the 16 bit scale value plus 1.
 tim_reg_base
 @param[in] value Unsigned int32. Prescaler values 0...0xFFFF.
 */
-
-pub fn set_prescaler(timer: Timer, value: u32) {
+pub fn set_prescaler(timer: &Timer, value: u32) {
     timer.psc.set(value);
 }
 
@@ -1328,11 +695,12 @@ count cycles have been completed.
 tim_reg_base
 @param[in] value Unsigned int32. Repetition values 0...0xFF.
 */
+pub fn set_repetition_counter(timer: &Timer, value: u32) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_repetition_counter(timer: Timer, value: u32) {
-    if timer.is_advanced() {
-        timer.rcr.set(value);
-    }
+    timer.rcr.set(value);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1344,8 +712,7 @@ Specify the timer period in the auto-reload register.
 tim_reg_base
 @param[in] period Unsigned int32. Period in counter clock ticks.
 */
-
-pub fn set_period(timer: Timer, period: u32) {
+pub fn set_period(timer: &Timer, period: u32) {
     timer.arr.set(period);
 }
 
@@ -1362,13 +729,12 @@ tim_reg_base
 @param[in] oc_id enum ::OutputCompareChannel OC channel designators
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (no action taken)
 */
-
-pub fn enable_oc_clear(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn enable_oc_clear(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccmr1.check(flags::ccmr1::OC1CE),
-        OutputCompareChannel::OChannel2 => timer.ccmr1.check(flags::ccmr1::OC2CE),
-        OutputCompareChannel::OChannel3 => timer.ccmr2.check(flags::ccmr2::OC3CE),
-        OutputCompareChannel::OChannel4 => timer.ccmr2.check(flags::ccmr2::OC4CE),
+        OutputCompareChannel::OChannel1 => timer.ccmr1.set_bit(flags::ccmr1::OC1CE),
+        OutputCompareChannel::OChannel2 => timer.ccmr1.set_bit(flags::ccmr1::OC2CE),
+        OutputCompareChannel::OChannel3 => timer.ccmr2.set_bit(flags::ccmr2::OC3CE),
+        OutputCompareChannel::OChannel4 => timer.ccmr2.set_bit(flags::ccmr2::OC4CE),
         _ => { }
 	};
 }
@@ -1381,13 +747,12 @@ tim_reg_base
 @param[in] oc_id enum ::OutputCompareChannel OC channel designators
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (no action taken)
 */
-
-pub fn disable_oc_clear(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn disable_oc_clear(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccmr1.uncheck(flags::ccmr1::OC1CE),
-        OutputCompareChannel::OChannel2 => timer.ccmr1.uncheck(flags::ccmr1::OC2CE),
-        OutputCompareChannel::OChannel3 => timer.ccmr2.uncheck(flags::ccmr2::OC3CE),
-        OutputCompareChannel::OChannel4 => timer.ccmr2.uncheck(flags::ccmr2::OC4CE),
+        OutputCompareChannel::OChannel1 => timer.ccmr1.clear_bit(flags::ccmr1::OC1CE),
+        OutputCompareChannel::OChannel2 => timer.ccmr1.clear_bit(flags::ccmr1::OC2CE),
+        OutputCompareChannel::OChannel3 => timer.ccmr2.clear_bit(flags::ccmr2::OC3CE),
+        OutputCompareChannel::OChannel4 => timer.ccmr2.clear_bit(flags::ccmr2::OC4CE),
         _ => { }
 	};
 }
@@ -1405,13 +770,12 @@ tim_reg_base
 @param[in] oc_id enum ::OutputCompareChannel OC channel designators
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (no action taken)
 */
-
-pub fn set_oc_fast_mode(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn set_oc_fast_mode(timer: &Timer, oc_id: OutputCompareChannel) {
 	match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccmr1.check(flags::ccmr1::OC1FE),
-        OutputCompareChannel::OChannel2 => timer.ccmr1.check(flags::ccmr1::OC2FE),
-        OutputCompareChannel::OChannel3 => timer.ccmr2.check(flags::ccmr2::OC3FE),
-        OutputCompareChannel::OChannel4 => timer.ccmr2.check(flags::ccmr2::OC4FE),
+        OutputCompareChannel::OChannel1 => timer.ccmr1.set_bit(flags::ccmr1::OC1FE),
+        OutputCompareChannel::OChannel2 => timer.ccmr1.set_bit(flags::ccmr1::OC2FE),
+        OutputCompareChannel::OChannel3 => timer.ccmr2.set_bit(flags::ccmr2::OC3FE),
+        OutputCompareChannel::OChannel4 => timer.ccmr2.set_bit(flags::ccmr2::OC4FE),
         _ => { }
 	};
 }
@@ -1427,13 +791,12 @@ tim_reg_base
 @param[in] oc_id enum ::OutputCompareChannel OC channel designators
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (no action taken)
 */
-
-pub fn set_oc_slow_mode(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn set_oc_slow_mode(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccmr1.uncheck(flags::ccmr1::OC1FE),
-        OutputCompareChannel::OChannel2 => timer.ccmr1.uncheck(flags::ccmr1::OC2FE),
-        OutputCompareChannel::OChannel3 => timer.ccmr2.uncheck(flags::ccmr2::OC3FE),
-        OutputCompareChannel::OChannel4 => timer.ccmr2.uncheck(flags::ccmr2::OC4FE),
+        OutputCompareChannel::OChannel1 => timer.ccmr1.clear_bit(flags::ccmr1::OC1FE),
+        OutputCompareChannel::OChannel2 => timer.ccmr1.clear_bit(flags::ccmr1::OC2FE),
+        OutputCompareChannel::OChannel3 => timer.ccmr2.clear_bit(flags::ccmr2::OC3FE),
+        OutputCompareChannel::OChannel4 => timer.ccmr2.clear_bit(flags::ccmr2::OC4FE),
         _ => { }
 	};
 }
@@ -1464,77 +827,12 @@ tim_reg_base
 		OutputCompareMode::Toggle, OutputCompareMode::ForceLow, OutputCompareMode::ForceHigh,
 		OutputCompareMode::PWM1, OutputCompareMode::PWM2
 */
-
-pub fn set_oc_mode(timer: Timer, oc_id: OutputCompareChannel, oc_mode: OutputCompareMode) {
+pub fn set_oc_mode(timer: &Timer, oc_id: OutputCompareChannel, oc_mode: OutputCompareMode) {
 	match oc_id {
-        OutputCompareChannel::OChannel1 => {
-            timer.ccmr1.uncheck(flags::ccmr1::CC1S_MASK);
-            timer.ccmr1.check(flags::ccmr1::CC1S_OUT);
-            timer.ccmr1.uncheck(flags::ccmr1::OC1M_MASK);
-
-            match oc_mode {
-                OutputCompareMode::Frozen => timer.ccmr1.check(flags::ccmr1::OC1M_FROZEN),
-                OutputCompareMode::Active => timer.ccmr1.check(flags::ccmr1::OC1M_ACTIVE),
-                OutputCompareMode::Inactive => timer.ccmr1.check(flags::ccmr1::OC1M_INACTIVE),
-                OutputCompareMode::Toggle => timer.ccmr1.check(flags::ccmr1::OC1M_TOGGLE),
-                OutputCompareMode::ForceLow => timer.ccmr1.check(flags::ccmr1::OC1M_FORCE_LOW),
-                OutputCompareMode::ForceHigh => timer.ccmr1.check(flags::ccmr1::OC1M_FORCE_HIGH),
-                OutputCompareMode::PWM1 => timer.ccmr1.check(flags::ccmr1::OC1M_PWM1),
-                OutputCompareMode::PWM2 => timer.ccmr1.check(flags::ccmr1::OC1M_PWM2),
-            }
-        },
-
-        OutputCompareChannel::OChannel2 => {
-            timer.ccmr1.uncheck(flags::ccmr1::CC2S_MASK);
-            timer.ccmr1.check(flags::ccmr1::CC2S_OUT);
-            timer.ccmr1.uncheck(flags::ccmr1::OC2M_MASK);
-
-            match oc_mode {
-                OutputCompareMode::Frozen => timer.ccmr1.check(flags::ccmr1::OC2M_FROZEN),
-                OutputCompareMode::Active => timer.ccmr1.check(flags::ccmr1::OC2M_ACTIVE),
-                OutputCompareMode::Inactive => timer.ccmr1.check(flags::ccmr1::OC2M_INACTIVE),
-                OutputCompareMode::Toggle => timer.ccmr1.check(flags::ccmr1::OC2M_TOGGLE),
-                OutputCompareMode::ForceLow => timer.ccmr1.check(flags::ccmr1::OC2M_FORCE_LOW),
-                OutputCompareMode::ForceHigh => timer.ccmr1.check(flags::ccmr1::OC2M_FORCE_HIGH),
-                OutputCompareMode::PWM1 => timer.ccmr1.check(flags::ccmr1::OC2M_PWM1),
-                OutputCompareMode::PWM2 => timer.ccmr1.check(flags::ccmr1::OC2M_PWM2),
-            }
-        },
-
-        OutputCompareChannel::OChannel3 => {
-            timer.ccmr2.uncheck(flags::ccmr2::CC3S_MASK);
-            timer.ccmr2.check(flags::ccmr2::CC3S_OUT);
-            timer.ccmr2.uncheck(flags::ccmr2::OC3M_MASK);
-
-            match oc_mode {
-                OutputCompareMode::Frozen => timer.ccmr2.check(flags::ccmr2::OC3M_FROZEN),
-                OutputCompareMode::Active => timer.ccmr2.check(flags::ccmr2::OC3M_ACTIVE),
-                OutputCompareMode::Inactive => timer.ccmr2.check(flags::ccmr2::OC3M_INACTIVE),
-                OutputCompareMode::Toggle => timer.ccmr2.check(flags::ccmr2::OC3M_TOGGLE),
-                OutputCompareMode::ForceLow => timer.ccmr2.check(flags::ccmr2::OC3M_FORCE_LOW),
-                OutputCompareMode::ForceHigh => timer.ccmr2.check(flags::ccmr2::OC3M_FORCE_HIGH),
-                OutputCompareMode::PWM1 => timer.ccmr2.check(flags::ccmr2::OC3M_PWM1),
-                OutputCompareMode::PWM2 => timer.ccmr2.check(flags::ccmr2::OC3M_PWM2),
-            }
-        },
-
-        OutputCompareChannel::OChannel4 => {
-            timer.ccmr2.uncheck(flags::ccmr2::CC4S_MASK);
-            timer.ccmr2.check(flags::ccmr2::CC4S_OUT);
-            timer.ccmr2.uncheck(flags::ccmr2::OC4M_MASK);
-
-            match oc_mode {
-                OutputCompareMode::Frozen => timer.ccmr2.check(flags::ccmr2::OC4M_FROZEN),
-                OutputCompareMode::Active => timer.ccmr2.check(flags::ccmr2::OC4M_ACTIVE),
-                OutputCompareMode::Inactive => timer.ccmr2.check(flags::ccmr2::OC4M_INACTIVE),
-                OutputCompareMode::Toggle => timer.ccmr2.check(flags::ccmr2::OC4M_TOGGLE),
-                OutputCompareMode::ForceLow => timer.ccmr2.check(flags::ccmr2::OC4M_FORCE_LOW),
-                OutputCompareMode::ForceHigh => timer.ccmr2.check(flags::ccmr2::OC4M_FORCE_HIGH),
-                OutputCompareMode::PWM1 => timer.ccmr2.check(flags::ccmr2::OC4M_PWM1),
-                OutputCompareMode::PWM2 => timer.ccmr2.check(flags::ccmr2::OC4M_PWM2),
-            }
-        },
-        
+        OutputCompareChannel::OChannel1 => timer.ccmr1.modify(|_, w| w.cc1s(0).oc1m(oc_mode)),
+        OutputCompareChannel::OChannel2 => timer.ccmr1.modify(|_, w| w.cc2s(0).oc2m(oc_mode)),
+        OutputCompareChannel::OChannel3 => timer.ccmr2.modify(|_, w| w.cc3s(0).oc3m(oc_mode)),
+        OutputCompareChannel::OChannel4 => timer.ccmr2.modify(|_, w| w.cc4s(0).oc4m(oc_mode)),
         _ => {}
     }
 }
@@ -1547,13 +845,12 @@ tim_reg_base
 @param[in] oc_id enum ::OutputCompareChannel OC channel designators
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (no action taken)
 */
-
-pub fn enable_oc_preload(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn enable_oc_preload(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccmr1.check(flags::ccmr1::OC1PE),
-        OutputCompareChannel::OChannel2 => timer.ccmr1.check(flags::ccmr1::OC2PE),
-        OutputCompareChannel::OChannel3 => timer.ccmr2.check(flags::ccmr2::OC3PE),
-        OutputCompareChannel::OChannel4 => timer.ccmr2.check(flags::ccmr2::OC4PE),
+        OutputCompareChannel::OChannel1 => timer.ccmr1.set_bit(flags::ccmr1::OC1PE),
+        OutputCompareChannel::OChannel2 => timer.ccmr1.set_bit(flags::ccmr1::OC2PE),
+        OutputCompareChannel::OChannel3 => timer.ccmr2.set_bit(flags::ccmr2::OC3PE),
+        OutputCompareChannel::OChannel4 => timer.ccmr2.set_bit(flags::ccmr2::OC4PE),
         _ => { }
 	};
 }
@@ -1566,13 +863,12 @@ tim_reg_base
 @param[in] oc_id enum ::OutputCompareChannel OC channel designators
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (no action)
 */
-
-pub fn disable_oc_preload(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn disable_oc_preload(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccmr1.uncheck(flags::ccmr1::OC1PE),
-        OutputCompareChannel::OChannel2 => timer.ccmr1.uncheck(flags::ccmr1::OC2PE),
-        OutputCompareChannel::OChannel3 => timer.ccmr2.uncheck(flags::ccmr2::OC3PE),
-        OutputCompareChannel::OChannel4 => timer.ccmr2.uncheck(flags::ccmr2::OC4PE),
+        OutputCompareChannel::OChannel1 => timer.ccmr1.clear_bit(flags::ccmr1::OC1PE),
+        OutputCompareChannel::OChannel2 => timer.ccmr1.clear_bit(flags::ccmr1::OC2PE),
+        OutputCompareChannel::OChannel3 => timer.ccmr2.clear_bit(flags::ccmr2::OC3PE),
+        OutputCompareChannel::OChannel4 => timer.ccmr2.clear_bit(flags::ccmr2::OC4PE),
         _ => { }
 	};
 }
@@ -1588,13 +884,12 @@ tim_reg_base
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3  (only for advanced
 		timers 1 and 8)
 */
-
-pub fn set_oc_polarity_high(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn set_oc_polarity_high(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.uncheck(flags::ccer::CC1P),
-        OutputCompareChannel::OChannel2 => timer.ccer.uncheck(flags::ccer::CC2P),
-        OutputCompareChannel::OChannel3 => timer.ccer.uncheck(flags::ccer::CC3P),
-        OutputCompareChannel::OChannel4 => timer.ccer.uncheck(flags::ccer::CC4P),
+        OutputCompareChannel::OChannel1 => timer.ccer.clear_bit(flags::ccer::CC1P),
+        OutputCompareChannel::OChannel2 => timer.ccer.clear_bit(flags::ccer::CC2P),
+        OutputCompareChannel::OChannel3 => timer.ccer.clear_bit(flags::ccer::CC3P),
+        OutputCompareChannel::OChannel4 => timer.ccer.clear_bit(flags::ccer::CC4P),
         _ => { }
 	};
 
@@ -1603,10 +898,10 @@ pub fn set_oc_polarity_high(timer: Timer, oc_id: OutputCompareChannel) {
     }
 
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.uncheck(flags::ccer::CC1NP),
-        OutputCompareChannel::OChannel2 => timer.ccer.uncheck(flags::ccer::CC2NP),
-        OutputCompareChannel::OChannel3 => timer.ccer.uncheck(flags::ccer::CC3NP),
-        OutputCompareChannel::OChannel4 => timer.ccer.uncheck(flags::ccer::CC4NP),
+        OutputCompareChannel::OChannel1 => timer.ccer.clear_bit(flags::ccer::CC1NP),
+        OutputCompareChannel::OChannel2 => timer.ccer.clear_bit(flags::ccer::CC2NP),
+        OutputCompareChannel::OChannel3 => timer.ccer.clear_bit(flags::ccer::CC3NP),
+        OutputCompareChannel::OChannel4 => timer.ccer.clear_bit(flags::ccer::CC4NP),
         _ => { }
 	};
 }
@@ -1622,13 +917,12 @@ tim_reg_base
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (only for advanced
 		timers 1 and 8)
 */
-
-pub fn set_oc_polarity_low(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn set_oc_polarity_low(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.check(flags::ccer::CC1P),
-        OutputCompareChannel::OChannel2 => timer.ccer.check(flags::ccer::CC2P),
-        OutputCompareChannel::OChannel3 => timer.ccer.check(flags::ccer::CC3P),
-        OutputCompareChannel::OChannel4 => timer.ccer.check(flags::ccer::CC4P),
+        OutputCompareChannel::OChannel1 => timer.ccer.set_bit(flags::ccer::CC1P),
+        OutputCompareChannel::OChannel2 => timer.ccer.set_bit(flags::ccer::CC2P),
+        OutputCompareChannel::OChannel3 => timer.ccer.set_bit(flags::ccer::CC3P),
+        OutputCompareChannel::OChannel4 => timer.ccer.set_bit(flags::ccer::CC4P),
         _ => { }
 	};
 
@@ -1637,10 +931,10 @@ pub fn set_oc_polarity_low(timer: Timer, oc_id: OutputCompareChannel) {
     }
 
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.check(flags::ccer::CC1NP),
-        OutputCompareChannel::OChannel2 => timer.ccer.check(flags::ccer::CC2NP),
-        OutputCompareChannel::OChannel3 => timer.ccer.check(flags::ccer::CC3NP),
-        OutputCompareChannel::OChannel4 => timer.ccer.check(flags::ccer::CC4NP),
+        OutputCompareChannel::OChannel1 => timer.ccer.set_bit(flags::ccer::CC1NP),
+        OutputCompareChannel::OChannel2 => timer.ccer.set_bit(flags::ccer::CC2NP),
+        OutputCompareChannel::OChannel3 => timer.ccer.set_bit(flags::ccer::CC3NP),
+        OutputCompareChannel::OChannel4 => timer.ccer.set_bit(flags::ccer::CC4NP),
         _ => { }
 	};
 }
@@ -1656,13 +950,12 @@ tim_reg_base
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (only for advanced
 		timers 1 and 8)
 */
-
-pub fn enable_oc_output(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn enable_oc_output(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.check(flags::ccer::CC1E),
-        OutputCompareChannel::OChannel2 => timer.ccer.check(flags::ccer::CC2E),
-        OutputCompareChannel::OChannel3 => timer.ccer.check(flags::ccer::CC3E),
-        OutputCompareChannel::OChannel4 => timer.ccer.check(flags::ccer::CC4E),
+        OutputCompareChannel::OChannel1 => timer.ccer.set_bit(flags::ccer::CC1E),
+        OutputCompareChannel::OChannel2 => timer.ccer.set_bit(flags::ccer::CC2E),
+        OutputCompareChannel::OChannel3 => timer.ccer.set_bit(flags::ccer::CC3E),
+        OutputCompareChannel::OChannel4 => timer.ccer.set_bit(flags::ccer::CC4E),
         _ => { }
 	};
 
@@ -1671,10 +964,9 @@ pub fn enable_oc_output(timer: Timer, oc_id: OutputCompareChannel) {
     }
 
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.check(flags::ccer::CC1NE),
-        OutputCompareChannel::OChannel2 => timer.ccer.check(flags::ccer::CC2NE),
-        OutputCompareChannel::OChannel3 => timer.ccer.check(flags::ccer::CC3NE),
-        OutputCompareChannel::OChannel4 => timer.ccer.check(flags::ccer::CC4NE),
+        OutputCompareChannel::OChannel1 => timer.ccer.set_bit(flags::ccer::CC1NE),
+        OutputCompareChannel::OChannel2 => timer.ccer.set_bit(flags::ccer::CC2NE),
+        OutputCompareChannel::OChannel3 => timer.ccer.set_bit(flags::ccer::CC3NE),
         _ => { }
 	};
 }
@@ -1690,13 +982,12 @@ tim_reg_base
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (only for advanced
 		timers 1 and 8)
 */
-
-pub fn disable_oc_output(timer: Timer, oc_id: OutputCompareChannel) {
+pub fn disable_oc_output(timer: &Timer, oc_id: OutputCompareChannel) {
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.uncheck(flags::ccer::CC1E),
-        OutputCompareChannel::OChannel2 => timer.ccer.uncheck(flags::ccer::CC2E),
-        OutputCompareChannel::OChannel3 => timer.ccer.uncheck(flags::ccer::CC3E),
-        OutputCompareChannel::OChannel4 => timer.ccer.uncheck(flags::ccer::CC4E),
+        OutputCompareChannel::OChannel1 => timer.ccer.clear_bit(flags::ccer::CC1E),
+        OutputCompareChannel::OChannel2 => timer.ccer.clear_bit(flags::ccer::CC2E),
+        OutputCompareChannel::OChannel3 => timer.ccer.clear_bit(flags::ccer::CC3E),
+        OutputCompareChannel::OChannel4 => timer.ccer.clear_bit(flags::ccer::CC4E),
         _ => { }
 	};
 
@@ -1705,10 +996,9 @@ pub fn disable_oc_output(timer: Timer, oc_id: OutputCompareChannel) {
     }
 
     match oc_id {
-        OutputCompareChannel::OChannel1 => timer.ccer.uncheck(flags::ccer::CC1NE),
-        OutputCompareChannel::OChannel2 => timer.ccer.uncheck(flags::ccer::CC2NE),
-        OutputCompareChannel::OChannel3 => timer.ccer.uncheck(flags::ccer::CC3NE),
-        OutputCompareChannel::OChannel4 => timer.ccer.uncheck(flags::ccer::CC4NE),
+        OutputCompareChannel::OChannel1 => timer.ccer.clear_bit(flags::ccer::CC1NE),
+        OutputCompareChannel::OChannel2 => timer.ccer.clear_bit(flags::ccer::CC2NE),
+        OutputCompareChannel::OChannel3 => timer.ccer.clear_bit(flags::ccer::CC3NE),
         _ => { }
 	};
 }
@@ -1727,19 +1017,20 @@ tim_reg_base
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (only for advanced
 		timers 1 and 8)
 */
+pub fn set_oc_idle_state_set(timer: &Timer, oc_id: OutputCompareChannel) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_oc_idle_state_set(timer: Timer, oc_id: OutputCompareChannel) {
-    if timer.is_advanced() {
-        match oc_id {
-            OutputCompareChannel::OChannel1  => timer.cr2.check(flags::cr2::OIS1),
-            OutputCompareChannel::OChannel1N => timer.cr2.check(flags::cr2::OIS1N),
-            OutputCompareChannel::OChannel2  => timer.cr2.check(flags::cr2::OIS2),
-            OutputCompareChannel::OChannel2N => timer.cr2.check(flags::cr2::OIS2N),
-            OutputCompareChannel::OChannel3  => timer.cr2.check(flags::cr2::OIS3),
-            OutputCompareChannel::OChannel3N => timer.cr2.check(flags::cr2::OIS3N),
-            OutputCompareChannel::OChannel4  => timer.cr2.check(flags::cr2::OIS4),
-        }
+    match oc_id {
+        OutputCompareChannel::OChannel1  => timer.cr2.set_bit(flags::cr2::OIS1),
+        OutputCompareChannel::OChannel1N => timer.cr2.set_bit(flags::cr2::OIS1N),
+        OutputCompareChannel::OChannel2  => timer.cr2.set_bit(flags::cr2::OIS2),
+        OutputCompareChannel::OChannel2N => timer.cr2.set_bit(flags::cr2::OIS2N),
+        OutputCompareChannel::OChannel3  => timer.cr2.set_bit(flags::cr2::OIS3),
+        OutputCompareChannel::OChannel3N => timer.cr2.set_bit(flags::cr2::OIS3N),
+        OutputCompareChannel::OChannel4  => timer.cr2.set_bit(flags::cr2::OIS4),
     }
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1756,19 +1047,20 @@ tim_reg_base
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (only for advanced
 		timers 1 and 8)
 */
+pub fn set_oc_idle_state_unset(timer: &Timer, oc_id: OutputCompareChannel) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_oc_idle_state_unset(timer: Timer, oc_id: OutputCompareChannel) {
-    if timer.is_advanced() {
-        match oc_id {
-            OutputCompareChannel::OChannel1  => timer.cr2.uncheck(flags::cr2::OIS1),
-            OutputCompareChannel::OChannel1N => timer.cr2.uncheck(flags::cr2::OIS1N),
-            OutputCompareChannel::OChannel2  => timer.cr2.uncheck(flags::cr2::OIS2),
-            OutputCompareChannel::OChannel2N => timer.cr2.uncheck(flags::cr2::OIS2N),
-            OutputCompareChannel::OChannel3  => timer.cr2.uncheck(flags::cr2::OIS3),
-            OutputCompareChannel::OChannel3N => timer.cr2.uncheck(flags::cr2::OIS3N),
-            OutputCompareChannel::OChannel4  => timer.cr2.uncheck(flags::cr2::OIS4),
-        }
+    match oc_id {
+        OutputCompareChannel::OChannel1  => timer.cr2.clear_bit(flags::cr2::OIS1),
+        OutputCompareChannel::OChannel1N => timer.cr2.clear_bit(flags::cr2::OIS1N),
+        OutputCompareChannel::OChannel2  => timer.cr2.clear_bit(flags::cr2::OIS2),
+        OutputCompareChannel::OChannel2N => timer.cr2.clear_bit(flags::cr2::OIS2N),
+        OutputCompareChannel::OChannel3  => timer.cr2.clear_bit(flags::cr2::OIS3),
+        OutputCompareChannel::OChannel3N => timer.cr2.clear_bit(flags::cr2::OIS3N),
+        OutputCompareChannel::OChannel4  => timer.cr2.clear_bit(flags::cr2::OIS4),
     }
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1783,8 +1075,7 @@ to the compare register.
 		TIM_OCx where x=1..4, TIM_OCxN where x=1..3 (no action taken)
 @param[in] value Unsigned int32. Compare value.
 */
-
-pub fn set_oc_value(timer: Timer, oc_id: OutputCompareChannel, value: u32) {
+pub fn set_oc_value(timer: &Timer, oc_id: OutputCompareChannel, value: u32) {
     match oc_id {
         OutputCompareChannel::OChannel1 => timer.ccr1.set(value),
         OutputCompareChannel::OChannel2 => timer.ccr2.set(value),
@@ -1809,11 +1100,12 @@ timer <b>even if break or deadtime features are not being used</b>.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn enable_break_main_output(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn enable_break_main_output(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.check(flags::bdtr::MOE);
-    }
+    timer.bdtr.set_bit(flags::bdtr::MOE);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1827,11 +1119,12 @@ the Master Output Enable in the Break and Deadtime Register.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn disable_break_main_output(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn disable_break_main_output(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.uncheck(flags::bdtr::MOE);
-    }
+    timer.bdtr.clear_bit(flags::bdtr::MOE);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1846,11 +1139,12 @@ break event.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn enable_break_automatic_output(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn enable_break_automatic_output(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.check(flags::bdtr::AOE);
-    }
+    timer.bdtr.set_bit(flags::bdtr::AOE);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1865,11 +1159,12 @@ break event.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn disable_break_automatic_output(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn disable_break_automatic_output(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.uncheck(flags::bdtr::AOE);
-    }
+    timer.bdtr.clear_bit(flags::bdtr::AOE);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1882,11 +1177,12 @@ Sets the break function to activate when the break input becomes high.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn set_break_polarity_high(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_break_polarity_high(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.check(flags::bdtr::BKP);
-    }
+    timer.bdtr.set_bit(flags::bdtr::BKP);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1899,11 +1195,12 @@ Sets the break function to activate when the break input becomes low.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn set_break_polarity_low(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_break_polarity_low(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.uncheck(flags::bdtr::BKP);
-    }
+    timer.bdtr.clear_bit(flags::bdtr::BKP);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1916,11 +1213,12 @@ Enables the break function of an advanced timer.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn enable_break(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn enable_break(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.check(flags::bdtr::BKE);
-    }
+    timer.bdtr.set_bit(flags::bdtr::BKE);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1933,11 +1231,12 @@ Disables the break function of an advanced timer.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn disable_break(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn disable_break(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.uncheck(flags::bdtr::BKE);
-    }
+    timer.bdtr.clear_bit(flags::bdtr::BKE);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1954,11 +1253,12 @@ inactive level as defined by the output polarity.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn set_enabled_off_state_in_run_mode(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_enabled_off_state_in_run_mode(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.check(flags::bdtr::OSSR);
-    }
+    timer.bdtr.set_bit(flags::bdtr::OSSR);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1974,11 +1274,12 @@ disabled, the output is also disabled.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn set_disabled_off_state_in_run_mode(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_disabled_off_state_in_run_mode(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.uncheck(flags::bdtr::OSSR);
-    }
+    timer.bdtr.clear_bit(flags::bdtr::OSSR);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -1993,11 +1294,12 @@ inactive level as defined by the output polarity.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn set_enabled_off_state_in_idle_mode(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_enabled_off_state_in_idle_mode(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.check(flags::bdtr::OSSI);
-    }
+    timer.bdtr.set_bit(flags::bdtr::OSSI);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2011,11 +1313,12 @@ timer. When the master output is disabled the output is also disabled.
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
 */
+pub fn set_disabled_off_state_in_idle_mode(timer: &Timer) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_disabled_off_state_in_idle_mode(timer: Timer) {
-    if timer.is_advanced() {
-        timer.bdtr.uncheck(flags::bdtr::OSSI);
-    }
+    timer.bdtr.clear_bit(flags::bdtr::OSSI);
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2029,13 +1332,14 @@ timer reset has occurred.
 
 @param[in] Timer struct. Timer register address base TIM1 or
 TIM8
-@param[in] lock Unsigned int32. Lock specification @ref tim_lock
+@param[in] lock ::LockLevel. Lock specification
 */
+pub fn set_break_lock(timer: &Timer, lock: LockLevel) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_break_lock(timer: Timer, lock: u32) {
-    if timer.is_advanced() {
-        timer.bdtr.check(lock);
-    }
+    timer.bdtr.modify(|_, w| w.lock(lock));
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2057,11 +1361,12 @@ TIM8
 @param[in] deadtime Unsigned int32. Deadtime count specification as defined
 above.
 */
+pub fn set_deadtime(timer: &Timer, deadtime: u32) -> Result<()> {
+    check_advanced(timer)?;
 
-pub fn set_deadtime(timer: Timer, deadtime: u32) {
-    if timer.is_advanced() {
-        timer.bdtr.check(deadtime);
-    }
+    timer.bdtr.modify(|_, w| w.dtg(deadtime));
+
+    Ok(())
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2074,11 +1379,10 @@ avoid uncertainties in the first cycle in case an update event may never be
 generated.
 
 @param[in] Timer struct. Timer register address base
-@param[in] event Unsigned int32. Event specification @ref tim_event_gen
+@param[in] event ::Event. Event to generate
 */
-
-pub fn generate_event(timer: Timer, event: u32) {
-    timer.egr.check(event);
+pub fn generate_event(timer: &Timer, event: Event) {
+    timer.egr.write(|w| w.set(flags::egr::UG.nth(event as u32, 1), true));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2089,8 +1393,7 @@ Read back the value of a timer's counter register contents
 @param[in] Timer struct. Timer register address base
 @returns Unsigned int32. Counter value.
 */
-
-pub fn get_counter(timer: Timer) -> u32 {
+pub fn get_counter(timer: &Timer) -> u32 {
     timer.cnt.get()
 }
 
@@ -2102,8 +1405,7 @@ Set the value of a timer's counter register contents.
 @param[in] Timer struct. Timer register address base
 @param[in] count Unsigned int32. Counter value.
 */
-
-pub fn set_counter(timer: Timer, count: u32) {
+pub fn set_counter(timer: &Timer, count: u32) {
     timer.cnt.set(count);
 }
 
@@ -2120,13 +1422,12 @@ valid.
 @param[in] ic ::InputCaptureChannel. Input Capture channel designator.
 @param[in] flt ::InputCaptureFilter. Input Capture Filter identifier.
 */
-
-pub fn ic_set_filter(timer: Timer, ic: InputCaptureChannel, flt: InputCaptureFilter) {
+pub fn ic_set_filter(timer: &Timer, ic: InputCaptureChannel, flt: InputCaptureFilter) {
     match ic {
-        InputCaptureChannel::Channel1 => timer.ccmr1.mask_set(flags::ccmr1::IC1F_MASK, 4,  flt as u32),
-        InputCaptureChannel::Channel2 => timer.ccmr1.mask_set(flags::ccmr1::IC2F_MASK, 12, flt as u32),
-        InputCaptureChannel::Channel3 => timer.ccmr2.mask_set(flags::ccmr2::IC3F_MASK, 4,  flt as u32),
-        InputCaptureChannel::Channel4 => timer.ccmr2.mask_set(flags::ccmr2::IC4F_MASK, 12, flt as u32),
+        InputCaptureChannel::Channel1 => timer.ccmr1.modify(|_, w| w.ic1f(flt)),
+        InputCaptureChannel::Channel2 => timer.ccmr1.modify(|_, w| w.ic2f(flt)),
+        InputCaptureChannel::Channel3 => timer.ccmr2.modify(|_, w| w.ic3f(flt)),
+        InputCaptureChannel::Channel4 => timer.ccmr2.modify(|_, w| w.ic4f(flt)),
     }
 }
 
@@ -2139,13 +1440,12 @@ Set the number of events between each capture.
 @param[in] ic ::InputCaptureChannel. Input Capture channel designator.
 @param[in] psc ::InputCapturePrescaler. Input Capture sample clock prescaler.
 */
-
-pub fn ic_set_prescaler(timer: Timer, ic: InputCaptureChannel, psc: InputCapturePrescaler) {
+pub fn ic_set_prescaler(timer: &Timer, ic: InputCaptureChannel, psc: InputCapturePrescaler) {
     match ic {
-        InputCaptureChannel::Channel1 => timer.ccmr1.mask_set(flags::ccmr1::IC1PSC_MASK, 2,  psc as u32),
-        InputCaptureChannel::Channel2 => timer.ccmr1.mask_set(flags::ccmr1::IC2PSC_MASK, 10, psc as u32),
-        InputCaptureChannel::Channel3 => timer.ccmr2.mask_set(flags::ccmr2::IC3PSC_MASK, 2,  psc as u32),
-        InputCaptureChannel::Channel4 => timer.ccmr2.mask_set(flags::ccmr2::IC4PSC_MASK, 10, psc as u32),
+        InputCaptureChannel::Channel1 => timer.ccmr1.modify(|_, w| w.ic1psc(psc)),
+        InputCaptureChannel::Channel2 => timer.ccmr1.modify(|_, w| w.ic2psc(psc)),
+        InputCaptureChannel::Channel3 => timer.ccmr2.modify(|_, w| w.ic3psc(psc)),
+        InputCaptureChannel::Channel4 => timer.ccmr2.modify(|_, w| w.ic4psc(psc)),
     };
 }
 
@@ -2169,8 +1469,7 @@ internal trigger input selected through TS bit
 @param[in] ic ::InputCaptureChannel. Input Capture channel designator.
 @param[in] in ::InputCaptureSource. Input Capture channel direction and source input.
 */
-
-pub fn ic_set_input(timer: Timer, ic: InputCaptureChannel, ics: InputCaptureSource) {
+pub fn ic_set_input(timer: &Timer, ic: InputCaptureChannel, ics: InputCaptureSource) {
 	let mut input = (ics as u32) & 3;
 
 	if ((ic == InputCaptureChannel::Channel2) || (ic == InputCaptureChannel::Channel4)) &&
@@ -2180,10 +1479,10 @@ pub fn ic_set_input(timer: Timer, ic: InputCaptureChannel, ics: InputCaptureSour
 	}
 
     match ic {
-        InputCaptureChannel::Channel1 => timer.ccmr1.mask_set(flags::ccmr1::CC1S_MASK, 0, input),
-        InputCaptureChannel::Channel2 => timer.ccmr1.mask_set(flags::ccmr1::CC2S_MASK, 8, input),
-        InputCaptureChannel::Channel3 => timer.ccmr2.mask_set(flags::ccmr2::CC3S_MASK, 0, input),
-        InputCaptureChannel::Channel4 => timer.ccmr2.mask_set(flags::ccmr2::CC4S_MASK, 8, input),
+        InputCaptureChannel::Channel1 => timer.ccmr1.modify(|_, w| w.cc1s(input)),
+        InputCaptureChannel::Channel2 => timer.ccmr1.modify(|_, w| w.cc2s(input)),
+        InputCaptureChannel::Channel3 => timer.ccmr2.modify(|_, w| w.cc3s(input)),
+        InputCaptureChannel::Channel4 => timer.ccmr2.modify(|_, w| w.cc4s(input)),
     };
 }
 
@@ -2193,9 +1492,8 @@ pub fn ic_set_input(timer: Timer, ic: InputCaptureChannel, ics: InputCaptureSour
 @param[in] Timer struct. Timer register address base
 @param[in] ic ::InputCaptureChannel. Input Capture channel designator.
 */
-
-pub fn ic_enable(timer: Timer, ic: InputCaptureChannel) {
-	timer.ccer.check(0x1 << ((ic as u32) * 4));
+pub fn ic_enable(timer: &Timer, ic: InputCaptureChannel) {
+	timer.ccer.set_bit(flags::ccer::CC1E.nth(ic as u32, 4));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2204,9 +1502,8 @@ pub fn ic_enable(timer: Timer, ic: InputCaptureChannel) {
 @param[in] Timer struct. Timer register address base
 @param[in] ic ::InputCaptureChannel. Input Capture channel designator.
 */
-
-pub fn ic_disable(timer: Timer, ic: InputCaptureChannel) {
-    timer.ccer.uncheck(0x1 << ((ic as u32) * 4));
+pub fn ic_disable(timer: &Timer, ic: InputCaptureChannel) {
+    timer.ccer.clear_bit(flags::ccer::CC1E.nth(ic as u32, 4));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2221,10 +1518,8 @@ valid.
 @param[in] Timer struct. Timer register address base
 @param[in] flt ::InputCaptureFilter. Input Capture Filter identifier.
 */
-
-pub fn slave_set_filter(timer: Timer, flt: InputCaptureFilter) {
-    timer.smcr.uncheck(flags::smcr::ETF_MASK);
-    timer.smcr.check((flt as u32) << 8);
+pub fn slave_set_filter(timer: &Timer, flt: InputCaptureFilter) {
+    timer.smcr.modify(|_, w| w.etf(flt));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2235,10 +1530,8 @@ Set the external trigger frequency division ratio.
 @param[in] Timer struct. Timer register address base
 @param[in] psc ::InputCapturePrescaler. Input Capture sample clock prescaler.
 */
-
-pub fn slave_set_prescaler(timer: Timer, psc: InputCapturePrescaler) {
-    timer.smcr.uncheck(flags::smcr::ETPS_MASK);
-    timer.smcr.check((psc as u32) << 12);
+pub fn slave_set_prescaler(timer: &Timer, psc: InputCapturePrescaler) {
+    timer.smcr.modify(|_, w| w.etps(psc));
 }
 
 /*---------------------------------------------------------------------------*/
@@ -2247,11 +1540,10 @@ pub fn slave_set_prescaler(timer: Timer, psc: InputCapturePrescaler) {
 @param[in] Timer struct. Timer register address base
 @param[in] pol ::ExternalTriggerPolarity. Slave External Trigger polarity.
 */
-
-pub fn slave_set_polarity(timer: Timer, pol: ExternalTriggerPolarity) {
+pub fn slave_set_polarity(timer: &Timer, pol: ExternalTriggerPolarity) {
     match pol {
-        ExternalTriggerPolarity::Rising => timer.smcr.uncheck(flags::smcr::ETP),
-        ExternalTriggerPolarity::Falling => timer.smcr.check(flags::smcr::ETP),
+        ExternalTriggerPolarity::Rising => timer.smcr.clear_bit(flags::smcr::ETP),
+        ExternalTriggerPolarity::Falling => timer.smcr.set_bit(flags::smcr::ETP),
     };
 }
 
@@ -2259,50 +1551,126 @@ pub fn slave_set_polarity(timer: Timer, pol: ExternalTriggerPolarity) {
 /** @brief Set Slave Mode
 
 @param[in] Timer struct. Timer register address base
-@param[in] mode Unsigned int8. Slave mode @ref tim_sms
+@param[in] mode ::SlaveMode. Slave mode
 */
-
-pub fn slave_set_mode(timer: Timer, mode: u8) {
-    timer.smcr.uncheck(flags::smcr::SMS_MASK);
-    timer.smcr.check(mode as u32);
+pub fn slave_set_mode(timer: &Timer, mode: SlaveMode) {
+    timer.smcr.modify(|_, w| w.sms(mode));
 }
 
 /*---------------------------------------------------------------------------*/
 /** @brief Set Slave Trigger Source
 
 @param[in] Timer struct. Timer register address base
-@param[in] trigger Unsigned int8. Slave trigger source @ref tim_ts
+@param[in] trigger ::Trigger. Slave trigger source
 */
-
-pub fn slave_set_trigger(timer: Timer, trigger: u8) {
-    timer.smcr.uncheck(flags::smcr::TS_MASK);
-    timer.smcr.check(trigger as u32);
+pub fn slave_set_trigger(timer: &Timer, trigger: Trigger) {
+    timer.smcr.modify(|_, w| w.ts(trigger));
 }
 
 /* TODO Timer DMA burst */
 
 /**@}*/
+/*---------------------------------------------------------------------------*/
+/** @brief Remap Internal Trigger 1 of TIM2
 
-pub fn set_option(timer: Timer, option: u32) {
-    if timer == TIM2 {
-        timer.or.uncheck(flags::or::ITR1_RMP_MASK);
-        timer.or.check(option);
-    } else if timer == TIM5 {
-        timer.or.uncheck(flags::or::ITR4_RMP_MASK);
-        timer.or.check(option);
-    }
+@param[in] remap ::Itr1Remap. Source connected to ITR1
+*/
+pub fn set_itr1_remap(remap: Itr1Remap) {
+    TIM2.or.modify(|_, w| w.itr1_rmp(remap));
 }
 
-pub fn ic_set_polarity(timer: Timer, ic: InputCaptureChannel, pol: InputCapturePolarity) {
+/*---------------------------------------------------------------------------*/
+/** @brief Remap Timer Input 4 of TIM5
+
+@param[in] remap ::Ti4Remap. Source connected to TI4
+*/
+pub fn set_ti4_remap(remap: Ti4Remap) {
+    TIM5.or.modify(|_, w| w.ti4_rmp(remap));
+}
+
+pub fn ic_set_polarity(timer: &Timer, ic: InputCaptureChannel, pol: InputCapturePolarity) {
 	/* Clear CCxP and CCxNP to zero. For both edge trigger both fields are
 	 * set. Case 10 is invalid.
 	 */
 
-    timer.ccer.uncheck(0xa << ((ic as u32) * 4));
+    let ccp = flags::ccer::CC1P.nth(ic as u32, 4);
+    let ccnp = flags::ccer::CC1NP.nth(ic as u32, 4);
 
 	match pol {
-	    /* 01 */ InputCapturePolarity::Falling => timer.ccer.check(0x2 << ((ic as u32) * 4)),
-	    /* 11 */ InputCapturePolarity::Both => timer.ccer.check(0xa << ((ic as u32) * 4)),
-        _ => {}
+	    /* 00 */ InputCapturePolarity::Rising => timer.ccer.modify(|_, w| w.set(ccp, false).set(ccnp, false)),
+	    /* 01 */ InputCapturePolarity::Falling => timer.ccer.modify(|_, w| w.set(ccp, true).set(ccnp, false)),
+	    /* 11 */ InputCapturePolarity::Both => timer.ccer.modify(|_, w| w.set(ccp, true).set(ccnp, true)),
 	}
-}
\ No newline at end of file
+}
+
+/// Busy-wait delays counted by a timer at 1 MHz.
+pub struct Delay {
+    timer: Timer,
+}
+
+impl Delay {
+    /// Takes `timer` over for delays. The prescaler follows the bus clock at
+    /// the time of the call, so set the clock up first.
+    pub fn new(timer: Timer) -> Delay {
+        set_prescaler(&timer, timer.clock_frequency() / 1_000_000 - 1);
+        one_shot_mode(&timer);
+        generate_event(&timer, Event::Update); /* Load the prescaler. */
+
+        Delay { timer }
+    }
+
+    /// Gives the timer back.
+    pub fn free(self) -> Timer {
+        self.timer
+    }
+}
+
+impl DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        let mut us = ns.div_ceil(1000);
+
+        /* Every timer has at least a 16-bit counter. */
+        while us > 0 {
+            let ticks = us.min(0x1_0000);
+
+            set_period(&self.timer, ticks - 1);
+            set_counter(&self.timer, 0);
+            clear_update_flag(&self.timer);
+            enable_counter(&self.timer);
+
+            while !is_update_flag_checked(&self.timer) { asm::nop(); }
+
+            us -= ticks;
+        }
+    }
+}
+
+/// One output compare channel of a timer, see `Timer::pwm_channel`.
+pub struct PwmChannel<'a> {
+    timer: &'a Timer,
+    channel: OutputCompareChannel,
+}
+
+impl<'a> pwm::ErrorType for PwmChannel<'a> {
+    type Error = Infallible;
+}
+
+impl<'a> pwm::SetDutyCycle for PwmChannel<'a> {
+    /* ARR + 1, or 0xFFFF scaled to the period on TIM2 and TIM5 when ARR is
+       at or above it. */
+    fn max_duty_cycle(&self) -> u16 {
+        self.timer.arr.get().saturating_add(1).min(0xffff) as u16
+    }
+
+    fn set_duty_cycle(&mut self, duty: u16) -> result::Result<(), Infallible> {
+        /* A compare value above ARR keeps the output active. */
+        let period = self.timer.arr.get() as u64 + 1;
+        let ccr = duty as u64 * period / self.max_duty_cycle() as u64;
+
+        /* Only TIM2 and TIM5 have 32-bit compare registers. */
+        let top = if *self.timer == TIM2 || *self.timer == TIM5 { u32::MAX } else { 0xffff };
+
+        set_oc_value(self.timer, self.channel, ccr.min(top as u64) as u32);
+        Ok(())
+    }
+}
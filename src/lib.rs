@@ -1,6 +1,18 @@
-#![feature(const_fn, asm)]
 #![no_std]
 #![allow(dead_code)]
+
+#[cfg(not(target_os = "none"))]
+#[macro_use]
+extern crate std;
+
+extern crate embedded_hal;
+extern crate embedded_hal_nb;
+
+#[macro_use]
 pub mod common;
+pub mod device;
+pub mod error;
 pub mod hal;
+pub mod rt;
+pub use self::error::{Error, Result};
 pub use self::hal::*;
\ No newline at end of file
@@ -0,0 +1,203 @@
+//! Startup runtime: the vector table, the Reset handler and the default
+//! handlers.
+//!
+//! The crate ships `link.x`, which places the vector table at the start of
+//! flash, `.data` and `.bss` in RAM, and pulls the memory regions from the
+//! application's `memory.x`. Binaries link with `-C link-arg=-Tlink.x` and
+//! name their entry point with `entry!`:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! #[macro_use]
+//! extern crate opencm;
+//!
+//! entry!(main);
+//!
+//! fn main() -> ! {
+//!     loop {}
+//! }
+//!
+//! interrupt!(USART2, on_usart2);
+//!
+//! fn on_usart2() {}
+//! ```
+//!
+//! The interrupt vectors are generated from the interrupts of the part's SVD,
+//! like `nvic::NvicIdx`, so every variant of it can be bound with
+//! `interrupt!`. Faults that are not bound go to the
+//! handler of `fault`, other exceptions and interrupts to `DefaultHandler`,
+//! which parks the core and can itself be replaced with
+//! `exception!(DefaultHandler, ...)`.
+
+/// One entry of the vector table.
+#[derive(Copy, Clone)]
+pub union Vector {
+    handler: unsafe extern "C" fn(),
+    reserved: usize,
+}
+
+/// Cortex-M system exceptions, numbered as in the vector table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exception {
+    NMI = 2,
+    HardFault = 3,
+    MemManage = 4,
+    BusFault = 5,
+    UsageFault = 6,
+    SVCall = 11,
+    DebugMonitor = 12,
+    PendSV = 14,
+    SysTick = 15,
+}
+
+include!(concat!(env!("OUT_DIR"), "/interrupts.rs"));
+
+#[cfg(target_os = "none")]
+extern "C" {
+    fn NMI();
+    fn HardFault();
+    fn MemManage();
+    fn BusFault();
+    fn UsageFault();
+    fn SVCall();
+    fn DebugMonitor();
+    fn PendSV();
+    fn SysTick();
+}
+
+#[cfg(target_os = "none")]
+#[link_section = ".vector_table.reset_vector"]
+#[no_mangle]
+#[used]
+pub static __RESET_VECTOR: unsafe extern "C" fn() -> ! = Reset;
+
+/// Vectors 2 to 15, the stack pointer and the reset vector come first.
+#[cfg(target_os = "none")]
+#[link_section = ".vector_table.exceptions"]
+#[no_mangle]
+#[used]
+pub static __EXCEPTIONS: [Vector; 14] = [
+    Vector { handler: NMI },
+    Vector { handler: HardFault },
+    Vector { handler: MemManage },
+    Vector { handler: BusFault },
+    Vector { handler: UsageFault },
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { reserved: 0 },
+    Vector { handler: SVCall },
+    Vector { handler: DebugMonitor },
+    Vector { reserved: 0 },
+    Vector { handler: PendSV },
+    Vector { handler: SysTick },
+];
+
+/// Initialises `.bss` and `.data`, turns on the FPU and calls the `main`
+/// named with `entry!`.
+///
+/// # Safety
+///
+/// Only the core may call this, straight out of reset.
+#[cfg(target_os = "none")]
+#[no_mangle]
+pub unsafe extern "C" fn Reset() -> ! {
+    use core::ptr::{self, addr_of, addr_of_mut};
+
+    extern "C" {
+        static mut __sbss: u32;
+        static mut __ebss: u32;
+        static mut __sdata: u32;
+        static mut __edata: u32;
+        static __sidata: u32;
+    }
+
+    extern "Rust" {
+        fn main() -> !;
+    }
+
+    let mut bss = addr_of_mut!(__sbss);
+    while bss < addr_of_mut!(__ebss) {
+        ptr::write_volatile(bss, 0);
+        bss = bss.add(1);
+    }
+
+    let mut data = addr_of_mut!(__sdata);
+    let mut init = addr_of!(__sidata);
+    while data < addr_of_mut!(__edata) {
+        ptr::write_volatile(data, ptr::read(init));
+        data = data.add(1);
+        init = init.add(1);
+    }
+
+    /* Every F4 has an FPU, and code built for eabihf uses it from the start. */
+    ::fpu::enable();
+
+    main()
+}
+
+/// Where unbound exceptions and interrupts end up, unless the application
+/// replaces it.
+///
+/// # Safety
+///
+/// Only the core may call this, as an exception handler.
+#[cfg(target_os = "none")]
+#[no_mangle]
+pub unsafe extern "C" fn DefaultHandler_() {
+    loop {
+        ::common::asm::bkpt();
+    }
+}
+
+/// Names the function the Reset handler calls once RAM is initialised, a
+/// `fn() -> !`.
+#[macro_export]
+macro_rules! entry {
+    ($main:path) => {
+        #[export_name = "main"]
+        pub unsafe fn __opencm_main() -> ! {
+            let main: fn() -> ! = $main;
+            main()
+        }
+    };
+}
+
+/// Binds a `fn()` to the interrupt named after its `NvicIdx` variant.
+#[macro_export]
+macro_rules! interrupt {
+    ($name:ident, $handler:path) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        pub unsafe extern "C" fn $name() {
+            let _ = $crate::nvic::NvicIdx::$name;
+            let handler: fn() = $handler;
+            handler()
+        }
+    };
+}
+
+/// Binds a `fn()` to a system exception named after its `Exception` variant,
+/// or to `DefaultHandler`.
+#[macro_export]
+macro_rules! exception {
+    (DefaultHandler, $handler:path) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        pub unsafe extern "C" fn DefaultHandler() {
+            let handler: fn() = $handler;
+            handler()
+        }
+    };
+    ($name:ident, $handler:path) => {
+        #[allow(non_snake_case)]
+        #[no_mangle]
+        pub unsafe extern "C" fn $name() {
+            let _ = $crate::rt::Exception::$name;
+            let handler: fn() = $handler;
+            handler()
+        }
+    };
+}
@@ -0,0 +1,79 @@
+extern crate opencm;
+
+use std::cell::Cell;
+
+use opencm::common::{asm, interrupt};
+use opencm::common::interrupt::Mutex;
+
+#[test]
+fn interrupt_free_nests() {
+    assert_eq!(asm::primask(), 0);
+
+    interrupt::free(|| {
+        assert_eq!(asm::primask(), 1);
+        interrupt::free(|| assert_eq!(asm::primask(), 1));
+        assert_eq!(asm::primask(), 1, "inner critical section unmasked early");
+    });
+
+    assert_eq!(asm::primask(), 0);
+}
+
+#[test]
+fn special_registers() {
+    unsafe {
+        asm::set_basepri_max(0x40);
+        asm::set_basepri_max(0x80);
+        assert_eq!(asm::basepri(), 0x40, "BASEPRI_MAX lowered the mask");
+        asm::set_basepri(0);
+
+        asm::set_psp(0x2000_1000);
+        asm::set_control(0b10);
+    }
+
+    assert_eq!(asm::basepri(), 0);
+    assert_eq!(asm::psp(), 0x2000_1000);
+    assert_eq!(asm::control(), 0b10);
+}
+
+#[test]
+fn priority_ceiling_sections() {
+    interrupt::with_ceiling(8, |cs| {
+        assert_eq!(cs.priority(), 8);
+        assert_eq!(asm::basepri(), 0x80);
+
+        interrupt::with_ceiling(3, |_| assert_eq!(asm::basepri(), 0x30));
+        assert_eq!(asm::basepri(), 0x80, "inner section didn't restore BASEPRI");
+
+        interrupt::with_ceiling(12, |_| assert_eq!(asm::basepri(), 0x80, "inner section lowered the mask"));
+        assert_eq!(asm::primask(), 0);
+    });
+    assert_eq!(asm::basepri(), 0);
+
+    interrupt::with_ceiling(0, |_| assert_eq!(asm::primask(), 1));
+    assert_eq!(asm::primask(), 0);
+}
+
+#[test]
+fn mutex_shared_between_priorities() {
+    static SHARED: Mutex<Cell<u32>> = Mutex::new(5, Cell::new(0));
+
+    SHARED.lock(|count| {
+        assert_eq!(asm::basepri(), 0x50);
+        count.set(count.get() + 1);
+    });
+
+    interrupt::with_ceiling(2, |cs| {
+        let count = SHARED.borrow(cs);
+        count.set(count.get() + 1);
+    });
+
+    assert_eq!(SHARED.lock(|count| count.get()), 2);
+}
+
+#[test]
+#[should_panic(expected = "doesn't mask the ceiling")]
+fn mutex_rejects_lower_section() {
+    let shared = Mutex::new(5, Cell::new(0));
+
+    interrupt::with_ceiling(6, |cs| shared.borrow(cs).set(1));
+}
@@ -0,0 +1,70 @@
+extern crate opencm;
+
+use std::mem::offset_of;
+
+use opencm::common::field::Register;
+use opencm::device::{gpio, pwr, rcc, tim};
+
+#[test]
+fn register_offsets() {
+    assert_eq!(offset_of!(rcc::Registers, ahb1enr), 0x30);
+    assert_eq!(offset_of!(rcc::Registers, apb2enr), 0x44);
+    assert_eq!(offset_of!(rcc::Registers, bdcr), 0x70);
+    #[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
+    assert_eq!(offset_of!(rcc::Registers, dckcfgr), 0x8c);
+    assert_eq!(offset_of!(gpio::Registers, afrh), 0x24);
+    assert_eq!(offset_of!(tim::Registers, ccr4), 0x40);
+    assert_eq!(offset_of!(tim::Registers, or), 0x50);
+}
+
+#[test]
+fn expanded_fields() {
+    assert_eq!(gpio::moder::MODER15.offset(), 30);
+    assert_eq!(gpio::afrh::AFRH15.offset(), 28);
+    assert_eq!(tim::ccer::CC4NP.offset(), 15);
+    assert_eq!(tim::ccmr1::IC2F.offset(), 12);
+    #[cfg(not(any(feature = "stm32f405", feature = "stm32f407")))]
+    assert_eq!(pwr::cr::VOS.mask(), 0b11 << 14);
+    #[cfg(any(feature = "stm32f405", feature = "stm32f407"))]
+    assert_eq!(pwr::cr::VOS.mask(), 1 << 14);
+    assert_eq!(pwr::cr::FPDS.mask() & pwr::cr::VOS.mask(), 0);
+}
+
+#[test]
+fn reset_values() {
+    assert_eq!(rcc::cr::Spec::RESET, 0x0000_0083);
+    assert_eq!(rcc::pllcfgr::Spec::RESET, 0x2400_3010);
+}
+
+/* Each part only describes what it has. */
+#[test]
+#[cfg(any(feature = "stm32f405", feature = "stm32f407"))]
+fn stm32f40x() {
+    assert_eq!(pwr::cr::Spec::RESET, 0x0000_4000);
+    assert_eq!(gpio::GPIOI - gpio::GPIOA, 8 * 0x400);
+    assert_eq!(opencm::device::flash::acr::LATENCY.mask(), 0b111);
+}
+
+#[test]
+#[cfg(any(feature = "stm32f401", feature = "stm32f411"))]
+fn stm32f4x1() {
+    assert_eq!(pwr::cr::Spec::RESET, 0x0000_8000);
+    assert_eq!(gpio::GPIOH - gpio::GPIOA, 7 * 0x400);
+    assert_eq!(rcc::ahb1enr::Spec::RESET, 0);
+}
+
+#[test]
+#[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+fn stm32f42x() {
+    assert_eq!(pwr::cr::Spec::RESET, 0x0000_c000);
+    assert_eq!(gpio::GPIOK - gpio::GPIOA, 10 * 0x400);
+    assert_eq!(pwr::cr::ODEN.offset(), 16);
+}
+
+#[test]
+#[cfg(feature = "stm32f446")]
+fn stm32f446() {
+    assert_eq!(pwr::cr::Spec::RESET, 0x0000_c000);
+    assert_eq!(gpio::GPIOH - gpio::GPIOA, 7 * 0x400);
+    assert_eq!(rcc::pllcfgr::PLLR.offset(), 28);
+}
@@ -0,0 +1,93 @@
+extern crate opencm;
+
+use opencm::common::mmio::host;
+use opencm::common::sim;
+use opencm::dwt::{self, Duration, Instant, Profile};
+use opencm::rcc;
+
+const DWT_BASE: usize = 0xE000_1000;
+const DWT_CTRL: usize = 0x0;
+const DEMCR_BASE: usize = 0xE000_EDF0;
+const DEMCR: usize = 0xC;
+
+fn advance(cycles: u32) {
+    host::with_model(DWT_BASE, |dwt: &mut sim::Dwt, regs| dwt.advance(regs, cycles));
+}
+
+#[test]
+fn cycle_counter_enable() {
+    host::attach(DWT_BASE, sim::Dwt::new());
+
+    dwt::enable();
+    assert_eq!(host::peek(DEMCR_BASE, DEMCR), 1 << 24, "TRCENA not set");
+    assert_eq!(host::peek(DWT_BASE, DWT_CTRL), 1);
+    assert!(dwt::is_enabled());
+
+    dwt::disable();
+    assert!(!dwt::is_enabled());
+}
+
+#[test]
+fn instant_and_duration() {
+    host::attach(DWT_BASE, sim::Dwt::new());
+    dwt::enable();
+
+    let start = Instant::now();
+    advance(999);
+    let elapsed = Instant::now() - start;
+    assert_eq!(elapsed.as_cycles(), 1000);
+    assert_eq!(start + elapsed, start + Duration::from_cycles(1000));
+
+    let ahb = rcc::ahb_frequency() as u64;
+    let second = Duration::from_cycles(ahb as u32);
+    assert_eq!(second.as_millis(), 1000);
+    assert_eq!(second.as_micros(), 1_000_000);
+    assert_eq!(Duration::from_cycles(ahb as u32 / 1000).as_nanos(), 1_000_000);
+
+    /* CYCCNT wrapping in between. */
+    host::poke(DWT_BASE, 0x4, 0xFFFF_FF00);
+    let start = Instant::now();
+    advance(0x1FF);
+    assert_eq!(start.elapsed().as_cycles(), 0x200);
+}
+
+#[test]
+fn delays_wait_for_counter() {
+    host::attach(DWT_BASE, sim::Dwt::new());
+    dwt::enable();
+
+    let start = dwt::cycles();
+    dwt::delay_cycles(500);
+    assert!(dwt::cycles().wrapping_sub(start) >= 500);
+
+    let start = Instant::now();
+    dwt::delay_ns(10_000);
+    assert!(start.elapsed().as_nanos() >= 10_000);
+}
+
+#[test]
+fn profile_statistics() {
+    host::attach(DWT_BASE, sim::Dwt::new());
+    dwt::enable();
+
+    let mut profile = Profile::new();
+    assert_eq!((profile.min(), profile.max(), profile.average()), (0, 0, 0));
+
+    /* Each region is one cycle longer than advanced, for the read ending it. */
+    for &cycles in [99, 299, 199].iter() {
+        profile.measure(|| advance(cycles));
+    }
+
+    {
+        let _scope = profile.scope();
+        advance(399);
+    }
+
+    assert_eq!(profile.count(), 4);
+    assert_eq!(profile.min(), 100);
+    assert_eq!(profile.max(), 400);
+    assert_eq!(profile.average(), 250);
+
+    profile.reset();
+    assert_eq!(profile.count(), 0);
+}
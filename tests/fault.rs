@@ -0,0 +1,93 @@
+extern crate opencm;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use opencm::common::mmio::host::{self, Op};
+use opencm::fault::{self, BusFaultReason, ExceptionFrame, FaultReport, HardFaultReason};
+use opencm::fault::{MemManageReason, UsageFaultReason};
+
+const SCB_BASE: usize = 0xE000_ED00;
+const SCB_SHCSR: usize = 0x24;
+const SCB_CFSR: usize = 0x28;
+const SCB_HFSR: usize = 0x2C;
+const SCB_MMFAR: usize = 0x34;
+const SCB_BFAR: usize = 0x38;
+
+fn frame(pc: u32) -> ExceptionFrame {
+    ExceptionFrame { pc, ..ExceptionFrame::default() }
+}
+
+#[test]
+fn enable_configurable_faults() {
+    fault::enable();
+    assert_eq!(host::peek(SCB_BASE, SCB_SHCSR), 0b111 << 16);
+}
+
+#[test]
+fn decode_configurable_faults() {
+    host::poke(SCB_BASE, SCB_CFSR, 1 << 7 | 1 << 4);
+    host::poke(SCB_BASE, SCB_MMFAR, 0x2000_0ff8);
+    assert_eq!(fault::decode(4, &frame(0x0800_1234)), FaultReport::MemManage {
+        pc: 0x0800_1234,
+        reason: MemManageReason::Stacking,
+        address: Some(0x2000_0ff8),
+    });
+
+    host::poke(SCB_BASE, SCB_CFSR, 1 << 10);
+    host::poke(SCB_BASE, SCB_BFAR, 0x6000_0000);
+    assert_eq!(fault::decode(5, &frame(0x0800_0100)), FaultReport::BusFault {
+        pc: 0x0800_0100,
+        reason: BusFaultReason::ImpreciseData,
+        address: None,
+    });
+
+    host::poke(SCB_BASE, SCB_CFSR, 1 << 25);
+    assert_eq!(fault::decode(6, &frame(0x0800_0200)), FaultReport::UsageFault {
+        pc: 0x0800_0200,
+        reason: UsageFaultReason::DivideByZero,
+    });
+}
+
+#[test]
+fn decode_hard_faults() {
+    /* Escalated from a precise bus fault. */
+    host::poke(SCB_BASE, SCB_HFSR, 1 << 30);
+    host::poke(SCB_BASE, SCB_CFSR, 1 << 15 | 1 << 9);
+    host::poke(SCB_BASE, SCB_BFAR, 0x4000_7c00);
+    assert_eq!(fault::decode(3, &frame(0x0800_0300)), FaultReport::BusFault {
+        pc: 0x0800_0300,
+        reason: BusFaultReason::PreciseData,
+        address: Some(0x4000_7c00),
+    });
+
+    host::poke(SCB_BASE, SCB_CFSR, 0);
+    host::poke(SCB_BASE, SCB_HFSR, 1 << 1);
+    assert_eq!(fault::decode(3, &frame(0x0800_0400)), FaultReport::HardFault {
+        pc: 0x0800_0400,
+        reason: HardFaultReason::VectorTable,
+    });
+}
+
+#[test]
+fn hook_gets_report() {
+    static PC: AtomicU32 = AtomicU32::new(0);
+
+    fn hook(report: &FaultReport, frame: &ExceptionFrame) {
+        assert_eq!(report.pc(), frame.pc);
+        PC.store(report.pc(), Ordering::SeqCst);
+    }
+
+    fault::set_hook(hook);
+
+    host::poke(SCB_BASE, SCB_CFSR, 1 << 16);
+    host::trace_start();
+    let report = fault::handle(6, &frame(0x0800_0500));
+    let trace = host::trace_stop();
+
+    assert_eq!(report, FaultReport::UsageFault { pc: 0x0800_0500, reason: UsageFaultReason::UndefinedInstruction });
+    assert_eq!(PC.load(Ordering::SeqCst), 0x0800_0500);
+
+    /* Status bits are write-one-to-clear. */
+    assert!(trace.iter().any(|a| a.op == Op::Write && a.base == SCB_BASE && a.offset == SCB_CFSR && a.new == 1 << 16),
+        "UNDEFINSTR not cleared");
+}
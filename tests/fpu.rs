@@ -0,0 +1,98 @@
+extern crate opencm;
+
+use std::cell::Cell;
+
+use opencm::common::asm;
+use opencm::common::mmio::host;
+use opencm::error::FpuError;
+use opencm::fpu::{self, Flags, Preservation};
+use opencm::nvic::host::raise;
+use opencm::nvic::{self, NvicIdx};
+use opencm::Error;
+
+const SCB_BASE: usize = 0xE000_ED00;
+const SCB_CPACR: usize = 0x88;
+const FPU_BASE: usize = 0xE000_EF34;
+const FPU_FPCCR: usize = 0x00;
+
+#[test]
+fn enable_and_disable() {
+    host::poke(SCB_BASE, SCB_CPACR, 0b11 << 18);
+
+    fpu::enable();
+    assert!(fpu::is_enabled());
+    assert_eq!(host::peek(SCB_BASE, SCB_CPACR), 0b1111 << 20 | 0b11 << 18);
+
+    fpu::disable();
+    assert!(!fpu::is_enabled());
+    assert_eq!(host::peek(SCB_BASE, SCB_CPACR), 0b11 << 18);
+}
+
+#[test]
+fn preservation() {
+    /* ASPEN | LSPEN out of reset, and LSPACT. */
+    host::poke(FPU_BASE, FPU_FPCCR, 0b11 << 30 | 1);
+    assert_eq!(fpu::preservation(), Preservation::Lazy);
+
+    fpu::set_preservation(Preservation::Automatic);
+    assert_eq!(host::peek(FPU_BASE, FPU_FPCCR), 1 << 31 | 1);
+    assert_eq!(fpu::preservation(), Preservation::Automatic);
+
+    fpu::set_preservation(Preservation::None);
+    assert_eq!(host::peek(FPU_BASE, FPU_FPCCR), 1);
+    assert_eq!(fpu::preservation(), Preservation::None);
+}
+
+#[test]
+fn flags_and_clear() {
+    /* Round towards zero, overflow and input denormal. */
+    unsafe { asm::set_fpscr(0b11 << 22 | 1 << 7 | 1 << 2) };
+
+    assert_eq!(fpu::flags(), Flags { overflow: true, input_denormal: true, ..Flags::default() });
+    assert!(fpu::flags().any());
+
+    fpu::clear_flags();
+    assert!(!fpu::flags().any());
+    assert_eq!(asm::fpscr(), 0b11 << 22);
+}
+
+thread_local! {
+    static REPORTED: Cell<Option<Flags>> = const { Cell::new(None) };
+}
+
+#[test]
+fn exceptions_reported_to_hook() {
+    fn hook(flags: Flags) {
+        REPORTED.with(|reported| reported.set(Some(flags)));
+    }
+
+    fpu::set_preservation(Preservation::None);
+    assert_eq!(fpu::report_exceptions(hook), Err(Error::Fpu(FpuError::NoPreservation)));
+    assert!(!nvic::is_enabled(NvicIdx::FPU));
+
+    fpu::set_preservation(Preservation::Lazy);
+    fpu::report_exceptions(hook).unwrap();
+    assert!(nvic::is_enabled(NvicIdx::FPU));
+
+    unsafe { asm::set_fpscr(1 << 1 | 1 << 0) };
+    assert!(raise(NvicIdx::FPU));
+
+    let reported = REPORTED.with(|reported| reported.get());
+    assert_eq!(reported, Some(Flags { invalid_operation: true, divide_by_zero: true, ..Flags::default() }));
+    assert_eq!(asm::fpscr(), 0);
+
+    /* Inexact and underflow are cleared, not reported. */
+    REPORTED.with(|reported| reported.set(None));
+    unsafe { asm::set_fpscr(1 << 4 | 1 << 3) };
+    assert!(raise(NvicIdx::FPU));
+
+    assert_eq!(REPORTED.with(|reported| reported.get()), None);
+    assert_eq!(asm::fpscr(), 0);
+
+    /* And left out alongside an error. */
+    unsafe { asm::set_fpscr(1 << 4 | 1 << 2) };
+    assert!(raise(NvicIdx::FPU));
+
+    let reported = REPORTED.with(|reported| reported.get());
+    assert_eq!(reported, Some(Flags { overflow: true, ..Flags::default() }));
+}
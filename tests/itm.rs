@@ -0,0 +1,121 @@
+extern crate embedded_hal_nb;
+extern crate opencm;
+
+use std::fmt::Write;
+
+use embedded_hal_nb::nb;
+
+use opencm::common::mmio::host;
+use opencm::common::sim;
+use opencm::error::ItmError;
+use opencm::itm::{self, host::{decode, Packet}};
+use opencm::Error;
+
+const ITM_BASE: usize = 0xE000_0000;
+const ITM_TER: usize = 0xE00;
+const ITM_TCR: usize = 0xE80;
+const ITM_LAR: usize = 0xFB0;
+const TPIU_BASE: usize = 0xE004_0000;
+const TPIU_ACPR: usize = 0x010;
+const TPIU_SPPR: usize = 0x0F0;
+const DBGMCU_BASE: usize = 0xE004_2000;
+const DBGMCU_CR: usize = 0x04;
+
+fn instrumentation(packets: &[Packet], port: u8) -> Vec<u8> {
+    packets.iter().flat_map(|p| match *p {
+        Packet::Instrumentation { port: p, ref payload } if p == port => payload.clone(),
+        _ => vec![],
+    }).collect()
+}
+
+#[test]
+fn enable_configures_swo() {
+    host::attach(ITM_BASE, sim::Itm::new());
+
+    /* 8 MHz HSI out of reset. */
+    itm::enable(2_000_000).unwrap();
+
+    assert_eq!(host::peek(TPIU_BASE, TPIU_ACPR), 3);
+    assert_eq!(host::peek(TPIU_BASE, TPIU_SPPR), 2);
+    assert_eq!(host::peek(DBGMCU_BASE, DBGMCU_CR), 1 << 5);
+    assert_eq!(host::peek(ITM_BASE, ITM_LAR), 0xC5AC_CE55);
+    assert_eq!(host::peek(ITM_BASE, ITM_TCR), 1 << 16 | 1 << 2 | 1);
+    assert!(itm::is_enabled());
+
+    assert_eq!(itm::enable(0), Err(Error::Itm(ItmError::InvalidBaudRate(0))));
+    assert_eq!(itm::enable(16_000_000), Err(Error::Itm(ItmError::InvalidBaudRate(16_000_000))));
+    assert_eq!(itm::enable(900), Err(Error::Itm(ItmError::InvalidBaudRate(900))));
+    assert_eq!(itm::port(32), Err(Error::Itm(ItmError::InvalidPort(32))));
+}
+
+#[test]
+fn formatted_output_decodes() {
+    host::attach(ITM_BASE, sim::Itm::new());
+    itm::enable(2_000_000).unwrap();
+    itm::enable_port(3).unwrap();
+    assert_eq!(host::peek(ITM_BASE, ITM_TER), 1 << 3);
+
+    let mut port = itm::port(3).unwrap();
+    write!(port, "tick {}", 42).unwrap();
+    port.try_write_u16(0xBEEF).unwrap();
+
+    let swo = host::with_model(ITM_BASE, |itm: &mut sim::Itm, _| itm.take());
+    let packets = decode(&swo);
+
+    /* "tick" in a word packet, then "42" a byte at a time. */
+    assert_eq!(packets[0], Packet::Instrumentation { port: 3, payload: b"tick".to_vec() });
+    assert_eq!(packets[1], Packet::Instrumentation { port: 3, payload: b" ".to_vec() });
+    assert_eq!(packets[4], Packet::Instrumentation { port: 3, payload: vec![0xEF, 0xBE] });
+    assert_eq!(instrumentation(&packets, 3), b"tick 42\xEF\xBE".to_vec());
+}
+
+#[test]
+fn writes_dropped_or_blocked() {
+    host::attach(ITM_BASE, sim::Itm::new());
+    itm::enable(2_000_000).unwrap();
+
+    /* The port is off, nothing goes out and nothing waits. */
+    let mut port = itm::port(0).unwrap();
+    assert!(!port.is_enabled());
+    port.write_str("lost").unwrap();
+
+    itm::enable_port(0).unwrap();
+    host::with_model(ITM_BASE, |itm: &mut sim::Itm, _| itm.stall(true));
+    assert_eq!(port.try_write_u8(b'x'), Err(nb::Error::WouldBlock));
+
+    host::with_model(ITM_BASE, |itm: &mut sim::Itm, _| itm.stall(false));
+    port.try_write_u32(0x0403_0201).unwrap();
+
+    let swo = host::with_model(ITM_BASE, |itm: &mut sim::Itm, _| itm.take());
+    assert_eq!(swo, vec![0b0000_0011, 1, 2, 3, 4]);
+
+    itm::disable();
+    port.write_str("lost").unwrap();
+    assert!(host::with_model(ITM_BASE, |itm: &mut sim::Itm, _| itm.take()).is_empty());
+}
+
+#[test]
+fn decoder_packet_kinds() {
+    let swo = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, /* sync */
+        0x70,                               /* overflow */
+        0x09, b'A',                         /* port 1, 1 byte */
+        0x1A, 0x34, 0x12,                   /* port 3, 2 bytes */
+        0x47, 0x01, 0x00, 0x00, 0x00,       /* hardware 8, 4 bytes */
+        0x30,                               /* timestamp 3 */
+        0xC0, 0x81, 0x01,                   /* timestamp 129 */
+        0x08,                               /* extension */
+        0x0B, 0xFF,                         /* port 1, cut short */
+    ];
+
+    assert_eq!(decode(&swo), vec![
+        Packet::Sync,
+        Packet::Overflow,
+        Packet::Instrumentation { port: 1, payload: vec![b'A'] },
+        Packet::Instrumentation { port: 3, payload: vec![0x34, 0x12] },
+        Packet::Hardware { id: 8, payload: vec![1, 0, 0, 0] },
+        Packet::Timestamp { delta: 3 },
+        Packet::Timestamp { delta: 129 },
+        Packet::Unknown(0x08),
+    ]);
+}
@@ -0,0 +1,201 @@
+extern crate opencm;
+
+use opencm::common::bitband;
+use opencm::common::mmio::host;
+use opencm::common::sim;
+use opencm::error::{GpioError, RccError, SpiError, TimerError, UartError};
+use opencm::{gpio, rcc, spi, timer, uart, Error, Peripherals};
+
+const RCC_BASE: usize = 0x4002_3800;
+const RCC_AHB1ENR: usize = 0x30;
+const RCC_APB2ENR: usize = 0x44;
+const RCC_APB2RSTR: usize = 0x24;
+const SPI1_BASE: usize = 0x4001_3000;
+
+#[test]
+fn gpio_pin_configuration() {
+    let p = Peripherals::take().unwrap();
+    p.GPIOA.set_pin_mode(5, gpio::PinMode::Output).unwrap();
+    p.GPIOA.set_pin_pupd(5, gpio::PuPdMode::Up).unwrap();
+
+    assert_eq!(p.GPIOA.moder.get(), 0b01 << 10);
+    assert_eq!(p.GPIOA.pupdr.get(), 0b01 << 10);
+    assert_eq!(p.GPIOB.moder.get(), 0);
+}
+
+#[test]
+fn gpio_alternate_function() {
+    let p = Peripherals::take().unwrap();
+    p.GPIOE.set_pin_af(2, gpio::PeripheralFunction::AF7).unwrap();
+    p.GPIOE.set_pin_af(9, gpio::PeripheralFunction::AF5).unwrap();
+    p.GPIOE.set_pin_output_type(9, gpio::OutputType::OpenDrain).unwrap();
+    p.GPIOE.set_pin_output_speed(9, gpio::OutputSpeed::High).unwrap();
+
+    assert_eq!(p.GPIOE.afrl.get(), 0x7 << 8);
+    assert_eq!(p.GPIOE.afrh.get(), 0x5 << 4);
+    assert_eq!(p.GPIOE.otyper.get(), 1 << 9);
+    assert_eq!(p.GPIOE.ospeedr.get(), 0b11 << 18);
+}
+
+#[test]
+fn gpio_read_write() {
+    let p = Peripherals::take().unwrap();
+    p.GPIOC.write(0xa5a5);
+    assert_eq!(p.GPIOC.odr.get(), 0xa5a5);
+
+    p.GPIOC.idr.set(1 << 13);
+    assert!(p.GPIOC.read_pin(13).unwrap());
+    assert!(!p.GPIOC.read_pin(12).unwrap());
+    assert_eq!(p.GPIOC.read_pins(0xf000), 1 << 13);
+}
+
+#[test]
+fn rcc_peripheral_enable() {
+    rcc::enable(rcc::Peripheral::GPIOD);
+    rcc::enable(rcc::Peripheral::SPI1);
+
+    assert_eq!(host::peek(RCC_BASE, RCC_AHB1ENR), 1 << 3);
+    assert_eq!(host::peek(RCC_BASE, RCC_APB2ENR), 1 << 12);
+}
+
+#[test]
+fn rcc_peripheral_disable_and_reset() {
+    rcc::enable(rcc::Peripheral::GPIOA);
+    rcc::enable(rcc::Peripheral::GPIOD);
+    rcc::disable(rcc::Peripheral::GPIOD);
+    assert_eq!(host::peek(RCC_BASE, RCC_AHB1ENR), 1 << 0);
+
+    rcc::reset_hold(rcc::Peripheral::SPI1);
+    assert_eq!(host::peek(RCC_BASE, RCC_APB2RSTR), 1 << 12);
+    rcc::reset_release(rcc::Peripheral::SPI1);
+    assert_eq!(host::peek(RCC_BASE, RCC_APB2RSTR), 0);
+}
+
+#[test]
+fn bitband_alias_addresses() {
+    /* RCC_AHB1ENR bit 3 and the first word of SRAM, bit 31. */
+    assert_eq!(bitband::alias(RCC_BASE + RCC_AHB1ENR, 3), Some(0x4247_060c));
+    assert_eq!(bitband::alias(0x2000_0000, 31), Some(0x2200_007c));
+    assert_eq!(bitband::alias(0x2010_0000, 0), None);
+    assert_eq!(bitband::alias(0xe000_e100, 0), None);
+}
+
+#[test]
+fn uart_enable() {
+    let p = Peripherals::take().unwrap();
+    let usart2 = uart::enable(p.USART2, 115_200, uart::StopBits::One, uart::Parity::None, false).unwrap();
+
+    let cr1 = usart2.cr1.get();
+    assert_eq!(usart2.brr.get(), (2 * rcc::apb1_frequency() + 115_200) / (2 * 115_200));
+    assert!(cr1 & (1 << 13) != 0, "UE not set");
+    assert!(cr1 & (1 << 12) == 0, "M set for 8 data bits");
+}
+
+#[test]
+fn spi_transfer() {
+    let p = Peripherals::take().unwrap();
+    let spi1 = p.SPI1.init_master(spi::BaudRate::Div16, spi::ClockPolarity::IdleHigh, spi::ClockPhase::SecondTransition,
+                                  spi::DataFrame::Bits8, spi::BitOrder::MsbFirst);
+    spi1.enable();
+    assert_eq!(spi1.cr1.get(), (1 << 6) | (0x3 << 3) | (1 << 2) | (1 << 1) | 1);
+
+    /* Plain memory never raises RXNE on its own. */
+    spi1.sr.set(1 << 0);
+    assert_eq!(spi1.xfer(0x42).unwrap(), 0x42);
+}
+
+#[test]
+fn timer_configuration() {
+    let p = Peripherals::take().unwrap();
+    timer::set_prescaler(&p.TIM3, 83);
+    timer::set_period(&p.TIM3, 999);
+    timer::set_oc_mode(&p.TIM3, timer::OutputCompareChannel::OChannel2, timer::OutputCompareMode::PWM1);
+    timer::set_oc_value(&p.TIM3, timer::OutputCompareChannel::OChannel2, 250);
+    timer::enable_counter(&p.TIM3);
+
+    assert_eq!(p.TIM3.psc.get(), 83);
+    assert_eq!(p.TIM3.arr.get(), 999);
+    assert_eq!(p.TIM3.ccmr1.get(), 0x6 << 12);
+    assert_eq!(p.TIM3.ccr2.get(), 250);
+    assert_eq!(p.TIM3.cr1.get(), 1);
+}
+
+#[test]
+fn register_files_reset() {
+    let p = Peripherals::take().unwrap();
+    p.GPIOE.write(0xffff);
+    host::poke(RCC_BASE, RCC_AHB1ENR, 0x1f);
+
+    host::reset();
+
+    assert_eq!(p.GPIOE.odr.get(), 0);
+    assert_eq!(host::peek(RCC_BASE, RCC_AHB1ENR), 0);
+}
+
+#[test]
+fn peripherals_taken_once() {
+    let p = Peripherals::take();
+
+    assert!(p.is_some());
+    assert!(Peripherals::take().is_none(), "peripherals handed out twice");
+}
+
+#[test]
+fn invalid_configuration_rejected() {
+    let p = Peripherals::take().unwrap();
+
+    assert_eq!(p.GPIOA.set_pin_mode(16, gpio::PinMode::Output), Err(Error::Gpio(GpioError::InvalidPin(16))));
+    assert_eq!(p.GPIOA.read_pin(16), Err(Error::Gpio(GpioError::InvalidPin(16))));
+    assert_eq!(p.SPI1.set_standard_mode(4), Err(Error::Spi(SpiError::InvalidMode(4))));
+    assert_eq!(timer::set_repetition_counter(&p.TIM2, 3), Err(Error::Timer(TimerError::NotAdvanced)));
+    assert!(uart::enable(p.USART2, 0, uart::StopBits::One, uart::Parity::None, false)
+        .err() == Some(Error::Uart(UartError::BaudRate(0))));
+
+    assert_eq!(p.GPIOA.moder.get(), 0);
+    assert_eq!(p.SPI1.cr1.get(), 0);
+    assert_eq!(p.TIM2.rcr.get(), 0);
+}
+
+#[test]
+fn spi_overrun_reported() {
+    let p = Peripherals::take().unwrap();
+    host::attach(SPI1_BASE, sim::Spi::loopback());
+
+    p.SPI1.sr.set(1 << 6 | 1 << 1);
+    assert_eq!(p.SPI1.xfer(0x42), Err(Error::Spi(SpiError::Overrun)));
+
+    /* Reporting it cleared OVR. */
+    assert_eq!(p.SPI1.sr.get() & 1 << 6, 0);
+    assert_eq!(p.SPI1.xfer(0x43), Ok(0x43));
+}
+
+#[test]
+fn spi_mode_fault_cleared() {
+    let p = Peripherals::take().unwrap();
+    host::attach(SPI1_BASE, sim::Spi::loopback());
+
+    p.SPI1.sr.set(1 << 5 | 1 << 1);
+    assert_eq!(p.SPI1.send(0x42), Err(Error::Spi(SpiError::ModeFault)));
+
+    /* MSTR and SPE are back. */
+    assert_eq!(p.SPI1.sr.get() & 1 << 5, 0);
+    assert_eq!(p.SPI1.cr1.get() & (1 << 6 | 1 << 2), 1 << 6 | 1 << 2);
+    assert_eq!(p.SPI1.xfer(0x43), Ok(0x43));
+}
+
+#[test]
+fn spi_crc_error_cleared() {
+    let p = Peripherals::take().unwrap();
+
+    p.SPI1.sr.set(1 << 4 | 1 << 1);
+    assert_eq!(p.SPI1.send(0x42), Err(Error::Spi(SpiError::Crc)));
+    assert_eq!(p.SPI1.sr.get(), 1 << 1);
+    assert_eq!(p.SPI1.send(0x43), Ok(()));
+}
+
+#[test]
+fn rcc_set_clock_times_out() {
+    /* Plain memory never reports HSIRDY. */
+    assert_eq!(rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock84MHz),
+               Err(Error::Rcc(RccError::OscillatorTimeout)));
+}
@@ -0,0 +1,75 @@
+extern crate opencm;
+
+use opencm::common::mmio::host::{self, Op};
+use opencm::error::MpuError;
+use opencm::mpu::{self, Access, Cache, Region};
+use opencm::Error;
+
+const MPU_BASE: usize = 0xE000_ED90;
+const MPU_CTRL: usize = 0x04;
+const MPU_RNR: usize = 0x08;
+const MPU_RBAR: usize = 0x0C;
+const MPU_RASR: usize = 0x10;
+
+fn writes(trace: Vec<host::Access>) -> Vec<(usize, u32)> {
+    trace.iter().filter(|a| a.op == Op::Write && a.base == MPU_BASE).map(|a| (a.offset, a.new)).collect()
+}
+
+#[test]
+fn region_attributes() {
+    host::trace_start();
+    mpu::set_region(2, &Region {
+        base: 0x2001_0000,
+        size: 64 * 1024,
+        access: Access::ReadWrite,
+        execute_never: true,
+        cache: Cache::WriteBack,
+        shareable: false,
+    }).unwrap();
+
+    /* AP = 0b011, XN, TEX = 0, C, B, SIZE = 15, ENABLE */
+    assert_eq!(writes(host::trace_stop()), vec![
+        (MPU_RNR, 2),
+        (MPU_RASR, 0),
+        (MPU_RBAR, 0x2001_0000),
+        (MPU_RASR, 1 << 28 | 0b011 << 24 | 0b11 << 16 | 15 << 1 | 1),
+    ]);
+
+    mpu::disable_region(2).unwrap();
+    assert_eq!(host::peek(MPU_BASE, MPU_RASR), 0);
+}
+
+#[test]
+fn invalid_regions_rejected() {
+    let mut region = Region {
+        base: 0x0800_0000,
+        size: 48,
+        access: Access::ReadOnly,
+        execute_never: false,
+        cache: Cache::WriteThrough,
+        shareable: false,
+    };
+
+    assert_eq!(mpu::set_region(8, &region), Err(Error::Mpu(MpuError::InvalidRegion(8))));
+    assert_eq!(mpu::set_region(0, &region), Err(Error::Mpu(MpuError::InvalidSize(48))));
+
+    region.size = 1024;
+    region.base = 0x0800_0200;
+    assert_eq!(mpu::set_region(0, &region), Err(Error::Mpu(MpuError::Misaligned(0x0800_0200))));
+    assert_eq!(host::peek(MPU_BASE, MPU_RASR), 0, "register touched by a rejected region");
+}
+
+#[test]
+fn stack_guard() {
+    mpu::set_guard(7, 0x2000_1100, mpu::GUARD_SIZE).unwrap();
+
+    assert_eq!(host::peek(MPU_BASE, MPU_RNR), 7);
+    assert_eq!(host::peek(MPU_BASE, MPU_RBAR), 0x2000_1100);
+    /* No access, never executed, strongly ordered and 256 bytes. */
+    assert_eq!(host::peek(MPU_BASE, MPU_RASR), 1 << 28 | 1 << 18 | 7 << 1 | 1);
+    assert_eq!(host::peek(MPU_BASE, MPU_CTRL), 0b101, "MPU not on with the default map");
+    assert!(mpu::is_enabled());
+
+    assert_eq!(mpu::set_guard(7, 0x2000_1020, 256), Err(Error::Mpu(MpuError::Misaligned(0x2000_1020))));
+    assert_eq!(mpu::set_guard(7, 0x2000_1000, 16), Err(Error::Mpu(MpuError::InvalidSize(16))));
+}
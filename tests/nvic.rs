@@ -0,0 +1,150 @@
+extern crate opencm;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use opencm::common::mmio::host;
+use opencm::common::sim;
+use opencm::error::NvicError;
+use opencm::nvic::{self, NvicIdx, PriorityGroup};
+use opencm::Error;
+use opencm::nvic::host::raise;
+
+const SCB_BASE: usize = 0xE000_ED00;
+const SCB_VTOR: usize = 0x08;
+const SCB_AIRCR: usize = 0x0C;
+const NVIC_BASE: usize = 0xE000_E100;
+const NVIC_IPR: usize = 0x300;
+
+#[test]
+fn register_moves_vector_table_to_ram() {
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+
+    fn on_usart2() {
+        HITS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    assert_eq!(host::peek(SCB_BASE, SCB_VTOR), 0);
+    assert!(nvic::register(NvicIdx::USART2, &on_usart2).is_none());
+
+    let vtor = host::peek(SCB_BASE, SCB_VTOR);
+    assert_ne!(vtor, 0);
+    assert_eq!(vtor % 512, 0, "VTOR misaligned");
+
+    assert!(raise(NvicIdx::USART2));
+    assert!(raise(NvicIdx::USART2));
+    assert_eq!(HITS.load(Ordering::SeqCst), 2);
+
+    assert!(nvic::register(NvicIdx::EXTI0, &|| ()).is_none());
+    assert_eq!(host::peek(SCB_BASE, SCB_VTOR), vtor, "table moved twice");
+}
+
+#[test]
+fn unregister_restores_vector() {
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+
+    fn first() {
+        HITS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn second() {
+        HITS.fetch_add(10, Ordering::SeqCst);
+    }
+
+    assert!(nvic::register(NvicIdx::TIM2, &first).is_none());
+    assert!(nvic::register(NvicIdx::TIM2, &second).is_some());
+    assert!(raise(NvicIdx::TIM2));
+    assert_eq!(HITS.load(Ordering::SeqCst), 10);
+
+    assert!(nvic::unregister(NvicIdx::TIM2).is_some());
+    assert!(nvic::unregister(NvicIdx::TIM2).is_none());
+    assert!(!raise(NvicIdx::TIM2));
+    assert_eq!(HITS.load(Ordering::SeqCst), 10);
+}
+
+#[test]
+fn vector_replaces_handler() {
+    static HITS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn vector() {
+        HITS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    assert!(nvic::register(NvicIdx::TIM3, &|| ()).is_none());
+    assert!(nvic::register_vector(NvicIdx::TIM3, vector).is_some());
+    assert!(raise(NvicIdx::TIM3));
+    assert_eq!(HITS.load(Ordering::SeqCst), 1);
+
+    assert!(nvic::unregister(NvicIdx::TIM3).is_none());
+    assert!(!raise(NvicIdx::TIM3));
+}
+
+#[test]
+fn priorities_use_top_four_bits() {
+    nvic::set_priority(NvicIdx::USART2, 12).unwrap();
+    nvic::set_priority(NvicIdx::TIM1_UP_TIM10, 1).unwrap();
+    nvic::enable(NvicIdx::USART2);
+
+    /* USART2 is byte 2 of the IPR word at 36, TIM1_UP_TIM10 byte 1 of the one at 24. */
+    assert_eq!(host::peek(NVIC_BASE, NVIC_IPR + 36), 0xC0 << 16);
+    assert_eq!(host::peek(NVIC_BASE, NVIC_IPR + 24), 0x10 << 8);
+    assert_eq!(nvic::get_priority(NvicIdx::USART2), 12, "enable overwrote the priority");
+    assert_eq!(nvic::get_priority(NvicIdx::TIM1_UP_TIM10), 1);
+
+    assert_eq!(nvic::set_priority(NvicIdx::USART2, 16), Err(Error::Nvic(NvicError::InvalidPriority(16))));
+    assert_eq!(nvic::get_priority(NvicIdx::USART2), 12);
+}
+
+#[test]
+fn priority_grouping() {
+    assert_eq!(nvic::priority_grouping(), PriorityGroup::Preempt4Sub0);
+
+    nvic::set_priority_grouping(PriorityGroup::Preempt2Sub2);
+    assert_eq!(host::peek(SCB_BASE, SCB_AIRCR), 0x05FA_0000 | 5 << 8);
+    assert_eq!(nvic::priority_grouping(), PriorityGroup::Preempt2Sub2);
+
+    assert_eq!(nvic::encode_priority(2, 1), Ok(0b1001));
+    assert_eq!(nvic::decode_priority(0b1001), (2, 1));
+    assert_eq!(nvic::encode_priority(4, 0), Err(Error::Nvic(NvicError::InvalidPriority(4))));
+    assert_eq!(nvic::encode_priority(0, 4), Err(Error::Nvic(NvicError::InvalidSubPriority(4))));
+
+    nvic::set_priority_grouping(PriorityGroup::Preempt0Sub4);
+    assert_eq!(nvic::decode_priority(0b1001), (0, 0b1001));
+}
+
+#[test]
+fn pending_enabled_and_active() {
+    host::attach(NVIC_BASE, sim::Nvic::new());
+
+    assert!(!nvic::is_enabled(NvicIdx::EXTI0));
+    nvic::enable(NvicIdx::EXTI0);
+    nvic::enable(NvicIdx::USART6);
+    assert!(nvic::is_enabled(NvicIdx::EXTI0));
+    assert!(nvic::is_enabled(NvicIdx::USART6));
+    nvic::disable(NvicIdx::EXTI0);
+    assert!(!nvic::is_enabled(NvicIdx::EXTI0));
+    assert!(nvic::is_enabled(NvicIdx::USART6));
+
+    nvic::set_pending(NvicIdx::USART6);
+    assert!(nvic::is_pending(NvicIdx::USART6));
+    nvic::clear_pending(NvicIdx::USART6);
+    assert!(!nvic::is_pending(NvicIdx::USART6));
+
+    assert!(!nvic::is_active(NvicIdx::USART6));
+    host::with_model(NVIC_BASE, |nvic: &mut sim::Nvic, regs| nvic.activate(regs, NvicIdx::USART6 as usize, true));
+    assert!(nvic::is_active(NvicIdx::USART6));
+}
+
+#[test]
+fn software_trigger() {
+    host::attach(NVIC_BASE, sim::Nvic::new());
+    host::trace_start();
+
+    nvic::trigger(NvicIdx::TIM2);
+
+    let trace = host::trace_stop();
+    assert_eq!(trace.len(), 1, "STIR takes a single write");
+    assert_eq!((trace[0].offset, trace[0].new), (0xE00, NvicIdx::TIM2 as u32));
+
+    assert!(nvic::is_pending(NvicIdx::TIM2));
+    assert!(!nvic::is_pending(NvicIdx::TIM3));
+}
@@ -0,0 +1,250 @@
+extern crate opencm;
+
+use std::panic;
+
+use opencm::common::mmio::host::{self, Op};
+use opencm::common::sim;
+use opencm::error::RccError;
+use opencm::flash::Latency;
+use opencm::rcc::{self, limits, ClockConfig, Hpre, Ppre, ResetCause, Source};
+use opencm::Error;
+
+const RCC_BASE: usize = 0x4002_3800;
+const RCC_CSR: usize = 0x74;
+const SCB_BASE: usize = 0xE000_ED00;
+const SCB_AIRCR: usize = 0x0C;
+
+#[test]
+fn power_on_reset_cause() {
+    host::attach(RCC_BASE, sim::Rcc);
+
+    let causes = rcc::reset_cause();
+    assert!(causes.contains(ResetCause::PowerOn));
+    assert!(causes.contains(ResetCause::BrownOut));
+    assert!(causes.contains(ResetCause::Pin));
+    assert!(!causes.contains(ResetCause::Software));
+    assert_eq!(causes.primary(), Some(ResetCause::PowerOn));
+
+    /* RMVF cleared the flags. */
+    assert_eq!(host::peek(RCC_BASE, RCC_CSR) & 0xFF00_0000, 0);
+    assert!(rcc::reset_cause().is_empty());
+}
+
+#[test]
+fn watchdog_reset_cause() {
+    host::attach(RCC_BASE, sim::Rcc);
+    /* IWDGRSTF, PINRSTF and LSION. */
+    host::poke(RCC_BASE, RCC_CSR, 1 << 29 | 1 << 26 | 1);
+
+    let causes = rcc::reset_cause();
+    assert_eq!(causes.iter().collect::<Vec<_>>(), vec![ResetCause::IndependentWatchdog, ResetCause::Pin]);
+    assert_eq!(host::peek(RCC_BASE, RCC_CSR) & 1, 1, "LSI switched off");
+}
+
+#[test]
+fn system_reset_requests_reset() {
+    /* PRIGROUP 5 */
+    host::poke(SCB_BASE, SCB_AIRCR, 5 << 8);
+
+    assert!(panic::catch_unwind(|| rcc::system_reset()).is_err());
+    assert_eq!(host::peek(SCB_BASE, SCB_AIRCR), 0x05FA << 16 | 5 << 8 | 1 << 2);
+}
+
+/* Evaluated by the compiler. */
+const USB_48MHZ: Result<ClockConfig, Error> = ClockConfig::solve(Source::HseCrystal(8_000_000), 48_000_000, true);
+
+#[test]
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))] /* 168 MHz */
+fn solve_168mhz_with_usb() {
+    let config = ClockConfig::solve(Source::HseCrystal(8_000_000), 168_000_000, true).unwrap();
+
+    /* 2 MHz into the VCO, 336 MHz out of it. */
+    assert_eq!((config.pllm, config.plln, config.pllp, config.pllq), (4, 168, 2, 7));
+    assert_eq!(config.sysclk(), 168_000_000);
+    assert_eq!(config.pll48(), 48_000_000);
+    assert_eq!((config.ppre1, config.ppre2), (Ppre::Div4, Ppre::Div2));
+    assert_eq!(config.vos, opencm::pwr::VOSScale::Scale1);
+    assert_eq!(config.flash_latency, Latency::Ws5);
+    assert!(!config.over_drive);
+}
+
+#[test]
+fn solve_within_part_limits() {
+    let config = USB_48MHZ.unwrap();
+
+    assert_eq!(config.sysclk(), 48_000_000);
+    assert_eq!(config.pll48(), 48_000_000);
+    assert_eq!(config.ppre2, Ppre::Div1);
+    assert_eq!(config.flash_latency, Latency::Ws1);
+
+    /* The fastest APB1 the part allows. */
+    assert!(config.apb1() <= limits::APB1);
+    assert!(config.apb1() * 2 > limits::APB1);
+}
+
+#[test]
+#[cfg(not(feature = "stm32f401"))] /* 100 MHz */
+fn solve_100mhz_from_10mhz() {
+    let config = ClockConfig::solve(Source::HseCrystal(10_000_000), 100_000_000, false).unwrap();
+
+    assert_eq!((config.pllm, config.plln, config.pllp), (5, 100, 2));
+    assert!(config.pll48() <= 48_000_000);
+    assert!(config.apb1() <= limits::APB1);
+    assert_eq!(config.flash_latency, Latency::Ws3);
+
+    /* A 200 or 400 MHz VCO has no 48 MHz output. */
+    assert_eq!(ClockConfig::solve(Source::HseCrystal(10_000_000), 100_000_000, true), Err(Error::Rcc(RccError::NoClockConfig)));
+}
+
+#[test]
+fn solve_with_ahb_prescaler() {
+    /* Below what the PLL puts out, from the 16 MHz HSI halved. */
+    let config = ClockConfig::solve(Source::Hsi, 8_000_000, false).unwrap();
+
+    assert!(!config.pll);
+    assert_eq!(config.hpre, Hpre::Div2);
+    assert_eq!(config.sysclk(), 16_000_000);
+    assert_eq!(config.ahb(), 8_000_000);
+    assert_eq!((config.ppre1, config.ppre2), (Ppre::Div1, Ppre::Div1));
+    assert_eq!(config.flash_latency, Latency::Ws0);
+
+    /* 6 MHz needs the PLL at 12 MHz, or more with USB. */
+    let config = ClockConfig::solve(Source::HseCrystal(8_000_000), 6_000_000, true).unwrap();
+
+    assert!(config.pll);
+    assert!(config.hpre != Hpre::Div1);
+    assert_eq!(config.ahb(), 6_000_000);
+    assert_eq!(config.pll48(), 48_000_000);
+
+    host::attach(RCC_BASE, sim::Rcc);
+    rcc::configure(&config).unwrap();
+
+    assert_eq!(rcc::ahb_frequency(), 6_000_000);
+    /* HPRE */
+    assert_eq!(host::peek(RCC_BASE, 0x08) >> 4 & 0xF, config.hpre as u32);
+}
+
+#[test]
+fn solve_out_of_range() {
+    assert_eq!(ClockConfig::solve(Source::HseCrystal(3_000_000), 48_000_000, false),
+               Err(Error::Rcc(RccError::FrequencyOutOfRange(3_000_000))));
+    assert_eq!(ClockConfig::solve(Source::HseCrystal(8_000_000), limits::SYSCLK + 1, false),
+               Err(Error::Rcc(RccError::FrequencyOutOfRange(limits::SYSCLK + 1))));
+}
+
+#[test]
+#[cfg(any(feature = "stm32f427", feature = "stm32f429", feature = "stm32f446"))] /* 180 MHz */
+fn configure_180mhz_with_over_drive() {
+    const PWR_BASE: usize = 0x4000_7000;
+
+    let config = ClockConfig::solve(Source::HseCrystal(24_000_000), 180_000_000, false).unwrap();
+
+    assert_eq!((config.pllm, config.plln, config.pllp), (12, 180, 2));
+    assert_eq!((config.ppre1, config.ppre2), (Ppre::Div4, Ppre::Div2));
+    assert_eq!(config.flash_latency, Latency::Ws5);
+    assert!(config.over_drive);
+
+    host::attach(RCC_BASE, sim::Rcc);
+    host::attach(PWR_BASE, sim::Pwr);
+    rcc::configure(&config).unwrap();
+
+    /* ODEN and ODSWEN */
+    assert_eq!(host::peek(PWR_BASE, 0x00) & (0b11 << 16), 0b11 << 16);
+    /* PLLSRC = HSE, M = 12, N = 180, P = 2 */
+    assert_eq!(host::peek(RCC_BASE, 0x04) & 0x0043_7FFF, 1 << 22 | 180 << 6 | 12);
+    assert_eq!(rcc::ahb_frequency(), 180_000_000);
+    assert_eq!(rcc::apb1_frequency(), 45_000_000);
+}
+
+#[test]
+#[cfg(feature = "stm32f411")]
+fn configure_sets_vos_on_f411() {
+    const PWR_BASE: usize = 0x4000_7000;
+
+    let config = ClockConfig::solve(Source::HseCrystal(8_000_000), 100_000_000, false).unwrap();
+    assert_eq!(config.vos, opencm::pwr::VOSScale::Scale1);
+
+    host::attach(RCC_BASE, sim::Rcc);
+    host::attach(PWR_BASE, sim::Pwr);
+    /* Scale 2 out of reset. */
+    assert_eq!(host::peek(PWR_BASE, 0x00) >> 14 & 0b11, 0b10);
+
+    rcc::configure(&config).unwrap();
+
+    /* PWREN, then VOS at scale 1. */
+    assert!(host::peek(RCC_BASE, 0x40) & (1 << 28) != 0, "PWR clock off");
+    assert_eq!(host::peek(PWR_BASE, 0x00) >> 14 & 0b11, 0b11);
+}
+
+#[test]
+fn hsi_without_pll() {
+    let config = ClockConfig::solve(Source::Hsi, 16_000_000, false).unwrap();
+
+    assert!(!config.pll);
+    assert_eq!(config.sysclk(), 16_000_000);
+    assert_eq!((config.ppre1, config.ppre2), (Ppre::Div1, Ppre::Div1));
+    assert_eq!(config.flash_latency, Latency::Ws0);
+
+    host::attach(RCC_BASE, sim::Rcc);
+    host::trace_start();
+    rcc::configure(&config).unwrap();
+
+    /* SW = HSI twice, no PLL, HSE off and HSI left on. */
+    let cfgr: Vec<u32> = host::trace_stop().into_iter()
+        .filter(|a| a.op == Op::Write && a.base == RCC_BASE && a.offset == 0x08)
+        .map(|a| a.new & 0b11)
+        .collect();
+    assert_eq!(cfgr.first(), Some(&0b00));
+    assert_eq!(cfgr.last(), Some(&0b00));
+    assert_eq!(host::peek(RCC_BASE, 0x00) & (1 << 24 | 1 << 16 | 1 << 0), 1 << 0);
+    assert_eq!(rcc::ahb_frequency(), 16_000_000);
+}
+
+#[test]
+fn hsi_fed_pll() {
+    let config = ClockConfig::solve(Source::Hsi, 84_000_000, true).unwrap();
+
+    assert!(config.pll);
+    assert_eq!(config.sysclk(), 84_000_000);
+    assert_eq!(config.pll48(), 48_000_000);
+
+    host::attach(RCC_BASE, sim::Rcc);
+    rcc::configure(&config).unwrap();
+
+    let cr = host::peek(RCC_BASE, 0x00);
+    let pllcfgr = host::peek(RCC_BASE, 0x04);
+    assert_eq!(pllcfgr & (1 << 22), 0, "PLLSRC not HSI");
+    assert_eq!(pllcfgr & 0x3F, config.pllm);
+    assert!(cr & (1 << 0) != 0, "HSI switched off");
+    assert!(cr & (1 << 16) == 0, "HSE switched on");
+    assert_eq!((host::peek(RCC_BASE, 0x08) >> 2) & 0b11, 0b10, "SYSCLK not switched to PLL");
+}
+
+#[test]
+fn hse_bypass() {
+    let config = ClockConfig::solve(Source::HseBypass(8_000_000), 8_000_000, false).unwrap();
+
+    assert!(!config.pll);
+
+    host::attach(RCC_BASE, sim::Rcc);
+    rcc::configure(&config).unwrap();
+
+    /* HSEBYP and HSEON, HSI off. */
+    assert_eq!(host::peek(RCC_BASE, 0x00) & (1 << 18 | 1 << 16 | 1 << 0), 1 << 18 | 1 << 16);
+    assert_eq!((host::peek(RCC_BASE, 0x08) >> 2) & 0b11, 0b01, "SYSCLK not switched to HSE");
+
+    /* Back to a crystal, HSEBYP cleared with HSE off. */
+    let crystal = ClockConfig::solve(Source::HseCrystal(8_000_000), 48_000_000, false).unwrap();
+
+    host::trace_start();
+    rcc::configure(&crystal).unwrap();
+
+    let cr: Vec<u32> = host::trace_stop().into_iter()
+        .filter(|a| a.op == Op::Write && a.base == RCC_BASE && a.offset == 0x00)
+        .map(|a| a.new & (1 << 18 | 1 << 16))
+        .collect();
+    assert_eq!(&cr[1..4], &[1 << 18, 0, 1 << 16]);
+
+    assert_eq!(ClockConfig::solve(Source::HseBypass(60_000_000), 48_000_000, false),
+               Err(Error::Rcc(RccError::FrequencyOutOfRange(60_000_000))));
+}
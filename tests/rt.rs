@@ -0,0 +1,56 @@
+#[macro_use]
+extern crate opencm;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use opencm::nvic::NvicIdx;
+use opencm::rt::{self, Exception};
+
+static USART2_HITS: AtomicUsize = AtomicUsize::new(0);
+static SYSTICK_HITS: AtomicUsize = AtomicUsize::new(0);
+
+fn on_usart2() {
+    USART2_HITS.fetch_add(1, Ordering::SeqCst);
+}
+
+fn on_systick() {
+    SYSTICK_HITS.fetch_add(1, Ordering::SeqCst);
+}
+
+interrupt!(USART2, on_usart2);
+exception!(SysTick, on_systick);
+
+#[test]
+fn handlers_bound_by_name() {
+    unsafe {
+        USART2();
+        SysTick();
+        SysTick();
+    }
+
+    assert_eq!(USART2_HITS.load(Ordering::SeqCst), 1);
+    assert_eq!(SYSTICK_HITS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn vector_numbers() {
+    assert_eq!(Exception::SysTick as usize, 15);
+    assert_eq!(Exception::PendSV as usize, 14);
+    assert!((NvicIdx::FPU as usize) < rt::INTERRUPTS);
+    assert!((NvicIdx::USART6 as usize) < rt::INTERRUPTS);
+}
+
+/* The table ends with the last interrupt of the selected part. */
+#[test]
+fn interrupt_count() {
+    #[cfg(feature = "stm32f401")]
+    assert_eq!(rt::INTERRUPTS, NvicIdx::SPI4 as usize + 1);
+    #[cfg(any(feature = "stm32f405", feature = "stm32f407"))]
+    assert_eq!(rt::INTERRUPTS, NvicIdx::FPU as usize + 1);
+    #[cfg(feature = "stm32f411")]
+    assert_eq!(rt::INTERRUPTS, NvicIdx::SPI5 as usize + 1);
+    #[cfg(any(feature = "stm32f427", feature = "stm32f429"))]
+    assert_eq!(rt::INTERRUPTS, NvicIdx::DMA2D as usize + 1);
+    #[cfg(feature = "stm32f446")]
+    assert_eq!(rt::INTERRUPTS, NvicIdx::FMPI2C1_ER as usize + 1);
+}
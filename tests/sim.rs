@@ -0,0 +1,208 @@
+extern crate embedded_hal;
+extern crate embedded_hal_nb;
+extern crate opencm;
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin, StatefulOutputPin};
+use embedded_hal::pwm::SetDutyCycle;
+use embedded_hal::spi::SpiBus;
+use embedded_hal_nb::nb;
+use embedded_hal_nb::serial::{Read, Write};
+
+use opencm::common::mmio::host;
+use opencm::common::sim;
+use opencm::{gpio, rcc, timer, uart, Peripherals};
+
+const RCC_BASE: usize = 0x4002_3800;
+const GPIOB_BASE: usize = 0x4002_0400;
+const USART2_BASE: usize = 0x4000_4400;
+const SPI1_BASE: usize = 0x4001_3000;
+const TIM2_BASE: usize = 0x4000_0000;
+
+#[test]
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))] /* 168 MHz */
+fn rcc_set_clock_completes() {
+    host::attach(RCC_BASE, sim::Rcc);
+
+    rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock168MHz).unwrap();
+
+    let cr = host::peek(RCC_BASE, 0x00);
+    let cfgr = host::peek(RCC_BASE, 0x08);
+    assert!(cr & (1 << 25) != 0, "PLL not ready");
+    assert!(cr & (1 << 0) == 0, "HSI still on");
+    assert_eq!((cfgr >> 2) & 0b11, 0b10, "SYSCLK not switched to PLL");
+    assert_eq!(rcc::ahb_frequency(), 168_000_000);
+}
+
+#[test]
+fn rcc_set_clock_within_part_limits() {
+    host::attach(RCC_BASE, sim::Rcc);
+
+    rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock84MHz).unwrap();
+
+    let cr = host::peek(RCC_BASE, 0x00);
+    let cfgr = host::peek(RCC_BASE, 0x08);
+    assert!(cr & (1 << 25) != 0, "PLL not ready");
+    assert_eq!((cfgr >> 2) & 0b11, 0b10, "SYSCLK not switched to PLL");
+    assert!(rcc::apb1_frequency() <= rcc::limits::APB1);
+    assert!(rcc::apb2_frequency() <= rcc::limits::APB2);
+}
+
+#[test]
+fn gpio_bsrr_and_idr() {
+    let p = Peripherals::take().unwrap();
+    host::attach(GPIOB_BASE, sim::Gpio::new());
+
+    p.GPIOB.set_pin_mode(0, gpio::PinMode::Output).unwrap();
+    p.GPIOB.set_high(1 << 0 | 1 << 1);
+    assert_eq!(p.GPIOB.odr.get(), 0b11);
+
+    p.GPIOB.toggle(1 << 0);
+    assert_eq!(p.GPIOB.odr.get(), 0b10);
+
+    host::with_model(GPIOB_BASE, |port: &mut sim::Gpio, _| port.drive(1 << 4, true));
+    assert_eq!(p.GPIOB.read(), 1 << 4, "only driven inputs and output pins read back");
+
+    p.GPIOB.set_high(1 << 0);
+    assert!(p.GPIOB.read_pin(0).unwrap());
+}
+
+#[test]
+fn uart_loopback() {
+    let p = Peripherals::take().unwrap();
+    host::attach(USART2_BASE, sim::Uart::loopback());
+
+    let usart2 = uart::enable(p.USART2, 115_200, uart::StopBits::One, uart::Parity::None, false).unwrap();
+    usart2.dr.set(0x55);
+
+    assert!(usart2.sr.test(1 << 5), "RXNE not set");
+    assert_eq!(usart2.dr.get(), 0x55);
+    assert!(!usart2.sr.test(1 << 5), "RXNE not cleared");
+}
+
+#[test]
+fn spi_scripted_transfer() {
+    let p = Peripherals::take().unwrap();
+    host::attach(SPI1_BASE, sim::Spi::scripted(&[0xa1, 0xb2]));
+
+    p.SPI1.enable();
+    assert_eq!(p.SPI1.xfer(0x01).unwrap(), 0xa1);
+    assert_eq!(p.SPI1.xfer(0x02).unwrap(), 0xb2);
+    assert_eq!(p.SPI1.xfer(0x03).unwrap(), 0x00);
+
+    p.SPI1.send(0x04).unwrap();
+    assert_eq!(p.SPI1.clean_disable().unwrap(), 0x00);
+
+    host::with_model(SPI1_BASE, |slave: &mut sim::Spi, _| {
+        assert_eq!(slave.sent(), &[0x01, 0x02, 0x03, 0x04]);
+    });
+}
+
+#[test]
+fn timer_update_flag() {
+    let p = Peripherals::take().unwrap();
+    host::attach(TIM2_BASE, sim::Timer::new());
+
+    timer::set_period(&p.TIM2, 9);
+    timer::set_oc_value(&p.TIM2, timer::OutputCompareChannel::OChannel1, 5);
+    timer::generate_event(&p.TIM2, timer::Event::Update);
+    timer::clear_update_flag(&p.TIM2);
+    timer::enable_counter(&p.TIM2);
+
+    host::with_model(TIM2_BASE, |tim: &mut sim::Timer, regs| tim.advance(regs, 5));
+    assert!(timer::get_flag(&p.TIM2, timer::Event::CC1), "CC1IF not set on compare match");
+
+    while !timer::is_update_flag_checked(&p.TIM2) {}
+    assert_eq!(timer::get_counter(&p.TIM2), 1);
+
+    timer::clear_update_flag(&p.TIM2);
+    assert!(!timer::get_flag(&p.TIM2, timer::Event::Update));
+}
+
+#[test]
+fn embedded_hal_pins() {
+    let p = Peripherals::take().unwrap();
+    host::attach(GPIOB_BASE, sim::Gpio::new());
+
+    p.GPIOB.set_pin_mode(3, gpio::PinMode::Output).unwrap();
+    let mut led = p.GPIOB.pin(3).unwrap();
+    let mut button = p.GPIOB.pin(7).unwrap();
+
+    led.set_high().unwrap();
+    assert!(led.is_set_high().unwrap());
+    led.toggle().unwrap();
+    assert_eq!(p.GPIOB.odr.get(), 0);
+
+    host::with_model(GPIOB_BASE, |port: &mut sim::Gpio, _| port.drive(1 << 7, true));
+    assert!(button.is_high().unwrap());
+    assert!(p.GPIOB.pin(16).is_err());
+}
+
+#[test]
+fn embedded_hal_serial() {
+    let p = Peripherals::take().unwrap();
+    host::attach(USART2_BASE, sim::Uart::new());
+
+    let mut usart2 = uart::enable(p.USART2, 115_200, uart::StopBits::One, uart::Parity::None, false).unwrap();
+    assert!(usart2.read().unwrap_err() == nb::Error::WouldBlock);
+
+    nb::block!(usart2.write(b'o')).unwrap();
+    nb::block!(usart2.write(b'k')).unwrap();
+    nb::block!(usart2.flush()).unwrap();
+    host::with_model(USART2_BASE, |uart: &mut sim::Uart, _| {
+        assert_eq!(uart.transmitted(), &[0x6f, 0x6b]);
+        uart.receive(&[0x41]);
+    });
+
+    assert_eq!(nb::block!(usart2.read()).unwrap(), 0x41);
+}
+
+#[test]
+fn embedded_hal_spi_bus() {
+    let mut p = Peripherals::take().unwrap();
+    host::attach(SPI1_BASE, sim::Spi::scripted(&[0x10, 0x20, 0x30]));
+    p.SPI1.enable();
+
+    let mut read = [0u8; 3];
+    p.SPI1.transfer(&mut read, &[0xaa]).unwrap();
+    p.SPI1.flush().unwrap();
+
+    assert_eq!(read, [0x10, 0x20, 0x30]);
+    host::with_model(SPI1_BASE, |slave: &mut sim::Spi, _| {
+        assert_eq!(slave.sent(), &[0xaa, 0x00, 0x00]);
+    });
+}
+
+#[test]
+fn embedded_hal_delay_and_pwm() {
+    let p = Peripherals::take().unwrap();
+    host::attach(TIM2_BASE, sim::Timer::new());
+
+    let mut delay = timer::Delay::new(p.TIM2);
+    delay.delay_us(100);
+
+    let tim2 = delay.free();
+    assert_eq!(tim2.psc.get(), rcc::apb1_frequency() / 1_000_000 - 1);
+    assert_eq!(tim2.arr.get(), 99);
+    assert!(tim2.cr1.get() & 1 == 0, "counter still running after the delay");
+
+    timer::set_period(&tim2, 999);
+    let mut pwm = tim2.pwm_channel(timer::OutputCompareChannel::OChannel3);
+    assert_eq!(pwm.max_duty_cycle(), 1000);
+    pwm.set_duty_cycle_percent(25).unwrap();
+    assert_eq!(tim2.ccr3.get(), 250);
+    pwm.set_duty_cycle_fully_on().unwrap();
+    assert_eq!(tim2.ccr3.get(), 1000);
+
+    /* A 32-bit period is scaled down to 16-bit duty cycles. */
+    timer::set_period(&tim2, 999_999);
+    let mut pwm = tim2.pwm_channel(timer::OutputCompareChannel::OChannel3);
+    assert_eq!(pwm.max_duty_cycle(), 0xffff);
+    pwm.set_duty_cycle_fully_on().unwrap();
+    assert_eq!(tim2.ccr3.get(), 1_000_000);
+    /* 25% of 0xFFFF is 16383. */
+    pwm.set_duty_cycle_percent(25).unwrap();
+    assert_eq!(tim2.ccr3.get(), 249_988);
+    pwm.set_duty_cycle_fully_off().unwrap();
+    assert_eq!(tim2.ccr3.get(), 0);
+}
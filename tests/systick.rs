@@ -0,0 +1,64 @@
+extern crate opencm;
+
+use opencm::common::mmio::host;
+use opencm::common::sim;
+use opencm::{rcc, systick};
+
+const RCC_BASE: usize = 0x4002_3800;
+const SYST_BASE: usize = 0xE000_E010;
+const SYST_CTRL: usize = 0x0;
+const SYST_LOAD: usize = 0x4;
+const SCB_BASE: usize = 0xE000_ED00;
+const SCB_ICSR: usize = 0x04;
+
+/* The core clock is shared by all tests, only this one changes it. */
+#[test]
+fn reload_follows_core_clock() {
+    host::attach(SYST_BASE, sim::SysTick::new());
+
+    systick::start();
+    assert_eq!(host::peek(SYST_BASE, SYST_CTRL), 0b111);
+    assert_eq!(host::peek(SYST_BASE, SYST_LOAD), rcc::ahb_frequency() / 1000 - 1);
+
+    host::attach(RCC_BASE, sim::Rcc);
+    rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock84MHz).unwrap();
+    assert_eq!(host::peek(SYST_BASE, SYST_LOAD), 83_999);
+
+    systick::stop();
+    assert!(!systick::is_running());
+}
+
+#[test]
+fn uptime_counts_ticks() {
+    host::attach(SYST_BASE, sim::SysTick::new());
+    systick::start();
+
+    let reload = host::peek(SYST_BASE, SYST_LOAD) + 1;
+    for _ in 0..3 {
+        systick::tick();
+    }
+
+    /* Lands one step short of the middle, the read of VAL is the last. */
+    host::with_model(SYST_BASE, |syst: &mut sim::SysTick, regs| syst.advance(regs, reload / 2));
+    assert_eq!(systick::uptime_us(), 3500);
+    assert_eq!(systick::uptime_ms(), 3);
+
+    /* A wrap the exception wasn't taken for yet counts as well. */
+    host::poke(SCB_BASE, SCB_ICSR, 1 << 26);
+    assert_eq!(systick::uptime_ms(), 4);
+}
+
+#[test]
+fn delay_waits_for_counter() {
+    host::attach(SYST_BASE, sim::SysTick::new());
+    systick::start();
+
+    let reload = host::peek(SYST_BASE, SYST_LOAD) as u64 + 1;
+    systick::delay_us(250);
+    let elapsed = host::with_model(SYST_BASE, |syst: &mut sim::SysTick, _| syst.elapsed());
+    assert!(elapsed >= reload / 4, "{} ticks", elapsed);
+
+    systick::delay_ms(2);
+    let elapsed = host::with_model(SYST_BASE, |syst: &mut sim::SysTick, _| syst.elapsed()) - elapsed;
+    assert!(elapsed >= 2 * reload && elapsed < 3 * reload, "{} ticks", elapsed);
+}
@@ -0,0 +1,136 @@
+extern crate opencm;
+
+use opencm::common::mmio::host::{self, Access, Op};
+use opencm::common::sim;
+use opencm::{rcc, Peripherals};
+
+const RCC_BASE: usize = 0x4002_3800;
+const GPIOA_BASE: usize = 0x4002_0000;
+const SPI1_BASE: usize = 0x4001_3000;
+
+fn read(base: usize, offset: usize, value: u32) -> Access {
+    Access { op: Op::Read, base, offset, old: value, new: value }
+}
+
+fn write(base: usize, offset: usize, old: u32, new: u32) -> Access {
+    Access { op: Op::Write, base, offset, old, new }
+}
+
+fn writes_to(base: usize, trace: Vec<Access>) -> Vec<(usize, u32)> {
+    trace.into_iter()
+        .filter(|a| a.op == Op::Write && a.base == base)
+        .map(|a| (a.offset, a.new))
+        .collect()
+}
+
+#[test]
+fn gpio_lock_key_sequence() {
+    let p = Peripherals::take().unwrap();
+    host::trace_start();
+    p.GPIOA.lock(0x0003);
+
+    assert_eq!(host::trace_stop(), vec![
+        write(GPIOA_BASE, 0x1c, 0x0000_0000, 0x0001_0003),
+        write(GPIOA_BASE, 0x1c, 0x0001_0003, 0x0000_0003),
+        write(GPIOA_BASE, 0x1c, 0x0000_0003, 0x0001_0003),
+        read(GPIOA_BASE, 0x1c, 0x0001_0003),
+        read(GPIOA_BASE, 0x1c, 0x0001_0003),
+    ]);
+}
+
+#[test]
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))] /* 168 MHz */
+fn rcc_set_clock_switch_sequence() {
+    host::attach(RCC_BASE, sim::Rcc);
+
+    host::trace_start();
+    rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock168MHz).unwrap();
+
+    assert_eq!(writes_to(RCC_BASE, host::trace_stop()), vec![
+        (0x00, 0x0000_0083), /* HSION */
+        (0x08, 0x0000_0000), /* SW = HSI */
+        (0x00, 0x0001_0083), /* HSEON */
+        (0x40, 0x1000_0000), /* PWREN */
+        (0x08, 0x0000_0000), /* HPRE = /1 */
+        (0x08, 0x0000_1400), /* PPRE1 = /4 */
+        (0x08, 0x0000_9400), /* PPRE2 = /2 */
+        (0x04, 0x2740_2A04), /* PLLSRC = HSE, M = 4, N = 168, P = 2, Q = 7 */
+        (0x00, 0x0103_0083), /* PLLON */
+        (0x08, 0x0000_9402), /* SW = PLL */
+        (0x00, 0x0303_0082), /* HSI off */
+    ]);
+}
+
+/* PLLSRC = HSE, P = 4, Q = 7, M = 4 and N = 168 for a 2 MHz VCO input, or
+   M = 5 and N = 210 where N starts at 192. */
+#[cfg(not(feature = "stm32f401"))]
+const PLLCFGR_84MHZ: u32 = 0x2741_2A04;
+#[cfg(feature = "stm32f401")]
+const PLLCFGR_84MHZ: u32 = 0x2741_3485;
+
+#[test]
+fn rcc_set_clock_84mhz_switch_sequence() {
+    host::attach(RCC_BASE, sim::Rcc);
+
+    host::trace_start();
+    rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock84MHz).unwrap();
+
+    assert_eq!(writes_to(RCC_BASE, host::trace_stop()), vec![
+        (0x00, 0x0000_0083), /* HSION */
+        (0x08, 0x0000_0000), /* SW = HSI */
+        (0x00, 0x0001_0083), /* HSEON */
+        (0x40, 0x1000_0000), /* PWREN */
+        (0x08, 0x0000_0000), /* HPRE = /1 */
+        (0x08, 0x0000_1000), /* PPRE1 = /2 */
+        (0x08, 0x0000_1000), /* PPRE2 = /1 */
+        (0x04, PLLCFGR_84MHZ),
+        (0x00, 0x0103_0083), /* PLLON */
+        (0x08, 0x0000_1002), /* SW = PLL */
+        (0x00, 0x0303_0082), /* HSI off */
+    ]);
+    assert_eq!(rcc::ahb_frequency(), 84_000_000);
+}
+
+#[test]
+#[cfg(not(any(feature = "stm32f401", feature = "stm32f411")))] /* 168 MHz */
+fn rcc_set_clock_keeps_cfgr_fields() {
+    host::attach(RCC_BASE, sim::Rcc);
+
+    rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock168MHz).unwrap();
+    host::trace_start();
+    rcc::set_clock(rcc::CrystalClock::Clock8MHz, rcc::Clock::Clock84MHz).unwrap();
+
+    let cfgr: Vec<u32> = writes_to(RCC_BASE, host::trace_stop()).into_iter()
+        .filter(|&(offset, _)| offset == 0x08)
+        .map(|(_, value)| value)
+        .collect();
+
+    assert_eq!(cfgr, vec![
+        0x0000_9408, /* SW = HSI */
+        0x0000_9400, /* HPRE = /1, PPRE1 and PPRE2 untouched */
+        0x0000_9000, /* PPRE1 = /2 */
+        0x0000_1000, /* PPRE2 = /1 */
+        0x0000_1002, /* SW = PLL */
+    ]);
+}
+
+#[test]
+fn spi_clean_disable_sequence() {
+    let p = Peripherals::take().unwrap();
+    host::attach(SPI1_BASE, sim::Spi::loopback());
+
+    p.SPI1.enable();
+    p.SPI1.send(0x5a).unwrap();
+
+    host::trace_start();
+    assert_eq!(p.SPI1.clean_disable().unwrap(), 0x5a);
+
+    assert_eq!(host::trace_stop(), vec![
+        read(SPI1_BASE, 0x08, 0x03),         /* wait for RXNE */
+        read(SPI1_BASE, 0x0c, 0x5a),         /* last data */
+        read(SPI1_BASE, 0x08, 0x02),         /* wait for TXE */
+        read(SPI1_BASE, 0x08, 0x02),         /* wait for !BSY */
+        read(SPI1_BASE, 0x00, 0x40),
+        write(SPI1_BASE, 0x00, 0x40, 0x00),  /* SPE off */
+    ]);
+}